@@ -0,0 +1,45 @@
+/* C interface to fdup, built into its cdylib with the `ffi` feature. Kept in sync with
+ * src/ffi.rs by hand. */
+
+#ifndef FDUP_H
+#define FDUP_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define FDUP_OK 0
+#define FDUP_NULL_POINTER (-1)
+#define FDUP_INVALID_PATH (-2)
+#define FDUP_PANICKED (-3)
+
+/* Roots to scan, and the number of paths left out of the last scan because of errors. */
+typedef struct FdupScanner FdupScanner;
+
+/* Called with the paths of each group of duplicates, and the context given to fdup_scan. */
+typedef void (*FdupGroupCallback)(void *context, const char *const *paths, size_t len);
+
+/* New scanner with no roots, to be freed with fdup_scanner_free. */
+FdupScanner *fdup_scanner_new(void);
+
+/* Free scanner, which may be null. */
+void fdup_scanner_free(FdupScanner *scanner);
+
+/* Add the directory or file at path to the roots of scanner. */
+int fdup_scanner_add_root(FdupScanner *scanner, const char *path);
+
+/* Scan the roots of scanner, calling callback with context for each group of duplicates. The
+ * callback may be called from any thread, but never by two at the same time, and the paths that
+ * it's given only live until it returns. */
+int fdup_scan(FdupScanner *scanner, FdupGroupCallback callback, void *context);
+
+/* Number of paths left out of the last scan of scanner because of errors. */
+size_t fdup_scanner_errors(const FdupScanner *scanner);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,86 @@
+//! Checksums of groups of duplicates that are there on purpose, e.g. license files or test
+//! fixtures, so that they can be left out of the results.
+
+use std::{fs, io, path::Path};
+
+/// Checksums of groups of duplicates acknowledged as intended.
+#[derive(Debug, Default)]
+pub struct Acknowledged {
+    checksums: Vec<Box<[u8]>>,
+}
+
+impl Acknowledged {
+    /// # Returns
+    ///
+    /// Checksums listed in the file at `path`, one per line in hex, as written by `sha512sum`.
+    /// Whatever follows a checksum on its line is ignored, like the lines that are blank or start
+    /// with `#`.
+    pub fn read(path: &Path) -> io::Result<Acknowledged> {
+        Acknowledged::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(list: &str) -> io::Result<Acknowledged> {
+        let mut acknowledged = Acknowledged::default();
+        for (i, line) in list.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hex = line.split_whitespace().next().unwrap();
+            match parse_hex(hex) {
+                Some(checksum) => acknowledged.checksums.push(checksum),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("line {}: not a checksum in hex: {}", i + 1, hex),
+                    ))
+                }
+            }
+        }
+        Ok(acknowledged)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if a group whose files have this checksum was acknowledged. Checksums
+    /// shortened with `--checksum-bits` match the listed ones that they're a prefix of.
+    pub fn contains(&self, checksum: &[u8]) -> bool {
+        self.checksums
+            .iter()
+            .any(|acknowledged| acknowledged.starts_with(checksum))
+    }
+}
+
+/// # Returns
+///
+/// Bytes spelled by `hex`, two digits per byte, or `None` if it isn't valid hex.
+fn parse_hex(hex: &str) -> Option<Box<[u8]>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| Some((digit(pair[0])? * 16 + digit(pair[1])?) as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acknowledged() {
+        let list = "# license files\n0aff01  LICENSE\n\n  beef\n";
+        let acknowledged = Acknowledged::parse(list).unwrap();
+        assert!(acknowledged.contains(&[0x0a, 0xff, 0x01]));
+        assert!(acknowledged.contains(&[0x0a, 0xff]));
+        assert!(acknowledged.contains(&[0xbe, 0xef]));
+        assert!(!acknowledged.contains(&[0x0a, 0xff, 0x02]));
+        assert!(!acknowledged.contains(&[0xbe, 0xef, 0x00]));
+
+        assert!(Acknowledged::parse("0aff\nLICENSE\n").is_err());
+        assert!(Acknowledged::parse("abc\n").is_err());
+        assert!(Acknowledged::parse("").unwrap().checksums.is_empty());
+    }
+}
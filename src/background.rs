@@ -0,0 +1,67 @@
+//! Lowering the CPU and IO priority of fdup, so that scans started in the background don't get in
+//! the way of interactive use.
+
+use std::io;
+
+/// Niceness of the process once its priority is lowered, the lowest there is.
+#[cfg(all(unix, not(target_os = "macos")))]
+const NICENESS: libc::c_int = 19;
+
+/// Give the calling thread, and every thread that it starts from then on, the lowest CPU and IO
+/// priority that the platform has, so it should be called before any other thread is started.
+///
+/// On Linux, the niceness is raised to its maximum and the IO priority set to the idle class. On
+/// macOS, the process is put in the background band, which throttles both. Elsewhere on Unix only
+/// the niceness is raised.
+pub fn lower_priority() -> io::Result<()> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+        let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    if unsafe { libc::setpriority(libc::PRIO_DARWIN_PROCESS, 0, libc::PRIO_DARWIN_BG) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[cfg(not(unix))]
+    return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "lowering the priority is only implemented on Unix",
+    ));
+
+    #[cfg(unix)]
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Only ever lowers the priority of the thread it runs on, and of threads started from it.
+    #[test]
+    fn test_lower_priority() {
+        thread::spawn(|| {
+            lower_priority().unwrap();
+            #[cfg(all(unix, not(target_os = "macos")))]
+            assert_eq!(NICENESS, unsafe {
+                libc::getpriority(libc::PRIO_PROCESS, 0)
+            });
+        })
+        .join()
+        .unwrap();
+    }
+}
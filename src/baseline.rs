@@ -0,0 +1,310 @@
+//! Groups of duplicates found by an earlier scan, read back from the results that it wrote with
+//! `--json`, so that only the groups that are new since can be reported.
+
+use crate::output::hex;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Groups of duplicates of a baseline scan, by the paths in them.
+#[derive(Debug, Default)]
+pub struct Baseline {
+    /// Index of the group that each path is in.
+    groups: HashMap<PathBuf, usize>,
+    /// Number of paths in the group of each fingerprint, in hex, if they were written with
+    /// `--fingerprints`.
+    fingerprints: HashMap<String, usize>,
+}
+
+impl Baseline {
+    /// # Returns
+    ///
+    /// Baseline of the results in the file at `path`, as written with `--json`, in any schema
+    /// version.
+    pub fn read(path: &Path) -> io::Result<Baseline> {
+        Baseline::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(json: &str) -> io::Result<Baseline> {
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+        let mut parser = Parser { rest: json };
+        let results = parser.parse_value().map_err(invalid)?;
+        parser.skip_whitespace();
+        if !parser.rest.is_empty() {
+            return Err(invalid("trailing characters after the results"));
+        }
+        let groups = match results.get("groups") {
+            Some(Json::Array(groups)) => groups,
+            _ => return Err(invalid("no groups in the results")),
+        };
+        let mut baseline = Baseline::default();
+        for (i, group) in groups.iter().enumerate() {
+            // Groups are written as objects along with `--fingerprints`.
+            let paths = match (group, group.get("paths")) {
+                (Json::Array(paths), _) | (_, Some(Json::Array(paths))) => paths,
+                _ => return Err(invalid("group isn't an array")),
+            };
+            if let Some(Json::String(fingerprint)) = group.get("fingerprint") {
+                baseline
+                    .fingerprints
+                    .insert(fingerprint.to_lowercase(), paths.len());
+            }
+            for path in paths {
+                // Paths are written as objects along with `--fields`.
+                let path = match path {
+                    Json::String(path) => path,
+                    object => match object.get("path") {
+                        Some(Json::String(path)) => path,
+                        _ => return Err(invalid("path isn't a string")),
+                    },
+                };
+                baseline.groups.insert(PathBuf::from(path), i);
+            }
+        }
+        Ok(baseline)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if every path of `group` was in the same group of the baseline, i.e.
+    /// the group is neither new nor grown since, or if it has the same `fingerprint` as a group of
+    /// the baseline with as many paths or more, even if they were at other paths then. Groups
+    /// that have shrunk are known too.
+    pub fn is_known(&self, group: &[PathBuf], fingerprint: Option<&[u8]>) -> bool {
+        let fingerprinted = fingerprint
+            .and_then(|fingerprint| self.fingerprints.get(&hex(fingerprint)))
+            .is_some_and(|&paths| group.len() <= paths);
+        // Paths that aren't valid unicode were written lossily, and are looked up as such.
+        let mut indices = group
+            .iter()
+            .map(|path| self.groups.get(Path::new(&*path.to_string_lossy())));
+        fingerprinted
+            || match indices.next() {
+                Some(Some(first)) => indices.all(|index| index == Some(first)),
+                _ => false,
+            }
+    }
+}
+
+/// JSON value, as far as results need to be read back.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    /// Left as it's written, since results only hold whole numbers.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// # Returns
+    ///
+    /// Value of the member named `key`, if this is an object that has one.
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t', '\n', '\r']);
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), &'static str> {
+        self.skip_whitespace();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                Ok(())
+            }
+            None => Err("malformed JSON"),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, &'static str> {
+        self.skip_whitespace();
+        match self.rest.as_bytes().first() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b'-' | b'0'..=b'9') => {
+                let end = self
+                    .rest
+                    .find(|c: char| !matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+                    .unwrap_or(self.rest.len());
+                let (number, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                Ok(Json::Number(number.to_string()))
+            }
+            _ => Err("malformed JSON"),
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, &'static str> {
+        self.expect("{")?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.rest.starts_with('}') {
+            self.expect("}")?;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.parse_string()?;
+            self.expect(":")?;
+            members.push((name, self.parse_value()?));
+            self.skip_whitespace();
+            if self.rest.starts_with(',') {
+                self.expect(",")?;
+            } else {
+                self.expect("}")?;
+                return Ok(Json::Object(members));
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Json, &'static str> {
+        self.expect("[")?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.rest.starts_with(']') {
+            self.expect("]")?;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            if self.rest.starts_with(',') {
+                self.expect(",")?;
+            } else {
+                self.expect("]")?;
+                return Ok(Json::Array(values));
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, &'static str> {
+        self.expect("\"")?;
+        let mut string = String::new();
+        let mut chars = self.rest.char_indices();
+        loop {
+            match chars.next() {
+                Some((i, '"')) => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(string);
+                }
+                Some((_, '\\')) => {
+                    let escaped = match chars.next() {
+                        Some((_, 'u')) => {
+                            let mut code = parse_hex4(&mut chars)?;
+                            // Characters outside of the BMP are escaped as surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code) {
+                                let low = match (chars.next(), chars.next()) {
+                                    (Some((_, '\\')), Some((_, 'u'))) => parse_hex4(&mut chars)?,
+                                    _ => return Err("malformed JSON"),
+                                };
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or("malformed JSON")?
+                        }
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, c @ ('"' | '\\' | '/'))) => c,
+                        _ => return Err("malformed JSON"),
+                    };
+                    string.push(escaped);
+                }
+                Some((_, c)) => string.push(c),
+                None => return Err("unterminated string"),
+            }
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut std::str::CharIndices) -> Result<u32, &'static str> {
+    (0..4).try_fold(0, |code, _| {
+        let digit = chars.next().and_then(|(_, c)| c.to_digit(16));
+        digit.map(|digit| code * 16 + digit).ok_or("malformed JSON")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_baseline() {
+        let json = concat!(
+            r#"{"schema_version":2,"groups":[["a","b\"c"],[{"path":"d","nlink":1},"#,
+            r#"{"path":"eé😀","nlink":null}]],"empty_files":[],"errors":[]}"#,
+            "\n"
+        );
+        let baseline = Baseline::parse(json).unwrap();
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert!(baseline.is_known(&paths(&["a", "b\"c"]), None));
+        assert!(baseline.is_known(&paths(&["b\"c", "a"]), None));
+        assert!(baseline.is_known(&paths(&["d", "eé😀"]), None));
+        // grown, merged and new groups aren't
+        assert!(!baseline.is_known(&paths(&["a", "b\"c", "f"]), None));
+        assert!(!baseline.is_known(&paths(&["a", "d"]), None));
+        assert!(!baseline.is_known(&paths(&["f", "g"]), None));
+
+        let version_1 = r#"{"groups":[["a","b"]],"empty_files":[],"errors":[]}"#;
+        assert!(Baseline::parse(version_1)
+            .unwrap()
+            .is_known(&paths(&["a", "b"]), None));
+
+        let fingerprinted = concat!(
+            r#"{"schema_version":2,"groups":[{"fingerprint":"BEEF","paths":["a","b"]}],"#,
+            r#""empty_files":[],"errors":[]}"#
+        );
+        let baseline = Baseline::parse(fingerprinted).unwrap();
+        assert!(baseline.is_known(&paths(&["a", "b"]), None));
+        // moved, but not grown
+        assert!(baseline.is_known(&paths(&["c", "d"]), Some(&[0xbe, 0xef])));
+        assert!(!baseline.is_known(&paths(&["c", "d", "e"]), Some(&[0xbe, 0xef])));
+        assert!(!baseline.is_known(&paths(&["c", "d"]), Some(&[0xbe])));
+        assert!(!baseline.is_known(&paths(&["c", "d"]), None));
+
+        assert!(Baseline::parse(r#"{"groups":[["a","b"]]"#).is_err());
+        assert!(Baseline::parse(r#"{"groups":[["a",1]]}"#).is_err());
+        assert!(Baseline::parse(r#"{"groups":[]} x"#).is_err());
+        assert!(Baseline::parse(r#"{"errors":[]}"#).is_err());
+        assert!(Baseline::parse(r#"[["a","b"]]"#).is_err());
+    }
+
+    // Check that paths that aren't valid unicode are known by the lossy forms they're written in.
+    #[cfg(unix)]
+    #[test]
+    fn test_baseline_invalid_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let json =
+            r#"{"schema_version":2,"groups":[["a\ufffd","b"]],"empty_files":[],"errors":[]}"#;
+        let baseline = Baseline::parse(json).unwrap();
+        let invalid = PathBuf::from(OsStr::from_bytes(b"a\xff"));
+        assert!(baseline.is_known(&[invalid.clone(), PathBuf::from("b")], None));
+        assert!(!baseline.is_known(&[invalid, PathBuf::from("c")], None));
+    }
+}
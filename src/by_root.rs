@@ -0,0 +1,68 @@
+//! Duplicates counted per root, by whether their copies are all under the same root or spread
+//! across several, to tell which of the trees scanned the redundancy comes from.
+
+use crate::fdup::root_of;
+use std::{path::PathBuf, sync::Mutex};
+
+/// Duplicates found under each root, by index.
+#[derive(Debug)]
+pub struct ByRoot {
+    roots: Vec<PathBuf>,
+    /// For each root, the copies in groups entirely under it, besides the first of each, and its
+    /// files in groups with copies under other roots too.
+    duplicates: Mutex<Vec<(u64, u64)>>,
+}
+
+impl ByRoot {
+    pub fn new(roots: &[PathBuf]) -> ByRoot {
+        ByRoot {
+            roots: roots.to_vec(),
+            duplicates: Mutex::new(vec![(0, 0); roots.len()]),
+        }
+    }
+
+    /// Count the duplicates in `group` towards the roots that they're under, the innermost if more
+    /// than one.
+    pub fn add_group(&self, group: &[PathBuf]) {
+        let roots: Vec<Option<usize>> = group
+            .iter()
+            .map(|path| root_of(path, &self.roots))
+            .collect();
+        let mut duplicates = self.duplicates.lock().unwrap();
+        match roots[..] {
+            [Some(root), ..] if roots.iter().all(|&other| other == Some(root)) => {
+                duplicates[root].0 += group.len() as u64 - 1;
+            }
+            _ => {
+                for root in roots.into_iter().flatten() {
+                    duplicates[root].1 += 1;
+                }
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// For each root, the copies in groups of duplicates entirely under it, besides the first of
+    /// each group, and how many of its files are in groups with copies under other roots too.
+    pub fn duplicates(&self) -> Vec<(u64, u64)> {
+        self.duplicates.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_by_root() {
+        let roots = [PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("a/c")];
+        let by_root = ByRoot::new(&roots);
+        let group = |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(PathBuf::from).collect() };
+        by_root.add_group(&group(&["a/1", "a/2", "a/d/3"]));
+        by_root.add_group(&group(&["a/4", "b/4", "b/5"]));
+        by_root.add_group(&group(&["a/c/6", "a/6"]));
+        by_root.add_group(&group(&["a/c/7", "a/c/8"]));
+        assert_eq!(vec![(2, 2), (0, 2), (1, 1)], by_root.duplicates());
+    }
+}
@@ -1,21 +1,1034 @@
-use std::path::PathBuf;
-use structopt::StructOpt;
+use crate::{
+    collate::Collation,
+    errors::ErrorPolicy,
+    fdup::{Backend, EmptyFiles, SampleOptions},
+    fstype::FsTypes,
+    glob::PathPattern,
+    hdd::HddMode,
+    normalize::Normalize,
+    output::{Field, Format, SCHEMA_VERSION},
+    remote::Remote,
+    script::Handler,
+    sniff::ContentType,
+    split::SplitBy,
+    walk::WalkOrder,
+};
+use std::{ffi::OsString, num::ParseIntError, path::PathBuf, time::Duration};
+use structopt::{clap::AppSettings, StructOpt};
+
+/// # Returns
+///
+/// Duration given as a whole number followed by one of the units `s`, `m`, `h` or `d`. A number
+/// without a unit is a number of minutes.
+fn parse_duration(s: &str) -> Result<Duration, ParseIntError> {
+    let (number, unit_secs) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        _ => (s, 60),
+    };
+    Ok(Duration::from_secs(
+        number.parse::<u64>()?.saturating_mul(unit_secs),
+    ))
+}
+
+/// # Returns
+///
+/// Positive, finite number of megabytes per second.
+fn parse_read_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && 0.0 < rate => Ok(rate),
+        _ => Err(format!("not a positive number: {}", s)),
+    }
+}
+
+/// # Returns
+///
+/// Number of bytes given as a whole number, optionally followed by one of the binary units `K`,
+/// `M`, `G` or `T`, in either case.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let error = || format!("not a size: {}", s);
+    let (number, shift) = match s.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => {
+            let shift = match unit.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(error()),
+            };
+            (&s[..i], shift)
+        }
+        _ => (s, 0),
+    };
+    let number: usize = number.parse().map_err(|_| error())?;
+    number.checked_mul(1 << shift).ok_or_else(error)
+}
+
+/// # Returns
+///
+/// Tier of files by size given as `<size>=full`, `<size>=prefix:<bytes>` or
+/// `<size>=sample:<blocks>x<bytes>`, with sizes as for `parse_size`.
+fn parse_hash_tier(s: &str) -> Result<SampleOptions, String> {
+    let error = || format!("not a hashing tier: {}", s);
+    let (threshold, strategy) = s.split_once('=').ok_or_else(error)?;
+    let size = |s: &str| parse_size(s).map(|size| size as u64);
+    let (blocks, block_size) = match strategy.split_once(':') {
+        None if strategy == "full" => (0, 0),
+        Some(("prefix", bytes)) => (1, size(bytes)?),
+        Some(("sample", sample)) => {
+            let (blocks, bytes) = sample.split_once('x').ok_or_else(error)?;
+            (blocks.parse().map_err(|_| error())?, size(bytes)?)
+        }
+        _ => return Err(error()),
+    };
+    if 0 < blocks && block_size == 0 {
+        return Err(error());
+    }
+    Ok(SampleOptions {
+        threshold: size(threshold)?,
+        blocks,
+        block_size,
+    })
+}
+
+/// # Returns
+///
+/// Version of the layout of the machine-readable output, which must be one that can be written.
+fn parse_schema_version(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(version) if (1..=SCHEMA_VERSION).contains(&version) => Ok(version),
+        _ => Err(format!(
+            "not a schema version between 1 and {}: {}",
+            SCHEMA_VERSION, s
+        )),
+    }
+}
+
+/// # Returns
+///
+/// Percentage greater than 0 and at most 100.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percent) if 0.0 < percent && percent <= 100.0 => Ok(percent),
+        _ => Err(format!("not a percentage between 0 and 100: {}", s)),
+    }
+}
+
+/// # Returns
+///
+/// Extension of file names, with or without its leading dot, without it.
+fn parse_extension(s: &str) -> String {
+    s.strip_prefix('.').unwrap_or(s).to_string()
+}
+
+/// # Returns
+///
+/// ID of the user given by their name or ID.
+fn parse_owner(s: &str) -> Result<u32, String> {
+    if let Ok(uid) = s.parse() {
+        return Ok(uid);
+    }
+    lookup_user(s).ok_or_else(|| format!("unknown user: {}", s))
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Option<u32> {
+    use std::{ffi::CString, mem, ptr};
+
+    let name = CString::new(name).ok()?;
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    match (status, result.is_null()) {
+        (0, false) => Some(unsafe { passwd.assume_init() }.pw_uid),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn lookup_user(_name: &str) -> Option<u32> {
+    None
+}
+
+/// # Returns
+///
+/// Remote directory given as `[user@]host:path`.
+fn parse_remote(s: &str) -> Result<Remote, String> {
+    match s.split_once(':') {
+        Some((host, path)) if !host.is_empty() && !path.is_empty() => Ok(Remote {
+            host: host.to_string(),
+            path: PathBuf::from(path),
+        }),
+        _ => Err(format!("not of the form [user@]host:path: {}", s)),
+    }
+}
+
+/// # Returns
+///
+/// Long names of the options of `Opt`, each with whether it takes a value.
+pub fn long_options() -> Vec<(String, bool)> {
+    // clap 2 has no API to list the arguments of an app, but the fields of its parser are public,
+    // if left out of its docs.
+    let app = Opt::clap();
+    let flags = app.p.flags.iter().map(|flag| (flag.s.long, false));
+    let opts = app.p.opts.iter().map(|opt| (opt.s.long, true));
+    flags
+        .chain(opts)
+        .filter_map(|(long, takes_value)| Some((long?.to_string(), takes_value)))
+        .collect()
+}
+
+/// Subcommand that fdup runs when it isn't given one, so that `fdup <roots>...` is the same as
+/// `fdup scan <roots>...`.
+pub const DEFAULT_SUBCOMMAND: &str = "scan";
+
+/// # Returns
+///
+/// `args`, the command line, with `DEFAULT_SUBCOMMAND` inserted after the name of the program
+/// unless it's followed by another subcommand, or by an option asking for help or the version.
+/// Roots named like a subcommand can be scanned with `fdup scan <root>` or `fdup ./<root>`.
+pub fn with_default_subcommand(mut args: Vec<OsString>) -> Vec<OsString> {
+    let app = Command::clap();
+    let subcommands = app.p.subcommands.iter().map(|s| s.p.meta.name.as_str());
+    let mut names: Vec<&str> = subcommands.collect();
+    names.extend(["help", "-h", "--help", "-V", "--version"]);
+    let is_subcommand = |arg: &OsString| names.iter().any(|name| arg == name);
+    if !args.get(1).is_some_and(is_subcommand) {
+        args.insert(args.len().min(1), OsString::from(DEFAULT_SUBCOMMAND));
+    }
+    args
+}
 
 /// Find duplicate files recursively and in parallel.
 ///
 /// fdup finds duplicate files quickly by checking file sizes and content checksums.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "fdup")]
+#[allow(clippy::large_enum_variant)] // only ever parsed once
+pub enum Command {
+    /// Find duplicate files under the roots, which is what fdup does when it isn't given a
+    /// subcommand.
+    ///
+    /// Default arguments are read from `fdup/config.toml` in the user's configuration directory,
+    /// if any, then by environment variables named after the options, e.g. `FDUP_THREADS` for
+    /// `--threads` or `FDUP_SKIP_JUNK=1` for `--skip-junk`, and then by the ones given on the
+    /// command line. Options that can be given more than once add to each other instead.
+    Scan(Opt),
+
+    /// Print the man page of fdup in roff.
+    Man,
+
+    /// Write a manifest of the files under the roots to stdout: the path, size and SHA512
+    /// checksum of each, hashed in full. It's what `fdup remote` runs on the other end.
+    Manifest(ManifestOpt),
+
+    /// Find the files under the roots that have duplicates on another machine, by running `fdup
+    /// manifest` there over ssh and comparing the checksums it sends back, without copying any
+    /// contents across. Only the local files of the same sizes as remote ones are hashed. Each
+    /// group lists the local copies first, then the remote ones as `host:path`.
+    Remote(RemoteOpt),
+
+    /// Check the machine that fdup runs on for what could make scans slow or make some of its
+    /// options fail, and print what could be done about each: whether the filesystem of the
+    /// directory supports reflinks and extended attributes, whether it's on a rotational disk,
+    /// whether the temporary directory can take spilled files, whether the configuration file
+    /// parses, the limit on open files, and how fast this machine hashes. Exits with 1 if any
+    /// check fails outright.
+    Doctor(DoctorOpt),
+}
+
+/// Arguments of `fdup manifest`.
+#[derive(StructOpt, Debug)]
+pub struct ManifestOpt {
+    /// Also list the files in cache directories, i.e. directories tagged with a CACHEDIR.TAG
+    /// file. They're skipped by default, unless they're one of the roots.
+    #[structopt(long = "include-caches")]
+    pub include_caches: bool,
+
+    /// Root directories to list the files under.
+    #[structopt(parse(from_os_str), required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+/// Arguments of `fdup remote`.
+#[derive(StructOpt, Debug)]
+pub struct RemoteOpt {
+    /// Command that connects to the remote machine, followed by the host and the command to run
+    /// there.
+    #[structopt(long = "ssh", default_value = "ssh")]
+    pub ssh: String,
+
+    /// Command that runs fdup on the remote machine, e.g. if it isn't on its `PATH`.
+    #[structopt(long = "remote-fdup", default_value = "fdup")]
+    pub remote_fdup: String,
+
+    /// Sort each group of duplicate files lexicographically.
+    #[structopt(long = "sort")]
+    pub sort: bool,
+
+    /// Remote directory to compare with, as `[user@]host:path`.
+    #[structopt(parse(try_from_str = parse_remote))]
+    pub remote: Remote,
+
+    /// Local root directories to compare with the remote one.
+    #[structopt(parse(from_os_str), required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+/// Arguments of `fdup doctor`.
+#[derive(StructOpt, Debug)]
+pub struct DoctorOpt {
+    /// Directory whose filesystem is checked, e.g. one that's going to be scanned. Defaults to
+    /// the current directory.
+    #[structopt(parse(from_os_str))]
+    pub dir: Option<PathBuf>,
+}
+
+/// Arguments of `fdup scan`.
+#[derive(StructOpt, Debug)]
+#[structopt(setting = AppSettings::AllArgsOverrideSelf)]
 pub struct Opt {
     /// Sort each group of duplicate files lexicographically.
     #[structopt(long = "sort")]
     pub sort: bool,
 
+    /// Order that `--sort` sorts paths in: by their `bytes`, the same on every platform and in
+    /// every locale, or by the collation of the `locale`, as set by `LC_ALL`, `LC_COLLATE` or
+    /// `LANG`, with ties broken by bytes. Either way, paths are compared component by component.
+    /// The locale is only followed on Unix.
+    #[structopt(
+        long = "collation",
+        default_value = "bytes",
+        possible_values = &["bytes", "locale"]
+    )]
+    pub collation: Collation,
+
+    /// How file contents are read while hashing. `io-uring` batches the opens and reads of many
+    /// files through io_uring; it is experimental and only available on Linux.
+    #[structopt(long = "backend", default_value = "read", possible_values = &["read", "io-uring"])]
+    pub backend: Backend,
+
+    /// Read the candidate files on each rotational disk one at a time, in the order they're laid
+    /// out on disk, instead of in parallel. `auto` detects rotational disks (Linux only), and
+    /// `always` treats every device as one.
+    #[structopt(long = "hdd", default_value = "never", possible_values = &["never", "auto", "always"])]
+    pub hdd_mode: HddMode,
+
+    /// Read at most this many files from the same device at the same time, so that a slow disk
+    /// isn't hammered with random reads while the other devices are still read in parallel.
+    /// Files are always read with the `read` backend then.
+    #[structopt(long = "reads-per-device")]
+    pub reads_per_device: Option<usize>,
+
+    /// Approximate amount of memory that the files found may take up before they're spilled to
+    /// temporary files, to be grouped one partition at a time, in bytes or with a suffix of `K`,
+    /// `M`, `G` or `T`, e.g. `512M`. Partitions that don't fit either are spilled again, and
+    /// reported if they still don't, since files of the same size are always grouped together.
+    #[structopt(long = "max-memory", parse(try_from_str = parse_size))]
+    pub max_memory: Option<usize>,
+
+    /// Maximum number of files kept open at the same time, by every thread together. Defaults to
+    /// what the limit on file descriptors leaves room for.
+    #[structopt(long = "max-open-files")]
+    pub max_open_files: Option<usize>,
+
+    /// Maximum rate at which file contents are read, by every thread together, in megabytes (of
+    /// 1000000 bytes) per second. Reads may burst up to a second's worth ahead of it.
+    #[structopt(long = "max-read-rate", parse(try_from_str = parse_read_rate))]
+    pub max_read_rate: Option<f64>,
+
+    /// Run with the lowest CPU and IO priority: the maximum niceness and the idle IO class on
+    /// Linux, and the background band on macOS. Elsewhere on Unix only the niceness is raised.
+    #[structopt(long = "background")]
+    pub background: bool,
+
+    /// Memory-map files of at least this many bytes when hashing them, instead of reading them
+    /// through a buffer. Falls back to reading if a file can't be mapped.
+    #[structopt(long = "mmap-threshold")]
+    pub mmap_threshold: Option<u64>,
+
+    /// Hint to the kernel that hashed files are read once and sequentially, so that scanning a
+    /// large tree doesn't evict the rest of the page cache. Only has an effect on Linux.
+    #[structopt(long = "fadvise")]
+    pub fadvise: bool,
+
+    /// While a file is being hashed, ask the kernel to start reading the next this many files of
+    /// its group, a batch at a time, so that reading overlaps with hashing. Linux only.
+    #[structopt(long = "readahead", default_value = "0")]
+    pub readahead: usize,
+
+    /// Take a shared advisory lock (flock) on each file while hashing it, and skip the files that
+    /// other processes hold an exclusive lock on. Only has an effect on Unix.
+    #[structopt(long = "lock")]
+    pub lock: bool,
+
+    /// Also scan cache directories, i.e. directories tagged with a CACHEDIR.TAG file. They're
+    /// skipped by default, unless they're one of the roots.
+    #[structopt(long = "include-caches")]
+    pub include_caches: bool,
+
+    /// Don't scan the filesystems of these types that are mounted below the roots, as a list
+    /// separated by commas, or an empty one to scan them all. Pseudo and volatile filesystems are
+    /// skipped by default, so that scanning `/` doesn't wander into `/proc`. Only implemented on
+    /// Linux, where devtmpfs and tmpfs can't be told apart, and on macOS.
+    #[structopt(long = "exclude-fs", default_value = "proc,sysfs,devtmpfs,tmpfs")]
+    pub exclude_fs: FsTypes,
+
+    /// Order in which the directories under each root are walked: `unordered`, in parallel,
+    /// which is the fastest, or the same on every run, for debugging and comparing runs, by
+    /// `breadth-first`, level by level, or `depth-first-sorted`, one directory at a time. Either
+    /// of those sorts the entries of each directory by name, and the files are found in that
+    /// order.
+    #[structopt(
+        long = "walk-order",
+        default_value = "unordered",
+        possible_values = &["unordered", "breadth-first", "depth-first-sorted"]
+    )]
+    pub walk_order: WalkOrder,
+
+    /// Once the scan is done, report the directories that were skipped because the same data had
+    /// already been scanned through another path, such as bind mounts and filesystems mounted
+    /// more than once. They're always skipped, so that their files aren't reported as duplicates
+    /// of themselves.
+    #[structopt(long = "report-mount-overlaps")]
+    pub report_mount_overlaps: bool,
+
+    /// Print the results as a single JSON document, with the groups of duplicates and the errors
+    /// because of which paths were left out of them. Same as `--format json`.
+    #[structopt(long = "json", conflicts_with = "format")]
+    pub json: bool,
+
+    /// How the results are printed. `json` is as with `--json`, and `msgpack` is a stream of
+    /// MessagePack objects: an array of paths for each group, followed by a map of the empty files
+    /// reported on their own and the errors. Defaults to `text`, one line per group.
+    #[structopt(long = "format", possible_values = &["text", "json", "msgpack"])]
+    pub format: Option<Format>,
+
+    /// Version of the layout of the machine-readable output to write, i.e. of the results with
+    /// `--json` or `--format msgpack`, and of `--stats-json`, which say which one they follow in a
+    /// `schema_version` field. Within a version, fields are only ever added; removing or changing
+    /// one bumps it. Version 1 is the layout from before it was versioned, without the field.
+    #[structopt(
+        long = "schema-version",
+        default_value = "2",
+        parse(try_from_str = parse_schema_version)
+    )]
+    pub schema_version: u32,
+
+    /// With `--json` or `--format msgpack`, write each path of the groups as an object with these
+    /// facts about its file too, separated by commas: the `dev`ice it's on, its `inode` and its
+    /// `nlink` count of hardlinks, so that consumers can tell which paths are the same file, its
+    /// `mtime` and `ctime` in seconds since the Unix epoch, the `uid` and `gid` of its owner and
+    /// its permission bits as a `mode`, so that they can choose which copies to keep.
+    #[structopt(
+        long = "fields",
+        require_delimiter = true,
+        possible_values = &["dev", "inode", "nlink", "mtime", "ctime", "uid", "gid", "mode"]
+    )]
+    pub fields: Vec<Field>,
+
+    /// Write a fingerprint of each group along with it, which stays the same across runs for as
+    /// long as the contents of its files do, whatever paths they're at: the SHA512 checksum of the
+    /// contents, in hex as `sha512sum` prints it, so that it can be listed with `--acknowledge`,
+    /// and matched by `--baseline`. As text, it's printed before the paths, separated by a tab;
+    /// with `--json`, each group is written as an object `{"fingerprint":...,"paths":[...]}`;
+    /// with `--format msgpack`, it's the first element of the array of each group. Reads one file
+    /// of each group found again.
+    #[structopt(long = "fingerprints")]
+    pub fingerprints: bool,
+
+    /// Instead of the groups, print how many bytes could be reclaimed in each directory, from the
+    /// most to the least, counting directories more than this many levels deep towards their
+    /// ancestors at that depth. All but the first path of each group count as reclaimable.
+    #[structopt(long = "rollup", conflicts_with_all = &["json", "format"])]
+    pub rollup: Option<usize>,
+
+    /// Instead of the groups, print how many bytes could be reclaimed from the files of each
+    /// owner, from the most to the least, by their user names, as with `--rollup`. Only
+    /// implemented on Unix.
+    #[structopt(long = "by-owner", conflicts_with_all = &["json", "format", "rollup"])]
+    pub by_owner: bool,
+
+    /// What to do about files and directories that can't be read: `skip` them silently, `warn`
+    /// about them on stderr once the scan is done, or `fail`, aborting with a nonzero exit status.
+    #[structopt(long = "errors", default_value = "warn", possible_values = &["skip", "warn", "fail"])]
+    pub error_policy: ErrorPolicy,
+
+    /// List the paths that were skipped because permission to read them was denied. Otherwise,
+    /// they're only counted.
+    #[structopt(long = "list-denied")]
+    pub list_denied: bool,
+
+    /// Number of times reads that fail with transient errors, such as timeouts or stale handles on
+    /// network filesystems, are retried before fdup gives up on them.
+    #[structopt(long = "retries", default_value = "3")]
+    pub retries: u32,
+
+    /// Milliseconds to wait before retrying a failed read. The wait doubles with every retry.
+    #[structopt(long = "retry-backoff", default_value = "100")]
+    pub retry_backoff: u64,
+
+    /// Give up on files that take longer than this many seconds to read, retries included, e.g.
+    /// because they're on a dead network mount. They're left out of the results and reported
+    /// once the scan is done.
+    #[structopt(long = "file-timeout")]
+    pub file_timeout: Option<u64>,
+
+    /// Only read the parts of sparse files that hold data, hashing their holes as zeros without
+    /// reading them. Files are still compared by their logical contents. Only has an effect on
+    /// Linux.
+    #[structopt(long = "sparse")]
+    pub sparse: bool,
+
+    /// Only compare the files of this percentage of the sizes shared by more than one file, picked
+    /// at random, and estimate how many bytes would be reclaimable if all of them were compared,
+    /// with a 95% confidence interval, on stderr. Only the groups among the sampled sizes are
+    /// reported.
+    #[structopt(long = "sample", parse(try_from_str = parse_percent))]
+    pub sample: Option<f64>,
+
+    /// Before hashing files of at least this many bytes in full, compare checksums of sampled
+    /// blocks of them, so that huge files that differ early on are never read in full.
+    #[structopt(long = "sample-threshold")]
+    pub sample_threshold: Option<u64>,
+
+    /// Number of blocks sampled from each file: the first and last blocks, and blocks evenly
+    /// spaced in between.
+    #[structopt(long = "sample-blocks", default_value = "16")]
+    pub sample_blocks: u64,
+
+    /// Size of each sampled block, in bytes.
+    #[structopt(long = "sample-block-size", default_value = "65536")]
+    pub sample_block_size: u64,
+
+    /// How files are first compared before they're hashed in full, by size, as a list of tiers
+    /// separated by commas, each starting at a size: `<size>=full` hashes them in full straight
+    /// away, `<size>=prefix:<bytes>` compares their first bytes and `<size>=sample:<blocks>x<bytes>`
+    /// compares sampled blocks, as with `--sample-threshold`. Sizes can be followed by `K`, `M`,
+    /// `G` or `T`, e.g. `0=full,64K=prefix:4K,1G=sample:16x64K`. Files smaller than every tier are
+    /// hashed in full.
+    #[structopt(
+        long = "hash-tiers",
+        require_delimiter = true,
+        conflicts_with = "sample-threshold",
+        parse(try_from_str = parse_hash_tier)
+    )]
+    pub hash_tiers: Vec<SampleOptions>,
+
+    /// Ignore files modified within this long, e.g. `30m` or `2h`, since they may still be in the
+    /// middle of being written. A number without a unit is a number of minutes.
+    #[structopt(long = "ignore-newer-than", parse(try_from_str = parse_duration))]
+    pub ignore_newer_than: Option<Duration>,
+
     /// Number of threads to use. 0 indicates
     #[structopt(long = "threads", default_value = "0")]
     pub num_threads: usize,
 
-    /// Root directory from which to start the search.
-    #[structopt(parse(from_os_str))]
-    pub root: PathBuf,
+    /// Ignore macOS AppleDouble files (`._*`), which hold the metadata of files on filesystems
+    /// that can't store it themselves, such as SMB shares.
+    #[structopt(long = "skip-appledouble")]
+    pub skip_appledouble: bool,
+
+    /// Ignore well-known junk files: .DS_Store, Thumbs.db, desktop.ini and .localized.
+    #[structopt(long = "skip-junk")]
+    pub skip_junk: bool,
+
+    /// Ignore files whose paths aren't valid unicode, such as names in the legacy encodings of
+    /// archives restored from old systems. Otherwise they're compared like any other file, and
+    /// written as they are to scripts, escaped in text results and lossily in JSON.
+    #[structopt(long = "skip-invalid-names")]
+    pub skip_invalid_names: bool,
+
+    /// Only compare files owned by this user, given by name or ID. Only has an effect on Unix.
+    #[structopt(long = "owner", parse(try_from_str = parse_owner))]
+    pub owner: Option<u32>,
+
+    /// Only compare files that the current user can write to.
+    #[structopt(long = "only-writable")]
+    pub only_writable: bool,
+
+    /// Only compare files of this type, as told by the magic bytes they start with rather than by
+    /// their names. Can be given more than once, to compare files of any of the given types.
+    #[structopt(
+        long = "type",
+        number_of_values = 1,
+        possible_values = &["image", "video", "audio", "archive", "document"]
+    )]
+    pub types: Vec<ContentType>,
+
+    /// What to do with empty files, which are all duplicates of each other: `ignore` them, only
+    /// counting them, `report` them as a group like any other, or `report-separately` after the
+    /// groups.
+    #[structopt(
+        long = "empty",
+        default_value = "ignore",
+        possible_values = &["ignore", "report", "report-separately"]
+    )]
+    pub empty: EmptyFiles,
+
+    /// Leave out groups whose files are all hardlinks to the same file, e.g. because they've
+    /// already been deduplicated.
+    #[structopt(long = "skip-hardlinked")]
+    pub skip_hardlinked: bool,
+
+    /// Only report groups with copies in more than one directory, leaving out those whose copies
+    /// all sit side by side.
+    #[structopt(long = "across-dirs-only")]
+    pub across_dirs_only: bool,
+
+    /// Only report groups whose copies are all in the same directory, leaving out copies
+    /// elsewhere in the tree, like backups.
+    #[structopt(long = "same-dir-only", conflicts_with = "across-dirs-only")]
+    pub same_dir_only: bool,
+
+    /// Scan each immediate child of the roots as a scope of its own, all in one walk: only report
+    /// duplicates within the same child, e.g. per user under `/home`, never across children.
+    /// Documents compared with `--normalize` are still compared across them.
+    #[structopt(long = "scope-per-child")]
+    pub scope_per_child: bool,
+
+    /// Once the scan is done, report how many bytes among the duplicates found are already saved
+    /// by hardlinks, and how many more could be reclaimed by hardlinking the rest, and the dedupe
+    /// ratio, the bytes that would be left after that as a fraction of those found, overall and
+    /// for each file extension with duplicates, from the most reclaimable. With more than
+    /// one root, also report for each the files and bytes found under it, the duplicates whose
+    /// copies are all under it, and how many of its files have copies under other roots.
+    #[structopt(long = "summary")]
+    pub summary: bool,
+
+    /// Keep the copies under this root: only report groups with copies both under it and
+    /// elsewhere, listing the ones under it first. Should be spelled as it is among the roots,
+    /// except for its case on Windows and macOS. Can be given more than once.
+    #[structopt(long = "keep-in", number_of_values = 1, parse(from_os_str))]
+    pub keep_in: Vec<PathBuf>,
+
+    /// Never get rid of the files at this path or under it, or matching this glob: list them with
+    /// the copies to keep, and leave out groups made up of nothing else. In globs, `*` matches
+    /// anything but `/`, `**` anything at all and `?` any one character, and globs without a `/`
+    /// are matched against file names, regardless of case on Windows and macOS. Can be given more
+    /// than once.
+    #[structopt(long = "protect", number_of_values = 1)]
+    pub protect: Vec<PathPattern>,
+
+    /// Only report groups with a copy at this path or under it, or matching this glob, as with
+    /// `--protect`, e.g. `'**/Downloads/**'`, whatever their other copies are. Given more than
+    /// once, groups need a copy matching each.
+    #[structopt(long = "group-must-include", number_of_values = 1)]
+    pub group_must_include: Vec<PathPattern>,
+
+    /// Leave out the groups with a copy at this path or under it, or matching this glob, as with
+    /// `--protect`, whatever their other copies are. Can be given more than once.
+    #[structopt(long = "group-must-exclude", number_of_values = 1)]
+    pub group_must_exclude: Vec<PathPattern>,
+
+    /// Only report groups that are new or have grown since an earlier scan, whose results were
+    /// written to this file with `--json`: groups all of whose paths were already in one of its
+    /// groups are left out. Paths are compared as they're written, so the roots should be given as
+    /// they were then. If both scans are run with `--fingerprints`, so are the groups with the
+    /// same fingerprint as one of its groups and no more paths, even if they've moved since.
+    #[structopt(long = "baseline", parse(from_os_str))]
+    pub baseline: Option<PathBuf>,
+
+    /// Leave out the groups of duplicates that are there on purpose, e.g. license files or test
+    /// fixtures, listed in this file by the SHA512 checksum of their contents, one per line in
+    /// hex, as `sha512sum` prints them. They're counted separately with `--summary` and
+    /// `--stats-json`. The checksums are those computed during the scan, except for groups that
+    /// aren't hashed in full, e.g. those of a single inode or those grouped with `--git-index` or
+    /// `--normalize`, one file of which is read again.
+    #[structopt(long = "acknowledge", parse(from_os_str))]
+    pub acknowledge: Option<PathBuf>,
+
+    /// Confirm that files with the same checksum are duplicates with this shell command, e.g.
+    /// `cmp -s`, run with the paths of two of them appended. Exiting with status 0 means that
+    /// they're duplicates and 1 that they aren't; files for which it fails otherwise are skipped.
+    #[structopt(long = "verify-cmd")]
+    pub verify_cmd: Option<String>,
+
+    /// Confirm that files with the same checksum are duplicates by comparing their contents byte
+    /// by byte, which reads them again.
+    #[structopt(long = "paranoid", conflicts_with = "verify-cmd")]
+    pub paranoid: bool,
+
+    /// Keep only the first this many bits of the checksum of each file while grouping them, to
+    /// save memory on huge scans, at the cost of the odds of two files colliding, which are still
+    /// negligible at 128 bits. Best combined with `--paranoid`.
+    #[structopt(long = "checksum-bits", possible_values = &["128", "256"])]
+    pub checksum_bits: Option<usize>,
+
+    /// Compare documents of this kind by their contents, leaving out the metadata that changes
+    /// whenever they're saved, so that re-exports of the same document are duplicates even if
+    /// their sizes differ. `pdf` leaves out the creation and modification dates and the IDs of
+    /// `*.pdf` files, and `zip` compares the members of zip containers, like `*.docx`, `*.xlsx`,
+    /// `*.pptx` and OpenDocument files, in the order of their names, leaving out their
+    /// timestamps. Can be given more than once.
+    #[structopt(long = "normalize", number_of_values = 1, possible_values = &["pdf", "zip"])]
+    pub normalize: Vec<Normalize>,
+
+    /// In git working trees, compare files that are unmodified since they were added to the index
+    /// by the IDs of the blobs git already hashed them to, instead of reading them. Groups with
+    /// files that aren't are read as usual. Files whose contents git converts when adding them,
+    /// e.g. their line endings, are compared as converted.
+    #[structopt(long = "git-index")]
+    pub git_index: bool,
+
+    /// Check whether each duplicate could be removed or replaced, e.g. by a hardlink, by the
+    /// current user, and report why on stderr for the ones that couldn't be. Duplicates whose
+    /// extended attributes, like security labels, differ from those of the first path of their
+    /// group are reported too, since replacing one with the other would change them, and so are
+    /// duplicates on a different filesystem from it, which can't be hardlinked to it.
+    #[structopt(long = "check-removable")]
+    pub check_removable: bool,
+
+    /// Once the scan is done, report the symlinks under the roots that resolve to duplicates,
+    /// directly or through other symlinks, noting those that would dangle if the duplicates were
+    /// removed, i.e. that don't resolve to the first path of their group. Groups of symlinks that
+    /// resolve to the same file are reported too. Symlinks are never grouped as duplicates
+    /// themselves.
+    #[structopt(long = "symlinks")]
+    pub symlinks: bool,
+
+    /// Print how many groups of duplicates there are, and how many bytes they could reclaim, for
+    /// files smaller than 1 KB, up to 100 KB, up to 10 MB and larger, on stderr.
+    #[structopt(long = "histogram")]
+    pub histogram: bool,
+
+    /// Print this many of the files that took the longest to hash, with their sizes and the rates
+    /// at which they were read, on stderr, to find failing disks and slow network mounts by. Not
+    /// measured with `--backend io-uring`.
+    #[structopt(long = "slowest")]
+    pub slowest: Option<usize>,
+
+    /// Write a report of the results to this file, as a standalone HTML page: a summary, the
+    /// reclaimable bytes by file size and by directory, as with `--rollup`, and every group, in
+    /// tables that can be sorted by clicking on their headers.
+    #[structopt(long = "report-html", parse(from_os_str))]
+    pub report_html: Option<PathBuf>,
+
+    /// Write the groups to files in this directory instead, creating it if it doesn't exist, one
+    /// for each part of the results that `--split-by` divides them into, named after it, so that
+    /// they can be reviewed, or handed to different owners, a piece at a time. With `--format json`
+    /// or `--format msgpack`, each file is whole results of its own, and empty files and errors are
+    /// still written to stdout.
+    #[structopt(
+        long = "split-output",
+        parse(from_os_str),
+        conflicts_with_all = &["rollup", "by-owner"]
+    )]
+    pub split_output: Option<PathBuf>,
+
+    /// How `--split-output` divides the groups: by the `size` of their files, under 1 KB, up to
+    /// 100 KB, up to 10 MB and larger, as with `--histogram`, or by the `top-dir`, the directory
+    /// right under the root that the first path of each group is in.
+    #[structopt(
+        long = "split-by",
+        default_value = "size",
+        possible_values = &["size", "top-dir"]
+    )]
+    pub split_by: SplitBy,
+
+    /// Write a shell script to this file that, when run, keeps the first path of each group, as
+    /// well as those under `--keep-in` or matching `--protect`, and deals with the others as
+    /// `--script-handler` says, skipping those that changed since the scan. Run it with `-n` to
+    /// print what it would do. The groups are reported as usual, e.g. with `--json`, alongside it.
+    #[structopt(long = "script", parse(from_os_str))]
+    pub script: Option<PathBuf>,
+
+    /// How the script written with `--script` deals with each duplicate: replace it by a `clone`
+    /// sharing its blocks, by a `hardlink`, `remove` it, or `keep` it. A list separated by commas
+    /// is tried in order until one of them succeeds, e.g. `clone,hardlink`.
+    #[structopt(
+        long = "script-handler",
+        require_delimiter = true,
+        default_value = "remove",
+        possible_values = &["clone", "hardlink", "remove", "keep"]
+    )]
+    pub script_handlers: Vec<Handler>,
+
+    /// Extensions of the companions of files, such as the sidecars of photos, e.g.
+    /// `.xmp,.srt,.cue`. Once the script written with `--script` removes a duplicate, it moves the
+    /// companions next to it, named after it with one of these extensions instead of or after its
+    /// own, to where those of the kept file would be, unless the kept file has its own. Those are
+    /// then removed if they're identical, and reported otherwise.
+    #[structopt(
+        long = "companions",
+        require_delimiter = true,
+        requires = "script",
+        parse(from_str = parse_extension)
+    )]
+    pub companions: Vec<String>,
+
+    /// Have the script written with `--script` re-hash the file kept in this percentage of the
+    /// groups, picked at random, once it has dealt with every duplicate, and report those whose
+    /// contents changed since the scan, e.g. because the storage corrupted them. `100` verifies
+    /// every kept file. Their checksums are those computed during the scan, except with
+    /// `--checksum-bits`, or for groups that aren't hashed in full, whose kept file is read again.
+    #[structopt(long = "script-verify", requires = "script", parse(try_from_str = parse_percent))]
+    pub script_verify: Option<f64>,
+
+    /// Make the script written with `--script` one that mistakes can be recovered from: it only
+    /// prints what it would do unless it's run with `--force`, and moves the duplicates that it
+    /// gets rid of or replaces to a `.trash` directory next to it instead, appending the commands
+    /// that move them back to a `.undo` script next to it. Also compares duplicates byte for byte,
+    /// as with `--paranoid`, unless `--verify-cmd` is given, has the script verify every kept
+    /// file, as with `--script-verify 100`, and never has it touch the paths of the operating
+    /// system, such as `/usr` and `/etc`, which are checked once resolved to absolute paths
+    /// without symlinks, however the roots are spelled.
+    #[structopt(long = "safe", requires = "script", conflicts_with = "script-verify")]
+    pub safe: bool,
+
+    /// Write figures about the whole run to this file once it's done, as a single JSON document:
+    /// counts of files, groups and bytes, totals of errors by operation, and how long the run and
+    /// its stages took.
+    #[structopt(long = "stats-json", parse(from_os_str))]
+    pub stats_json: Option<PathBuf>,
+
+    /// Print the stages that the scan would go through with the other options, and how each of
+    /// them is configured, from the filters and the hash function to how files are read and the
+    /// number of threads, without scanning anything.
+    #[structopt(long = "explain")]
+    pub explain: bool,
+
+    /// Write why each file that was found was left out of the results to this file once the scan
+    /// is done, as a JSON object per line: `{"path":...,"reason":...}`, where the reason is one of
+    /// `symlink`, `excluded_name`, `too_new`, `not_owned`, `not_writable`, `limit`,
+    /// `unique_size`, `empty`, `unsampled_size`, `content_type`, `unique_key`, `unique_sample`,
+    /// `unique_checksum`, `hardlinked`, `spread`, `kept`, `group_filtered` or `error`, along with
+    /// its `operation`.
+    /// Directories skipped by the walk aren't listed.
+    #[structopt(long = "explain-skips", parse(from_os_str))]
+    pub explain_skips: Option<PathBuf>,
+
+    /// Guarantee that the scan leaves the filesystem as it is, e.g. for media under legal hold:
+    /// options that write files, such as `--script`, `--split-output` or `--max-memory`, are
+    /// rejected, and any other write is refused. Reading files may still update their access
+    /// times, unless the filesystem is mounted read-only or with `noatime`.
+    #[structopt(
+        long = "assert-read-only",
+        conflicts_with_all = &[
+            "script",
+            "report-html",
+            "stats-json",
+            "explain-skips",
+            "max-memory",
+            "split-output"
+        ]
+    )]
+    pub assert_read_only: bool,
+
+    /// Report the progress of the scan on stderr: how many files were found, then how much of
+    /// those that may be duplicates has been read, and about how long the rest should take at the
+    /// throughput so far. Updated every second on a terminal, and every 30 seconds otherwise.
+    #[structopt(long = "progress")]
+    pub progress: bool,
+
+    /// Pager that the results are piped through when they're printed as text to a terminal,
+    /// split on whitespace, e.g. `less -R`. Defaults to `PAGER`, or `less -FSX`, which keeps each
+    /// group on a single line so that moving by a line moves by a group. The progress isn't
+    /// reported while paging, since it would be drawn over the pager.
+    #[structopt(long = "pager")]
+    pub pager: Option<String>,
+
+    /// Print the results straight to the terminal, without a pager.
+    #[structopt(long = "no-pager", conflicts_with = "pager")]
+    pub no_pager: bool,
+
+    /// Stop finding files once this many have been found, and report that the results are
+    /// incomplete, so that scans of unknown trees can't run away.
+    #[structopt(long = "max-files")]
+    pub max_files: Option<u64>,
+
+    /// Stop finding files once the next one would take the bytes found past this, and report that
+    /// the results are incomplete.
+    #[structopt(long = "max-bytes")]
+    pub max_bytes: Option<u64>,
+
+    /// Count the files and bytes under the roots with a first walk that only reads metadata, so
+    /// that `--progress` can tell how far along the walk itself is. Doubles the walk on cold
+    /// caches.
+    #[structopt(long = "precount")]
+    pub precount: bool,
+
+    /// Find the sizes shared by more than one file with a first walk that only reads metadata, so
+    /// that only the files of those sizes are kept in memory once they're found for real, instead
+    /// of every file. Doubles the walk on cold caches, and may miss files resized in between.
+    #[structopt(long = "size-census")]
+    pub size_census: bool,
+
+    /// Stop once the files are grouped by size, and print every group of files of the same size,
+    /// each labeled `unconfirmed`, without reading any of them. A quick first approximation on
+    /// slow storage, to narrow down the roots of a full scan. Nothing is counted as reclaimable.
+    #[structopt(
+        long = "sizes-only",
+        conflicts_with_all = &[
+            "json", "format", "fingerprints", "rollup", "by-owner", "report-html", "split-output",
+            "script", "acknowledge", "type", "scope-per-child", "sample-threshold", "hash-tiers",
+            "verify-cmd", "paranoid", "normalize", "git-index",
+        ]
+    )]
+    pub sizes_only: bool,
+
+    /// Don't print anything besides the results, and what's asked for by other options, like
+    /// `--summary`. In particular, leave out warnings about paths that were skipped.
+    #[structopt(short = "q", long = "quiet")]
+    pub quiet: bool,
+
+    /// Don't print the results either, as with `--quiet`, and exit with status 2 if any
+    /// duplicates were found.
+    #[structopt(long = "silent")]
+    pub silent: bool,
+
+    // The configuration arguments are read by `config::with_config_args`, before the others are
+    // parsed, so they're only here to be accepted and documented.
+    /// Read default arguments from this configuration file instead of `fdup/config.toml` in the
+    /// user's configuration directory.
+    #[structopt(long = "config", parse(from_os_str))]
+    #[allow(dead_code)]
+    pub config: Option<PathBuf>,
+
+    /// Don't read default arguments from any configuration file.
+    #[structopt(long = "no-config")]
+    #[allow(dead_code)]
+    pub no_config: bool,
+
+    /// Also apply the arguments of this profile of the configuration file, i.e. those of its
+    /// `[profiles.<name>]` table.
+    #[structopt(long = "profile")]
+    #[allow(dead_code)]
+    pub profile: Option<String>,
+
+    /// Root directories from which to start the search. Files that can be reached through more
+    /// than one of them, e.g. because they overlap, are only considered once.
+    #[structopt(parse(from_os_str), required = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assert_read_only() {
+        let parse = |args: &[&str]| Opt::from_iter_safe(["fdup"].iter().chain(args));
+        assert!(parse(&["--assert-read-only", "root"]).is_ok());
+        for option in ["--script", "--split-output", "--stats-json"] {
+            let error = parse(&["--assert-read-only", option, "out", "root"]).unwrap_err();
+            assert_eq!(structopt::clap::ErrorKind::ArgumentConflict, error.kind);
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Duration::from_secs(90), parse_duration("90s").unwrap());
+        assert_eq!(Duration::from_secs(30 * 60), parse_duration("30m").unwrap());
+        assert_eq!(Duration::from_secs(30 * 60), parse_duration("30").unwrap());
+        assert_eq!(
+            Duration::from_secs(2 * 60 * 60),
+            parse_duration("2h").unwrap()
+        );
+        assert_eq!(
+            Duration::from_secs(7 * 24 * 60 * 60),
+            parse_duration("7d").unwrap()
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("2w").is_err());
+    }
+
+    #[test]
+    fn test_parse_read_rate() {
+        assert_eq!(Ok(1.5), parse_read_rate("1.5"));
+        assert_eq!(Ok(100.0), parse_read_rate("100"));
+        assert!(parse_read_rate("0").is_err());
+        assert!(parse_read_rate("-1").is_err());
+        assert!(parse_read_rate("inf").is_err());
+        assert!(parse_read_rate("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(1000), parse_size("1000"));
+        assert_eq!(Ok(512 << 20), parse_size("512M"));
+        assert_eq!(Ok(4 << 30), parse_size("4g"));
+        assert_eq!(Ok(2 << 10), parse_size("2K"));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("1P").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_hash_tier() {
+        let tier = |threshold, blocks, block_size| SampleOptions {
+            threshold,
+            blocks,
+            block_size,
+        };
+        assert_eq!(Ok(tier(0, 0, 0)), parse_hash_tier("0=full"));
+        assert_eq!(
+            Ok(tier(64 << 10, 1, 4096)),
+            parse_hash_tier("64K=prefix:4K")
+        );
+        assert_eq!(
+            Ok(tier(1 << 30, 16, 65536)),
+            parse_hash_tier("1G=sample:16x64K")
+        );
+        assert!(parse_hash_tier("1G").is_err());
+        assert!(parse_hash_tier("1G=prefix").is_err());
+        assert!(parse_hash_tier("1G=prefix:0").is_err());
+        assert!(parse_hash_tier("1G=sample:16").is_err());
+        assert!(parse_hash_tier("1G=sampled:16x1K").is_err());
+        assert!(parse_hash_tier("=full").is_err());
+    }
+
+    #[test]
+    fn test_parse_schema_version() {
+        assert_eq!(Ok(1), parse_schema_version("1"));
+        assert_eq!(
+            Ok(SCHEMA_VERSION),
+            parse_schema_version(&SCHEMA_VERSION.to_string())
+        );
+        assert!(parse_schema_version("0").is_err());
+        assert!(parse_schema_version(&(SCHEMA_VERSION + 1).to_string()).is_err());
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(Ok(0.5), parse_percent("0.5"));
+        assert_eq!(Ok(100.0), parse_percent("100"));
+        assert!(parse_percent("0").is_err());
+        assert!(parse_percent("101").is_err());
+        assert!(parse_percent("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_remote() {
+        let remote = parse_remote("user@host:/srv/data").unwrap();
+        assert_eq!("user@host", remote.host);
+        assert_eq!(PathBuf::from("/srv/data"), remote.path);
+        assert_eq!(PathBuf::from("a:b"), parse_remote("host:a:b").unwrap().path);
+        assert!(parse_remote("/srv/data").is_err());
+        assert!(parse_remote(":/srv/data").is_err());
+        assert!(parse_remote("host:").is_err());
+    }
+
+    #[test]
+    fn test_parse_owner() {
+        assert_eq!(Ok(1234), parse_owner("1234"));
+        assert!(parse_owner("no such user").is_err());
+        #[cfg(unix)]
+        assert_eq!(Ok(0), parse_owner("root"));
+    }
 }
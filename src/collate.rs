@@ -0,0 +1,116 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Order that paths are sorted in.
+///
+/// Paths are always compared component by component, so that a directory's files come right after
+/// it whatever the characters in their names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// By the bytes of each component, as UTF-8 where it's valid, so that the same tree sorts the
+    /// same on every platform and in every locale.
+    #[default]
+    Bytes,
+
+    /// By the collation of the locale, as set by `LC_ALL`, `LC_COLLATE` or `LANG`, with ties broken
+    /// by bytes. Only implemented on Unix; elsewhere this is equivalent to `Bytes`.
+    Locale,
+}
+
+impl FromStr for Collation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(Collation::Bytes),
+            "locale" => Ok(Collation::Locale),
+            _ => Err(format!("unknown collation: {}", s)),
+        }
+    }
+}
+
+impl Collation {
+    /// Sort `paths` in this order.
+    pub fn sort(self, paths: &mut [PathBuf]) {
+        match self {
+            Collation::Bytes => paths.sort(),
+            Collation::Locale => paths.sort_by_cached_key(|path| locale_key(path)),
+        }
+    }
+}
+
+/// # Returns
+///
+/// Key that orders `path` by the collation of the locale: each of its components transformed by
+/// `strxfrm`, which compare as `strcoll` compares the originals, along with their bytes.
+#[cfg(unix)]
+fn locale_key(path: &Path) -> Vec<(Vec<u8>, Vec<u8>)> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt, sync::Once};
+
+    static SET_LOCALE: Once = Once::new();
+    // Rust programs start in the C locale, whose collation is by bytes.
+    SET_LOCALE.call_once(|| unsafe {
+        libc::setlocale(libc::LC_COLLATE, b"\0".as_ptr().cast());
+    });
+
+    let transform = |bytes: &[u8]| {
+        // Names can't hold nul bytes.
+        let s = match CString::new(bytes) {
+            Ok(s) => s,
+            Err(_) => return bytes.to_vec(),
+        };
+        let len = unsafe { libc::strxfrm(std::ptr::null_mut(), s.as_ptr(), 0) };
+        let mut transformed = vec![0u8; len + 1];
+        unsafe {
+            libc::strxfrm(
+                transformed.as_mut_ptr().cast(),
+                s.as_ptr(),
+                transformed.len(),
+            );
+        }
+        transformed.truncate(len);
+        transformed
+    };
+    path.components()
+        .map(|component| {
+            let bytes = component.as_os_str().as_bytes();
+            (transform(bytes), bytes.to_vec())
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn locale_key(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collation() {
+        let mut paths: Vec<PathBuf> = ["b", "a/c", "a-b", "B", "é", "e"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        Collation::Bytes.sort(&mut paths);
+        let expected: Vec<PathBuf> = ["B", "a/c", "a-b", "b", "e", "é"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(expected, paths);
+
+        // Whatever the locale, directories come right before their files, and ties are broken by
+        // bytes.
+        Collation::Locale.sort(&mut paths);
+        let position = |path: &str| paths.iter().position(|p| p == Path::new(path)).unwrap();
+        assert!(position("a/c") < position("a-b"));
+        assert_eq!(6, paths.len());
+
+        assert_eq!(Ok(Collation::Locale), "locale".parse());
+        assert!("natural".parse::<Collation>().is_err());
+    }
+}
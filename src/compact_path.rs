@@ -0,0 +1,98 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Path stored as its final component and a shared reference to its parent directory.
+///
+/// Every file and directory found in the same directory shares that directory's node, and every
+/// directory node shares its own parent's, so each directory name under the root is stored once no
+/// matter how many files are found below it. The full path is only materialized when it's needed.
+#[derive(Clone, Debug)]
+pub struct CompactPath {
+    parent: Option<Arc<CompactPath>>,
+    name: Box<OsStr>,
+}
+
+impl CompactPath {
+    /// # Returns
+    ///
+    /// Path to `name` within `parent`, or to `name` itself if `parent` is `None`.
+    pub fn new(parent: Option<Arc<CompactPath>>, name: &OsStr) -> CompactPath {
+        CompactPath {
+            parent,
+            name: name.into(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Number of bytes in the final component of this path, which is all the memory it doesn't
+    /// share with other paths.
+    pub fn name_len(&self) -> usize {
+        self.name.len()
+    }
+
+    /// # Returns
+    ///
+    /// Final component of this path.
+    pub fn file_name(&self) -> &OsStr {
+        // Roots are stored whole, as the name of a path without a parent.
+        Path::new(&self.name).file_name().unwrap_or(&self.name)
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if every component of this path is valid unicode.
+    pub fn is_unicode(&self) -> bool {
+        let mut node = Some(self);
+        while let Some(path) = node {
+            if path.name.to_str().is_none() {
+                return false;
+            }
+            node = path.parent.as_deref();
+        }
+        true
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        let mut names = vec![&self.name];
+        let mut node = &self.parent;
+        while let Some(parent) = node {
+            names.push(&parent.name);
+            node = &parent.parent;
+        }
+
+        names.iter().rev().map(|name| &***name).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Check that `to_path_buf` joins every component, and that siblings share their parent.
+    #[test]
+    fn test_to_path_buf() {
+        let root = Arc::new(CompactPath::new(None, "some/root".as_ref()));
+        let dir = Arc::new(CompactPath::new(Some(root.clone()), "dir".as_ref()));
+        let file_in_root = CompactPath::new(Some(root), "f1".as_ref());
+        let file_in_dir = CompactPath::new(Some(dir.clone()), "f2".as_ref());
+        let other_file_in_dir = CompactPath::new(Some(dir), "f3".as_ref());
+
+        assert_eq!(PathBuf::from("some/root/f1"), file_in_root.to_path_buf());
+        assert_eq!(PathBuf::from("some/root/dir/f2"), file_in_dir.to_path_buf());
+        assert_eq!(
+            PathBuf::from("some/root/dir/f3"),
+            other_file_in_dir.to_path_buf()
+        );
+        assert!(Arc::ptr_eq(
+            file_in_dir.parent.as_ref().unwrap(),
+            other_file_in_dir.parent.as_ref().unwrap()
+        ));
+        assert_eq!(2, file_in_dir.name_len());
+        assert_eq!("f2", file_in_dir.file_name());
+        assert_eq!("root", file_in_root.parent.as_ref().unwrap().file_name());
+    }
+}
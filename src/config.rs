@@ -0,0 +1,471 @@
+//! Default arguments read from a configuration file, in a subset of TOML:
+//!
+//! ```toml
+//! # Arguments for every scan.
+//! sort = true
+//! hdd = "auto"
+//!
+//! # Arguments for scans with `--profile photos`, on top of the ones above.
+//! [profiles.photos]
+//! type = ["image", "video"]
+//! skip-junk = true
+//! ```
+//!
+//! Keys are the names of long options. `true` passes a flag and `false` leaves it out, other
+//! values are passed as the option's value, and arrays pass the option once per element.
+//!
+//! Default arguments can also be given by environment variables, between the configuration file
+//! and the command line.
+
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Value of a key in the configuration file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+/// Arguments given by a configuration file, as `(key, value)` pairs in the order they appear in.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub defaults: Vec<(String, Value)>,
+    pub profiles: HashMap<String, Vec<(String, Value)>>,
+}
+
+impl Config {
+    /// # Returns
+    ///
+    /// Arguments given by the defaults, followed by the ones given by `profile` if any, or an
+    /// error if there's no such profile.
+    pub fn args(&self, profile: Option<&str>) -> Result<Vec<OsString>, String> {
+        let mut args = Vec::new();
+        push_args(&mut args, &self.defaults);
+        if let Some(profile) = profile {
+            match self.profiles.get(profile) {
+                Some(entries) => push_args(&mut args, entries),
+                None => return Err(format!("unknown profile: {}", profile)),
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn push_args(args: &mut Vec<OsString>, entries: &[(String, Value)]) {
+    for (key, value) in entries {
+        push_arg(args, key, value);
+    }
+}
+
+fn push_arg(args: &mut Vec<OsString>, key: &str, value: &Value) {
+    match value {
+        Value::Bool(true) => args.push(format!("--{}", key).into()),
+        Value::Bool(false) => {}
+        Value::String(value) => args.push(format!("--{}={}", key, value).into()),
+        Value::Array(values) => {
+            for value in values {
+                push_arg(args, key, value);
+            }
+        }
+    }
+}
+
+/// # Returns
+///
+/// `args`, the command line from the subcommand on, with the arguments given by the configuration
+/// file and by environment variables inserted after the subcommand, in that order, so that the ones
+/// given by environment variables override the ones given by the configuration file, and the ones
+/// given on the command line override both. `options` are the long options that can be given by
+/// environment variables, as returned by `clargs::long_options`.
+///
+/// The configuration file is the one given by `--config`, or the one at `default_path` if any,
+/// unless `--no-config` is given. Its profile named by `--profile` is applied on top of its
+/// defaults.
+pub fn with_config_args(
+    args: Vec<OsString>,
+    options: &[(String, bool)],
+) -> Result<Vec<OsString>, String> {
+    let mut args = args.into_iter();
+    let program = args.next();
+    let args: Vec<OsString> = env_args(options, |name| env::var_os(name))?
+        .into_iter()
+        .chain(args)
+        .collect();
+
+    let value_of = |name: &str| find_value(&args, name);
+    let path = match (value_of("no-config"), value_of("config")) {
+        (Some(_), _) | (None, Some(None)) => None,
+        (None, Some(Some(path))) => Some(PathBuf::from(path)),
+        (None, None) => default_path(),
+    };
+    let profile = match value_of("profile") {
+        Some(Some(profile)) => Some(profile.to_string_lossy().into_owned()),
+        _ => None,
+    };
+    let config_args = match path {
+        Some(path) => read(&path)?.args(profile.as_deref())?,
+        None => Vec::new(),
+    };
+
+    Ok(program.into_iter().chain(config_args).chain(args).collect())
+}
+
+/// # Returns
+///
+/// Arguments given by the environment variables named `FDUP_` followed by the name of each of
+/// `options` in upper case with underscores for dashes, e.g. `FDUP_THREADS` for `--threads`, as
+/// looked up by `var`. Each option is paired with whether it takes a value; the variables of
+/// those that don't pass them if they're set to `1`, `true`, `yes` or `on`.
+fn env_args(
+    options: &[(String, bool)],
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Result<Vec<OsString>, String> {
+    let mut args = Vec::new();
+    for (long, takes_value) in options {
+        let name = format!("FDUP_{}", long.to_uppercase().replace('-', "_"));
+        let value = match var(&name) {
+            Some(value) => value,
+            None => continue,
+        };
+        if *takes_value {
+            let mut arg = OsString::from(format!("--{}=", long));
+            arg.push(value);
+            args.push(arg);
+            continue;
+        }
+        match value.to_string_lossy().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => args.push(format!("--{}", long).into()),
+            "" | "0" | "false" | "no" | "off" => {}
+            value => return Err(format!("{}: expected a boolean, got {}", name, value)),
+        }
+    }
+    Ok(args)
+}
+
+/// # Returns
+///
+/// `Some` if the long option `name` is among `args`, with its value if it's followed by one,
+/// looking no further than `--`. The last occurrence wins.
+fn find_value(args: &[OsString], name: &str) -> Option<Option<OsString>> {
+    let flag = format!("--{}", name);
+    let prefix = format!("--{}=", name);
+    let mut found = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg_str = arg.to_string_lossy();
+        if arg_str == "--" {
+            break;
+        } else if arg_str == flag {
+            found = Some(args.next().cloned());
+        } else if let Some(value) = arg_str.strip_prefix(&prefix) {
+            found = Some(Some(OsString::from(value)));
+        }
+    }
+    found
+}
+
+/// # Returns
+///
+/// Path to the configuration file, which is `fdup/config.toml` in the user's configuration
+/// directory.
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("fdup").join("config.toml"))
+}
+
+/// # Returns
+///
+/// Configuration read from the file at `path`, which is empty if there's no such file.
+pub fn read(path: &Path) -> Result<Config, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text).map_err(|error| format!("{}: {}", path.display(), error)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(error) => Err(format!("{}: {}", path.display(), error)),
+    }
+}
+
+/// # Returns
+///
+/// Configuration given by `text`, or an error naming the line it failed to parse.
+pub fn parse(text: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    let mut profile = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", i + 1, message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unclosed table"))?;
+            let name = header
+                .trim()
+                .strip_prefix("profiles.")
+                .ok_or_else(|| error("only [profiles.<name>] tables are supported"))?;
+            let name = unquote(name.trim()).ok_or_else(|| error("invalid profile name"))?;
+            config.profiles.entry(name.clone()).or_default();
+            profile = Some(name);
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let key = unquote(key.trim()).ok_or_else(|| error("invalid key"))?;
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+        let entries = match &profile {
+            Some(name) => config.profiles.get_mut(name).unwrap(),
+            None => &mut config.defaults,
+        };
+        entries.push((key, value));
+    }
+    Ok(config)
+}
+
+/// # Returns
+///
+/// `line` without its comment, if any. Comments start at a `#` outside of strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// # Returns
+///
+/// `key`, which is either bare or quoted.
+fn unquote(key: &str) -> Option<String> {
+    if key.starts_with('"') || key.starts_with('\'') {
+        return match parse_value(key)? {
+            Value::String(key) => Some(key),
+            _ => None,
+        };
+    }
+    let is_bare = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    (!key.is_empty() && key.chars().all(is_bare)).then(|| key.to_string())
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    match s {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(s) = s.strip_prefix('[') {
+        let s = s.strip_suffix(']')?.trim();
+        let values = split_array(s)?
+            .into_iter()
+            .map(|value| parse_value(value.trim()))
+            .collect::<Option<_>>()?;
+        return Some(Value::Array(values));
+    }
+    if let Some(s) = s.strip_prefix('\'') {
+        return Some(Value::String(s.strip_suffix('\'')?.to_string()));
+    }
+    if let Some(s) = s.strip_prefix('"') {
+        let s = s.strip_suffix('"')?;
+        let mut value = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            value.push(match c {
+                '\\' => match chars.next()? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    'n' => '\n',
+                    't' => '\t',
+                    _ => return None,
+                },
+                '"' => return None,
+                c => c,
+            });
+        }
+        return Some(Value::String(value));
+    }
+    // Numbers, and anything else that's passed on as is, such as durations like 30m.
+    let is_bare = |c: char| c.is_ascii_alphanumeric() || "+-_.".contains(c);
+    (!s.is_empty() && s.chars().all(is_bare)).then(|| Value::String(s.replace('_', "")))
+}
+
+/// # Returns
+///
+/// Elements of the array whose contents are `s`, split at the commas outside of strings, or
+/// `None` if a string is left open.
+fn split_array(s: &str) -> Option<Vec<&str>> {
+    let mut elements = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, ',') => {
+                elements.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    if quote.is_some() {
+        return None;
+    }
+    // Trailing commas are allowed.
+    if !s[start..].trim().is_empty() {
+        elements.push(&s[start..]);
+    }
+    Some(elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = parse(
+            r##"
+            # defaults
+            sort = true
+            fadvise = false
+            hdd = "auto"  # trailing comment
+            "max-memory" = 1_000_000
+
+            [profiles.photos]
+            type = ["image", 'video',]
+            ignore-newer-than = 30m
+            [ profiles."a # b" ]
+            errors = "a \"#\" b"
+            "##,
+        )
+        .unwrap();
+
+        let entry = |key: &str, value| (key.to_string(), value);
+        assert_eq!(
+            vec![
+                entry("sort", Value::Bool(true)),
+                entry("fadvise", Value::Bool(false)),
+                entry("hdd", string("auto")),
+                entry("max-memory", string("1000000")),
+            ],
+            config.defaults
+        );
+        assert_eq!(
+            vec![
+                entry("type", Value::Array(vec![string("image"), string("video")])),
+                entry("ignore-newer-than", string("30m")),
+            ],
+            config.profiles["photos"]
+        );
+        assert_eq!(
+            vec![entry("errors", string("a \"#\" b"))],
+            config.profiles["a # b"]
+        );
+
+        for invalid in [
+            "sort",
+            "sort = ",
+            "hdd = \"auto",
+            "type = [\"a\", \"b]",
+            "[profiles.photos",
+            "[other]",
+            "a b = true",
+            "hdd = \"\\x\"",
+        ] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_find_value() {
+        let args: Vec<OsString> = ["--profile", "a", "--config=b", "--", "--profile", "c"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(Some(Some("a".into())), find_value(&args, "profile"));
+        assert_eq!(Some(Some("b".into())), find_value(&args, "config"));
+        assert_eq!(None, find_value(&args, "no-config"));
+        assert_eq!(Some(None), find_value(&args[..1], "profile"));
+    }
+
+    #[test]
+    fn test_env_args() {
+        let options = [
+            ("threads".to_string(), true),
+            ("skip-junk".to_string(), false),
+        ];
+        let env_args = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, OsString> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), OsString::from(value)))
+                .collect();
+            env_args(&options, |name| vars.get(name).cloned())
+        };
+        let args = |args: &[&str]| -> Vec<OsString> { args.iter().map(OsString::from).collect() };
+
+        assert_eq!(Ok(args(&[])), env_args(&[]));
+        assert_eq!(
+            Ok(args(&["--threads=4", "--skip-junk"])),
+            env_args(&[("FDUP_THREADS", "4"), ("FDUP_SKIP_JUNK", "Yes")])
+        );
+        assert_eq!(Ok(args(&[])), env_args(&[("FDUP_SKIP_JUNK", "0")]));
+        assert!(env_args(&[("FDUP_SKIP_JUNK", "maybe")]).is_err());
+    }
+
+    #[test]
+    fn test_args() {
+        let config = parse(
+            "sort = true\nfadvise = false\nhdd = \"auto\"\n\
+             [profiles.photos]\ntype = [\"image\", \"video\"]\n",
+        )
+        .unwrap();
+        let args = |args: &[&str]| -> Vec<OsString> { args.iter().map(OsString::from).collect() };
+
+        assert_eq!(Ok(args(&["--sort", "--hdd=auto"])), config.args(None));
+        assert_eq!(
+            Ok(args(&[
+                "--sort",
+                "--hdd=auto",
+                "--type=image",
+                "--type=video"
+            ])),
+            config.args(Some("photos"))
+        );
+        assert!(config.args(Some("music")).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Limit on how many files are read from the same device at the same time.
+//!
+//! Reads from a slow device, like a USB hard disk, would otherwise take up as many threads as
+//! those from a fast one, and make the slow one seek back and forth between them. With a limit per
+//! device, the other threads keep reading from the other devices instead.
+
+use std::{
+    collections::HashMap,
+    sync::{Condvar, Mutex, OnceLock},
+};
+
+static READS: OnceLock<DeviceReads> = OnceLock::new();
+
+/// Counting semaphore for the reads from each device.
+#[derive(Debug, Default)]
+pub struct DeviceReads {
+    /// Number of files being read from each device with any.
+    reading: Mutex<HashMap<u64, usize>>,
+    done: Condvar,
+}
+
+/// Permission to read a file from a device, which is counted until it's dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    reads: &'a DeviceReads,
+    dev: u64,
+}
+
+impl DeviceReads {
+    /// Wait until a file can be read from the device `dev` without more than `max` of them being
+    /// read from it at the same time.
+    pub fn acquire(&self, dev: u64, max: usize) -> Permit<'_> {
+        let mut reading = self.reading.lock().unwrap();
+        while max.max(1) <= reading.get(&dev).copied().unwrap_or(0) {
+            reading = self.done.wait(reading).unwrap();
+        }
+        *reading.entry(dev).or_default() += 1;
+        Permit { reads: self, dev }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut reading = self.reads.reading.lock().unwrap();
+        let count = reading.get_mut(&self.dev).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            reading.remove(&self.dev);
+        }
+        self.reads.done.notify_all();
+    }
+}
+
+/// Wait until a file can be read from the device `dev` without more than `max` of them being read
+/// from it at the same time, across this process.
+pub fn acquire(dev: u64, max: usize) -> Permit<'static> {
+    READS.get_or_init(Default::default).acquire(dev, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    // Check that no more reads are counted per device than the limit, while other devices are
+    // read from alongside.
+    #[test]
+    fn test_device_reads() {
+        let reads = DeviceReads::default();
+        let reading = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let max_reading = [AtomicUsize::new(0), AtomicUsize::new(0)];
+        let (reads, reading, max_reading) = (&reads, &reading, &max_reading);
+        thread::scope(|scope| {
+            for dev in [0, 1, 0, 0, 1, 0, 1, 1, 0] {
+                scope.spawn(move || {
+                    let _permit = reads.acquire(dev as u64, 2);
+                    let now_reading = reading[dev].fetch_add(1, Ordering::SeqCst) + 1;
+                    max_reading[dev].fetch_max(now_reading, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    reading[dev].fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        for max_reading in max_reading {
+            assert!(max_reading.load(Ordering::SeqCst) <= 2);
+        }
+        assert!(reads.reading.lock().unwrap().is_empty());
+    }
+}
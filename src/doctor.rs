@@ -0,0 +1,486 @@
+//! Checks of the machine that fdup runs on, `fdup doctor`, for what makes scans slow or handlers
+//! fail there, each with what could be done about it.
+
+use crate::{
+    clargs::DoctorOpt,
+    config,
+    hasher::{self, NewHasher},
+    hdd::HddMode,
+    open_files, removable,
+};
+use std::{
+    env, fmt,
+    fs::{create_dir, remove_dir_all, write},
+    io,
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Limit on file descriptors below which fdup reads noticeably fewer files at once than it could.
+const MIN_FD_LIMIT: usize = 1024;
+
+/// Space left in the temporary directory below which files spilled with `--max-memory` may not
+/// fit.
+const MIN_TEMP_SPACE: u64 = 1 << 30;
+
+/// Rate of hashing across every thread, in bytes per second, below which hashing may well be
+/// slower than reading.
+const MIN_HASH_RATE: f64 = 500e6;
+
+/// How long hashing is timed for.
+const HASH_TIME: Duration = Duration::from_millis(500);
+
+/// Outcome of a check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+
+    /// Scans work, but something about them could be faster, or some options won't work.
+    Warning,
+
+    /// Scans that need it will fail.
+    Failure,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Failure => "failure",
+        })
+    }
+}
+
+/// Outcome of a check, what was found, and what could be done about it, if anything.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub finding: String,
+    pub advice: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, finding: String) -> Check {
+        Check {
+            name,
+            status: Status::Ok,
+            finding,
+            advice: None,
+        }
+    }
+
+    fn warning(name: &'static str, finding: String, advice: Option<&str>) -> Check {
+        Check {
+            name,
+            status: Status::Warning,
+            finding,
+            advice: advice.map(String::from),
+        }
+    }
+
+    fn failure(name: &'static str, finding: String, advice: &str) -> Check {
+        Check {
+            name,
+            status: Status::Failure,
+            finding,
+            advice: Some(advice.to_string()),
+        }
+    }
+}
+
+/// Run `fdup doctor`, printing the outcome of each check to stdout.
+///
+/// # Returns
+///
+/// Exit status of fdup, which is 1 if any check failed.
+pub fn run_doctor(opt: DoctorOpt) -> i32 {
+    let dir = opt.dir.unwrap_or_else(|| PathBuf::from("."));
+    if !dir.is_dir() {
+        eprintln!("fdup: {}: not a directory", dir.display());
+        return 1;
+    }
+    let mut checks = Vec::new();
+    // The filesystem is checked on files of its own, which are removed afterwards.
+    let scratch = dir.join(format!(".fdup-doctor-{}", process::id()));
+    match create_dir(&scratch) {
+        Ok(()) => {
+            checks.push(check_reflinks(&scratch, &dir));
+            checks.push(check_xattrs(&scratch, &dir));
+            let _ = remove_dir_all(&scratch);
+        }
+        Err(error) => {
+            let finding = format!("couldn't write to {} to check it: {}", dir.display(), error);
+            checks.push(Check::warning("reflinks", finding.clone(), None));
+            checks.push(Check::warning("extended attributes", finding, None));
+        }
+    }
+    checks.push(check_disk(&dir));
+    checks.push(check_temp_dir(&env::temp_dir()));
+    checks.push(check_config(config::default_path()));
+    checks.push(check_fd_limit(open_files::fd_limit()));
+    checks.push(check_hash_rate(
+        hash_rate(hasher::sha512, HASH_TIME),
+        rayon::current_num_threads(),
+    ));
+
+    for check in &checks {
+        println!("{:<8} {}: {}", check.status, check.name, check.finding);
+        if let Some(advice) = &check.advice {
+            println!("{:<8} {}", "", advice);
+        }
+    }
+    if checks.iter().any(|check| check.status == Status::Failure) {
+        1
+    } else {
+        0
+    }
+}
+
+/// # Returns
+///
+/// Whether files in `scratch`, a new directory in `dir`, can be cloned the way that the `clone`
+/// handler of scripts written with `--script` does it.
+fn check_reflinks(scratch: &Path, dir: &Path) -> Check {
+    let (original, clone) = (scratch.join("original"), scratch.join("clone"));
+    if let Err(error) = write(&original, "fdup") {
+        let finding = format!("couldn't write to {} to check it: {}", dir.display(), error);
+        return Check::warning("reflinks", finding, None);
+    }
+    let status = Command::new("cp")
+        .args(["--reflink=always", "-p", "--"])
+        .args([&original, &clone])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => Check::ok(
+            "reflinks",
+            format!("supported on the filesystem of {}", dir.display()),
+        ),
+        Ok(_) => Check::warning(
+            "reflinks",
+            format!(
+                "`cp --reflink=always` can't clone files on the filesystem of {}",
+                dir.display()
+            ),
+            Some(
+                "the `clone` handler of `--script-handler` will fail there; `clone,hardlink` \
+                 falls back to hardlinks",
+            ),
+        ),
+        Err(error) => Check::warning(
+            "reflinks",
+            format!("`cp` couldn't be run: {}", error),
+            Some("the `clone` handler of `--script-handler` needs it on the `PATH`"),
+        ),
+    }
+}
+
+/// # Returns
+///
+/// Whether extended attributes can be set on files in `scratch`, a new directory in `dir`, and
+/// read back.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn check_xattrs(scratch: &Path, dir: &Path) -> Check {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let name = "extended attributes";
+    let file = scratch.join("xattrs");
+    let set = write(&file, "fdup").and_then(|()| {
+        let path = CString::new(file.as_os_str().as_bytes()).unwrap();
+        let xattr = CString::new("user.fdup-doctor").unwrap();
+        match unsafe { set_xattr(&path, &xattr, b"1") } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    });
+    match set.and_then(|()| removable::xattrs(&file)) {
+        Ok(xattrs) if xattrs.iter().any(|(name, _)| name == "user.fdup-doctor") => Check::ok(
+            name,
+            format!("supported on the filesystem of {}", dir.display()),
+        ),
+        Err(error) if error.raw_os_error() == Some(libc::ENOTSUP) => Check::warning(
+            name,
+            format!("not supported on the filesystem of {}", dir.display()),
+            Some("`--check-removable` can't tell if duplicates there differ in them"),
+        ),
+        Ok(_) => Check::warning(
+            name,
+            format!(
+                "couldn't be read back on the filesystem of {}",
+                dir.display()
+            ),
+            None,
+        ),
+        Err(error) => Check::warning(
+            name,
+            format!(
+                "couldn't be set on the filesystem of {}: {}",
+                dir.display(),
+                error
+            ),
+            None,
+        ),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn check_xattrs(_scratch: &Path, _dir: &Path) -> Check {
+    Check::warning(
+        "extended attributes",
+        "only checked on Linux and macOS".to_string(),
+        None,
+    )
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_xattr(path: &std::ffi::CStr, name: &std::ffi::CStr, value: &[u8]) -> libc::c_int {
+    libc::lsetxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        value.as_ptr().cast(),
+        value.len(),
+        0,
+    )
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn set_xattr(path: &std::ffi::CStr, name: &std::ffi::CStr, value: &[u8]) -> libc::c_int {
+    libc::setxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        value.as_ptr().cast(),
+        value.len(),
+        0,
+        libc::XATTR_NOFOLLOW,
+    )
+}
+
+/// # Returns
+///
+/// Whether the disk that `dir` is on is rotational, as far as `--hdd auto` can tell.
+fn check_disk(dir: &Path) -> Check {
+    let name = "disk";
+    match removable::device(dir) {
+        Ok(dev) if HddMode::Auto.applies_to(dev) => Check::warning(
+            name,
+            format!("{} is on a rotational disk", dir.display()),
+            Some(
+                "`--hdd auto` reads its files one at a time in the order they're laid out, \
+                 instead of seeking between them",
+            ),
+        ),
+        Ok(_) => Check::ok(
+            name,
+            format!("{} isn't known to be on a rotational disk", dir.display()),
+        ),
+        Err(error) => Check::warning(name, format!("{}: {}", dir.display(), error), None),
+    }
+}
+
+/// # Returns
+///
+/// Whether files can be spilled to the temporary directory `temp_dir` with `--max-memory`, and
+/// whether there's much space left for them.
+fn check_temp_dir(temp_dir: &Path) -> Check {
+    let name = "temporary directory";
+    let scratch = temp_dir.join(format!("fdup-doctor-{}", process::id()));
+    if let Err(error) = create_dir(&scratch) {
+        return Check::failure(
+            name,
+            format!("{} can't be written to: {}", temp_dir.display(), error),
+            "`--max-memory` spills files there; point `TMPDIR` at a writable directory",
+        );
+    }
+    let _ = remove_dir_all(&scratch);
+    match available_space(temp_dir) {
+        Some(space) if space < MIN_TEMP_SPACE => Check::warning(
+            name,
+            format!(
+                "only {} MB left in {}",
+                space / 1_000_000,
+                temp_dir.display()
+            ),
+            Some(
+                "`--max-memory` spills files there, which may not fit; point `TMPDIR` at a \
+                 larger filesystem",
+            ),
+        ),
+        Some(space) => Check::ok(
+            name,
+            format!("{} MB left in {}", space / 1_000_000, temp_dir.display()),
+        ),
+        None => Check::ok(name, format!("{} can be written to", temp_dir.display())),
+    }
+}
+
+/// # Returns
+///
+/// Bytes left for unprivileged users on the filesystem of `dir`. Only implemented on Unix.
+#[cfg(unix)]
+fn available_space(dir: &Path) -> Option<u64> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // The fields are narrower on some platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// # Returns
+///
+/// Whether the configuration file at `path`, if any, can be read and parsed.
+fn check_config(path: Option<PathBuf>) -> Check {
+    let name = "configuration";
+    let path = match path {
+        Some(path) => path,
+        None => return Check::ok(name, "no configuration directory".to_string()),
+    };
+    if !path.exists() {
+        return Check::ok(name, format!("no file at {}", path.display()));
+    }
+    match config::read(&path) {
+        Ok(_) => Check::ok(name, format!("read {}", path.display())),
+        Err(error) => Check::failure(
+            name,
+            error,
+            "`fdup scan` fails until the file is fixed or removed",
+        ),
+    }
+}
+
+/// # Returns
+///
+/// Whether the soft limit on file descriptors, `limit`, leaves room for reading many files at
+/// once.
+fn check_fd_limit(limit: Option<usize>) -> Check {
+    let name = "open files";
+    match limit {
+        Some(limit) if limit < MIN_FD_LIMIT => Check::warning(
+            name,
+            format!("at most {} file descriptors per process", limit),
+            Some(
+                "fewer files are read at once to stay under the limit; raise it, e.g. with \
+                 `ulimit -n 4096`",
+            ),
+        ),
+        Some(limit) => Check::ok(
+            name,
+            format!("at most {} file descriptors per process", limit),
+        ),
+        None => Check::ok(name, "no limit on file descriptors".to_string()),
+    }
+}
+
+/// # Returns
+///
+/// Whether hashing at `rate` bytes per second on each of `threads` threads is likely to keep up
+/// with reading.
+fn check_hash_rate(rate: f64, threads: usize) -> Check {
+    let name = "hashing";
+    let finding = format!(
+        "SHA512 at about {:.0} MB/s per thread, {:.0} MB/s across {} threads",
+        rate / 1e6,
+        rate * threads as f64 / 1e6,
+        threads
+    );
+    if rate * (threads as f64) < MIN_HASH_RATE {
+        let advice = "hashing may be slower than reading; `--hash-tiers` or `--sample-threshold` \
+                      hash less of large files before hashing them in full";
+        Check::warning(name, finding, Some(advice))
+    } else {
+        Check::ok(name, finding)
+    }
+}
+
+/// # Returns
+///
+/// Bytes per second hashed with `new_hasher` on one thread, timed for about `duration`.
+fn hash_rate(new_hasher: NewHasher, duration: Duration) -> f64 {
+    let chunk = vec![0u8; 1 << 20];
+    let mut hasher = new_hasher();
+    let mut hashed = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        hasher.update(&chunk);
+        hashed += chunk.len();
+    }
+    hasher.finalize();
+    hashed as f64 / start.elapsed().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir_all;
+
+    #[test]
+    fn test_check_config() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let dir = env::temp_dir().join(prefix);
+        if dir.exists() {
+            remove_dir_all(&dir).unwrap();
+        }
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        assert_eq!(Status::Ok, check_config(None).status);
+        assert_eq!(Status::Ok, check_config(Some(path.clone())).status);
+        write(&path, "sort = true\n").unwrap();
+        assert_eq!(Status::Ok, check_config(Some(path.clone())).status);
+        write(&path, "sort true\n").unwrap();
+        let check = check_config(Some(path.clone()));
+        assert_eq!(Status::Failure, check.status);
+        assert!(check.advice.is_some());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    // Check that the filesystem checks don't fail outright, whatever the filesystem supports, and
+    // that the temporary directory is left as it was.
+    #[test]
+    fn test_check_filesystem() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let dir = env::temp_dir().join(prefix);
+        if dir.exists() {
+            remove_dir_all(&dir).unwrap();
+        }
+        create_dir_all(dir.join("scratch")).unwrap();
+
+        let scratch = dir.join("scratch");
+        assert_ne!(Status::Failure, check_reflinks(&scratch, &dir).status);
+        assert_ne!(Status::Failure, check_xattrs(&scratch, &dir).status);
+        assert_ne!(Status::Failure, check_disk(&dir).status);
+        assert_ne!(Status::Failure, check_temp_dir(&scratch).status);
+        remove_dir_all(&scratch).unwrap();
+        assert_eq!(Status::Failure, check_temp_dir(&scratch).status);
+        assert_eq!(0, dir.read_dir().unwrap().count());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_fd_limit() {
+        assert_eq!(Status::Warning, check_fd_limit(Some(256)).status);
+        assert_eq!(Status::Ok, check_fd_limit(Some(MIN_FD_LIMIT)).status);
+        assert_eq!(Status::Ok, check_fd_limit(None).status);
+    }
+
+    #[test]
+    fn test_check_hash_rate() {
+        assert_eq!(Status::Warning, check_hash_rate(100e6, 4).status);
+        assert_eq!(Status::Ok, check_hash_rate(200e6, 4).status);
+        assert!(0.0 < hash_rate(hasher::sha512, Duration::from_millis(10)));
+    }
+}
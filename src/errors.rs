@@ -0,0 +1,238 @@
+use std::{
+    fmt, io,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// What happens when an IO error keeps a file or directory from being scanned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Leave it out of the results without saying so.
+    Skip,
+
+    /// Leave it out of the results, and report the error on stderr once the scan is done.
+    #[default]
+    Warn,
+
+    /// Abort the scan, exiting with a nonzero status.
+    Fail,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "warn" => Ok(ErrorPolicy::Warn),
+            "fail" => Ok(ErrorPolicy::Fail),
+            _ => Err(format!("unknown error policy: {}", s)),
+        }
+    }
+}
+
+/// What fdup was doing with a file or directory when it failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Listing the entries of a directory.
+    ReadDir,
+
+    /// Reading the metadata of a file or directory.
+    Metadata,
+
+    /// Reading the start of a file to sniff its content type.
+    Sniff,
+
+    /// Reading sampled blocks of a file.
+    Sample,
+
+    /// Computing one of the extra keys that files are grouped by.
+    Key,
+
+    /// Reading a whole file to compute its checksum.
+    Hash,
+
+    /// Confirming that a file is a duplicate of another.
+    Verify,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 7] = [
+        Operation::ReadDir,
+        Operation::Metadata,
+        Operation::Sniff,
+        Operation::Sample,
+        Operation::Key,
+        Operation::Hash,
+        Operation::Verify,
+    ];
+
+    /// # Returns
+    ///
+    /// Name of this operation in machine-readable output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::ReadDir => "read_dir",
+            Operation::Metadata => "metadata",
+            Operation::Sniff => "sniff",
+            Operation::Sample => "sample",
+            Operation::Key => "key",
+            Operation::Hash => "hash",
+            Operation::Verify => "verify",
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if this reads a file that was found, so that failing because the file
+    /// isn't there anymore only means that it was deleted since. Verifying may run a command,
+    /// which may not be there either.
+    pub fn reads_found_file(self) -> bool {
+        matches!(
+            self,
+            Operation::Sniff | Operation::Sample | Operation::Key | Operation::Hash
+        )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Operation::ReadDir => "listing",
+            Operation::Metadata => "reading metadata",
+            Operation::Sniff => "sniffing",
+            Operation::Sample => "sampling",
+            Operation::Key => "computing a grouping key",
+            Operation::Hash => "hashing",
+            Operation::Verify => "verifying",
+        })
+    }
+}
+
+/// IO error because of which a file or directory was left out of the results.
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub operation: Operation,
+    pub error: io::Error,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: error while {}: {}",
+            self.path.display(),
+            self.operation,
+            self.error
+        )
+    }
+}
+
+/// Errors recorded during a scan, handled according to an `ErrorPolicy`. Clones share the same
+/// record, so that one can be kept to read the errors back once the scan is done.
+///
+/// Files deleted between being found and being read, which happens all the time in build
+/// directories and temporary trees, aren't errors: they're only counted, whatever the policy.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorLog {
+    policy: ErrorPolicy,
+    errors: Arc<Mutex<Vec<ScanError>>>,
+    vanished: Arc<AtomicU64>,
+}
+
+impl ErrorLog {
+    pub fn new(policy: ErrorPolicy) -> ErrorLog {
+        ErrorLog {
+            policy,
+            errors: Default::default(),
+            vanished: Default::default(),
+        }
+    }
+
+    /// Record `error`, or crash with it if the policy is `ErrorPolicy::Fail`, unless it's for a
+    /// file that was deleted since it was found, which is only counted.
+    pub fn record(&self, error: ScanError) {
+        if error.operation.reads_found_file() && error.error.kind() == io::ErrorKind::NotFound {
+            self.vanished.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.policy == ErrorPolicy::Fail {
+            panic!("{}", error);
+        }
+        self.errors.lock().unwrap().push(error);
+    }
+
+    /// # Returns
+    ///
+    /// Errors recorded so far, which are then removed from the log.
+    pub fn take(&self) -> Vec<ScanError> {
+        std::mem::take(&mut *self.errors.lock().unwrap())
+    }
+
+    /// # Returns
+    ///
+    /// Files left out so far because they were deleted between being found and being read.
+    pub fn vanished(&self) -> u64 {
+        self.vanished.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(path: &str, operation: Operation, kind: io::ErrorKind) -> ScanError {
+        ScanError {
+            path: PathBuf::from(path),
+            operation,
+            error: kind.into(),
+        }
+    }
+
+    fn denied(path: &str) -> ScanError {
+        error(path, Operation::Hash, io::ErrorKind::PermissionDenied)
+    }
+
+    // Check that errors are shared between clones, and only taken once.
+    #[test]
+    fn test_error_log() {
+        for policy in [ErrorPolicy::Skip, ErrorPolicy::Warn] {
+            let errors = ErrorLog::new(policy);
+            errors.clone().record(denied("a"));
+            errors.record(denied("b"));
+
+            let taken: Vec<PathBuf> = errors.take().into_iter().map(|error| error.path).collect();
+            assert_eq!(vec![PathBuf::from("a"), PathBuf::from("b")], taken);
+            assert!(errors.take().is_empty());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a: error while hashing")]
+    fn test_error_log_fail() {
+        ErrorLog::new(ErrorPolicy::Fail).record(denied("a"));
+    }
+
+    // Check that files deleted once found are only counted, even if errors are fatal, but that
+    // roots and commands that don't exist are still errors.
+    #[test]
+    fn test_vanished() {
+        let errors = ErrorLog::new(ErrorPolicy::Fail);
+        for operation in [Operation::Sniff, Operation::Sample, Operation::Hash] {
+            errors.record(error("a", operation, io::ErrorKind::NotFound));
+        }
+        assert_eq!(3, errors.vanished());
+        assert!(errors.take().is_empty());
+
+        let errors = ErrorLog::new(ErrorPolicy::Warn);
+        errors.record(error("b", Operation::Metadata, io::ErrorKind::NotFound));
+        errors.record(error("c", Operation::Verify, io::ErrorKind::NotFound));
+        assert_eq!(0, errors.vanished());
+        assert_eq!(2, errors.take().len());
+    }
+}
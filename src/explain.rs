@@ -0,0 +1,360 @@
+//! Description of the stages that a scan goes through with the given options, printed by
+//! `--explain` instead of scanning, so that what produced a set of results can be told and
+//! reported.
+
+use crate::{
+    collate::Collation,
+    fdup::{Backend, EmptyFiles, SampleOptions, ScanOptions, Spread, JUNK_FILES},
+    glob::PathPattern,
+    hdd::HddMode,
+    output::Format,
+    read_only,
+    walk::WalkOrder,
+};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// What a scan is configured with besides its `ScanOptions`, which can't tell about themselves,
+/// since some of them are closures, or which apply to the whole process.
+pub struct Context<'a> {
+    pub roots: &'a [PathBuf],
+    pub threads: usize,
+    /// Names of the `ScanOptions::keys`, in order.
+    pub keys: Vec<&'static str>,
+    /// Description of `ScanOptions::verify`, if given.
+    pub verify: Option<String>,
+    pub max_open_files: Option<usize>,
+    /// In bytes per second.
+    pub max_read_rate: Option<f64>,
+    pub format: Format,
+    pub fingerprints: bool,
+}
+
+/// Write the stages that a scan of `context.roots` with `options` would go through, numbered as
+/// in `group_files`, each with what it's configured with.
+pub fn write_pipeline(
+    out: &mut impl Write,
+    options: &ScanOptions,
+    context: &Context,
+) -> io::Result<()> {
+    let roots: Vec<String> = context
+        .roots
+        .iter()
+        .map(|root| root.display().to_string())
+        .collect();
+    writeln!(out, "roots: {}", roots.join(", "))?;
+    writeln!(out, "threads: {}", context.threads)?;
+
+    let walk = &options.walk;
+    let mut walked = vec![if walk.include_caches {
+        "cache directories included".to_string()
+    } else {
+        "cache directories skipped".to_string()
+    }];
+    match walk.order {
+        WalkOrder::Unordered => {}
+        WalkOrder::BreadthFirst => walked.push("breadth first, sorted by name".to_string()),
+        WalkOrder::DepthFirstSorted => walked.push("depth first, sorted by name".to_string()),
+    }
+    if !walk.exclude_fs.is_empty() {
+        let names: Vec<&str> = walk.exclude_fs.names().collect();
+        walked.push(format!("filesystems not walked: {}", names.join(", ")));
+    }
+    if options.precount {
+        walked.push("counted by a first walk".to_string());
+    }
+    if options.size_census {
+        walked.push("shared sizes found by a first walk".to_string());
+    }
+    writeln!(out, "walk: {}", walked.join("; "))?;
+
+    let mut filters = Vec::new();
+    if options.skip_appledouble {
+        filters.push("AppleDouble files (._*) skipped".to_string());
+    }
+    if options.skip_junk {
+        filters.push(format!("junk files skipped: {}", JUNK_FILES.join(", ")));
+    }
+    if options.skip_invalid_names {
+        filters.push("files whose paths aren't valid unicode skipped".to_string());
+    }
+    if let Some(age) = options.ignore_newer_than {
+        filters.push(format!(
+            "files modified in the last {}s skipped",
+            age.as_secs()
+        ));
+    }
+    if let Some(uid) = options.owner {
+        filters.push(format!("only files owned by user {}", uid));
+    }
+    if options.only_writable {
+        filters.push("only writable files".to_string());
+    }
+    if let Some(max_files) = options.max_files {
+        filters.push(format!("at most {} files", max_files));
+    }
+    if let Some(max_bytes) = options.max_bytes {
+        filters.push(format!("at most {} bytes", max_bytes));
+    }
+    writeln!(out, "files: {}", list_or(&filters, "every regular file"))?;
+    if !options.normalize.is_empty() {
+        let kinds: Vec<String> = options
+            .normalize
+            .iter()
+            .map(|kind| format!("{:?}", kind).to_lowercase())
+            .collect();
+        writeln!(
+            out,
+            "normalized documents: {}, grouped by their normalized checksums instead of steps 1 \
+             to 7",
+            kinds.join(", ")
+        )?;
+    }
+
+    let empty = match options.empty {
+        EmptyFiles::Ignore => "empty files ignored",
+        EmptyFiles::Report => "empty files grouped",
+        EmptyFiles::ReportSeparately => "empty files reported separately",
+    };
+    let memory = match options.max_memory {
+        Some(max_memory) => format!("spilled to disk past {} bytes", max_memory),
+        None => "held in memory".to_string(),
+    };
+    let mut by_size = vec![empty.to_string(), memory];
+    if let Some(fraction) = options.sample_sizes {
+        by_size.push(format!(
+            "{}% of the shared sizes compared",
+            fraction * 100.0
+        ));
+    }
+    if options.sizes_only {
+        by_size.push("groups reported unconfirmed, skipping steps 2 to 7".to_string());
+    }
+    writeln!(out, "1. size: {}", by_size.join("; "))?;
+
+    let types: Vec<String> = options
+        .types
+        .iter()
+        .map(|kind| format!("{:?}", kind).to_lowercase())
+        .collect();
+    writeln!(out, "2. content type: {}", list_or(&types, "not checked"))?;
+    writeln!(out, "3. keys: {}", list_or(&context.keys, "none"))?;
+
+    let tiers: Vec<String> = options.sample.iter().map(describe_tier).collect();
+    writeln!(out, "4. sampled blocks: {}", list_or(&tiers, "none"))?;
+    writeln!(out, "5. hardlinks: each inode hashed once")?;
+
+    let hash = options.hash;
+    let mut checksum = vec![match hash.checksum_len {
+        Some(len) => format!("SHA512, truncated to {} bits", len * 8),
+        None => "SHA512".to_string(),
+    }];
+    if options.git.is_some() {
+        checksum.push("git blob IDs for files clean in the index".to_string());
+    }
+    writeln!(out, "6. checksum: {}", checksum.join("; "))?;
+    let mut reads = vec![match (hash.backend, hash.uses_io_uring()) {
+        (Backend::IoUring, true) => "io_uring backend".to_string(),
+        (Backend::IoUring, false) => "read backend, since io_uring can't be used".to_string(),
+        (Backend::Read, _) => "read backend".to_string(),
+    }];
+    if let Some(threshold) = hash.mmap_threshold {
+        reads.push(format!("memory-mapped from {} bytes", threshold));
+    }
+    if hash.fadvise {
+        reads.push("page cache spared".to_string());
+    }
+    if 0 < hash.readahead {
+        reads.push(format!("next {} files read ahead", hash.readahead));
+    }
+    if hash.sparse {
+        reads.push("holes skipped".to_string());
+    }
+    if hash.lock {
+        reads.push("locked while read".to_string());
+    }
+    match hash.hdd_mode {
+        HddMode::Never => {}
+        HddMode::Auto => reads.push("rotational disks read in order".to_string()),
+        HddMode::Always => reads.push("every disk read in order".to_string()),
+    }
+    if let Some(max) = hash.reads_per_device {
+        reads.push(format!("at most {} reads per device", max));
+    }
+    if let Some(max) = context.max_open_files {
+        reads.push(format!("at most {} open files", max));
+    }
+    if let Some(rate) = context.max_read_rate {
+        reads.push(format!("at most {} MB/s", rate / 1e6));
+    }
+    reads.push(format!(
+        "{} retries from {}ms",
+        hash.retry.retries,
+        hash.retry.backoff.as_millis()
+    ));
+    if let Some(timeout) = hash.timeout {
+        reads.push(format!("timing out after {}s", timeout.as_secs()));
+    }
+    if 0 < options.slowest_reads {
+        reads.push(format!("{} slowest files recorded", options.slowest_reads));
+    }
+    writeln!(out, "   reads: {}", reads.join("; "))?;
+    writeln!(
+        out,
+        "7. verify: {}",
+        context.verify.as_deref().unwrap_or("none")
+    )?;
+
+    writeln!(
+        out,
+        "8. space: {}",
+        if options.skip_hardlinked {
+            "recorded; groups of hardlinks to a single inode left out"
+        } else {
+            "recorded"
+        }
+    )?;
+    let mut groups = Vec::new();
+    match options.spread {
+        Spread::Any => {}
+        Spread::AcrossDirs => groups.push("only across directories".to_string()),
+        Spread::SameDir => groups.push("only within a directory".to_string()),
+    }
+    let patterns = |patterns: &[PathPattern]| -> String {
+        let patterns: Vec<&str> = patterns
+            .iter()
+            .map(|pattern| match pattern {
+                PathPattern::Literal(pattern) | PathPattern::Glob(pattern) => pattern.as_str(),
+            })
+            .collect();
+        patterns.join(", ")
+    };
+    if !options.group_must_include.is_empty() {
+        let include = patterns(&options.group_must_include);
+        groups.push(format!("only with copies matching each of {}", include));
+    }
+    if !options.group_must_exclude.is_empty() {
+        let exclude = patterns(&options.group_must_exclude);
+        groups.push(format!("none with copies matching {}", exclude));
+    }
+    if !options.keep_in.is_empty() {
+        let keep_in: Vec<String> = options
+            .keep_in
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect();
+        groups.push(format!("copies kept in {}", keep_in.join(", ")));
+    }
+    if !options.protect.is_empty() {
+        groups.push(format!("protected: {}", patterns(&options.protect)));
+    }
+    match (options.sort, options.collation) {
+        (false, _) => {}
+        (true, Collation::Bytes) => groups.push("sorted by bytes".to_string()),
+        (true, Collation::Locale) => groups.push("sorted by the locale".to_string()),
+    }
+    writeln!(out, "9. groups: {}", list_or(&groups, "every one"))?;
+    let mut format = match context.format {
+        Format::Text => "text",
+        Format::Json => "json",
+        Format::Msgpack => "msgpack",
+    }
+    .to_string();
+    if context.fingerprints {
+        format.push_str(", with fingerprints");
+    }
+    if read_only::is_enforced() {
+        writeln!(out, "output: {}; nothing written to the filesystem", format)
+    } else {
+        writeln!(out, "output: {}", format)
+    }
+}
+
+/// # Returns
+///
+/// How files are sampled in the tier with `options`.
+fn describe_tier(options: &SampleOptions) -> String {
+    match options.blocks {
+        0 => format!("from {} bytes, hashed in full", options.threshold),
+        1 => format!(
+            "from {} bytes, prefix of {} bytes",
+            options.threshold, options.block_size
+        ),
+        blocks => format!(
+            "from {} bytes, {} blocks of {} bytes",
+            options.threshold, blocks, options.block_size
+        ),
+    }
+}
+
+/// # Returns
+///
+/// `items` joined by commas, or `none` if there are none.
+fn list_or(items: &[impl AsRef<str>], none: &str) -> String {
+    match items {
+        [] => none.to_string(),
+        _ => {
+            let items: Vec<&str> = items.iter().map(AsRef::as_ref).collect();
+            items.join(", ")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdup::HashOptions;
+
+    #[test]
+    fn test_write_pipeline() {
+        let roots = [PathBuf::from("a"), PathBuf::from("b")];
+        let context = Context {
+            roots: &roots,
+            threads: 4,
+            keys: vec!["child of root"],
+            verify: None,
+            max_open_files: None,
+            max_read_rate: None,
+            format: Format::Json,
+            fingerprints: true,
+        };
+        let options = ScanOptions {
+            sample: vec![
+                SampleOptions {
+                    threshold: 1 << 20,
+                    blocks: 1,
+                    block_size: 4096,
+                },
+                SampleOptions {
+                    threshold: 1 << 30,
+                    blocks: 8,
+                    block_size: 4096,
+                },
+            ],
+            hash: HashOptions {
+                backend: Backend::IoUring,
+                lock: true,
+                checksum_len: Some(16),
+                ..HashOptions::default()
+            },
+            spread: Spread::AcrossDirs,
+            ..ScanOptions::default()
+        };
+        let mut out = Vec::new();
+        write_pipeline(&mut out, &options, &context).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("roots: a, b\nthreads: 4\n"));
+        assert!(out.contains("\n2. content type: not checked\n3. keys: child of root\n"));
+        assert!(out.contains(
+            "\n4. sampled blocks: from 1048576 bytes, prefix of 4096 bytes, from 1073741824 \
+             bytes, 8 blocks of 4096 bytes\n"
+        ));
+        assert!(out.contains("\n6. checksum: SHA512, truncated to 128 bits\n"));
+        assert!(out.contains("\n   reads: read backend, since io_uring can't be used;"));
+        assert!(out.contains("\n7. verify: none\n8. space: recorded\n"));
+        assert!(out.contains("\n9. groups: only across directories\n"));
+        assert!(out.ends_with("\noutput: json, with fingerprints\n"));
+    }
+}
@@ -1,44 +1,711 @@
-use rayon::prelude::*;
-use sha2::{Digest, Sha512};
+use crate::{
+    collate::Collation,
+    compact_path::CompactPath,
+    device_reads,
+    errors::{ErrorLog, ErrorPolicy, Operation, ScanError},
+    git::GitBlobs,
+    glob::{self, PathPattern},
+    hasher::{sha512, NewHasher},
+    hdd::HddMode,
+    mmap::Mmap,
+    normalize::Normalize,
+    open_files,
+    retry::RetryPolicy,
+    skips::{SkipLog, SkipReason},
+    sniff::{sniff_file, ContentType},
+    spill::{self, Collected, Collector},
+    stats::{Stats, StatsLog},
+    throttle::throttle,
+    uring,
+    walk::{walk, WalkOptions},
+};
+use rayon::{iter::Either, prelude::*};
 use std::{
     borrow::Borrow,
-    collections::HashMap,
-    fs::File,
-    hash::Hash,
-    io::Read,
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    ffi::OsStr,
+    fmt,
+    fs::{File, Metadata},
+    hash::{BuildHasher, Hash},
+    io::{self, Read, Seek, SeekFrom},
+    ops::ControlFlow,
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use walkdir::{DirEntry, WalkDir};
+
+pub use crate::hasher::Checksum;
+
+/// How file contents are read while they're being hashed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Read each file with blocking syscalls, one file at a time per thread.
+    #[default]
+    Read,
+
+    /// Batch the opens and reads of many files through io_uring. Experimental, and only available
+    /// on Linux; falls back to `Read` if io_uring can't be set up.
+    IoUring,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Backend::Read),
+            "io-uring" if cfg!(target_os = "linux") => Ok(Backend::IoUring),
+            "io-uring" => Err("the io-uring backend is only available on Linux".to_string()),
+            _ => Err(format!("unknown backend: {}", s)),
+        }
+    }
+}
+
+/// Options controlling how file contents are read while they're being hashed.
+#[derive(Clone, Copy, Debug)]
+pub struct HashOptions {
+    /// Creates the hasher of each file, and of its sampled blocks. SHA512 by default.
+    pub new_hasher: NewHasher,
+
+    pub backend: Backend,
+
+    /// Files of at least this many bytes are memory-mapped and hashed in place, rather than being
+    /// copied through a small buffer. If the mapping can't be created, the file is read instead.
+    pub mmap_threshold: Option<u64>,
+
+    /// Advise the kernel that each file will be read sequentially, and that its pages won't be
+    /// needed again once it has been hashed, so that a large scan doesn't evict the page cache.
+    ///
+    /// Neither this nor `mmap_threshold` apply to the io_uring backend.
+    pub fadvise: bool,
+
+    /// Only read the parts of sparse files that hold data, and hash their holes as the zeros they
+    /// read as, without reading them. Only implemented on Linux, and doesn't apply to the io_uring
+    /// backend either.
+    pub sparse: bool,
+
+    /// Which devices to read sequentially and in physical order. Files on those devices are
+    /// always read with the default backend.
+    pub hdd_mode: HddMode,
+
+    /// Take a shared advisory lock on each file while it's being hashed, and skip the files that
+    /// another process holds an exclusive lock on, since they may be in the middle of being
+    /// written. Only implemented on Unix, and files are always read with the default backend.
+    pub lock: bool,
+
+    /// How reads that fail with transient errors are retried, including sampled reads.
+    pub retry: RetryPolicy,
+
+    /// If given, files that take longer than this to be read, retries included, are left out and
+    /// their errors recorded, so that one hung read doesn't stall the scan. Files are always read
+    /// with the default backend.
+    pub timeout: Option<Duration>,
+
+    /// If given, no more than this many files are read from the same device at the same time,
+    /// whatever they're read for, so that threads that would otherwise all wait on a slow device
+    /// read from the others instead. Files are always read with the default backend.
+    pub reads_per_device: Option<usize>,
+
+    /// Number of the next files of a group whose reads are hinted to the kernel ahead of time
+    /// while one is being hashed, a batch at a time, so that reading them overlaps with hashing.
+    /// Only the first `PREFETCH_BYTES` of each are hinted, since reading ahead from there is
+    /// left to the kernel. Only implemented on Linux, and doesn't apply to the io_uring backend,
+    /// which already keeps several reads in flight.
+    pub readahead: usize,
+
+    /// If given, checksums of whole files are truncated to their first this many bytes, so that
+    /// they take up less memory while files are grouped by them, at the cost of the odds of two
+    /// files colliding. Sampled checksums are never truncated.
+    pub checksum_len: Option<usize>,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        HashOptions {
+            new_hasher: sha512,
+            backend: Backend::default(),
+            mmap_threshold: None,
+            fadvise: false,
+            sparse: false,
+            hdd_mode: HddMode::default(),
+            lock: false,
+            retry: RetryPolicy::default(),
+            timeout: None,
+            reads_per_device: None,
+            readahead: 0,
+            checksum_len: None,
+        }
+    }
+}
+
+impl HashOptions {
+    /// # Returns
+    ///
+    /// `checksum`, truncated to `checksum_len` bytes if that's given.
+    fn truncate(self, checksum: Checksum) -> Checksum {
+        match self.checksum_len {
+            Some(len) if len < checksum.len() => checksum[..len].into(),
+            _ => checksum,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if files are read through io_uring, which can neither lock files, time
+    /// out on them nor limit the reads per device.
+    pub fn uses_io_uring(self) -> bool {
+        self.backend == Backend::IoUring
+            && !self.lock
+            && self.timeout.is_none()
+            && self.reads_per_device.is_none()
+    }
+}
+
+/// Options for comparing sampled blocks of huge files before reading them in full.
+///
+/// Files of the same size that already differ in one of the sampled blocks are told apart without
+/// reading the rest of them. Files whose samples match are still hashed in full. A single block
+/// samples the prefix of each file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SampleOptions {
+    /// Only files of at least this many bytes are sampled.
+    pub threshold: u64,
+
+    /// Number of blocks sampled from each file: the first and last blocks, and blocks evenly
+    /// spaced in between. With none, files are hashed in full straight away.
+    pub blocks: u64,
+
+    /// Size of each sampled block, in bytes.
+    pub block_size: u64,
+}
+
+/// Extra stage of grouping, computed from the path and size of each file: files can only be
+/// duplicates if their keys are equal, e.g. if they have the same owner, or the same first 16
+/// bytes. Files for which the key can't be computed are left out.
+#[derive(Clone)]
+pub struct GroupingKey(Arc<KeyFn>);
+
+type KeyFn = dyn Fn(&Path, u64) -> io::Result<Vec<u8>> + Send + Sync;
+
+impl GroupingKey {
+    pub fn new(key: impl Fn(&Path, u64) -> io::Result<Vec<u8>> + Send + Sync + 'static) -> Self {
+        GroupingKey(Arc::new(key))
+    }
+
+    /// # Returns
+    ///
+    /// Key of the file at `path`, which is `size` bytes long.
+    pub fn key(&self, path: &Path, size: u64) -> io::Result<Vec<u8>> {
+        (self.0)(path, size)
+    }
+
+    /// # Returns
+    ///
+    /// Key of the immediate child of one of `roots` that each file is under, so that files can
+    /// only be duplicates of others under the same one, as though each child were scanned on its
+    /// own. Files right under a root are children of their own, and of no group. Files under more
+    /// than one of `roots` are keyed by the innermost.
+    pub fn child_of_root(roots: &[PathBuf]) -> Self {
+        let roots = roots.to_vec();
+        GroupingKey::new(move |path, _| {
+            let scopes = roots.iter().enumerate().filter_map(|(i, root)| {
+                let child = path.strip_prefix(root).ok()?.components().next()?;
+                Some((root.components().count(), i, child))
+            });
+            let mut key = Vec::new();
+            match scopes.max_by_key(|&(depth, _, _)| depth) {
+                Some((_, i, child)) => {
+                    key.extend((i as u64).to_le_bytes());
+                    key.extend(child.as_os_str().as_encoded_bytes());
+                }
+                None => key.extend(path.as_os_str().as_encoded_bytes()),
+            }
+            Ok(key)
+        })
+    }
+}
+
+impl fmt::Debug for GroupingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("GroupingKey")
+    }
+}
+
+/// # Returns
+///
+/// Index of the innermost of `roots` that `path` is under, as spelled, if any.
+pub fn root_of(path: &Path, roots: &[PathBuf]) -> Option<usize> {
+    roots
+        .iter()
+        .enumerate()
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+        .map(|(i, _)| i)
+}
+
+/// Final check that files with the same checksum really are duplicates, e.g. by comparing their
+/// contents byte by byte, or by a definition of identical of its own for some format. Given the
+/// paths of two files, it returns whether they're duplicates of each other.
+#[derive(Clone)]
+pub struct Verifier(Arc<VerifyFn>);
+
+type VerifyFn = dyn Fn(&Path, &Path) -> io::Result<bool> + Send + Sync;
+
+impl Verifier {
+    pub fn new(verify: impl Fn(&Path, &Path) -> io::Result<bool> + Send + Sync + 'static) -> Self {
+        Verifier(Arc::new(verify))
+    }
+
+    /// # Returns
+    ///
+    /// Verifier that compares the contents of files byte by byte, reading them in full.
+    pub fn byte_for_byte() -> Self {
+        Verifier::new(compare_contents)
+    }
+
+    /// # Returns
+    ///
+    /// Whether the files at `a` and `b` are duplicates of each other.
+    pub fn verify(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        (self.0)(a, b)
+    }
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Verifier")
+    }
+}
+
+/// # Returns
+///
+/// Whether the files at `a` and `b` have the same contents, read until they differ.
+fn compare_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let _permit = open_files::acquire(2);
+    let (mut a, mut b) = (open_regular_file(a)?, open_regular_file(b)?);
+    let (mut a_buffer, mut b_buffer) = (vec![0u8; 64 * 1024], vec![0u8; 64 * 1024]);
+    loop {
+        let (a_len, b_len) = (fill(&mut a, &mut a_buffer)?, fill(&mut b, &mut b_buffer)?);
+        throttle(a_len + b_len);
+        if a_buffer[..a_len] != b_buffer[..b_len] {
+            return Ok(false);
+        }
+        if a_len < a_buffer.len() {
+            return Ok(true);
+        }
+    }
+}
+
+/// Read from `file` until `buffer` is full or the end of the file is reached.
+///
+/// # Returns
+///
+/// Number of bytes read.
+fn fill(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match file.read(&mut buffer[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(len)
+}
+
+/// Pass `advice` about the whole of `file` to the kernel, if the platform supports it. Failures
+/// are ignored, since the advice is only a hint.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn fadvise(file: &File, advice: libc::c_int) {
+    use std::os::unix::io::AsRawFd;
+
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice);
+    }
+}
+
+/// Number of bytes at the start of each file whose reads are hinted ahead of time, as with
+/// `HashOptions::readahead`.
+pub const PREFETCH_BYTES: u64 = 8 << 20;
+
+/// Hint to the kernel that the first `PREFETCH_BYTES` of the file at `path` will be read soon, so
+/// that it starts reading them in the background. Failures are ignored, since it's only a hint.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn prefetch(path: &Path) {
+    use std::os::unix::io::AsRawFd;
+
+    let _permit = open_files::acquire(1);
+    if let Ok(file) = open_regular_file(path) {
+        unsafe {
+            let len = PREFETCH_BYTES as libc::off_t;
+            libc::posix_fadvise(file.as_raw_fd(), 0, len, libc::POSIX_FADV_WILLNEED);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn prefetch(_path: &Path) {}
+
+/// At the start of each batch of `readahead` of `paths`, i.e. if the `i`th one starts one,
+/// prefetch the next `readahead` after it.
+fn prefetch_batch(paths: &[PathBuf], i: usize, readahead: usize) {
+    if 0 < readahead && i.is_multiple_of(readahead) {
+        paths.iter().skip(i + 1).take(readahead).for_each(|path| {
+            prefetch(path);
+        });
+    }
+}
+
+/// Take a shared advisory lock on `file`, which is released once it's closed.
+///
+/// # Returns
+///
+/// `false` if and only if another process holds an exclusive lock on the file. Filesystems that
+/// don't support locking are treated as if the file weren't locked.
+#[cfg(unix)]
+fn lock_shared(file: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) } == 0 {
+            return true;
+        }
+        match io::Error::last_os_error().kind() {
+            io::ErrorKind::Interrupted => continue,
+            io::ErrorKind::WouldBlock => return false,
+            _ => return true,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock_shared(_file: &File) -> bool {
+    true
+}
+
+/// # Returns
+///
+/// Result of `op`, or a `TimedOut` error if it doesn't finish within `timeout`.
+///
+/// With a timeout, `op` runs on a thread of its own. A read that hangs can't be interrupted, so
+/// that thread is left behind if it times out.
+fn with_timeout<T: Send + 'static>(
+    timeout: Option<Duration>,
+    op: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return op(),
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = sender.send(op());
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no response within {:?}", timeout),
+        )),
+        Err(RecvTimeoutError::Disconnected) => panic!("reading thread panicked"),
+    }
+}
+
+/// # Returns
+///
+/// Result of `read` on the file whose filepath is `path`, retried and timed out according to
+/// `options`, or `None` if it failed, in which case the error is recorded in `errors`.
+fn read_file<T: Send + 'static>(
+    path: &Path,
+    operation: Operation,
+    options: HashOptions,
+    errors: &ErrorLog,
+    read: impl Fn(&Path) -> io::Result<T> + Send + 'static,
+) -> Option<T> {
+    // Files that are gone are left to fail to be read.
+    let _permit = options.reads_per_device.and_then(|max| {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(device_reads::acquire(get_file_id(&metadata).0, max))
+    });
+    let owned_path = path.to_path_buf();
+    // Each attempt reopens the file and starts over from its beginning.
+    let result = with_timeout(options.timeout, move || {
+        options.retry.run(|| read(&owned_path))
+    });
+    match result {
+        Ok(value) => Some(value),
+        Err(error) => {
+            errors.record(ScanError {
+                path: path.to_path_buf(),
+                operation,
+                error,
+            });
+            None
+        }
+    }
+}
+
+/// # Returns
+///
+/// Checksum of the contents of the file whose filepath is `path`, or `None` if
+/// `options.lock` is set and the file is locked by another process, or if it couldn't be read, in
+/// which case the error is recorded in `errors`.
+pub fn get_checksum(path: &Path, options: HashOptions, errors: &ErrorLog) -> Option<Checksum> {
+    read_file(path, Operation::Hash, options, errors, move |path| {
+        hash_file(path, options)
+    })
+    .flatten()
+}
+
+/// # Returns
+///
+/// Error for a file that turned out not to be a regular file when it was opened.
+pub fn not_a_regular_file() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "not a regular file")
+}
+
+/// Open the file whose filepath is `path` for reading, making sure that it's a regular file.
+///
+/// Only files that were found to be regular files are ever opened, but they may have been
+/// replaced since. Opening a FIFO blocks until something writes to it, and reading a device may
+/// never end, so the file is opened without blocking and checked before it's read.
+pub(crate) fn open_regular_file(path: &Path) -> io::Result<File> {
+    let mut options = File::options();
+    options.read(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        // Has no effect on reads from regular files.
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+
+    let file = options.open(path)?;
+    if !file.metadata()?.is_file() {
+        return Err(not_a_regular_file());
+    }
+    Ok(file)
+}
+
+/// Same as `get_checksum`, except that IO errors are returned rather than retried.
+fn hash_file(path: &Path, options: HashOptions) -> io::Result<Option<Checksum>> {
+    let _permit = open_files::acquire(1);
+    let mut file = open_regular_file(path)?;
+    if options.lock && !lock_shared(&file) {
+        return Ok(None);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if options.fadvise {
+        fadvise(&file, libc::POSIX_FADV_SEQUENTIAL);
+    }
+
+    let hash = hash_contents(&mut file, options)?;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if options.fadvise {
+        fadvise(&file, libc::POSIX_FADV_DONTNEED);
+    }
+
+    Ok(Some(options.truncate(hash)))
+}
 
 /// # Returns
 ///
-/// SHA512 checksum of the contents of the file whose filepath is `path`.
-fn get_sha512_hash(path: &Path) -> impl AsRef<[u8; 64]> + Eq + Hash + Send + Sync {
-    debug_assert!(path.exists());
-    debug_assert!(path.is_file());
+/// SHA512 checksum of the contents of `file`, read from its current position.
+fn hash_contents(file: &mut File, options: HashOptions) -> io::Result<Checksum> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if options.sparse {
+        if let Some(hash) = hash_sparse_contents(file, options.new_hasher)? {
+            return Ok(hash);
+        }
+    }
+
+    let mut hasher = (options.new_hasher)();
+
+    if let Some(threshold) = options.mmap_threshold {
+        let size = file.metadata()?.len();
+        if threshold <= size {
+            if let Ok(mmap) = Mmap::map(file, size as usize) {
+                // Hashed in chunks only so that reading the mapping in can be throttled.
+                for chunk in mmap.chunks(64 * 1024) {
+                    throttle(chunk.len());
+                    hasher.update(chunk);
+                }
+                return Ok(hasher.finalize());
+            }
+        }
+    }
 
     let mut buffer = [0u8; 4096];
-    let mut file = File::open(path).unwrap();
-    let mut hasher = Sha512::new();
 
     // Read `BUFFER_SIZE` bytes from `file` at a time and feed them to `hasher`.
     loop {
-        match file.read(&mut buffer).unwrap() {
-            0 => break hasher.finalize(),
-            size => hasher.update(&buffer[..size]),
+        match file.read(&mut buffer)? {
+            0 => break Ok(hasher.finalize()),
+            size => {
+                throttle(size);
+                hasher.update(&buffer[..size]);
+            }
+        }
+    }
+}
+
+/// # Returns
+///
+/// SHA512 checksum of the contents of `file`, read from its beginning, or `None` if the file isn't
+/// sparse or its filesystem can't tell where its holes are. Only the parts of the file that hold
+/// data are read; the holes in between are hashed as the zeros they read as.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn hash_sparse_contents(file: &mut File, new_hasher: NewHasher) -> io::Result<Option<Checksum>> {
+    use std::os::unix::{fs::MetadataExt, io::AsRawFd};
+
+    static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+    // `blocks` is in 512-byte units, whatever the block size of the filesystem.
+    if size <= metadata.blocks() * 512 {
+        return Ok(None);
+    }
+
+    // # Returns
+    //
+    // Offset of the next data or hole at or after `offset`, or `None` if there's no more data.
+    let fd = file.as_raw_fd();
+    let seek = |offset: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+        match unsafe { libc::lseek(fd, offset as libc::off_t, whence) } {
+            -1 => match io::Error::last_os_error() {
+                error if error.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                error => Err(error),
+            },
+            offset => Ok(Some((offset as u64).min(size))),
+        }
+    };
+
+    let mut hasher = new_hasher();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut offset = 0;
+    while offset < size {
+        let data = match seek(offset, libc::SEEK_DATA) {
+            Ok(data) => data.unwrap_or(size),
+            Err(error) if offset == 0 && error.raw_os_error() == Some(libc::EINVAL) => {
+                return Ok(None);
+            }
+            Err(error) => return Err(error),
+        };
+        let mut zeros = data - offset;
+        while 0 < zeros {
+            let len = zeros.min(ZEROS.len() as u64);
+            hasher.update(&ZEROS[..len as usize]);
+            zeros -= len;
+        }
+        if data == size {
+            break;
         }
+
+        let hole = seek(data, libc::SEEK_HOLE)?.unwrap_or(size);
+        file.seek(SeekFrom::Start(data))?;
+        let mut extent = (&mut *file).take(hole - data);
+        loop {
+            match extent.read(&mut buffer)? {
+                0 => break,
+                len => {
+                    throttle(len);
+                    hasher.update(&buffer[..len]);
+                }
+            }
+        }
+        offset = hole;
+    }
+
+    Ok(Some(hasher.finalize()))
+}
+
+/// # Returns
+///
+/// SHA512 checksum of the blocks of the file whose filepath is `path` and whose size is `size`,
+/// sampled according to `options`.
+fn get_sampled_hash(
+    path: &Path,
+    size: u64,
+    options: SampleOptions,
+    new_hasher: NewHasher,
+) -> io::Result<Checksum> {
+    let _permit = open_files::acquire(1);
+    let mut file = open_regular_file(path)?;
+    let mut hasher = new_hasher();
+
+    let block_size = options.block_size.min(size);
+    let mut buffer = vec![0u8; block_size as usize];
+    let last_offset = (size - block_size) as u128;
+    for i in 0..options.blocks {
+        let offset = match options.blocks {
+            1 => 0,
+            blocks => (last_offset * i as u128 / (blocks - 1) as u128) as u64,
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+        throttle(buffer.len());
+        hasher.update(&buffer);
     }
+
+    Ok(hasher.finalize())
+}
+
+/// # Returns
+///
+/// Options of the tier of `tiers` that files of `size` bytes are in, i.e. the one with the greatest
+/// threshold that's at most `size`, unless it samples no blocks or there's none.
+fn sample_tier(tiers: &[SampleOptions], size: u64) -> Option<SampleOptions> {
+    tiers
+        .iter()
+        .filter(|options| options.threshold <= size)
+        .max_by_key(|options| options.threshold)
+        .filter(|options| 0 < options.blocks)
+        .copied()
 }
 
 /// # Returns
 ///
-/// Size of the file described by `entry`, in bytes.
-fn get_file_size(entry: &DirEntry) -> usize {
-    debug_assert!(entry.path().exists());
-    debug_assert!(entry.file_type().is_file());
-    debug_assert!(!entry.file_type().is_symlink());
+/// Size of the file described by `metadata`, in bytes.
+fn get_file_size(metadata: &Metadata) -> usize {
+    debug_assert!(metadata.is_file());
 
-    entry.metadata().unwrap().len() as usize
+    metadata.len() as usize
+}
+
+/// # Returns
+///
+/// `(device, inode)` pair identifying the file described by `metadata`.
+///
+/// On platforms that don't have them, every call returns an id of its own, so that no two files
+/// are ever mistaken for the same one.
+pub fn get_file_id(metadata: &Metadata) -> (u64, u64) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        (metadata.dev(), metadata.ino())
+    }
+
+    #[cfg(not(unix))]
+    {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let _ = metadata;
+        (0, NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 /// # Returns
@@ -76,8 +743,9 @@ where
 }
 
 /// Partition the sequence `items` into subsequences such that two items `a, b` will be in the same
-/// subsequence if and only if `get_key(a) == get_key(b)`.
-fn partition_by_key<Key, T, TBorrowed>(
+/// subsequence if and only if `get_key(a) == get_key(b)`. Each stage of `group_duplicate_files` is
+/// one of these, so custom stages can be composed out of them in the same way.
+pub fn partition_by_key<Key, T, TBorrowed>(
     get_key: impl Fn(&TBorrowed) -> Key + Send + Sync,
     items: impl ParallelIterator<Item = T>,
 ) -> impl ParallelIterator<Item = Vec<T>>
@@ -108,228 +776,2991 @@ where
     union.into_par_iter().map(|(_, v)| v)
 }
 
-/// # Returns
-///
-/// Sequence of `Vec<PathBuf>` where each vector contains paths to files with the same size and
-/// SHA512 checksum.
-pub fn group_duplicate_files(
-    sort: bool,
-    root: &Path,
-) -> impl ParallelIterator<Item = Vec<PathBuf>> {
-    // Grab all files under `root`, crashing if we encounter any IO errors.
-    let files = WalkDir::new(&root)
-        .follow_links(false)
-        .into_iter()
-        .par_bridge()
-        .map(walkdir::Result::unwrap)
-        .filter(|dir_entry| dir_entry.file_type().is_file());
-
-    // 1. Group `items` by size, using `get_file_size`. Discard groups with size less than 2.
-    // 2. Within each group, subgroup its items by checksum, using `get_sha512_hash`. Discard
-    //    subgroups with size less than 2.
-    // 3. Flatten, to return an iterator of subgroups.
-    partition_by_key(&get_file_size, files)
-        .filter(|p| 1 < p.len())
-        .map(|files_with_same_size| {
-            files_with_same_size
-                .into_par_iter()
-                .map(DirEntry::into_path)
-        })
-        .flat_map(|files_with_same_size| {
-            partition_by_key(&get_sha512_hash, files_with_same_size).filter(|p| 1 < p.len())
-        })
-        .map(move |mut files_with_same_hash| {
-            if sort {
-                files_with_same_hash.sort();
-            }
-            files_with_same_hash
-        })
+/// How empty files are treated. They're all duplicates of each other, so when there are many,
+/// their group dwarfs every other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyFiles {
+    /// Leave them out of the groups, recording them in `Stats::empty_files` so they can be
+    /// counted.
+    Ignore,
+
+    /// Group them like any other files.
+    #[default]
+    Report,
+
+    /// Leave them out of the groups, recording them in `Stats::empty_files` to be reported on
+    /// their own.
+    ReportSeparately,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::{
-        collections::HashSet,
-        env,
-        fmt::Display,
-        fs::{create_dir_all, read_to_string, remove_dir_all, remove_file, File},
-        io::Write,
-        path::PathBuf,
-    };
+impl FromStr for EmptyFiles {
+    type Err = String;
 
-    // Wrapper around `std::fs::File` that deletes itself when it's dropped.
-    struct TempFile {
-        file: Option<File>,
-        is_file: bool,
-        path: PathBuf,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(EmptyFiles::Ignore),
+            "report" => Ok(EmptyFiles::Report),
+            "report-separately" => Ok(EmptyFiles::ReportSeparately),
+            _ => Err(format!("unknown empty file handling: {}", s)),
+        }
     }
-    impl TempFile {
-        pub fn new(is_file: bool, path: impl Borrow<Path>) -> TempFile {
-            let mut file = None;
-            let path_borrowed = path.borrow();
-            // Delete the file/dir if it already exists, before creating a new one.
-            if is_file {
-                if path_borrowed.exists() {
-                    remove_file(path_borrowed).unwrap();
-                }
-                file = Some(File::create(path_borrowed).unwrap());
-            } else {
-                if path_borrowed.exists() {
-                    remove_dir_all(path_borrowed).unwrap();
-                }
-                create_dir_all(path_borrowed).unwrap();
-            }
+}
 
-            TempFile {
-                file,
-                is_file,
-                path: path_borrowed.to_path_buf(),
-            }
-        }
-        pub fn path<'a>(&'a self) -> &'a Path {
-            &self.path
+/// Which groups are returned, by the directories that their files are in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Spread {
+    /// Every group.
+    #[default]
+    Any,
+
+    /// Only groups with files in more than one directory, leaving out copies that sit side by
+    /// side, which are obvious anyway.
+    AcrossDirs,
+
+    /// Only groups whose files are all in the same directory, like copies of copies, leaving out
+    /// copies elsewhere in the tree, like backups.
+    SameDir,
+}
+
+impl Spread {
+    /// # Returns
+    ///
+    /// `true` if and only if `group` is to be returned.
+    fn allows(self, group: &[PathBuf]) -> bool {
+        let first_dir = group[0].parent();
+        let in_one_dir = group.iter().all(|path| path.parent() == first_dir);
+        match self {
+            Spread::Any => true,
+            Spread::AcrossDirs => !in_one_dir,
+            Spread::SameDir => in_one_dir,
+        }
+    }
+}
+
+/// Options controlling which files are compared, and how.
+#[derive(Clone, Debug, Default)]
+pub struct ScanOptions {
+    /// Sort each group of duplicate files lexicographically.
+    pub sort: bool,
+
+    /// Order that groups are sorted in, with `sort`.
+    pub collation: Collation,
+
+    /// Approximate number of bytes of memory that files found under the root may take up before
+    /// they're spilled to temporary files on disk. Once spilled, files are read back and grouped
+    /// in partitions independently of one another, so that only about one partition's worth of
+    /// files is held in memory at a time. Partitions that still take up more than this are
+    /// spilled again, unless their files all have the same size, in which case the most memory
+    /// that one took up is recorded in `stats`. Duplicate groups that have been found are still
+    /// held in memory until they're returned.
+    pub max_memory: Option<usize>,
+
+    /// If given, no more files are found once this many have been, or once finding the next one
+    /// would take the bytes they take up past `max_bytes`, so that scans of huge trees finish in
+    /// bounded time. Whether they were reached is recorded in `stats`; if so, groups are missing
+    /// the files that weren't found.
+    pub max_files: Option<u64>,
+    pub max_bytes: Option<u64>,
+
+    /// If given, only this fraction of the sizes shared by more than one file are compared,
+    /// picked at random, so that the reclaimable bytes of a huge scan can be estimated from a
+    /// fraction of the reads, with `Stats::estimate_reclaimable_bytes`. Every file of a size is
+    /// either compared or not, so that no duplicates are missed among those that are.
+    pub sample_sizes: Option<f64>,
+
+    /// Tiers of files by size, each starting at the `threshold` of its options, whose files are
+    /// first compared by the checksums of blocks sampled as given, e.g. by a prefix of the small
+    /// ones and by blocks spread across the huge ones. Files smaller than every threshold are
+    /// hashed in full straight away.
+    pub sample: Vec<SampleOptions>,
+
+    /// If given, files modified more recently than this are ignored, since they may still be in
+    /// the middle of being written.
+    pub ignore_newer_than: Option<Duration>,
+
+    /// Ignore AppleDouble files, named `._*`, in which macOS stores the metadata of files on
+    /// filesystems that can't hold it themselves. They're often identical to each other.
+    pub skip_appledouble: bool,
+
+    /// Ignore well-known junk files, named in `JUNK_FILES`.
+    pub skip_junk: bool,
+
+    /// Ignore files whose paths aren't valid unicode, such as names in the legacy encodings of
+    /// archives restored from old systems. They're compared like any other file otherwise.
+    pub skip_invalid_names: bool,
+
+    /// If given, only files owned by the user with this ID are compared. Only implemented on
+    /// Unix; elsewhere, every file is.
+    pub owner: Option<u32>,
+
+    /// Only compare files that the current user can write to.
+    pub only_writable: bool,
+
+    /// If not empty, only files whose contents are sniffed to be one of these types are compared.
+    /// Files are only sniffed once they're known to have the same size as another file.
+    pub types: Vec<ContentType>,
+
+    /// Extra stages of grouping, by which files of the same size and type are subgrouped in
+    /// order, before they're sampled or hashed.
+    pub keys: Vec<GroupingKey>,
+
+    pub empty: EmptyFiles,
+
+    /// Leave out groups whose files are all hardlinks to the same inode, since there's nothing
+    /// left to reclaim from them.
+    pub skip_hardlinked: bool,
+
+    /// Which groups are returned, by the directories that their files are in. The space that the
+    /// others take up is still recorded in `stats`.
+    pub spread: Spread,
+
+    /// If not empty, only groups with copies both under one of these roots and elsewhere are
+    /// returned, with the copies under them first: those are the copies to keep, and the rest the
+    /// ones to get rid of. Paths are compared as given, so these should be spelled as they are
+    /// among the roots, except for their case where `glob::CASE_INSENSITIVE`.
+    pub keep_in: Vec<PathBuf>,
+
+    /// Paths that must never be gotten rid of, whatever `keep_in` is: they're listed with the
+    /// copies to keep, and groups made up of nothing else are left out.
+    pub protect: Vec<PathPattern>,
+
+    /// If not empty, only groups with a copy matching each of these patterns are returned, e.g.
+    /// one under some directory, whatever their other copies are.
+    pub group_must_include: Vec<PathPattern>,
+
+    /// Groups with a copy matching any of these patterns are left out, whatever their other
+    /// copies are.
+    pub group_must_exclude: Vec<PathPattern>,
+
+    /// If given, groups of files with the same checksum are only returned once this confirms that
+    /// they're duplicates, and split up into the files that it confirms are duplicates of each
+    /// other otherwise.
+    pub verify: Option<Verifier>,
+
+    /// Kinds of documents that are grouped by their checksums with their volatile parts left out,
+    /// as computed by `Normalize::checksum`, rather than by their sizes and contents, so that saves
+    /// of the same document are duplicates. They're left out of the other steps of grouping,
+    /// including `types`, `keys` and `verify`, since their bytes differ by design. Documents of
+    /// different sizes are only compared if they're spilled to the same partition, as with
+    /// `max_memory`.
+    pub normalize: Vec<Normalize>,
+
+    /// If given, groups of files that are all clean in the index of a git working tree are
+    /// grouped by the IDs of their blobs instead of by their checksums, without reading them.
+    /// Files that aren't fall back to being hashed, along with the rest of their group.
+    pub git: Option<GitBlobs>,
+
+    pub walk: WalkOptions,
+
+    /// Count the files under the roots, and the bytes they take up, with a first walk that only
+    /// reads metadata, before they're found for real. The counts are recorded in `stats`, so that
+    /// the progress of the walk itself can be told, and the memory that the files found take up is
+    /// allocated up front. Doubles the walk on cold caches.
+    pub precount: bool,
+
+    /// Find the sizes that more than one file under the roots has with a first walk that only
+    /// reads metadata, as with `precount`, so that the files found for real can be left out
+    /// straight away unless their size is one of those, or 0, or they're documents compared as
+    /// with `normalize`, whatever their sizes. The files kept in memory are then bounded by the
+    /// number of candidates for duplicates, rather than of files. Files that are resized in
+    /// between the walks may be missed.
+    pub size_census: bool,
+
+    /// Stop once the files are grouped by size, and return every group of files of the same size
+    /// as it is, without reading any of them. The groups are only candidates for duplicates, not
+    /// confirmed ones, and `types`, `keys`, `sample`, `verify`, `normalize` and `git` don't apply.
+    /// Nothing is recorded as reclaimable for them either.
+    pub sizes_only: bool,
+
+    /// Record this many of the files that took the longest to hash in `Stats::slowest_reads`, to
+    /// find failing disks and slow mounts by. Files hashed in batches, as with
+    /// `Backend::IoUring`, aren't timed one by one, and so aren't recorded.
+    pub slowest_reads: usize,
+
+    /// Record the bytes found, and those of the duplicates, by the extension of the files' names
+    /// in `Stats::by_extension`, to break the dedupe ratio of the scan down by type of file.
+    pub by_extension: bool,
+
+    pub hash: HashOptions,
+
+    /// Where the errors because of which files were left out of the results are recorded.
+    pub errors: ErrorLog,
+
+    /// Where figures about the scan are recorded.
+    pub stats: StatsLog,
+
+    /// Where the files left out of the results for reasons other than errors are recorded, one by
+    /// one, if at all.
+    pub skips: SkipLog,
+}
+
+/// File found under the root.
+#[derive(Clone)]
+pub struct FileEntry {
+    pub path: CompactPath,
+    /// As returned by `get_file_size`.
+    pub size: usize,
+    /// As returned by `get_file_id`.
+    pub id: (u64, u64),
+}
+
+impl FileEntry {
+    fn new(path: CompactPath, metadata: &Metadata) -> FileEntry {
+        FileEntry {
+            path,
+            size: get_file_size(metadata),
+            id: get_file_id(metadata),
+        }
+    }
+}
+
+/// # Returns
+///
+/// Sequence of `Vec<PathBuf>` where each vector contains paths to files under `roots` with the
+/// same size and SHA512 checksum. Each file is only considered once, even if it can be reached
+/// through more than one of `roots`.
+pub fn group_duplicate_files(
+    options: ScanOptions,
+    roots: &[PathBuf],
+) -> impl ParallelIterator<Item = Vec<PathBuf>> {
+    group_duplicate_files_with_checksums(options, roots).map(|(_, group)| group)
+}
+
+/// # Returns
+///
+/// Same groups as `group_duplicate_files`, each with the checksum of its files' contents as
+/// computed while grouping them, shortened as by `HashOptions::checksum_len`, so that it doesn't
+/// have to be computed again. `None` for groups whose contents weren't hashed in full, e.g. those
+/// of a single inode, or those grouped by `ScanOptions::git` or `ScanOptions::normalize`.
+pub fn group_duplicate_files_with_checksums(
+    options: ScanOptions,
+    roots: &[PathBuf],
+) -> impl ParallelIterator<Item = (Option<Checksum>, Vec<PathBuf>)> {
+    let cutoff = options
+        .ignore_newer_than
+        .map(|age| SystemTime::now().checked_sub(age).unwrap_or(UNIX_EPOCH));
+
+    let skip_reason = |(path, metadata): &(CompactPath, Metadata)| {
+        if metadata.file_type().is_symlink() {
+            Some(SkipReason::Symlink)
+        } else if is_excluded(path.file_name(), &options) {
+            Some(SkipReason::ExcludedName)
+        } else if options.skip_invalid_names && !path.is_unicode() {
+            Some(SkipReason::InvalidName)
+        } else if cutoff.is_some_and(|cutoff| is_newer(metadata, cutoff)) {
+            Some(SkipReason::TooNew)
+        } else if !options.owner.is_none_or(|uid| is_owned_by(metadata, uid)) {
+            Some(SkipReason::NotOwned)
+        } else if options.only_writable && !is_writable(&path.to_path_buf()) {
+            Some(SkipReason::NotWritable)
+        } else {
+            None
+        }
+    };
+    let is_included = |file: &(CompactPath, Metadata)| skip_reason(file).is_none();
+    let collector = Collector::new(options.max_memory.unwrap_or(usize::MAX));
+
+    // Sizes of more than one file, if `options.size_census`.
+    let mut repeated_sizes = None;
+    if options.precount || options.size_census {
+        // Errors are recorded by the walk that follows.
+        let (files, bytes) = (AtomicU64::new(0), AtomicU64::new(0));
+        // (sizes seen at least once, sizes seen more than once)
+        let sizes = Mutex::new((HashSet::new(), HashSet::new()));
+        walk(
+            roots,
+            options.walk,
+            &ErrorLog::new(ErrorPolicy::Skip),
+            |found| {
+                let found: Vec<usize> = found
+                    .iter()
+                    .filter(|file| is_included(file))
+                    .map(|(_, metadata)| get_file_size(metadata))
+                    .collect();
+                files.fetch_add(found.len() as u64, Ordering::Relaxed);
+                let size: usize = found.iter().sum();
+                bytes.fetch_add(size as u64, Ordering::Relaxed);
+                if options.size_census {
+                    let (seen, repeated) = &mut *sizes.lock().unwrap();
+                    for size in found {
+                        if !seen.insert(size) {
+                            repeated.insert(size);
+                        }
+                    }
+                }
+                ControlFlow::Continue(())
+            },
+        );
+        if options.size_census {
+            repeated_sizes = Some(sizes.into_inner().unwrap().1);
+        }
+        if options.precount {
+            let (files, bytes) = (files.into_inner(), bytes.into_inner());
+            collector.reserve(files as usize);
+            options.stats.record(|stats| {
+                stats.precounted_files = Some(files);
+                stats.precounted_bytes = Some(bytes);
+            });
+        }
+    }
+
+    // Grab all files under `roots`, recording any IO errors.
+    let walk_start = Instant::now();
+    let overlaps = walk(roots, options.walk, &options.errors, |files| {
+        let symlinks = files
+            .iter()
+            .filter(|(_, metadata)| metadata.file_type().is_symlink())
+            .map(|(path, _)| path.to_path_buf());
+        let symlinks: Vec<PathBuf> = symlinks.collect();
+        if !symlinks.is_empty() {
+            options
+                .stats
+                .record(|stats| stats.symlinks.extend(symlinks));
+        }
+        let mut files: Vec<FileEntry> = files
+            .into_iter()
+            .filter(|file| match skip_reason(file) {
+                Some(reason) => {
+                    options.skips.record(reason, || [file.0.to_path_buf()]);
+                    false
+                }
+                None => true,
+            })
+            .map(|(path, metadata)| FileEntry::new(path, &metadata))
+            .collect();
+        let mut flow = ControlFlow::Continue(());
+        let mut beyond_limits = Vec::new();
+        // Files are passed a directory at a time, so they're all under the same root.
+        let root = match (roots.len(), files.first()) {
+            (2.., Some(entry)) => root_of(&entry.path.to_path_buf(), roots),
+            _ => None,
+        };
+        options.stats.record(|stats| {
+            // Files are only found until the next one would exceed either limit.
+            let within_limits = files
+                .iter()
+                .take_while(|entry| {
+                    let within = options.max_files.is_none_or(|max| stats.files_found < max)
+                        && options
+                            .max_bytes
+                            .is_none_or(|max| stats.bytes_found + entry.size as u64 <= max);
+                    if within {
+                        stats.files_found += 1;
+                        stats.bytes_found += entry.size as u64;
+                    }
+                    within
+                })
+                .count();
+            if let Some(root) = root {
+                stats.found_by_root.resize(roots.len(), (0, 0));
+                let found = &mut stats.found_by_root[root];
+                found.0 += within_limits as u64;
+                found.1 += files[..within_limits]
+                    .iter()
+                    .map(|entry| entry.size as u64)
+                    .sum::<u64>();
+            }
+            if options.by_extension {
+                for entry in &files[..within_limits] {
+                    let extension = extension_of(entry.path.file_name());
+                    stats.by_extension.entry(extension).or_default().found += entry.size as u64;
+                }
+            }
+            if within_limits < files.len() {
+                beyond_limits = files.split_off(within_limits);
+                stats.truncated = true;
+                flow = ControlFlow::Break(());
+            }
+        });
+        options.skips.record(SkipReason::Limit, || {
+            beyond_limits.iter().map(|entry| entry.path.to_path_buf())
+        });
+        if let Some(repeated_sizes) = &repeated_sizes {
+            let is_document = |entry: &FileEntry| {
+                let path = entry.path.to_path_buf();
+                options.normalize.iter().any(|kind| kind.applies_to(&path))
+            };
+            files.retain(|entry| {
+                let retained =
+                    entry.size == 0 || repeated_sizes.contains(&entry.size) || is_document(entry);
+                if !retained {
+                    let path = || [entry.path.to_path_buf()];
+                    options.skips.record(SkipReason::UniqueSize, path);
+                }
+                retained
+            });
+        }
+        collector.add(files);
+        flow
+    });
+    let walk_duration = walk_start.elapsed();
+    options.stats.record(|stats| {
+        stats.walk_duration = walk_duration;
+        stats.mount_overlaps = overlaps;
+    });
+
+    match collector.finish() {
+        Collected::InMemory(files) => Either::Left(group_files(files.into_par_iter(), options)),
+        Collected::Spilled(spill) => {
+            // Each partition holds every file of the sizes spilled to it, so partitions can be
+            // grouped one at a time.
+            let groups: Vec<(Option<Checksum>, Vec<PathBuf>)> = spill
+                .into_partitions()
+                .flat_map(|files| {
+                    let memory = spill::estimate_total_memory(&files) as u64;
+                    if options.max_memory.is_some_and(|max| max < memory as usize) {
+                        options.stats.record(|stats| {
+                            stats.partition_over_memory = stats.partition_over_memory.max(memory);
+                        });
+                    }
+                    group_files(files.into_par_iter(), options.clone()).collect::<Vec<_>>()
+                })
+                .collect();
+            Either::Right(groups.into_par_iter())
+        }
+    }
+}
+
+/// # Returns
+///
+/// Handle of a background thread that finds the same groups as `group_duplicate_files`, and the
+/// receiving end of the channel that it sends them over as they're found, so that they can be
+/// handled outside of rayon's pool. The channel is unbounded, so that the pool is never held up
+/// by a slow receiver, and closed once the scan is done. Once the receiver is dropped, the scan
+/// stops at the next group found.
+pub fn scan_channel(
+    options: ScanOptions,
+    roots: &[PathBuf],
+) -> (thread::JoinHandle<()>, mpsc::Receiver<Vec<PathBuf>>) {
+    let roots = roots.to_vec();
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let _ = group_duplicate_files(options, &roots).try_for_each(|group| sender.send(group));
+    });
+    (handle, receiver)
+}
+
+/// Names of files that operating systems and file managers litter directories with. They're tiny,
+/// often identical, and never worth deduplicating. Matched regardless of case.
+pub const JUNK_FILES: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini", ".localized"];
+
+/// # Returns
+///
+/// `true` if and only if files named `name` are ignored according to `options`.
+pub(crate) fn is_excluded(name: &OsStr, options: &ScanOptions) -> bool {
+    let name = name.to_string_lossy();
+    (options.skip_appledouble && name.starts_with("._"))
+        || (options.skip_junk
+            && JUNK_FILES
+                .iter()
+                .any(|junk| junk.eq_ignore_ascii_case(&name)))
+}
+
+/// # Returns
+///
+/// `true` if and only if the file was modified after `cutoff`. Files whose modification times
+/// aren't available on this platform are never considered newer.
+fn is_newer(metadata: &Metadata, cutoff: SystemTime) -> bool {
+    metadata.modified().is_ok_and(|modified| cutoff < modified)
+}
+
+/// # Returns
+///
+/// `true` if and only if the file described by `metadata` is owned by the user with ID `uid`.
+#[cfg(unix)]
+fn is_owned_by(metadata: &Metadata, uid: u32) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.uid() == uid
+}
+
+#[cfg(not(unix))]
+fn is_owned_by(_metadata: &Metadata, _uid: u32) -> bool {
+    true
+}
+
+/// # Returns
+///
+/// `true` if and only if the current user can write to the file whose filepath is `path`.
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    // Checks the effective user ID, which is what opening the file for writing would check.
+    let result =
+        unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) };
+    result == 0
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+/// # Returns
+///
+/// Sequence of `Vec<PathBuf>` where each vector contains paths to those of `files` with the same
+/// size and SHA512 checksum, along with that checksum, as returned by
+/// `group_duplicate_files_with_checksums`.
+fn group_files(
+    files: impl ParallelIterator<Item = FileEntry>,
+    options: ScanOptions,
+) -> impl ParallelIterator<Item = (Option<Checksum>, Vec<PathBuf>)> {
+    let ScanOptions {
+        sort,
+        collation,
+        sample: sample_options,
+        sample_sizes,
+        types,
+        keys,
+        empty,
+        skip_hardlinked,
+        spread,
+        keep_in,
+        protect,
+        group_must_include,
+        group_must_exclude,
+        verify,
+        normalize,
+        git,
+        sizes_only,
+        slowest_reads,
+        by_extension,
+        hash: hash_options,
+        errors,
+        stats,
+        skips,
+        ..
+    } = options;
+
+    // 1. Group `files` by size, using `get_file_size`. Discard groups with size less than 2, so
+    //    that files with a unique size are never opened, and empty files unless they're reported
+    //    as a group.
+    // 2. If filtering by content type, discard files whose type isn't one of `types`, using
+    //    `sniff_file`, and groups with size less than 2.
+    // 3. Subgroup the groups by each of `keys` in turn, using `GroupingKey::key`. Discard files
+    //    whose keys couldn't be computed, and subgroups with size less than 2.
+    // 4. If sampling, subgroup groups of huge files by the checksum of their sampled blocks, using
+    //    `get_sampled_hash`. Discard files that couldn't be read, and subgroups with size less
+    //    than 2.
+    // 5. Within each group, gather hardlinks to the same inode together, using `get_file_id`, so
+    //    that each inode is only hashed once.
+    // 6. Subgroup the inodes in each group by checksum, using `get_checksum`. Discard inodes
+    //    that are locked by other processes or couldn't be read, and subgroups with less than 2
+    //    paths. If given `git`, groups of inodes that all have blob IDs are subgrouped by those
+    //    instead, using `group_by_blob_id`.
+    // 7. If verifying, split each subgroup into the inodes that `verify` confirms are duplicates
+    //    of each other, using `verify_group`. Discard inodes that couldn't be compared, and
+    //    subgroups with less than 2 paths.
+    // 8. Record the space that each subgroup takes up, and how much of it is already shared
+    //    through hardlinks. If skipping hardlinked groups, discard subgroups of a single inode.
+    // 9. Flatten, to return an iterator of subgroups, filtered by `spread`, `group_must_include`
+    //    and `group_must_exclude`, and ordered and filtered by `keep_in` and `protect`.
+    // Documents that are normalized skip steps 1 to 7, and are grouped by `group_documents`
+    // instead. If `sizes_only`, groups skip steps 2 to 7 and the recording of step 8 instead.
+    // Files discarded by any step but for errors are recorded in `skips`, along with why.
+    let (documents, files) = if normalize.is_empty() || sizes_only {
+        (Vec::new(), Either::Left(files))
+    } else {
+        let (documents, files): (Vec<FileEntry>, Vec<FileEntry>) = files.partition(|entry| {
+            let path = entry.path.to_path_buf();
+            0 < entry.size && normalize.iter().any(|kind| kind.applies_to(&path))
+        });
+        (documents, Either::Right(files.into_par_iter()))
+    };
+    let documents_size: usize = documents.iter().map(|entry| entry.size).sum();
+    stats.record(|stats| stats.bytes_to_read += documents_size as u64);
+    let documents = group_documents(documents, &normalize, hash_options, &errors);
+    let verify_errors = errors.clone();
+    let empty_stats = stats.clone();
+    let size_skips = skips.clone();
+    let files_with_same_size =
+        partition_by_key(|entry: &FileEntry| entry.size, files).filter(move |p| {
+            let paths = || p.iter().map(|entry| entry.path.to_path_buf());
+            if p[0].size == 0 && empty != EmptyFiles::Report {
+                if empty == EmptyFiles::Ignore {
+                    size_skips.record(SkipReason::Empty, paths);
+                }
+                empty_stats.record(|stats| stats.empty_files.extend(paths()));
+                return false;
+            }
+            if p.len() < 2 {
+                size_skips.record(SkipReason::UniqueSize, paths);
+            }
+            1 < p.len()
+        });
+    // Collected, to know how much there is to read before any of it is.
+    let mut files_with_same_size: Vec<Vec<FileEntry>> = files_with_same_size.collect();
+    if let Some(fraction) = sample_sizes {
+        let state = RandomState::new();
+        files_with_same_size.retain(|files| {
+            let hash = state.hash_one(files[0].size) as f64;
+            let sampled = 1.0 <= fraction || hash < fraction * u64::MAX as f64;
+            if !sampled {
+                let paths = || files.iter().map(|entry| entry.path.to_path_buf());
+                skips.record(SkipReason::UnsampledSize, paths);
+            }
+            sampled
+        });
+    }
+    let candidates: Vec<Vec<Links>> = if sizes_only {
+        let candidates = std::mem::take(&mut files_with_same_size).into_par_iter();
+        candidates
+            .map(|files| group_links(files.into_par_iter()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let bytes_to_read: usize = files_with_same_size
+        .iter()
+        .map(|files| files.len() * files[0].size)
+        .sum();
+    stats.record(|stats| stats.bytes_to_read += bytes_to_read as u64);
+    let files_with_same_size = files_with_same_size.into_par_iter();
+    let sniff_errors = errors.clone();
+    let sniff_skips = skips.clone();
+    let files_of_types = files_with_same_size.filter_map(move |files_with_same_size| {
+        if types.is_empty() {
+            return Some(files_with_same_size);
+        }
+        let files_of_types: Vec<FileEntry> = files_with_same_size
+            .into_par_iter()
+            .filter(|entry| {
+                let path = entry.path.to_path_buf();
+                let content_type = read_file(
+                    &path,
+                    Operation::Sniff,
+                    hash_options,
+                    &sniff_errors,
+                    sniff_file,
+                );
+                let of_types = match content_type {
+                    // Errors are recorded as such.
+                    None => return false,
+                    Some(content_type) => content_type.is_some_and(|t| types.contains(&t)),
+                };
+                if !of_types {
+                    sniff_skips.record(SkipReason::ContentType, || [path]);
+                }
+                of_types
+            })
+            .collect();
+        if files_of_types.len() < 2 {
+            let paths = || files_of_types.iter().map(|entry| entry.path.to_path_buf());
+            sniff_skips.record(SkipReason::ContentType, paths);
+        }
+        (1 < files_of_types.len()).then_some(files_of_types)
+    });
+    let key_skips = skips.clone();
+    let key_errors = errors.clone();
+    let files_with_same_keys = files_of_types.flat_map(move |files_of_types| {
+        let size = files_of_types[0].size as u64;
+        let mut groups = vec![files_of_types];
+        for key in &keys {
+            groups = groups
+                .into_par_iter()
+                .flat_map(|group| {
+                    let entries_with_keys = group.into_par_iter().filter_map(|entry| {
+                        let path = entry.path.to_path_buf();
+                        let key = key.clone();
+                        let read = move |path: &Path| key.key(path, size);
+                        read_file(&path, Operation::Key, hash_options, &key_errors, read)
+                            .map(|key| (key, entry))
+                    });
+                    partition_by_key(|(key, _)| key.clone(), entries_with_keys)
+                        .filter(|p| {
+                            let paths = || p.iter().map(|(_, entry)| entry.path.to_path_buf());
+                            if p.len() < 2 {
+                                key_skips.record(SkipReason::UniqueKey, paths);
+                            }
+                            1 < p.len()
+                        })
+                        .map(|group| group.into_iter().map(|(_, entry)| entry).collect())
+                })
+                .collect();
+        }
+        groups
+    });
+    let sample_errors = errors.clone();
+    let sample_skips = skips.clone();
+    let files_with_same_samples = files_with_same_keys.flat_map(move |files_with_same_size| {
+        let size = files_with_same_size[0].size as u64;
+        match sample_tier(&sample_options, size) {
+            Some(options) => {
+                let errors = sample_errors.clone();
+                let entries_with_hashes =
+                    files_with_same_size
+                        .into_par_iter()
+                        .filter_map(move |entry: FileEntry| {
+                            let path = entry.path.to_path_buf();
+                            let new_hasher = hash_options.new_hasher;
+                            let read = move |path: &Path| {
+                                get_sampled_hash(path, size, options, new_hasher)
+                            };
+                            read_file(&path, Operation::Sample, hash_options, &errors, read)
+                                .map(|hash| (hash, entry))
+                        });
+                let skips = sample_skips.clone();
+                Either::Left(
+                    partition_by_key(|(hash, _)| hash.clone(), entries_with_hashes)
+                        .filter(move |p| {
+                            let paths = || p.iter().map(|(_, entry)| entry.path.to_path_buf());
+                            if p.len() < 2 {
+                                skips.record(SkipReason::UniqueSample, paths);
+                            }
+                            1 < p.len()
+                        })
+                        .map(|group| group.into_iter().map(|(_, entry)| entry).collect()),
+                )
+            }
+            _ => Either::Right(rayon::iter::once(files_with_same_size)),
+        }
+    });
+    let links_with_same_samples =
+        files_with_same_samples.map(|files| group_links(files.into_par_iter()));
+    let (links_with_same_blob_id, links_with_same_samples) = match &git {
+        Some(git) => {
+            let (known, unknown): (Vec<Vec<Vec<Links>>>, Vec<Vec<Links>>) = links_with_same_samples
+                .partition_map(|links| match group_by_blob_id(links, git) {
+                    Ok(groups) => Either::Left(groups),
+                    Err(links) => Either::Right(links),
+                });
+            let known: Vec<Vec<Links>> = known.into_iter().flatten().collect();
+            (known, Either::Left(unknown.into_par_iter()))
+        }
+        None => (Vec::new(), Either::Right(links_with_same_samples)),
+    };
+    let hasher = TimedHasher {
+        options: hash_options,
+        errors,
+        stats: stats.clone(),
+        slowest: slowest_reads,
+    };
+    let links_with_same_hash = match hash_options.hdd_mode {
+        HddMode::Never => Either::Left(group_by_checksum(
+            links_with_same_samples,
+            hasher,
+            skips.clone(),
+        )),
+        _ => Either::Right(group_by_checksum_in_physical_order(
+            links_with_same_samples,
+            hasher,
+            skips.clone(),
+        )),
+    };
+
+    let links_with_same_hash = links_with_same_hash
+        .chain(
+            links_with_same_blob_id
+                .into_par_iter()
+                .map(|links| (None, links)),
+        )
+        .flat_map(move |(checksum, links_with_same_hash)| match &verify {
+            Some(verifier) => {
+                verify_group(links_with_same_hash, verifier, hash_options, &verify_errors)
+                    .into_iter()
+                    .map(|links| (checksum.clone(), links))
+                    .collect()
+            }
+            None => vec![(checksum, links_with_same_hash)],
+        });
+
+    let hardlinked_skips = skips.clone();
+    let links_with_same_hash = links_with_same_hash
+        .chain(documents.into_par_iter().map(|links| (None, links)))
+        .inspect(move |(_, links)| {
+            stats.record(|stats| {
+                record_group(stats, links, by_extension);
+                if sample_sizes.is_some() {
+                    let reclaimable_bytes = (links.len() as u64 - 1) * links[0].size as u64;
+                    *stats
+                        .reclaimable_by_size
+                        .entry(links[0].size as u64)
+                        .or_default() += reclaimable_bytes;
+                }
+            })
+        })
+        .chain(candidates.into_par_iter().map(|links| (None, links)))
+        .filter(move |(_, links)| {
+            let kept = !skip_hardlinked || 1 < links.len();
+            if !kept {
+                let paths = links.iter().flat_map(|links| &links.paths);
+                hardlinked_skips.record(SkipReason::Hardlinked, || {
+                    paths.map(CompactPath::to_path_buf)
+                });
+            }
+            kept
+        });
+
+    links_with_same_hash.filter_map(move |(checksum, links_with_same_hash)| {
+        let mut files_with_same_hash: Vec<PathBuf> = links_with_same_hash
+            .into_iter()
+            .flat_map(|links| links.paths)
+            .map(|path| path.to_path_buf())
+            .collect();
+        if !spread.allows(&files_with_same_hash) {
+            skips.record(SkipReason::Spread, || files_with_same_hash);
+            return None;
+        }
+        let matched = |pattern: &PathPattern| {
+            files_with_same_hash
+                .iter()
+                .any(|path| pattern.matches(path))
+        };
+        if !group_must_include.iter().all(matched) || group_must_exclude.iter().any(matched) {
+            skips.record(SkipReason::GroupFiltered, || files_with_same_hash);
+            return None;
+        }
+        if sort {
+            collation.sort(&mut files_with_same_hash);
+        }
+        if keep_in.is_empty() && protect.is_empty() {
+            return Some((checksum, files_with_same_hash));
+        }
+        let group = skips.is_recording().then(|| files_with_same_hash.clone());
+        let kept_first = order_kept_first(files_with_same_hash, &keep_in, &protect);
+        if kept_first.is_none() {
+            skips.record(SkipReason::Kept, || group.unwrap());
+        }
+        kept_first.map(|group| (checksum, group))
+    })
+}
+
+/// # Returns
+///
+/// `files` gathered by the inodes that they're hardlinks to.
+fn group_links(files: impl ParallelIterator<Item = FileEntry>) -> Vec<Links> {
+    partition_by_key(|entry: &FileEntry| entry.id, files)
+        .map(|links| Links {
+            id: links[0].id,
+            size: links[0].size,
+            paths: links.into_iter().map(|entry| entry.path).collect(),
+        })
+        .collect()
+}
+
+/// # Returns
+///
+/// Subgroups of `group` by the IDs of the blobs that `git` has for its inodes, leaving out those
+/// with less than 2 paths. `group` itself if any of them has none, or if it's a single inode,
+/// which is identical without being read anyway.
+fn group_by_blob_id(group: Vec<Links>, git: &GitBlobs) -> Result<Vec<Vec<Links>>, Vec<Links>> {
+    if group.len() == 1 {
+        return Err(group);
+    }
+    let blob_ids: Option<Vec<Box<[u8]>>> = group
+        .iter()
+        .map(|links| git.blob_id(&links.path()))
+        .collect();
+    let blob_ids = match blob_ids {
+        Some(blob_ids) => blob_ids,
+        None => return Err(group),
+    };
+    let mut subgroups: HashMap<Box<[u8]>, Vec<Links>> = HashMap::new();
+    for (blob_id, links) in blob_ids.into_iter().zip(group) {
+        subgroups.entry(blob_id).or_default().push(links);
+    }
+    let subgroups = subgroups.into_values();
+    Ok(subgroups.filter(|group| 1 < count_paths(group)).collect())
+}
+
+/// # Returns
+///
+/// Groups of the inodes of `documents` with the same checksum as computed by the first of
+/// `normalize` that applies to them, leaving out those with less than 2 paths. Documents that
+/// couldn't be read are left out, and the errors are recorded in `errors`.
+fn group_documents(
+    documents: Vec<FileEntry>,
+    normalize: &[Normalize],
+    hash_options: HashOptions,
+    errors: &ErrorLog,
+) -> Vec<Vec<Links>> {
+    let links =
+        partition_by_key(|entry: &FileEntry| entry.id, documents.into_par_iter()).map(|links| {
+            Links {
+                id: links[0].id,
+                size: links[0].size,
+                paths: links.into_iter().map(|entry| entry.path).collect(),
+            }
+        });
+    let links_with_checksums = links.filter_map(|links| {
+        let path = links.path();
+        let kind = *normalize.iter().find(|kind| kind.applies_to(&path))?;
+        let new_hasher = hash_options.new_hasher;
+        let read = move |path: &Path| kind.checksum(path, new_hasher);
+        read_file(&path, Operation::Hash, hash_options, errors, read)
+            .map(|checksum| ((kind, checksum), links))
+    });
+    partition_by_key(|(key, _)| key.clone(), links_with_checksums)
+        .map(|group| {
+            group
+                .into_iter()
+                .map(|(_, links)| links)
+                .collect::<Vec<Links>>()
+        })
+        .filter(|group| 1 < count_paths(group))
+        .collect()
+}
+
+/// # Returns
+///
+/// `group` split into the subgroups of inodes that `verifier` confirms are duplicates of the first
+/// inode of their subgroup, leaving out those with less than 2 paths. Comparisons that fail count
+/// as mismatches, so that inodes that can't be compared to any other are left out, and the errors
+/// are recorded in `errors`.
+fn verify_group(
+    group: Vec<Links>,
+    verifier: &Verifier,
+    hash_options: HashOptions,
+    errors: &ErrorLog,
+) -> Vec<Vec<Links>> {
+    // Links to a single inode are identical without having to be compared.
+    if group.len() == 1 {
+        return vec![group];
+    }
+    let mut subgroups: Vec<Vec<Links>> = Vec::new();
+    'links: for links in group {
+        for subgroup in &mut subgroups {
+            let (first, verifier) = (subgroup[0].path(), verifier.clone());
+            let verify = move |path: &Path| verifier.verify(&first, path);
+            let path = links.path();
+            if read_file(&path, Operation::Verify, hash_options, errors, verify) == Some(true) {
+                subgroup.push(links);
+                continue 'links;
+            }
+        }
+        subgroups.push(vec![links]);
+    }
+    subgroups.retain(|subgroup| {
+        1 < subgroup
+            .iter()
+            .map(|links| links.paths.len())
+            .sum::<usize>()
+    });
+    subgroups
+}
+
+/// # Returns
+///
+/// `group`, with the paths to keep, i.e. those that are under one of `keep_in` or match one of
+/// `protect`, moved before the others. `None` if there are no others, or if `keep_in` isn't empty
+/// but none of the paths are under it.
+fn order_kept_first(
+    group: Vec<PathBuf>,
+    keep_in: &[PathBuf],
+    protect: &[PathPattern],
+) -> Option<Vec<PathBuf>> {
+    let is_under_keep_in =
+        |path: &PathBuf| keep_in.iter().any(|root| glob::starts_with(path, root));
+    let (mut kept, others): (Vec<PathBuf>, Vec<PathBuf>) = group
+        .into_iter()
+        .partition(|path| is_kept(path, keep_in, protect));
+    if others.is_empty() || (!keep_in.is_empty() && !kept.iter().any(is_under_keep_in)) {
+        return None;
+    }
+    kept.extend(others);
+    Some(kept)
+}
+
+/// # Returns
+///
+/// Number of paths at the start of `group` that are kept, as ordered by `order_kept_first`, i.e.
+/// those that are under one of `keep_in` or match one of `protect`.
+pub fn count_kept(group: &[PathBuf], keep_in: &[PathBuf], protect: &[PathPattern]) -> usize {
+    group
+        .iter()
+        .take_while(|path| is_kept(path, keep_in, protect))
+        .count()
+}
+
+/// # Returns
+///
+/// `true` if and only if `path` is under one of `keep_in` or matches one of `protect`.
+fn is_kept(path: &Path, keep_in: &[PathBuf], protect: &[PathPattern]) -> bool {
+    keep_in.iter().any(|root| glob::starts_with(path, root))
+        || protect.iter().any(|pattern| pattern.matches(path))
+}
+
+/// Paths to files that are hardlinks to the same inode, and therefore have the same contents.
+struct Links {
+    /// `(device, inode)` pair shared by the files, as returned by `get_file_id`.
+    id: (u64, u64),
+    /// Size of the files, in bytes.
+    size: usize,
+    paths: Vec<CompactPath>,
+}
+
+impl Links {
+    /// # Returns
+    ///
+    /// Path to read the files' contents from.
+    fn path(&self) -> PathBuf {
+        self.paths[0].to_path_buf()
+    }
+}
+
+/// Add the space taken up by the duplicates in `group` to `stats`, and to
+/// `Stats::by_extension` too if `by_extension`.
+fn record_group(stats: &mut Stats, group: &[Links], by_extension: bool) {
+    for links in group {
+        stats.linked_bytes += (links.paths.len() as u64 - 1) * links.size as u64;
+    }
+    let reclaimable_bytes = (group.len() as u64 - 1) * group[0].size as u64;
+    stats.reclaimable_bytes += reclaimable_bytes;
+    let bucket = stats.bucket_mut(group[0].size as u64);
+    bucket.groups += 1;
+    bucket.reclaimable_bytes += reclaimable_bytes;
+    if by_extension {
+        for (i, links) in group.iter().enumerate() {
+            for (j, path) in links.paths.iter().enumerate() {
+                let bytes = stats
+                    .by_extension
+                    .entry(extension_of(path.file_name()))
+                    .or_default();
+                match (i, j) {
+                    (0, 0) => {}
+                    (_, 0) => bytes.reclaimable += links.size as u64,
+                    _ => bytes.linked += links.size as u64,
+                }
+            }
+        }
+    }
+}
+
+/// # Returns
+///
+/// Extension of `name`, lowercased, or "" if it has none, as keyed in `Stats::by_extension`.
+fn extension_of(name: &OsStr) -> String {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// # Returns
+///
+/// Total number of paths in `group`.
+fn count_paths(group: &[Links]) -> usize {
+    group.iter().map(|links| links.paths.len()).sum()
+}
+
+/// # Returns
+///
+/// Subgroups of each of `groups`, such that each subgroup contains links to inodes with the same
+/// SHA512 checksum, along with that checksum, or `None` for groups of a single inode, which aren't
+/// read. Subgroups with less than 2 paths are discarded.
+fn group_by_checksum(
+    groups: impl ParallelIterator<Item = Vec<Links>>,
+    hasher: TimedHasher,
+    skips: SkipLog,
+) -> impl ParallelIterator<Item = (Option<Checksum>, Vec<Links>)> {
+    let hash_options = hasher.options;
+    groups
+        .flat_map(move |links_with_same_size| {
+            // Links to a single inode are identical without having to be read.
+            if links_with_same_size.len() == 1 {
+                return Either::Left(rayon::iter::once((None, links_with_same_size)));
+            }
+
+            let hasher = hasher.clone();
+            let links_with_checksums = if hash_options.uses_io_uring() {
+                // The whole group is hashed as one batch on the current thread. Files are only ever
+                // skipped for being locked when locking, so the fallback always returns a checksum.
+                let paths: Vec<PathBuf> = links_with_same_size.iter().map(Links::path).collect();
+                let checksums = uring::hash_files(&paths, hash_options.new_hasher, |path| {
+                    let hash = hash_options.retry.run(|| hash_file(path, hash_options));
+                    hash.map(Option::unwrap)
+                });
+                let links_with_checksums = checksums
+                    .into_iter()
+                    .zip(links_with_same_size)
+                    .filter_map(|(checksum, links)| match checksum {
+                        Ok(checksum) => Some((hash_options.truncate(checksum), links)),
+                        Err(error) => {
+                            hasher.errors.record(ScanError {
+                                path: links.path(),
+                                operation: Operation::Hash,
+                                error,
+                            });
+                            None
+                        }
+                    });
+                Either::Left(links_with_checksums.collect::<Vec<_>>().into_par_iter())
+            } else {
+                let paths = prefetched_paths(&links_with_same_size, hash_options);
+                Either::Right(links_with_same_size.into_par_iter().enumerate().filter_map(
+                    move |(i, links)| {
+                        prefetch_batch(&paths, i, hash_options.readahead);
+                        let hash = hasher.checksum(&links)?;
+                        Some((hash, links))
+                    },
+                ))
+            };
+            Either::Right(
+                partition_by_key(|(checksum, _)| checksum.clone(), links_with_checksums).map(
+                    |group| {
+                        let checksum = group[0].0.clone();
+                        let links: Vec<Links> = group.into_iter().map(|(_, links)| links).collect();
+                        (Some(checksum), links)
+                    },
+                ),
+            )
+        })
+        .filter(move |(_, group)| has_other_paths(group, SkipReason::UniqueChecksum, &skips))
+}
+
+/// Hashes inodes as `get_checksum` does, recording the `slowest` of them in `stats`.
+#[derive(Clone)]
+struct TimedHasher {
+    options: HashOptions,
+    errors: ErrorLog,
+    stats: StatsLog,
+    slowest: usize,
+}
+
+impl TimedHasher {
+    fn checksum(&self, links: &Links) -> Option<Checksum> {
+        let path = links.path();
+        let start = Instant::now();
+        let checksum = get_checksum(&path, self.options, &self.errors)?;
+        if 0 < self.slowest {
+            let duration = start.elapsed();
+            self.stats.record(|stats| {
+                stats.record_read(|| path, links.size as u64, duration, self.slowest)
+            });
+        }
+        Some(checksum)
+    }
+}
+
+/// # Returns
+///
+/// `true` if and only if `group` has more than one path, or else records its path in `skips` as
+/// left out for `reason`.
+fn has_other_paths(group: &[Links], reason: SkipReason, skips: &SkipLog) -> bool {
+    let has_other_paths = 1 < count_paths(group);
+    if !has_other_paths {
+        let paths = group.iter().flat_map(|links| &links.paths);
+        skips.record(reason, || paths.map(CompactPath::to_path_buf));
+    }
+    has_other_paths
+}
+
+/// # Returns
+///
+/// Paths of `links` in order, to prefetch with `prefetch_batch`, or none unless reading ahead.
+fn prefetched_paths<'a>(
+    links: impl IntoIterator<Item = &'a Links>,
+    hash_options: HashOptions,
+) -> Vec<PathBuf> {
+    match hash_options.readahead {
+        0 => Vec::new(),
+        _ => links.into_iter().map(Links::path).collect(),
+    }
+}
+
+/// Same as `group_by_checksum`, except that the inodes on each device that
+/// `hash_options.hdd_mode` applies to are hashed one at a time, in inode order.
+fn group_by_checksum_in_physical_order(
+    groups: impl ParallelIterator<Item = Vec<Links>>,
+    hasher: TimedHasher,
+    skips: SkipLog,
+) -> impl ParallelIterator<Item = (Option<Checksum>, Vec<Links>)> {
+    let hash_options = hasher.options;
+    let get_hash = move |links: &Links| hasher.checksum(links);
+
+    // Links to a single inode are identical without having to be read.
+    let (single_inode, multiple_inodes): (Vec<Vec<Links>>, Vec<Vec<Links>>) =
+        groups.partition(|group| group.len() == 1);
+    // Each inode is tagged with the index of its group, which it's regrouped within once hashed.
+    let (sequential, parallel): (Vec<_>, Vec<_>) = multiple_inodes
+        .into_par_iter()
+        .enumerate()
+        .flat_map_iter(|(i, group)| group.into_iter().map(move |links| (i, links)))
+        .partition(|(_, links)| hash_options.hdd_mode.applies_to(links.id.0));
+
+    // Devices are read in parallel, but the inodes on each device are read sequentially.
+    let get_hash_sequentially = get_hash.clone();
+    let hashed_sequentially = partition_by_key(
+        |(_, links): &(usize, Links)| links.id.0,
+        sequential.into_par_iter(),
+    )
+    .flat_map_iter(move |mut links_on_device| {
+        let get_hash = get_hash_sequentially.clone();
+        links_on_device.sort_by_key(|(_, links)| links.id.1);
+        let paths = prefetched_paths(links_on_device.iter().map(|(_, links)| links), hash_options);
+        links_on_device
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(i, (group, links))| {
+                prefetch_batch(&paths, i, hash_options.readahead);
+                Some(((group, links.size, get_hash(&links)?), links))
+            })
+    });
+    let paths = prefetched_paths(parallel.iter().map(|(_, links)| links), hash_options);
+    let hashed_in_parallel =
+        parallel
+            .into_par_iter()
+            .enumerate()
+            .filter_map(move |(i, (group, links))| {
+                prefetch_batch(&paths, i, hash_options.readahead);
+                Some(((group, links.size, get_hash(&links)?), links))
+            });
+
+    // Key by the group as well as size and checksum, so that inodes are only ever grouped with
+    // inodes from the same incoming group, as in `group_by_checksum`, which keeps those split by
+    // `ScanOptions::keys` apart.
+    let links_with_same_hash = partition_by_key(
+        |(key, _): &((usize, usize, Checksum), Links)| key.clone(),
+        hashed_sequentially.chain(hashed_in_parallel),
+    )
+    .map(|group| {
+        let (_, _, checksum) = group[0].0.clone();
+        let links: Vec<Links> = group.into_iter().map(|(_, links)| links).collect();
+        (Some(checksum), links)
+    })
+    .filter(move |(_, group)| has_other_paths(group, SkipReason::UniqueChecksum, &skips));
+
+    single_inode
+        .into_par_iter()
+        .map(|links| (None, links))
+        .chain(links_with_same_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hasher::ContentHasher,
+        stats::{Bucket, ByteCounts},
+    };
+    use sha2::{Digest, Sha512};
+    use std::{
+        collections::{HashMap, HashSet},
+        env,
+        fmt::Display,
+        fs::{create_dir_all, read_to_string, remove_dir_all, remove_file, File},
+        io::Write,
+        path::PathBuf,
+    };
+
+    // Wrapper around `std::fs::File` that deletes itself when it's dropped.
+    struct TempFile {
+        file: Option<File>,
+        is_file: bool,
+        path: PathBuf,
+    }
+    impl TempFile {
+        pub fn new(is_file: bool, path: impl Borrow<Path>) -> TempFile {
+            let mut file = None;
+            let path_borrowed = path.borrow();
+            // Delete the file/dir if it already exists, before creating a new one.
+            if is_file {
+                if path_borrowed.exists() {
+                    remove_file(path_borrowed).unwrap();
+                }
+                file = Some(File::create(path_borrowed).unwrap());
+            } else {
+                if path_borrowed.exists() {
+                    remove_dir_all(path_borrowed).unwrap();
+                }
+                create_dir_all(path_borrowed).unwrap();
+            }
+
+            TempFile {
+                file,
+                is_file,
+                path: path_borrowed.to_path_buf(),
+            }
+        }
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+        pub fn file(&self) -> &File {
+            self.file.as_ref().unwrap()
+        }
+    }
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            if self.is_file {
+                remove_file(self.path()).unwrap();
+            } else {
+                remove_dir_all(self.path()).unwrap();
+            }
+        }
+    }
+
+    // # Returns
+    //
+    // Path to some newly created tempfile in some OS-managed tempdir. The basename of this file
+    // will be prefixed with `prefix` and its content equal to `content`.
+    //
+    // Whether or not the resulting path is unique depends on the prefix.
+    fn mktemp(prefix: impl Borrow<str>, content: impl Display) -> TempFile {
+        // construct a path to a temporary file
+        let path = env::temp_dir().as_path().join(prefix.borrow());
+        let tempfile = TempFile::new(true /* is_file */, path);
+
+        // write `content` to the file
+        write!(tempfile.file(), "{}", content).unwrap();
+        tempfile
+    }
+
+    fn test_data() -> Vec<String> {
+        vec![
+            " ",
+            " 12p oka0sd k\n rn12w\r\r\n \t asof AWSDJO !@# @$ ",
+            "",
+            "12asdopjkzx",
+            "2",
+            "QxmPzHlMLisNDJm3LKT5LRoTyU9Z06ze",
+            "\n",
+            "\r",
+            "\r\n",
+            "\t",
+            "g",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect()
+    }
+
+    // Check that `get_checksum` returns a unique checksum for each test data.
+    #[test]
+    fn test_get_checksum() {
+        let sums: HashSet<Vec<u8>> = test_data()
+            .into_iter()
+            .enumerate()
+            .map(|(index, content)| {
+                let prefix = format!("{}_{}_{}_{}", module_path!(), line!(), column!(), index);
+                let tempfile = mktemp(prefix, &content);
+                assert_eq!(content, read_to_string(tempfile.path()).unwrap());
+
+                // pseudo check that the function is deterministic, and that reading and mapping
+                // the file produce the same checksum
+                let sums: HashSet<Vec<u8>> = [None, Some(0), None, Some(0)]
+                    .iter()
+                    .map(|&mmap_threshold| HashOptions {
+                        mmap_threshold,
+                        fadvise: true,
+                        ..HashOptions::default()
+                    })
+                    .map(|options| {
+                        get_checksum(tempfile.path(), options, &ErrorLog::default())
+                            .unwrap()
+                            .to_vec()
+                    })
+                    .collect();
+                assert_eq!(1, sums.len());
+                sums.into_iter().next().unwrap()
+            })
+            .collect();
+
+        // # of Checksum512s == # of input data
+        assert_eq!(test_data().len(), sums.len());
+    }
+
+    // Check that files locked by other processes are only skipped when locking.
+    #[cfg(unix)]
+    #[test]
+    fn test_get_checksum_locked() {
+        use std::os::unix::io::AsRawFd;
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let tempfile = mktemp(prefix, "some content");
+        let lock = HashOptions {
+            lock: true,
+            ..HashOptions::default()
+        };
+        assert!(get_checksum(tempfile.path(), lock, &ErrorLog::default()).is_some());
+
+        // locks belong to open files, so this conflicts with the shared lock even in one process
+        let writer = File::options().write(true).open(tempfile.path()).unwrap();
+        assert_eq!(0, unsafe {
+            libc::flock(writer.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+        });
+        assert!(get_checksum(tempfile.path(), lock, &ErrorLog::default()).is_none());
+        assert!(get_checksum(
+            tempfile.path(),
+            HashOptions::default(),
+            &ErrorLog::default()
+        )
+        .is_some());
+
+        drop(writer);
+        assert!(get_checksum(tempfile.path(), lock, &ErrorLog::default()).is_some());
+    }
+
+    // Check that files are hashed with the hasher they're configured with, however they're read.
+    #[test]
+    fn test_get_checksum_custom_hasher() {
+        // Checksum of the number of bytes hashed, so that contents of the same size collide.
+        struct CountingHasher(u64);
+        impl ContentHasher for CountingHasher {
+            fn update(&mut self, bytes: &[u8]) {
+                self.0 += bytes.len() as u64;
+            }
+            fn finalize(self: Box<Self>) -> Checksum {
+                Box::from(&self.0.to_le_bytes()[..])
+            }
+        }
+        fn counting_hasher() -> Box<dyn ContentHasher> {
+            Box::new(CountingHasher(0))
+        }
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let (a, b) = (
+            mktemp(prefix.clone() + "a", "abc"),
+            mktemp(prefix + "b", "xyz"),
+        );
+        for options in [
+            HashOptions::default(),
+            HashOptions {
+                mmap_threshold: Some(0),
+                ..HashOptions::default()
+            },
+            HashOptions {
+                sparse: true,
+                ..HashOptions::default()
+            },
+        ] {
+            let checksum = |path, new_hasher| {
+                let options = HashOptions {
+                    new_hasher,
+                    ..options
+                };
+                get_checksum(path, options, &ErrorLog::default()).unwrap()
+            };
+            assert_ne!(checksum(a.path(), sha512), checksum(b.path(), sha512));
+            assert_eq!(
+                &3u64.to_le_bytes()[..],
+                &*checksum(a.path(), counting_hasher)
+            );
+            assert_eq!(
+                checksum(a.path(), counting_hasher),
+                checksum(b.path(), counting_hasher)
+            );
+        }
+    }
+
+    // Check that operations that take too long time out, and that others aren't affected.
+    #[test]
+    fn test_with_timeout() {
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(1, with_timeout(None, || Ok(1)).unwrap());
+        assert_eq!(1, with_timeout(timeout, || Ok(1)).unwrap());
+        let not_found = with_timeout(timeout, || -> io::Result<()> {
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert_eq!(io::ErrorKind::NotFound, not_found.unwrap_err().kind());
+
+        let hung = with_timeout(timeout, || {
+            thread::sleep(Duration::from_secs(1));
+            Ok(())
+        });
+        assert_eq!(io::ErrorKind::TimedOut, hung.unwrap_err().kind());
+    }
+
+    // Check that special files are refused rather than read, without blocking.
+    #[cfg(unix)]
+    #[test]
+    fn test_special_files() {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let fifo = TempFile::new(true /* is_file */, env::temp_dir().join(prefix));
+        let _ = remove_file(fifo.path());
+        let fifo_path = CString::new(fifo.path().as_os_str().as_bytes()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(fifo_path.as_ptr(), 0o600) });
+
+        let paths = [fifo.path().to_path_buf(), PathBuf::from("/dev/zero")];
+        for path in &paths {
+            let error = hash_file(path, HashOptions::default()).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+            let error = get_sampled_hash(
+                path,
+                1,
+                SampleOptions {
+                    threshold: 0,
+                    blocks: 1,
+                    block_size: 1,
+                },
+                sha512,
+            );
+            assert_eq!(io::ErrorKind::InvalidInput, error.unwrap_err().kind());
+        }
+        let checksums = uring::hash_files(&paths, sha512, |_| panic!("fell back"));
+        for checksum in checksums {
+            assert_eq!(io::ErrorKind::InvalidInput, checksum.unwrap_err().kind());
+        }
+    }
+
+    // Check that sparse files hash the same as dense files with the same contents.
+    #[test]
+    fn test_hash_sparse_contents() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let size = 1024 * 1024;
+        let mut contents = vec![0u8; size];
+        let sparse = mktemp(format!("{}_sparse", prefix), "");
+        let dense = mktemp(format!("{}_dense", prefix), "");
+        sparse.file().set_len(size as u64).unwrap();
+        for offset in [4096, 300 * 1024, size - 3] {
+            contents[offset..offset + 3].copy_from_slice(b"abc");
+            let mut file = sparse.file();
+            file.seek(SeekFrom::Start(offset as u64)).unwrap();
+            file.write_all(b"abc").unwrap();
+        }
+        dense.file().write_all(&contents).unwrap();
+
+        let sparse_options = HashOptions {
+            sparse: true,
+            ..HashOptions::default()
+        };
+        let sums: HashSet<Vec<u8>> = [
+            (sparse.path(), sparse_options),
+            (sparse.path(), HashOptions::default()),
+            (dense.path(), sparse_options),
+        ]
+        .iter()
+        .map(|&(path, options)| hash_file(path, options).unwrap().unwrap().to_vec())
+        .collect();
+        assert_eq!(1, sums.len());
+        assert_eq!(
+            Sha512::digest(&contents).to_vec(),
+            sums.into_iter().next().unwrap()
+        );
+    }
+
+    // Check that `get_sampled_hash` only looks at the sampled blocks.
+    #[test]
+    fn test_get_sampled_hash() {
+        let options = SampleOptions {
+            threshold: 0,
+            blocks: 3,
+            block_size: 2,
+        };
+        let sums: Vec<Vec<u8>> = ["ab cd ef", "ab_cd_ef", "ab cd eF", "Ab cd ef", "ab cD ef"]
+            .iter()
+            .enumerate()
+            .map(|(index, content)| {
+                let prefix = format!("{}_{}_{}_{}", module_path!(), line!(), column!(), index);
+                let tempfile = mktemp(prefix, content);
+                get_sampled_hash(tempfile.path(), content.len() as u64, options, sha512)
+                    .unwrap()
+                    .to_vec()
+            })
+            .collect();
+
+        // bytes outside of the sampled blocks are ignored
+        assert_eq!(sums[0], sums[1]);
+        // the head, tail, and middle blocks are all sampled
+        assert_ne!(sums[0], sums[2]);
+        assert_ne!(sums[0], sums[3]);
+        assert_ne!(sums[0], sums[4]);
+    }
+
+    // Check that files are sampled as by the tier of their size.
+    #[test]
+    fn test_sample_tier() {
+        let tier = |threshold, blocks| SampleOptions {
+            threshold,
+            blocks,
+            block_size: 4096,
+        };
+        let tiers = [tier(1 << 30, 16), tier(64 << 10, 1), tier(1 << 20, 0)];
+        assert_eq!(None, sample_tier(&tiers, 1000));
+        assert_eq!(Some(tiers[1]), sample_tier(&tiers, 64 << 10));
+        assert_eq!(None, sample_tier(&tiers, 1 << 20));
+        assert_eq!(Some(tiers[0]), sample_tier(&tiers, 1 << 40));
+        assert_eq!(None, sample_tier(&[], 1 << 40));
+    }
+
+    // Check that `get_file_size` returns the correct file size in bytes.
+    #[test]
+    fn test_get_file_size() {
+        test_data()
+            .into_iter()
+            .enumerate()
+            .for_each(|(index, content)| {
+                let prefix = format!("{}_{}_{}_{}", module_path!(), line!(), column!(), index);
+                let tempfile = mktemp(prefix, &content);
+                assert_eq!(content, read_to_string(tempfile.path()).unwrap());
+                let metadata = std::fs::symlink_metadata(tempfile.path()).unwrap();
+                let result = get_file_size(&metadata);
+                let expected = content.len();
+                assert_eq!(expected, result);
+            });
+    }
+
+    // E2E test.
+    #[test]
+    fn fdup() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+
+        create_dir_all(test_dir.path().join("d1/d2/d3/d4")).unwrap();
+
+        // create and populate each file
+        let _tempfiles: Vec<_> = vec![
+            ("d1/f1", ""),
+            ("d1/f2", ""),
+            ("d1/f3", "\n"),
+            ("d1/d2/f4", "a\nbc2"),
+            ("d1/d2/d3/f4", "abcde"),
+            ("d1/d2/d3/d4/f6", "a\nbc2"),
+            ("d1/d2/d3/d4/f7", "\n"),
+            ("d1/d2/d3/d4/f8", "\n"),
+            ("d1/d2/d3/d4/f9", "xyz"),
+        ]
+        .into_iter()
+        .map(|(path, content)| (test_dir.path().join(path), content))
+        .map(|(path_buf, content)| {
+            let file = mktemp(path_buf.to_str().unwrap(), content);
+            assert_eq!(read_to_string(&path_buf).unwrap(), content);
+            file
+        })
+        .collect();
+
+        // hardlinks are grouped with each other, and with other copies of their contents
+        std::fs::hard_link(test_dir.path().join("d1/f1"), test_dir.path().join("d1/l1")).unwrap();
+        std::fs::hard_link(
+            test_dir.path().join("d1/d2/d3/f4"),
+            test_dir.path().join("d1/l2"),
+        )
+        .unwrap();
+        std::fs::hard_link(
+            test_dir.path().join("d1/d2/d3/d4/f9"),
+            test_dir.path().join("d1/l3"),
+        )
+        .unwrap();
+
+        let expected = HashSet::from([
+            vec![
+                test_dir.path().join("d1/f1"),
+                test_dir.path().join("d1/f2"),
+                test_dir.path().join("d1/l1"),
+            ],
+            vec![
+                test_dir.path().join("d1/d2/d3/f4"),
+                test_dir.path().join("d1/l2"),
+            ],
+            vec![
+                test_dir.path().join("d1/d2/d3/d4/f9"),
+                test_dir.path().join("d1/l3"),
+            ],
+            vec![
+                test_dir.path().join("d1/d2/d3/d4/f6"),
+                test_dir.path().join("d1/d2/f4"),
+            ],
+            vec![
+                test_dir.path().join("d1/d2/d3/d4/f7"),
+                test_dir.path().join("d1/d2/d3/d4/f8"),
+                test_dir.path().join("d1/f3"),
+            ],
+        ]);
+        let all_hash_options = [
+            HashOptions::default(),
+            HashOptions {
+                backend: Backend::IoUring,
+                ..HashOptions::default()
+            },
+            HashOptions {
+                hdd_mode: HddMode::Always,
+                ..HashOptions::default()
+            },
+            HashOptions {
+                backend: Backend::IoUring,
+                lock: true,
+                ..HashOptions::default()
+            },
+            HashOptions {
+                timeout: Some(Duration::from_secs(60)),
+                ..HashOptions::default()
+            },
+            HashOptions {
+                new_hasher: crate::hasher::sha256,
+                ..HashOptions::default()
+            },
+            HashOptions {
+                backend: Backend::IoUring,
+                reads_per_device: Some(1),
+                ..HashOptions::default()
+            },
+            HashOptions {
+                readahead: 2,
+                ..HashOptions::default()
+            },
+            HashOptions {
+                hdd_mode: HddMode::Always,
+                readahead: 1,
+                ..HashOptions::default()
+            },
+        ];
+        let sample_options = SampleOptions {
+            threshold: 2,
+            blocks: 2,
+            block_size: 1,
+        };
+        let all_options = all_hash_options
+            .iter()
+            .map(|&hash| ScanOptions {
+                hash,
+                ..ScanOptions::default()
+            })
+            .chain([
+                ScanOptions {
+                    sample: vec![sample_options],
+                    ..ScanOptions::default()
+                },
+                ScanOptions {
+                    sample: vec![
+                        SampleOptions {
+                            threshold: 0,
+                            blocks: 1,
+                            block_size: 1,
+                        },
+                        SampleOptions {
+                            blocks: 0,
+                            ..sample_options
+                        },
+                        SampleOptions {
+                            threshold: 4,
+                            ..sample_options
+                        },
+                    ],
+                    ..ScanOptions::default()
+                },
+                ScanOptions {
+                    max_memory: Some(0),
+                    ..ScanOptions::default()
+                },
+            ]);
+        for options in all_options {
+            let results: HashSet<Vec<PathBuf>> =
+                group_duplicate_files(options, &[test_dir.path().to_path_buf()])
+                    .map(|mut v| {
+                        v.sort();
+                        v
+                    })
+                    .collect();
+            assert_eq!(expected, results);
+        }
+
+        let (handle, receiver) = scan_channel(ScanOptions::default(), &[test_dir.path().into()]);
+        let results: HashSet<Vec<PathBuf>> = receiver
+            .into_iter()
+            .map(|mut v| {
+                v.sort();
+                v
+            })
+            .collect();
+        handle.join().unwrap();
+        assert_eq!(expected, results);
+
+        // files reachable through more than one root are only considered once
+        let d1 = test_dir.path().join("d1");
+        let overlapping_roots = [
+            d1.join("d2"),
+            test_dir.path().to_path_buf(),
+            d1.join("f1"),
+            d1.clone(),
+            d1.join("d2/d3/../d3"),
+        ];
+        let results: HashSet<Vec<PathBuf>> =
+            group_duplicate_files(ScanOptions::default(), &overlapping_roots)
+                .map(|mut v| {
+                    v.sort();
+                    v
+                })
+                .collect();
+        assert_eq!(expected, results);
+
+        // recently modified files are ignored
+        let ignore_recent = ScanOptions {
+            ignore_newer_than: Some(Duration::from_secs(60 * 60)),
+            ..ScanOptions::default()
+        };
+        let roots = [test_dir.path().to_path_buf()];
+        assert_eq!(
+            0,
+            group_duplicate_files(ignore_recent.clone(), &roots).count()
+        );
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        for path in ["d1/f3", "d1/d2/d3/d4/f7"] {
+            let file = File::options()
+                .write(true)
+                .open(test_dir.path().join(path))
+                .unwrap();
+            file.set_modified(two_hours_ago).unwrap();
+        }
+        let results: Vec<Vec<PathBuf>> = group_duplicate_files(ignore_recent, &roots)
+            .map(|mut v| {
+                v.sort();
+                v
+            })
+            .collect();
+        assert_eq!(
+            vec![vec![
+                test_dir.path().join("d1/d2/d3/d4/f7"),
+                test_dir.path().join("d1/f3"),
+            ]],
+            results
+        );
+    }
+
+    // # Returns
+    //
+    // Groups of duplicates found under `roots`, each sorted.
+    fn group_sorted(options: ScanOptions, roots: &[PathBuf]) -> HashSet<Vec<PathBuf>> {
+        group_duplicate_files(options, roots)
+            .map(|mut v| {
+                v.sort();
+                v
+            })
+            .collect()
+    }
+
+    fn count_paths_in(groups: HashSet<Vec<PathBuf>>) -> usize {
+        groups.iter().map(Vec::len).sum()
+    }
+
+    // Check that files excluded by name or by their owner are never grouped.
+    #[test]
+    fn test_exclusions() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("d")).unwrap();
+        let _tempfiles: Vec<_> = ["f", "d/f", "._f", "d/._f", ".DS_Store", "d/thumbs.db"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "abc"))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+
+        assert_eq!(
+            HashSet::from([paths(&[
+                ".DS_Store",
+                "._f",
+                "d/._f",
+                "d/f",
+                "d/thumbs.db",
+                "f"
+            ])]),
+            group_sorted(ScanOptions::default(), &roots)
+        );
+
+        let skip_appledouble = ScanOptions {
+            skip_appledouble: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            HashSet::from([paths(&[".DS_Store", "d/f", "d/thumbs.db", "f"])]),
+            group_sorted(skip_appledouble, &roots)
+        );
+
+        let skip_junk = ScanOptions {
+            skip_junk: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            HashSet::from([paths(&["._f", "d/._f", "d/f", "f"])]),
+            group_sorted(skip_junk, &roots)
+        );
+
+        // nobody else owns these files, and the read-only ones can't be written to
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            let owner = std::fs::metadata(test_dir.path().join("f")).unwrap().uid();
+            let owned_by = |owner| ScanOptions {
+                owner: Some(owner),
+                ..ScanOptions::default()
+            };
+            assert_eq!(6, count_paths_in(group_sorted(owned_by(owner), &roots)));
+            assert!(group_sorted(owned_by(owner.wrapping_add(1)), &roots).is_empty());
+        }
+        let only_writable = ScanOptions {
+            only_writable: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            6,
+            count_paths_in(group_sorted(only_writable.clone(), &roots))
+        );
+        // root can write to anything
+        if !is_writable(Path::new("/")) {
+            for path in ["d/f", "._f"] {
+                let path = test_dir.path().join(path);
+                let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+                permissions.set_readonly(true);
+                std::fs::set_permissions(&path, permissions).unwrap();
+            }
+            assert_eq!(
+                HashSet::from([paths(&[".DS_Store", "d/._f", "d/thumbs.db", "f"])]),
+                group_sorted(only_writable, &roots)
+            );
+        }
+    }
+
+    // Check that only the slowest of the files hashed are recorded, from the slowest.
+    #[test]
+    fn test_slowest_reads() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        let _tempfiles: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "same"))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            slowest_reads: 2,
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(1, group_sorted(options, &roots).len());
+        let slowest_reads = stats.take().slowest_reads;
+        assert_eq!(2, slowest_reads.len());
+        assert!(slowest_reads[1].duration <= slowest_reads[0].duration);
+        assert!(slowest_reads.iter().all(|read| read.size == 4));
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(1, group_sorted(options, &roots).len());
+        assert!(stats.take().slowest_reads.is_empty());
+    }
+
+    // Check that with `by_extension`, the bytes found and those of the duplicates are broken down
+    // by extension, and that they add up to the totals.
+    #[test]
+    fn test_by_extension() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        let files = [
+            ("a.txt", "same"),
+            ("b.txt", "same"),
+            ("c.jpg", "pics"),
+            ("d.JPG", "pics"),
+            ("f", "uniq"),
+        ];
+        let _tempfiles: Vec<_> = files
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        let path = |path| test_dir.path().join(path);
+        std::fs::hard_link(path("a.txt"), path("e.txt")).unwrap();
+        let roots = [test_dir.path().to_path_buf()];
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            by_extension: true,
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(2, group_sorted(options, &roots).len());
+        let stats = stats.take();
+        let bytes = |found, linked, reclaimable| ByteCounts {
+            found,
+            linked,
+            reclaimable,
+        };
+        let expected = HashMap::from([
+            ("txt".to_string(), bytes(12, 4, 4)),
+            ("jpg".to_string(), bytes(8, 0, 4)),
+            (String::new(), bytes(4, 0, 0)),
+        ]);
+        assert_eq!(expected, stats.by_extension);
+        assert_eq!(bytes(24, 4, 8), stats.bytes());
+        assert_eq!(0.5, stats.bytes().ratio());
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(2, group_sorted(options, &roots).len());
+        assert!(stats.take().by_extension.is_empty());
+    }
+
+    // Check that with `sizes_only`, files of the same size are grouped without being read, and
+    // that nothing is recorded as reclaimable for them.
+    #[test]
+    fn test_sizes_only() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        let files = [("a1", "a"), ("a2", "a"), ("b", "b"), ("cc", "cc")];
+        let _tempfiles: Vec<_> = files
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+
+        let stats = StatsLog::default();
+        let sizes_only = ScanOptions {
+            sizes_only: true,
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2", "b"])]),
+            group_sorted(sizes_only, &roots)
+        );
+        let stats = stats.take();
+        assert_eq!(0, stats.bytes_to_read);
+        assert_eq!(0, stats.reclaimable_bytes);
+    }
+
+    // Check that files whose paths aren't valid unicode, in their names or their directories', are
+    // grouped like any other, unless they're skipped.
+    #[cfg(unix)]
+    #[test]
+    fn test_invalid_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        let invalid = OsStr::from_bytes(b"\xff\xfe");
+        create_dir_all(test_dir.path().join(invalid)).unwrap();
+        let paths = [
+            test_dir.path().join("f"),
+            test_dir.path().join(invalid),
+            test_dir.path().join(invalid).join("f"),
+        ]
+        .map(|path| path.join("g"));
+        for path in &paths {
+            create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "abc").unwrap();
+        }
+        let roots = [test_dir.path().to_path_buf()];
+
+        let mut expected = paths.to_vec();
+        expected.sort();
+        assert_eq!(
+            HashSet::from([expected]),
+            group_sorted(ScanOptions::default(), &roots)
+        );
+
+        let skips = SkipLog::recording();
+        let skip_invalid_names = ScanOptions {
+            skip_invalid_names: true,
+            skips: skips.clone(),
+            ..ScanOptions::default()
+        };
+        assert!(group_sorted(skip_invalid_names, &roots).is_empty());
+        let mut skipped: Vec<_> = skips
+            .take()
+            .into_iter()
+            .filter(|skip| skip.reason == SkipReason::InvalidName)
+            .map(|skip| skip.path)
+            .collect();
+        skipped.sort();
+        assert_eq!(vec![paths[2].clone(), paths[1].clone()], skipped);
+    }
+
+    // Check that only files of the given content types are compared, even among files of the same
+    // size.
+    #[test]
+    fn test_types() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [
+            ("a.pdf", "%PDF-1"),
+            ("b.pdf", "%PDF-1"),
+            ("a.zip", "PK\x03\x04ab"),
+            ("b.zip", "PK\x03\x04ab"),
+            ("a.txt", "plain!"),
+            ("b.txt", "plain!"),
+        ]
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        let of_types = |types: &[ContentType]| ScanOptions {
+            types: types.to_vec(),
+            ..ScanOptions::default()
+        };
+
+        assert_eq!(3, group_sorted(of_types(&[]), &roots).len());
+        assert_eq!(
+            HashSet::from([paths(&["a.pdf", "b.pdf"])]),
+            group_sorted(of_types(&[ContentType::Document]), &roots)
+        );
+        assert_eq!(
+            HashSet::from([paths(&["a.pdf", "b.pdf"]), paths(&["a.zip", "b.zip"])]),
+            group_sorted(
+                of_types(&[ContentType::Archive, ContentType::Document]),
+                &roots
+            )
+        );
+        assert!(group_sorted(of_types(&[ContentType::Image]), &roots).is_empty());
+    }
+
+    // Check that files are subgrouped by each extra key, and left out if it can't be computed.
+    #[test]
+    fn test_keys() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = ["a1", "a2", "b1", "b2", "c1"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "same"))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        let name_byte = |index: usize| {
+            GroupingKey::new(move |path: &Path, size| {
+                assert_eq!(4, size);
+                let name = path.file_name().unwrap().to_str().unwrap();
+                match name.as_bytes()[index] {
+                    b'c' => Err(io::Error::other("no key")),
+                    byte => Ok(vec![byte]),
+                }
+            })
+        };
+        let errors = ErrorLog::default();
+        let with_keys = |keys: Vec<GroupingKey>| ScanOptions {
+            keys,
+            errors: errors.clone(),
+            ..ScanOptions::default()
+        };
+
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2"]), paths(&["b1", "b2"])]),
+            group_sorted(with_keys(vec![name_byte(0)]), &roots)
+        );
+        let errors = errors.take();
+        assert_eq!(1, errors.len());
+        assert_eq!(Operation::Key, errors[0].operation);
+        assert!(group_sorted(with_keys(vec![name_byte(0), name_byte(1)]), &roots).is_empty());
+    }
+
+    // Check that files deleted once found are left out and counted, even if errors are fatal.
+    #[test]
+    fn test_vanished() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        let _tempfiles: Vec<_> = [("a1", "aaaa"), ("a2", "aaaa"), ("b1", "bbbb")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        // Left to be removed along with the directory, since it's deleted by the scan.
+        std::fs::write(test_dir.path().join("b2"), "bbbb").unwrap();
+        let roots = [test_dir.path().to_path_buf()];
+        // Deletes one of the files once it's been found, before it's read.
+        let delete_b2 = GroupingKey::new(|path: &Path, _| {
+            if path.file_name().unwrap() == "b2" {
+                std::fs::remove_file(path)?;
+            }
+            Ok(Vec::new())
+        });
+        let errors = ErrorLog::new(ErrorPolicy::Fail);
+        let options = ScanOptions {
+            keys: vec![delete_b2],
+            errors: errors.clone(),
+            ..ScanOptions::default()
+        };
+
+        let a = vec![test_dir.path().join("a1"), test_dir.path().join("a2")];
+        assert_eq!(HashSet::from([a]), group_sorted(options, &roots));
+        assert_eq!(1, errors.vanished());
+        assert!(errors.take().is_empty());
+    }
+
+    // Check that files are only duplicates of others under the same child of their root, whether
+    // or not they're hashed in physical order.
+    #[test]
+    fn test_child_of_root() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        for dir in ["a/d", "b", "c/e"] {
+            create_dir_all(test_dir.path().join(dir)).unwrap();
         }
-        pub fn file<'a>(&'a self) -> &'a File {
-            &self.file.as_ref().unwrap()
+        let _tempfiles: Vec<_> = ["a/1", "a/d/2", "b/3", "c/e/4", "c/e/5", "6", "7"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "same"))
+            .collect();
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        for hdd_mode in [HddMode::Never, HddMode::Always] {
+            let scoped = |roots: &[PathBuf]| ScanOptions {
+                keys: vec![GroupingKey::child_of_root(roots)],
+                hash: HashOptions {
+                    hdd_mode,
+                    ..HashOptions::default()
+                },
+                ..ScanOptions::default()
+            };
+
+            let roots = [test_dir.path().to_path_buf()];
+            assert_eq!(
+                HashSet::from([paths(&["a/1", "a/d/2"]), paths(&["c/e/4", "c/e/5"])]),
+                group_sorted(scoped(&roots), &roots)
+            );
+            // Under nested roots, by the innermost.
+            let roots = [test_dir.path().to_path_buf(), test_dir.path().join("c")];
+            assert_eq!(
+                HashSet::from([paths(&["a/1", "a/d/2"]), paths(&["c/e/4", "c/e/5"])]),
+                group_sorted(scoped(&roots), &roots)
+            );
+            let roots = [test_dir.path().join("a"), test_dir.path().join("c")];
+            assert_eq!(
+                HashSet::from([paths(&["c/e/4", "c/e/5"])]),
+                group_sorted(scoped(&roots), &roots)
+            );
         }
     }
-    impl Drop for TempFile {
-        fn drop(&mut self) {
-            if self.is_file {
-                remove_file(&self.path()).unwrap();
+
+    // Check that the files found are counted towards the innermost root that they're under.
+    #[test]
+    fn test_found_by_root() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("a/b")).unwrap();
+        create_dir_all(test_dir.path().join("c")).unwrap();
+        let _tempfiles: Vec<_> = [
+            ("a/1", "1"),
+            ("a/b/2", "22"),
+            ("a/b/3", "333"),
+            ("c/4", "4"),
+        ]
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let roots = [
+            test_dir.path().join("a"),
+            test_dir.path().join("a/b"),
+            test_dir.path().join("c"),
+        ];
+        assert_eq!(Some(1), root_of(&roots[1].join("2"), &roots));
+        assert_eq!(None, root_of(test_dir.path(), &roots));
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        group_sorted(options, &roots);
+        assert_eq!(vec![(1, 1), (2, 5), (1, 1)], stats.take().found_by_root);
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        group_sorted(options, &roots[..1]);
+        assert!(stats.take().found_by_root.is_empty());
+    }
+
+    // Check that groups are split up into the files that the verifier confirms are duplicates, and
+    // that files it fails on are left out.
+    #[test]
+    fn test_verify() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = ["a1", "a2", "b1", "b2", "c1"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "same"))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        let first_byte = |path: &Path| path.file_name().unwrap().to_str().unwrap().as_bytes()[0];
+        let verifier = Verifier::new(move |a, b| match (first_byte(a), first_byte(b)) {
+            (b'c', _) | (_, b'c') => Err(io::Error::other("can't compare")),
+            (a, b) => Ok(a == b),
+        });
+        let errors = ErrorLog::default();
+        let options = ScanOptions {
+            verify: Some(verifier),
+            errors: errors.clone(),
+            ..ScanOptions::default()
+        };
+
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2"]), paths(&["b1", "b2"])]),
+            group_sorted(options, &roots)
+        );
+        assert!(errors
+            .take()
+            .iter()
+            .all(|error| error.operation == Operation::Verify));
+    }
+
+    // Check that contents are compared in full, across the buffers they're read into.
+    #[test]
+    fn test_compare_contents() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let long = "a".repeat(100_000);
+        let _tempfiles: Vec<_> = [
+            ("a", long.clone()),
+            ("b", long.clone()),
+            ("c", format!("{}b", &long[1..])),
+            ("d", format!("{}a", long)),
+        ]
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let path = |name: &str| test_dir.path().join(name);
+        let verifier = Verifier::byte_for_byte();
+
+        assert!(verifier.verify(&path("a"), &path("b")).unwrap());
+        assert!(!verifier.verify(&path("a"), &path("c")).unwrap());
+        assert!(!verifier.verify(&path("a"), &path("d")).unwrap());
+        assert!(verifier.verify(&path("a"), &path("missing")).is_err());
+    }
+
+    // Check that truncated checksums still group files as full ones do.
+    #[test]
+    fn test_checksum_len() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [("a1", "a"), ("a2", "a"), ("b", "b")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let hash = HashOptions {
+            checksum_len: Some(16),
+            ..HashOptions::default()
+        };
+        let checksum = get_checksum(&test_dir.path().join("a1"), hash, &ErrorLog::default());
+        assert_eq!(16, checksum.unwrap().len());
+        let options = ScanOptions {
+            hash,
+            ..ScanOptions::default()
+        };
+
+        let group = vec![test_dir.path().join("a1"), test_dir.path().join("a2")];
+        assert_eq!(HashSet::from([group]), group_sorted(options, &roots));
+    }
+
+    // Check that groups come with the checksums computed while grouping them, shortened as those
+    // are, and without one when they're of a single inode, which isn't read.
+    #[cfg(unix)]
+    #[test]
+    fn test_group_checksums() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [("a1", "a"), ("a2", "a"), ("l1", "linked")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        let path = |path: &str| test_dir.path().join(path);
+        std::fs::hard_link(path("l1"), path("l2")).unwrap();
+        let roots = [test_dir.path().to_path_buf()];
+        let checksum = Sha512::digest(b"a").to_vec().into_boxed_slice();
+
+        for (checksum_len, checksum) in
+            [(None, checksum.clone()), (Some(16), checksum[..16].into())]
+        {
+            for hdd_mode in [HddMode::Never, HddMode::Always] {
+                let options = ScanOptions {
+                    hash: HashOptions {
+                        checksum_len,
+                        hdd_mode,
+                        ..HashOptions::default()
+                    },
+                    ..ScanOptions::default()
+                };
+                let groups: HashSet<(Option<Checksum>, Vec<PathBuf>)> =
+                    group_duplicate_files_with_checksums(options, &roots)
+                        .map(|(checksum, mut group)| {
+                            group.sort();
+                            (checksum, group)
+                        })
+                        .collect();
+                assert_eq!(
+                    HashSet::from([
+                        (Some(checksum.clone()), vec![path("a1"), path("a2")]),
+                        (None, vec![path("l1"), path("l2")]),
+                    ]),
+                    groups
+                );
+            }
+        }
+    }
+
+    // Check that empty files are only grouped when they're reported as a group, and recorded
+    // otherwise.
+    #[test]
+    fn test_empty_files() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [("e1", ""), ("e2", ""), ("e3", ""), ("f1", "a"), ("f2", "a")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+
+        for empty in [
+            EmptyFiles::Report,
+            EmptyFiles::Ignore,
+            EmptyFiles::ReportSeparately,
+        ] {
+            let options = ScanOptions {
+                empty,
+                ..ScanOptions::default()
+            };
+            let stats = options.stats.clone();
+            let mut groups = HashSet::from([paths(&["f1", "f2"])]);
+            if empty == EmptyFiles::Report {
+                groups.insert(paths(&["e1", "e2", "e3"]));
+            }
+            assert_eq!(groups, group_sorted(options, &roots));
+
+            let mut empty_files = stats.take().empty_files;
+            empty_files.sort();
+            if empty == EmptyFiles::Report {
+                assert!(empty_files.is_empty());
             } else {
-                remove_dir_all(&self.path()).unwrap();
+                assert_eq!(paths(&["e1", "e2", "e3"]), empty_files);
             }
         }
     }
 
-    // # Returns
-    //
-    // Path to some newly created tempfile in some OS-managed tempdir. The basename of this file
-    // will be prefixed with `prefix` and its content equal to `content`.
-    //
-    // Whether or not the resulting path is unique depends on the prefix.
-    fn mktemp(prefix: impl Borrow<str>, content: impl Display) -> TempFile {
-        // construct a path to a temporary file
-        let path = env::temp_dir().as_path().join(prefix.borrow());
-        let tempfile = TempFile::new(true /* is_file */, path);
+    // Check that groups of hardlinks to a single inode are only left out when they're skipped, and
+    // that the space saved by hardlinks is recorded.
+    #[test]
+    fn test_skip_hardlinked() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [("a1", "a"), ("b1", "b"), ("b2", "b")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        for (original, link) in [("a1", "a2"), ("b1", "b3")] {
+            let path = |path| test_dir.path().join(path);
+            std::fs::hard_link(path(original), path(link)).unwrap();
+        }
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
 
-        // write `content` to the file
-        write!(tempfile.file(), "{}", content).unwrap();
-        tempfile
+        let options = ScanOptions::default();
+        let stats = options.stats.clone();
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2"]), paths(&["b1", "b2", "b3"])]),
+            group_sorted(options, &roots)
+        );
+        let stats = stats.take();
+        assert_eq!(2, stats.linked_bytes);
+        assert_eq!(1, stats.reclaimable_bytes);
+        let bucket = Bucket {
+            groups: 2,
+            reclaimable_bytes: 1,
+        };
+        assert_eq!(
+            [
+                bucket,
+                Bucket::default(),
+                Bucket::default(),
+                Bucket::default()
+            ],
+            stats.histogram
+        );
+
+        // the space they take up is still recorded
+        for hdd_mode in [HddMode::Never, HddMode::Always] {
+            let skip_hardlinked = ScanOptions {
+                skip_hardlinked: true,
+                hash: HashOptions {
+                    hdd_mode,
+                    ..HashOptions::default()
+                },
+                ..ScanOptions::default()
+            };
+            let stats = skip_hardlinked.stats.clone();
+            assert_eq!(
+                HashSet::from([paths(&["b1", "b2", "b3"])]),
+                group_sorted(skip_hardlinked, &roots)
+            );
+            assert_eq!(2, stats.take().linked_bytes);
+        }
     }
 
-    fn test_data() -> Vec<String> {
-        vec![
-            " ",
-            " 12p oka0sd k\n rn12w\r\r\n \t asof AWSDJO !@# @$ ",
-            "",
-            "12asdopjkzx",
-            "2",
-            "QxmPzHlMLisNDJm3LKT5LRoTyU9Z06ze",
-            "\n",
-            "\r",
-            "\r\n",
-            "\t",
-            "g",
+    // Check that the precount counts the same files as the walk, excluded files aside.
+    #[test]
+    fn test_precount() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("d")).unwrap();
+        let _tempfiles: Vec<_> = [
+            ("a", "ab"),
+            ("d/b", "ab"),
+            ("d/c", "c"),
+            (".DS_Store", "junk"),
         ]
-        .into_iter()
-        .map(String::from)
-        .collect()
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let roots = [test_dir.path().to_path_buf()];
+
+        let options = ScanOptions {
+            precount: true,
+            skip_junk: true,
+            ..ScanOptions::default()
+        };
+        let stats = options.stats.clone();
+        assert_eq!(1, group_sorted(options, &roots).len());
+        let stats = stats.take();
+        assert_eq!((3, 5), (stats.files_found, stats.bytes_found));
+        assert_eq!(Some(3), stats.precounted_files);
+        assert_eq!(Some(5), stats.precounted_bytes);
+
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        group_sorted(options, &roots);
+        assert_eq!(None, stats.take().precounted_files);
+
+        // Files of unique sizes are left out before they're grouped, but still found.
+        let stats = StatsLog::default();
+        let options = ScanOptions {
+            size_census: true,
+            skip_junk: true,
+            stats: stats.clone(),
+            ..ScanOptions::default()
+        };
+        let group = vec![test_dir.path().join("a"), test_dir.path().join("d/b")];
+        assert_eq!(HashSet::from([group]), group_sorted(options, &roots));
+        let stats = stats.take();
+        assert_eq!((3, 5), (stats.files_found, stats.bytes_found));
+        assert_eq!(None, stats.precounted_files);
     }
 
-    // Check that `get_sha512_hash` returns a unique checksum for each test data.
+    // Check that groups are filtered by the directories that their files are in.
     #[test]
-    fn test_get_sha512_hash() {
-        let sums: HashSet<Vec<u8>> = test_data()
-            .into_iter()
-            .enumerate()
-            .map(|(index, content)| {
-                let prefix = format!("{}_{}_{}_{}", module_path!(), line!(), column!(), index);
-                let tempfile = mktemp(prefix, &content);
-                assert_eq!(content, read_to_string(&tempfile.path()).unwrap());
-
-                // pseudo check that the function is deterministic
-                let sums: HashSet<Vec<u8>> = (0..4)
-                    .map(|_| get_sha512_hash(&tempfile.path()).as_ref().to_vec())
-                    .collect();
-                assert_eq!(1, sums.len());
-                sums.into_iter().nth(0).unwrap()
-            })
+    fn test_spread() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("d")).unwrap();
+        let _tempfiles: Vec<_> = [("a1", "a"), ("a2", "a"), ("b1", "bb"), ("d/b2", "bb")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
             .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        let spread = |spread| ScanOptions {
+            spread,
+            ..ScanOptions::default()
+        };
 
-        // # of Checksum512s == # of input data
-        assert_eq!(test_data().len(), sums.len());
+        assert_eq!(2, group_sorted(spread(Spread::Any), &roots).len());
+        assert_eq!(
+            HashSet::from([paths(&["b1", "d/b2"])]),
+            group_sorted(spread(Spread::AcrossDirs), &roots)
+        );
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2"])]),
+            group_sorted(spread(Spread::SameDir), &roots)
+        );
     }
 
-    // Check that `get_file_size` returns the correct file size in bytes.
+    // Check that groups are filtered by whether any of their files match the given patterns.
     #[test]
-    fn test_get_file_size() {
-        test_data()
+    fn test_group_must_include() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("d")).unwrap();
+        let _tempfiles: Vec<_> = [
+            ("a1", "a"),
+            ("a2", "a"),
+            ("b1", "bb"),
+            ("d/b2", "bb"),
+            ("c1", "ccc"),
+            ("d/c2", "ccc"),
+        ]
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        let patterns = |patterns: &[&str]| -> Vec<PathPattern> {
+            patterns
+                .iter()
+                .map(|pattern| pattern.parse().unwrap())
+                .collect()
+        };
+        let in_d = test_dir.path().join("d");
+        let in_d = in_d.to_str().unwrap();
+        let filtered = |include: &[&str], exclude: &[&str]| {
+            let options = ScanOptions {
+                group_must_include: patterns(include),
+                group_must_exclude: patterns(exclude),
+                ..ScanOptions::default()
+            };
+            group_sorted(options, &roots)
+        };
+
+        assert_eq!(3, filtered(&[], &[]).len());
+        assert_eq!(
+            HashSet::from([paths(&["b1", "d/b2"]), paths(&["c1", "d/c2"])]),
+            filtered(&[in_d], &[])
+        );
+        assert_eq!(
+            HashSet::from([paths(&["c1", "d/c2"])]),
+            filtered(&[in_d, "c*"], &[])
+        );
+        assert_eq!(
+            HashSet::from([paths(&["b1", "d/b2"])]),
+            filtered(&[in_d], &["c*"])
+        );
+        assert_eq!(
+            HashSet::from([paths(&["a1", "a2"])]),
+            filtered(&[], &[in_d])
+        );
+    }
+
+    // Check that files left out of the results are recorded along with why, and only when asked.
+    #[cfg(unix)]
+    #[test]
+    fn test_skips() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path().join("d")).unwrap();
+        let files = [
+            ("a1", "a"),
+            ("a2", "a"),
+            ("b", "bb"),
+            ("c1", "ccc"),
+            ("c2", "cCc"),
+            ("d/a3", "a"),
+            ("._a", "a"),
+        ];
+        let _tempfiles: Vec<_> = files
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+            .collect();
+        std::os::unix::fs::symlink("a1", test_dir.path().join("l")).unwrap();
+        let roots = [test_dir.path().to_path_buf()];
+        let skips = SkipLog::recording();
+        let options = ScanOptions {
+            skip_appledouble: true,
+            spread: Spread::SameDir,
+            walk: WalkOptions {
+                symlinks: true,
+                ..WalkOptions::default()
+            },
+            skips: skips.clone(),
+            ..ScanOptions::default()
+        };
+
+        assert!(group_sorted(options, &roots).is_empty());
+        let mut skipped: Vec<(PathBuf, SkipReason)> = skips
+            .take()
             .into_iter()
-            .enumerate()
-            .for_each(|(index, content)| {
-                let prefix = format!("{}_{}_{}_{}", module_path!(), line!(), column!(), index);
-                let tempfile = mktemp(prefix, &content);
-                assert_eq!(content, read_to_string(&tempfile.path()).unwrap());
-                let temp_as_entry = WalkDir::new(&tempfile.path())
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .nth(0)
-                    .unwrap();
-                let result = get_file_size(&temp_as_entry);
-                let expected = content.len();
-                assert_eq!(expected, result);
-            });
+            .map(|skip| (skip.path, skip.reason))
+            .collect();
+        skipped.sort_by(|a, b| a.0.cmp(&b.0));
+        let skip = |path, reason| (test_dir.path().join(path), reason);
+        let expected = vec![
+            skip("._a", SkipReason::ExcludedName),
+            skip("a1", SkipReason::Spread),
+            skip("a2", SkipReason::Spread),
+            skip("b", SkipReason::UniqueSize),
+            skip("c1", SkipReason::UniqueChecksum),
+            skip("c2", SkipReason::UniqueChecksum),
+            skip("d/a3", SkipReason::Spread),
+            skip("l", SkipReason::Symlink),
+        ];
+        assert_eq!(expected, skipped);
+
+        let skips = SkipLog::default();
+        let options = ScanOptions {
+            skips: skips.clone(),
+            ..ScanOptions::default()
+        };
+        assert_eq!(1, group_sorted(options, &roots).len());
+        assert!(skips.take().is_empty());
     }
 
-    // E2E test.
+    // Check that documents that only differ in their volatile metadata are grouped, whatever
+    // their sizes, and that other files aren't.
     #[test]
-    fn fdup() {
+    fn test_normalize() {
         let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
         let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [
+            ("a.pdf", "<< /ModDate (D:2020) >> text"),
+            ("b.PDF", "<< /ModDate (D:20211231) >> text"),
+            ("c.pdf", "<< /ModDate (D:2020) >> edit"),
+            ("d.txt", "<< /ModDate (D:2020) >> txt1"),
+            ("e.txt", "<< /ModDate (D:2021) >> txt1"),
+        ]
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
+        .collect();
+        let roots = [test_dir.path().to_path_buf()];
+        let options = ScanOptions {
+            normalize: vec![Normalize::Pdf],
+            ..ScanOptions::default()
+        };
 
-        create_dir_all(test_dir.path().join("d1/d2/d3/d4")).unwrap();
+        let pdfs = vec![test_dir.path().join("a.pdf"), test_dir.path().join("b.PDF")];
+        assert_eq!(HashSet::from([pdfs]), group_sorted(options, &roots));
+        assert!(group_sorted(ScanOptions::default(), &roots).is_empty());
+    }
 
-        // create and populate each file
-        let _tempfiles: Vec<_> = vec![
-            ("d1/f1", ""),
-            ("d1/f2", ""),
-            ("d1/f3", "\n"),
-            ("d1/d2/f4", "a\nbc2"),
-            ("d1/d2/d3/f4", "abcde"),
-            ("d1/d2/d3/d4/f6", "a\nbc2"),
-            ("d1/d2/d3/d4/f7", "\n"),
-            ("d1/d2/d3/d4/f8", "\n"),
+    // Check that groups of files that are clean in a git index are compared by their blob IDs,
+    // and mixed groups by their contents.
+    #[test]
+    fn test_git() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        // Line endings are converted when added, so that only blob IDs say these are the same.
+        let _tempfiles: Vec<_> = [
+            (".gitattributes", "* text\n"),
+            ("a", "x\r\ny\n"),
+            ("b", "x\ny\r\n"),
+            ("c", "same"),
+            ("d", "same"),
         ]
-        .into_iter()
-        .map(|(path, content)| (test_dir.path().join(path), content))
-        .map(|(path_buf, content)| {
-            let file = mktemp(path_buf.to_str().unwrap(), content);
-            assert_eq!(read_to_string(&path_buf).unwrap(), content);
-            file
-        })
+        .iter()
+        .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
         .collect();
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(test_dir.path())
+                .args(args)
+                .output()
+                .is_ok_and(|output| output.status.success())
+        };
+        if !git(&["init", "-q"]) {
+            // git isn't installed
+            return;
+        }
+        assert!(git(&["add", ".gitattributes", "a", "b", "c"]));
+        let roots = [test_dir.path().to_path_buf()];
+        let options = ScanOptions {
+            git: Some(GitBlobs::default()),
+            ..ScanOptions::default()
+        };
 
-        let results: HashSet<Vec<PathBuf>> = group_duplicate_files(false, &test_dir.path())
-            .map(|mut v| {
-                v.sort();
-                v
-            })
+        let paths = |paths: &[&str]| -> Vec<PathBuf> {
+            paths
+                .iter()
+                .map(|path| test_dir.path().join(path))
+                .collect()
+        };
+        assert_eq!(
+            HashSet::from([paths(&["a", "b"]), paths(&["c", "d"])]),
+            group_sorted(options, &roots)
+        );
+        assert_eq!(
+            HashSet::from([paths(&["c", "d"])]),
+            group_sorted(ScanOptions::default(), &roots)
+        );
+    }
+
+    // Check that sizes are either compared in full or not at all, and their bytes recorded.
+    #[test]
+    fn test_sample_sizes() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = [("a1", "a"), ("a2", "a"), ("b1", "bb"), ("b2", "bb")]
+            .iter()
+            .map(|(path, content)| mktemp(test_dir.path().join(path).to_str().unwrap(), content))
             .collect();
-        let expected = HashSet::from([
-            vec![test_dir.path().join("d1/f1"), test_dir.path().join("d1/f2")],
-            vec![
-                test_dir.path().join("d1/d2/d3/d4/f6"),
-                test_dir.path().join("d1/d2/f4"),
-            ],
-            vec![
-                test_dir.path().join("d1/d2/d3/d4/f7"),
-                test_dir.path().join("d1/d2/d3/d4/f8"),
-                test_dir.path().join("d1/f3"),
-            ],
-        ]);
-        assert_eq!(expected, results);
+        let roots = [test_dir.path().to_path_buf()];
+
+        let sampled = |fraction| {
+            let options = ScanOptions {
+                sample_sizes: Some(fraction),
+                ..ScanOptions::default()
+            };
+            let stats = options.stats.clone();
+            (group_sorted(options, &roots), stats.take())
+        };
+        let (groups, stats) = sampled(1.0);
+        assert_eq!(2, groups.len());
+        assert_eq!(HashMap::from([(1, 1), (2, 2)]), stats.reclaimable_by_size);
+        let (groups, stats) = sampled(0.0);
+        assert!(groups.is_empty());
+        assert_eq!(4, stats.files_found);
+        // each size is either compared or not
+        for _ in 0..8 {
+            let (groups, _) = sampled(0.5);
+            assert!(groups.iter().all(|group| group.len() == 2));
+        }
+    }
+
+    // Check that no more files are found once either limit is reached.
+    #[test]
+    fn test_limits() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let test_dir = TempFile::new(false /* is_file */, std::env::temp_dir().join(prefix));
+        create_dir_all(test_dir.path()).unwrap();
+        let _tempfiles: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|path| mktemp(test_dir.path().join(path).to_str().unwrap(), "abc"))
+            .collect();
+        let roots = [test_dir.path().to_path_buf()];
+
+        for (max_files, max_bytes, found, truncated) in [
+            (None, None, 3, false),
+            (Some(3), Some(9), 3, false),
+            (Some(2), None, 2, true),
+            (None, Some(8), 2, true),
+            (Some(0), None, 0, true),
+        ] {
+            let options = ScanOptions {
+                max_files,
+                max_bytes,
+                ..ScanOptions::default()
+            };
+            let stats = options.stats.clone();
+            let groups = group_sorted(options, &roots);
+            assert_eq!(found, count_paths_in(groups));
+            let stats = stats.take();
+            assert_eq!(
+                (found as u64, truncated),
+                (stats.files_found, stats.truncated)
+            );
+        }
+    }
+
+    #[test]
+    fn test_order_kept_first() {
+        let paths = |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(PathBuf::from).collect() };
+        let keep_in = paths(&["archive", "backup/a"]);
+
+        assert_eq!(
+            Some(paths(&[
+                "archive/f",
+                "backup/a/f",
+                "downloads/f",
+                "backup/ab/f"
+            ])),
+            order_kept_first(
+                paths(&["downloads/f", "archive/f", "backup/ab/f", "backup/a/f"]),
+                &keep_in,
+                &[]
+            )
+        );
+        assert_eq!(
+            None,
+            order_kept_first(paths(&["archive/f", "archive/g"]), &keep_in, &[])
+        );
+        assert_eq!(
+            None,
+            order_kept_first(paths(&["downloads/f", "downloads/g"]), &keep_in, &[])
+        );
+
+        // protected paths are kept too, but don't stand in for `keep_in`
+        let protect = ["*.jpg".parse().unwrap()];
+        assert_eq!(
+            Some(paths(&["downloads/f.jpg", "archive/f", "downloads/f"])),
+            order_kept_first(
+                paths(&["downloads/f", "downloads/f.jpg", "archive/f"]),
+                &keep_in,
+                &protect
+            )
+        );
+        assert_eq!(
+            None,
+            order_kept_first(paths(&["archive/f", "downloads/f.jpg"]), &keep_in, &protect)
+        );
+        assert_eq!(
+            None,
+            order_kept_first(
+                paths(&["downloads/f.jpg", "downloads/f"]),
+                &keep_in,
+                &protect
+            )
+        );
+        assert_eq!(
+            Some(paths(&["downloads/f.jpg", "downloads/f"])),
+            order_kept_first(paths(&["downloads/f", "downloads/f.jpg"]), &[], &protect)
+        );
+        assert_eq!(
+            Some(paths(&["downloads/f", "downloads/g"])),
+            order_kept_first(paths(&["downloads/f", "downloads/g"]), &[], &protect)
+        );
+        assert_eq!(
+            None,
+            order_kept_first(paths(&["a.jpg", "b.jpg"]), &[], &protect)
+        );
+        assert_eq!(
+            2,
+            count_kept(
+                &paths(&["archive/f", "downloads/f.jpg", "downloads/g"]),
+                &keep_in,
+                &protect
+            )
+        );
+        assert_eq!(0, count_kept(&paths(&["downloads/f"]), &keep_in, &protect));
     }
 }
@@ -0,0 +1,216 @@
+//! C interface to the engine, behind the `ffi` feature, declared in `include/fdup.h`.
+//!
+//! A scanner is an opaque handle holding the roots to scan and the errors of its last scan. Every
+//! function returns one of the `FDUP_*` codes rather than unwinding into C.
+
+use crate::{
+    errors::{ErrorLog, ErrorPolicy},
+    fdup::{group_duplicate_files, ScanOptions},
+};
+use rayon::prelude::*;
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int, c_void},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+pub const FDUP_OK: c_int = 0;
+pub const FDUP_NULL_POINTER: c_int = -1;
+pub const FDUP_INVALID_PATH: c_int = -2;
+pub const FDUP_PANICKED: c_int = -3;
+
+/// Called with the paths of each group of duplicates, and the context given to `fdup_scan`.
+pub type FdupGroupCallback =
+    extern "C" fn(context: *mut c_void, paths: *const *const c_char, len: usize);
+
+/// Roots to scan, and the number of paths left out of the last scan because of errors.
+pub struct FdupScanner {
+    roots: Vec<PathBuf>,
+    errors: usize,
+}
+
+/// # Returns
+///
+/// New scanner with no roots, to be freed with `fdup_scanner_free`.
+#[no_mangle]
+pub extern "C" fn fdup_scanner_new() -> *mut FdupScanner {
+    Box::into_raw(Box::new(FdupScanner {
+        roots: Vec::new(),
+        errors: 0,
+    }))
+}
+
+/// Free `scanner`, which may be null.
+///
+/// # Safety
+///
+/// `scanner` must have been returned by `fdup_scanner_new`, and not been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn fdup_scanner_free(scanner: *mut FdupScanner) {
+    if !scanner.is_null() {
+        drop(Box::from_raw(scanner));
+    }
+}
+
+/// Add the directory or file at `path`, a null-terminated string, to the roots of `scanner`.
+///
+/// # Safety
+///
+/// `scanner` must be a live scanner, and `path` a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fdup_scanner_add_root(
+    scanner: *mut FdupScanner,
+    path: *const c_char,
+) -> c_int {
+    let (scanner, path) = match (scanner.as_mut(), path.is_null()) {
+        (Some(scanner), false) => (scanner, CStr::from_ptr(path)),
+        _ => return FDUP_NULL_POINTER,
+    };
+    #[cfg(unix)]
+    let path = {
+        use std::os::unix::ffi::OsStrExt;
+
+        PathBuf::from(std::ffi::OsStr::from_bytes(path.to_bytes()))
+    };
+    #[cfg(not(unix))]
+    let path = match path.to_str() {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => return FDUP_INVALID_PATH,
+    };
+    scanner.roots.push(path);
+    FDUP_OK
+}
+
+/// Scan the roots of `scanner`, calling `callback` with `context` for each group of duplicates.
+/// The callback may be called from any thread, but never by two at the same time, and the paths
+/// that it's given only live until it returns. Paths that can't be read are left out, and
+/// counted by `fdup_scanner_errors`.
+///
+/// # Safety
+///
+/// `scanner` must be a live scanner, and `callback` safe to call with `context` from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn fdup_scan(
+    scanner: *mut FdupScanner,
+    callback: Option<FdupGroupCallback>,
+    context: *mut c_void,
+) -> c_int {
+    let (scanner, callback) = match (scanner.as_mut(), callback) {
+        (Some(scanner), Some(callback)) => (scanner, callback),
+        _ => return FDUP_NULL_POINTER,
+    };
+    // Raw pointers aren't `Send`, but the caller vouched for using the context from any thread.
+    let context = context as usize;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let errors = ErrorLog::new(ErrorPolicy::Skip);
+        let options = ScanOptions {
+            errors: errors.clone(),
+            ..Default::default()
+        };
+        let callback = Mutex::new(callback);
+        group_duplicate_files(options, &scanner.roots).for_each(|group| {
+            let paths: Vec<CString> = group.iter().filter_map(|path| to_c_string(path)).collect();
+            let pointers: Vec<*const c_char> = paths.iter().map(|path| path.as_ptr()).collect();
+            let callback = callback.lock().unwrap();
+            callback(context as *mut c_void, pointers.as_ptr(), pointers.len());
+        });
+        errors.take().len()
+    }));
+    match result {
+        Ok(errors) => {
+            scanner.errors = errors;
+            FDUP_OK
+        }
+        Err(_) => FDUP_PANICKED,
+    }
+}
+
+/// # Returns
+///
+/// Number of paths left out of the last scan of `scanner` because of errors.
+///
+/// # Safety
+///
+/// `scanner` must be a live scanner.
+#[no_mangle]
+pub unsafe extern "C" fn fdup_scanner_errors(scanner: *const FdupScanner) -> usize {
+    scanner.as_ref().map_or(0, |scanner| scanner.errors)
+}
+
+/// # Returns
+///
+/// `path` as a null-terminated string, or `None` if it contains a null byte.
+fn to_c_string(path: &std::path::Path) -> Option<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes().to_vec()
+    };
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+    CString::new(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+        ptr,
+    };
+
+    extern "C" fn collect(context: *mut c_void, paths: *const *const c_char, len: usize) {
+        let groups = unsafe { &mut *(context as *mut Vec<Vec<String>>) };
+        let paths = unsafe { std::slice::from_raw_parts(paths, len) };
+        let mut group: Vec<String> = paths
+            .iter()
+            .map(|&path| {
+                unsafe { CStr::from_ptr(path) }
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        group.sort();
+        groups.push(group);
+    }
+
+    #[test]
+    fn test_scan() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        for (path, content) in [("a", "x"), ("b", "x"), ("c", "y")] {
+            write(root.join(path), content).unwrap();
+        }
+
+        let mut groups: Vec<Vec<String>> = Vec::new();
+        unsafe {
+            let scanner = fdup_scanner_new();
+            let root_path = CString::new(root.to_str().unwrap()).unwrap();
+            assert_eq!(FDUP_OK, fdup_scanner_add_root(scanner, root_path.as_ptr()));
+            assert_eq!(
+                FDUP_NULL_POINTER,
+                fdup_scanner_add_root(scanner, ptr::null())
+            );
+            let context = &mut groups as *mut Vec<Vec<String>> as *mut c_void;
+            assert_eq!(FDUP_OK, fdup_scan(scanner, Some(collect), context));
+            assert_eq!(FDUP_NULL_POINTER, fdup_scan(scanner, None, context));
+            assert_eq!(0, fdup_scanner_errors(scanner));
+            fdup_scanner_free(scanner);
+        }
+        let expected: Vec<String> = ["a", "b"]
+            .iter()
+            .map(|path| root.join(path).to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(vec![expected], groups);
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Types of the filesystems that directories are on, as told by `statfs`, so that pseudo and
+//! volatile filesystems can be left out of a walk.
+
+use std::{path::Path, str::FromStr};
+
+/// Names of the filesystem types that can be told apart, with the magic number that `statfs`
+/// reports for each on Linux. devtmpfs reports the same one as tmpfs, so either of them stands for
+/// both there.
+pub const FS_TYPES: &[(&str, u32)] = &[
+    ("autofs", 0x0187),
+    ("bpf", 0xcafe_4a11),
+    ("cgroup", 0x0027_e0eb),
+    ("cgroup2", 0x6367_7270),
+    ("configfs", 0x6265_6570),
+    ("debugfs", 0x6462_6720),
+    ("devfs", 0x1373),
+    ("devpts", 0x1cd1),
+    ("devtmpfs", 0x0102_1994),
+    ("fusectl", 0x6573_5543),
+    ("hugetlbfs", 0x9584_58f6),
+    ("mqueue", 0x1980_0202),
+    ("nfs", 0x6969),
+    ("overlay", 0x794c_7630),
+    ("proc", 0x9fa0),
+    ("pstore", 0x6165_676c),
+    ("ramfs", 0x8584_58f6),
+    ("securityfs", 0x7363_6673),
+    ("smb", 0xfe53_4d42),
+    ("squashfs", 0x7371_7368),
+    ("sysfs", 0x6265_6572),
+    ("tmpfs", 0x0102_1994),
+    ("tracefs", 0x7472_6163),
+];
+
+/// Set of the filesystem types of `FS_TYPES`, given as a list of their names separated by commas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FsTypes(u64);
+
+impl FromStr for FsTypes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut types = FsTypes::default();
+        for name in s.split(',').filter(|name| !name.is_empty()) {
+            match FS_TYPES.iter().position(|&(known, _)| known == name) {
+                Some(i) => types.0 |= 1 << i,
+                None => return Err(format!("unknown filesystem type: {}", name)),
+            }
+        }
+        Ok(types)
+    }
+}
+
+impl FsTypes {
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// # Returns
+    ///
+    /// Names of the filesystem types in the set, in the order of `FS_TYPES`.
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        self.iter().map(|&(name, _)| name)
+    }
+
+    /// # Returns
+    ///
+    /// Entries of `FS_TYPES` in the set.
+    fn iter(self) -> impl Iterator<Item = &'static (&'static str, u32)> {
+        (0..FS_TYPES.len())
+            .filter(move |i| self.0 & (1 << i) != 0)
+            .map(|i| &FS_TYPES[i])
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if the directory at `path` is on a filesystem of one of these types.
+    /// Only implemented on Linux, by magic number, and on macOS, by name; elsewhere, and if
+    /// `statfs` fails, never.
+    pub fn contains_fs_of(self, path: &Path) -> bool {
+        !self.is_empty() && self.contains(path)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn contains(self, path: &Path) -> bool {
+        match statfs(path) {
+            // Only the low 32 bits of the magic are reported on some platforms, sign-extended.
+            Some(stat) => self.iter().any(|&(_, magic)| stat.f_type as u32 == magic),
+            None => false,
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn contains(self, path: &Path) -> bool {
+        use std::ffi::CStr;
+
+        match statfs(path) {
+            Some(stat) => {
+                let name = unsafe { CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+                self.iter()
+                    .any(|&(known, _)| name.to_bytes() == known.as_bytes())
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+    fn contains(self, _path: &Path) -> bool {
+        false
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn statfs(path: &Path) -> Option<libc::statfs> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = mem::MaybeUninit::<libc::statfs>::uninit();
+    match unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } {
+        0 => Some(unsafe { stat.assume_init() }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fs_types() {
+        let types: FsTypes = "proc,sysfs".parse().unwrap();
+        assert_eq!(vec!["proc", "sysfs"], types.names().collect::<Vec<_>>());
+        assert!("".parse::<FsTypes>().unwrap().is_empty());
+        assert!("proc,nope".parse::<FsTypes>().is_err());
+        assert!(FS_TYPES.len() <= 64);
+
+        assert!(!FsTypes::default().contains_fs_of(Path::new("/")));
+        #[cfg(target_os = "linux")]
+        if Path::new("/proc/self").exists() {
+            assert!(types.contains_fs_of(Path::new("/proc")));
+            assert!(!types.contains_fs_of(Path::new("/")));
+        }
+    }
+}
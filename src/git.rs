@@ -0,0 +1,162 @@
+//! Shortcut for files tracked by git: the IDs of the blobs that git already hashed their contents
+//! to, as recorded in the index of their working tree, which can be compared instead of reading
+//! them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+};
+
+/// Blob IDs by the canonical paths of the files they're of.
+type Blobs = HashMap<PathBuf, Box<[u8]>>;
+
+/// IDs of the blobs of the files that are clean in the index of the git working tree they're in,
+/// listed by running `git` once per working tree. Clones share what's been listed so far.
+#[derive(Clone, Debug, Default)]
+pub struct GitBlobs {
+    cache: Arc<Mutex<Cache>>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    /// Canonical path of each directory, as given, and of the working tree it's in, if any.
+    dirs: HashMap<PathBuf, Option<(PathBuf, PathBuf)>>,
+    /// Blobs of the clean files of each working tree, which are none if git couldn't list them.
+    trees: HashMap<PathBuf, Arc<Blobs>>,
+}
+
+impl GitBlobs {
+    /// # Returns
+    ///
+    /// ID of the blob of the file at `path`, if it's a regular file tracked by git and clean in
+    /// the index, i.e. unmodified since it was last added. `None` otherwise, including if git
+    /// couldn't be run. Files whose contents git converts when adding them, e.g. their line
+    /// endings, have the ID of their converted contents.
+    pub fn blob_id(&self, path: &Path) -> Option<Box<[u8]>> {
+        let name = path.file_name()?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut cache = self.cache.lock().unwrap();
+        let Cache { dirs, trees } = &mut *cache;
+        let (dir, tree) = dirs
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let dir = dir.canonicalize().ok()?;
+                let tree = dir.ancestors().find(|dir| dir.join(".git").exists())?;
+                Some((dir.clone(), tree.to_path_buf()))
+            })
+            .clone()?;
+        let blobs = trees
+            .entry(tree.clone())
+            .or_insert_with(|| Arc::new(list_clean_blobs(&tree).unwrap_or_default()))
+            .clone();
+        // Lookups of other working trees don't have to wait for this one.
+        drop(cache);
+        blobs.get(&dir.join(name)).cloned()
+    }
+}
+
+/// # Returns
+///
+/// Blobs of the regular files in the index of the working tree at `tree` that aren't modified in
+/// it, leaving out unmerged ones.
+fn list_clean_blobs(tree: &Path) -> io::Result<Blobs> {
+    let modified = git(tree, &["diff-files", "--name-only", "-z"])?;
+    let modified: HashSet<&[u8]> = modified.split(|&b| b == 0).collect();
+    let staged = git(tree, &["ls-files", "--stage", "-z"])?;
+    let blobs = staged
+        .split(|&b| b == 0)
+        .filter_map(|entry| {
+            // Each entry is "<mode> <object> <stage>\t<path>".
+            let tab = entry.iter().position(|&b| b == b'\t')?;
+            let (fields, path) = (&entry[..tab], &entry[tab + 1..]);
+            let mut fields = fields.split(|&b| b == b' ');
+            let (mode, id, stage) = (fields.next()?, fields.next()?, fields.next()?);
+            let is_regular_file = mode == b"100644" || mode == b"100755";
+            (is_regular_file && stage == b"0" && !modified.contains(path))
+                .then(|| (tree.join(path_from_bytes(path)), id.into()))
+        })
+        .collect();
+    Ok(blobs)
+}
+
+/// # Returns
+///
+/// Standard output of `git` run with `args` in the working tree at `tree`.
+fn git(tree: &Path, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(tree)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            output.status
+        )));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    PathBuf::from(OsStr::from_bytes(path))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(path).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+    };
+
+    // Check that only files that are clean in the index have blob IDs.
+    #[test]
+    fn test_blob_id() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(root.join("tree/d")).unwrap();
+        let tree = root.join("tree");
+        for (path, contents) in [("a", "same"), ("d/b", "same"), ("c", "diff"), ("e", "e")] {
+            write(tree.join(path), contents).unwrap();
+        }
+        write(root.join("outside"), "same").unwrap();
+        if git(&tree, &["init", "-q"]).is_err() {
+            // git isn't installed
+            remove_dir_all(&root).unwrap();
+            return;
+        }
+        git(&tree, &["add", "a", "d/b", "c"]).unwrap();
+        write(tree.join("c"), "changed").unwrap();
+
+        let blobs = GitBlobs::default();
+        let a = blobs.blob_id(&tree.join("a"));
+        assert!(a.is_some());
+        assert_eq!(a, blobs.blob_id(&tree.join("d/../d/b")));
+        assert_ne!(a, blobs.blob_id(&tree.join("c")));
+        assert_eq!(None, blobs.blob_id(&tree.join("c")));
+        assert_eq!(None, blobs.blob_id(&tree.join("e")));
+        assert_eq!(None, blobs.blob_id(&root.join("outside")));
+        assert_eq!(None, blobs.blob_id(&tree.join("missing")));
+        remove_dir_all(&root).unwrap();
+    }
+}
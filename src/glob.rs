@@ -0,0 +1,166 @@
+use std::{ffi::OsStr, path::Path, str::FromStr};
+
+/// Whether paths are compared regardless of case, since the filesystems that Windows and macOS
+/// use by default are case-insensitive, and paths that only differ in case name the same file.
+pub const CASE_INSENSITIVE: bool = cfg!(any(windows, target_os = "macos"));
+
+/// Path given either literally, matching itself and everything under it, or as a glob in which
+/// `*` matches any run of characters other than `/`, `**` any run of characters at all, and `?`
+/// any one character. Globs without a `/` are matched against file names, and other globs against
+/// whole paths.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathPattern {
+    Literal(String),
+    Glob(String),
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty path pattern".to_string());
+        }
+        if s.contains(['*', '?']) {
+            Ok(PathPattern::Glob(s.to_string()))
+        } else {
+            Ok(PathPattern::Literal(s.to_string()))
+        }
+    }
+}
+
+impl PathPattern {
+    /// # Returns
+    ///
+    /// `true` if and only if `path`, which isn't valid unicode only at its own risk, matches this
+    /// pattern, regardless of case if `CASE_INSENSITIVE`.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.matches_folded(path, CASE_INSENSITIVE)
+    }
+
+    fn matches_folded(&self, path: &Path, fold: bool) -> bool {
+        let fold_str = |s: &str| {
+            if fold {
+                s.to_lowercase()
+            } else {
+                s.to_string()
+            }
+        };
+        match self {
+            PathPattern::Literal(literal) => starts_with_folded(path, Path::new(literal), fold),
+            PathPattern::Glob(glob) if glob.contains('/') => glob_matches(
+                fold_str(glob).as_bytes(),
+                fold_str(&path.to_string_lossy()).as_bytes(),
+            ),
+            PathPattern::Glob(glob) => path.file_name().is_some_and(|name| {
+                glob_matches(
+                    fold_str(glob).as_bytes(),
+                    fold_str(&name.to_string_lossy()).as_bytes(),
+                )
+            }),
+        }
+    }
+}
+
+/// # Returns
+///
+/// `true` if and only if `path` is `base` or under it, comparing their components regardless of
+/// case if `CASE_INSENSITIVE`.
+pub fn starts_with(path: &Path, base: &Path) -> bool {
+    starts_with_folded(path, base, CASE_INSENSITIVE)
+}
+
+fn starts_with_folded(path: &Path, base: &Path, fold: bool) -> bool {
+    if !fold {
+        return path.starts_with(base);
+    }
+    let mut components = path.components();
+    base.components().all(|base| {
+        components
+            .next()
+            .is_some_and(|component| eq_folded(component.as_os_str(), base.as_os_str()))
+    })
+}
+
+/// # Returns
+///
+/// `true` if and only if `a` and `b` are the same name regardless of case. Names that aren't valid
+/// unicode are compared lossily.
+pub fn eq_folded(a: &OsStr, b: &OsStr) -> bool {
+    a == b || a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+/// # Returns
+///
+/// `true` if and only if all of `s` matches `glob`.
+fn glob_matches(glob: &[u8], s: &[u8]) -> bool {
+    match glob {
+        [] => s.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=s.len()).any(|i| glob_matches(rest, &s[i..])),
+        [b'*', rest @ ..] => {
+            // Up to and including the next `/`, which `*` doesn't match.
+            let end = s.iter().position(|&c| c == b'/').unwrap_or(s.len());
+            (0..=end).any(|i| glob_matches(rest, &s[i..]))
+        }
+        [b'?', rest @ ..] => !s.is_empty() && glob_matches(rest, &s[1..]),
+        [c, rest @ ..] => s.first() == Some(c) && glob_matches(rest, &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let matches = |pattern: &str, path: &str| {
+            let pattern: PathPattern = pattern.parse().unwrap();
+            pattern.matches(Path::new(path))
+        };
+
+        assert!(matches("a/b", "a/b"));
+        assert!(matches("a/b", "a/b/c"));
+        assert!(!matches("a/b", "a/bc"));
+        assert!(matches("*.jpg", "a/b/c.jpg"));
+        assert!(!matches("*.jpg", "a/b.jpg/c"));
+        assert!(matches("a/*/c", "a/b/c"));
+        assert!(!matches("a/*/c", "a/b/b/c"));
+        assert!(matches("a/**/c", "a/b/b/c"));
+        assert!(matches("/**/c?", "/a/b/cd"));
+        assert!(!matches("/**/c?", "/a/b/c"));
+        assert!("".parse::<PathPattern>().is_err());
+    }
+
+    #[test]
+    fn test_matches_folded() {
+        let matches = |pattern: &str, path: &str, fold: bool| {
+            let pattern: PathPattern = pattern.parse().unwrap();
+            pattern.matches_folded(Path::new(path), fold)
+        };
+
+        assert!(matches("C:/Data", "c:/data/file.txt", true));
+        assert!(!matches("C:/Data", "c:/data/file.txt", false));
+        assert!(!matches("C:/Data", "c:/database", true));
+        assert!(matches("*.JPG", "a/b.jpg", true));
+        assert!(!matches("*.JPG", "a/b.jpg", false));
+        assert!(matches("A/*/c", "a/B/C", true));
+
+        assert!(starts_with_folded(
+            Path::new("/Data/File.TXT"),
+            Path::new("/data"),
+            true
+        ));
+        assert!(!starts_with_folded(
+            Path::new("/Data/File.TXT"),
+            Path::new("/data"),
+            false
+        ));
+        assert!(!starts_with_folded(
+            Path::new("/data"),
+            Path::new("/data/file.txt"),
+            true
+        ));
+        assert!(eq_folded(OsStr::new("File.TXT"), OsStr::new("file.txt")));
+        assert!(!eq_folded(OsStr::new("file.txt"), OsStr::new("file.txs")));
+    }
+}
@@ -0,0 +1,73 @@
+//! Hash functions that file contents are compared by.
+
+use sha2::{Digest, Sha256, Sha512};
+
+/// Checksum of a file's contents, as computed by a `ContentHasher`.
+pub type Checksum = Box<[u8]>;
+
+/// Hash function computed incrementally over the contents of a file, read in chunks. Files are
+/// considered duplicates if and only if their checksums are equal, so it should be collision
+/// resistant.
+pub trait ContentHasher: Send {
+    /// Feed the next chunk of contents to the hash.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// # Returns
+    ///
+    /// Checksum of all the contents fed to the hash.
+    fn finalize(self: Box<Self>) -> Checksum;
+}
+
+/// Creates the hasher of each file. A function pointer rather than a closure, so that the options
+/// holding it can be copied around freely.
+pub type NewHasher = fn() -> Box<dyn ContentHasher>;
+
+impl ContentHasher for Sha512 {
+    fn update(&mut self, bytes: &[u8]) {
+        Digest::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Checksum {
+        Box::from(&Digest::finalize(*self)[..])
+    }
+}
+
+impl ContentHasher for Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        Digest::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Checksum {
+        Box::from(&Digest::finalize(*self)[..])
+    }
+}
+
+/// SHA512, the default.
+pub fn sha512() -> Box<dyn ContentHasher> {
+    Box::new(Sha512::new())
+}
+
+pub fn sha256() -> Box<dyn ContentHasher> {
+    Box::new(Sha256::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Check that chunks are hashed the same however they're split.
+    #[test]
+    fn test_content_hasher() {
+        for new_hasher in [sha512 as NewHasher, sha256] {
+            let mut whole = new_hasher();
+            whole.update(b"abcdef");
+            let mut chunked = new_hasher();
+            chunked.update(b"ab");
+            chunked.update(b"");
+            chunked.update(b"cdef");
+            assert_eq!(whole.finalize(), chunked.finalize());
+        }
+        assert_eq!(64, sha512().finalize().len());
+        assert_eq!(32, sha256().finalize().len());
+    }
+}
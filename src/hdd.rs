@@ -0,0 +1,93 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+/// Whether candidate files are hashed in physical order, one file at a time per device.
+///
+/// Random parallel reads make a rotational disk seek constantly, which can make a parallel scan
+/// slower than a sequential one. In HDD mode, the files on each such device are sorted by inode
+/// number, which on most filesystems approximates their order on disk, and read one after the
+/// other. Different devices are still read in parallel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HddMode {
+    /// Read every file in parallel.
+    #[default]
+    Never,
+
+    /// Use HDD mode for the devices that report themselves as rotational. Detection is only
+    /// implemented on Linux; elsewhere this is equivalent to `Never`.
+    Auto,
+
+    /// Use HDD mode for every device.
+    Always,
+}
+
+impl FromStr for HddMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(HddMode::Never),
+            "auto" => Ok(HddMode::Auto),
+            "always" => Ok(HddMode::Always),
+            _ => Err(format!("unknown HDD mode: {}", s)),
+        }
+    }
+}
+
+impl HddMode {
+    /// # Returns
+    ///
+    /// `true` if and only if files on the device `dev` should be read sequentially.
+    pub fn applies_to(self, dev: u64) -> bool {
+        match self {
+            HddMode::Never => false,
+            HddMode::Auto => is_rotational(dev),
+            HddMode::Always => true,
+        }
+    }
+}
+
+/// # Returns
+///
+/// `true` if the block device `dev` is known to be rotational. Results are cached, since every
+/// candidate file asks about its device.
+fn is_rotational(dev: u64) -> bool {
+    static CACHE: OnceLock<Mutex<HashMap<u64, bool>>> = OnceLock::new();
+
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some(&rotational) = cache.lock().unwrap().get(&dev) {
+        return rotational;
+    }
+    let rotational = detect_rotational(dev);
+    cache.lock().unwrap().insert(dev, rotational);
+    rotational
+}
+
+#[cfg(target_os = "linux")]
+fn detect_rotational(dev: u64) -> bool {
+    use std::{fs::read_to_string, path::PathBuf};
+
+    let (major, minor) = unsafe { (libc::major(dev), libc::minor(dev)) };
+    let sys_path = match PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor)).canonicalize()
+    {
+        Ok(sys_path) => sys_path,
+        // Not a block device, e.g. tmpfs or a network filesystem.
+        Err(_) => return false,
+    };
+
+    // Partitions don't have a queue of their own; it belongs to the parent disk.
+    [Some(sys_path.as_path()), sys_path.parent()]
+        .iter()
+        .flatten()
+        .map(|dir| dir.join("queue/rotational"))
+        .find_map(|path| read_to_string(path).ok())
+        .is_some_and(|rotational| rotational.trim() == "1")
+}
+
+#[cfg(not(target_os = "linux"))]
+fn detect_rotational(_dev: u64) -> bool {
+    false
+}
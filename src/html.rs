@@ -0,0 +1,202 @@
+//! Self-contained HTML page reporting the results of a scan, for readers who'd rather not work
+//! from terminal output. Everything, including the script that sorts its tables, is inline.
+
+use crate::stats::{Stats, SIZE_BUCKETS};
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// Directories listed in the rollup, at most.
+const MAX_DIRECTORIES: usize = 100;
+
+/// Everything that's written to the page.
+pub struct HtmlReport<'a> {
+    pub stats: &'a Stats,
+
+    /// Groups of duplicates, and the size of each of their files.
+    pub groups: &'a [(u64, Vec<PathBuf>)],
+
+    /// Bytes that could be reclaimed in each directory, from the most to the least.
+    pub rollup: &'a [(PathBuf, u64)],
+
+    /// Paths that were left out of the groups because of errors.
+    pub errors: usize,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;\
+                     margin-bottom:2em}th,td{border:1px solid #ccc;padding:.3em .6em;\
+                     text-align:left;vertical-align:top}th{cursor:pointer;background:#eee}\
+                     td.n{text-align:right}.bar{background:#48c;height:1em}";
+
+/// Sorts the rows of a table by the column whose header was clicked, numerically if the cells
+/// have a `data-n` attribute, toggling between ascending and descending.
+const SCRIPT: &str = "document.querySelectorAll('th').forEach(function(th){\
+                      th.onclick=function(){var t=th.closest('table'),b=t.tBodies[0],\
+                      i=th.cellIndex,d=th.dataset.d=th.dataset.d==='1'?'-1':'1';\
+                      var k=function(r){var c=r.cells[i];return c.dataset.n!==undefined?\
+                      Number(c.dataset.n):c.textContent};\
+                      Array.from(b.rows).sort(function(x,y){var a=k(x),c=k(y);\
+                      return (a<c?-1:a>c?1:0)*d}).forEach(function(r){b.appendChild(r)})}})";
+
+/// Write `report` to `out` as an HTML page.
+pub fn write_html_report(out: &mut impl Write, report: &HtmlReport) -> io::Result<()> {
+    let HtmlReport {
+        stats,
+        groups,
+        rollup,
+        errors,
+    } = report;
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(
+        out,
+        "<html><head><meta charset=\"utf-8\"><title>fdup report</title>"
+    )?;
+    writeln!(out, "<style>{}</style></head><body>", STYLE)?;
+
+    writeln!(out, "<h1>Duplicate files</h1>")?;
+    writeln!(out, "<table><tbody>")?;
+    let duplicates: usize = groups.iter().map(|(_, paths)| paths.len()).sum();
+    for (name, value) in [
+        ("Files found", stats.files_found),
+        ("Groups of duplicates", groups.len() as u64),
+        ("Duplicate files", duplicates as u64),
+        ("Bytes reclaimable", stats.reclaimable_bytes),
+        ("Bytes already saved by hardlinks", stats.linked_bytes),
+        ("Paths skipped because of errors", *errors as u64),
+    ] {
+        writeln!(
+            out,
+            "<tr><th>{}</th><td class=\"n\">{}</td></tr>",
+            name, value
+        )?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    writeln!(out, "<h2>By file size</h2>")?;
+    writeln!(
+        out,
+        "<table><thead><tr><th>Size</th><th>Groups</th><th>Bytes reclaimable</th>"
+    )?;
+    writeln!(out, "<th></th></tr></thead><tbody>")?;
+    let max_bytes = stats
+        .histogram
+        .iter()
+        .map(|bucket| bucket.reclaimable_bytes)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for ((name, _), bucket) in SIZE_BUCKETS.iter().zip(stats.histogram) {
+        let width = bucket.reclaimable_bytes as f64 / max_bytes as f64 * 20.0;
+        write!(out, "<tr><td>{}</td>", escape(name))?;
+        write!(
+            out,
+            "<td class=\"n\" data-n=\"{0}\">{0}</td>",
+            bucket.groups
+        )?;
+        write!(
+            out,
+            "<td class=\"n\" data-n=\"{0}\">{0}</td>",
+            bucket.reclaimable_bytes
+        )?;
+        writeln!(
+            out,
+            "<td><div class=\"bar\" style=\"width:{:.2}em\"></div></td></tr>",
+            width
+        )?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    writeln!(out, "<h2>By directory</h2>")?;
+    writeln!(
+        out,
+        "<table><thead><tr><th>Directory</th><th>Bytes reclaimable</th></tr></thead>"
+    )?;
+    writeln!(out, "<tbody>")?;
+    for (dir, bytes) in rollup.iter().take(MAX_DIRECTORIES) {
+        write!(out, "<tr><td>{}</td>", escape(&dir.to_string_lossy()))?;
+        writeln!(out, "<td class=\"n\" data-n=\"{0}\">{0}</td></tr>", bytes)?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    writeln!(out, "<h2>Groups</h2>")?;
+    writeln!(out, "<table><thead><tr><th>File size</th><th>Copies</th>")?;
+    writeln!(
+        out,
+        "<th>Bytes reclaimable</th><th>Paths</th></tr></thead><tbody>"
+    )?;
+    for (size, paths) in groups.iter() {
+        let reclaimable = size * (paths.len() as u64 - 1);
+        write!(out, "<tr><td class=\"n\" data-n=\"{0}\">{0}</td>", size)?;
+        write!(out, "<td class=\"n\" data-n=\"{0}\">{0}</td>", paths.len())?;
+        write!(
+            out,
+            "<td class=\"n\" data-n=\"{0}\">{0}</td><td>",
+            reclaimable
+        )?;
+        for (i, path) in paths.iter().enumerate() {
+            if 0 < i {
+                write!(out, "<br>")?;
+            }
+            write!(out, "{}", escape(&path.to_string_lossy()))?;
+        }
+        writeln!(out, "</td></tr>")?;
+    }
+    writeln!(out, "</tbody></table>")?;
+
+    writeln!(out, "<script>{}</script></body></html>", SCRIPT)
+}
+
+/// # Returns
+///
+/// `s`, with the characters that HTML would otherwise interpret escaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_html_report() {
+        let stats = Stats {
+            files_found: 3,
+            reclaimable_bytes: 5,
+            ..Default::default()
+        };
+        let groups = [(5, vec![PathBuf::from("a"), PathBuf::from("<b>")])];
+        let rollup = [(PathBuf::from("."), 5)];
+        let report = HtmlReport {
+            stats: &stats,
+            groups: &groups,
+            rollup: &rollup,
+            errors: 0,
+        };
+        let mut out = Vec::new();
+        write_html_report(&mut out, &report).unwrap();
+        let html = String::from_utf8(out).unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>\n"));
+        assert!(html.contains("<tr><th>Groups of duplicates</th><td class=\"n\">1</td></tr>"));
+        assert!(html.contains("<td>a<br>&lt;b&gt;</td>"));
+        assert!(html.contains("<tr><td>.</td><td class=\"n\" data-n=\"5\">5</td></tr>"));
+        assert!(html.trim_end().ends_with("</html>"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("&lt;a href=&quot;&amp;&#39;&gt;", escape("<a href=\"&'>"));
+    }
+}
@@ -0,0 +1,30 @@
+//! Engine of fdup: finding groups of duplicate files recursively and in parallel. The `fdup`
+//! binary is a command-line interface to `fdup::group_duplicate_files`.
+
+pub mod collate;
+pub mod compact_path;
+pub mod device_reads;
+pub mod errors;
+pub mod fdup;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fstype;
+pub mod git;
+pub mod glob;
+pub mod hasher;
+pub mod hdd;
+pub mod manifest;
+pub mod mmap;
+pub mod normalize;
+pub mod open_files;
+pub mod read_only;
+pub mod retry;
+pub mod signals;
+pub mod sink;
+pub mod skips;
+pub mod sniff;
+pub mod spill;
+pub mod stats;
+pub mod throttle;
+pub mod uring;
+pub mod walk;
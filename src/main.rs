@@ -1,19 +1,187 @@
+mod acknowledge;
+mod background;
+mod baseline;
+mod by_root;
 mod clargs;
-mod fdup;
+mod config;
+mod doctor;
+mod explain;
+mod html;
+mod man;
+mod output;
+mod owners;
+mod pager;
+mod progress;
+mod remote;
+mod removable;
+mod rollup;
+mod script;
+mod split;
+mod symlinks;
+mod verify;
 
+// Brought in at the root so that the binary's own modules can refer to the library's as they
+// refer to each other.
+use ::fdup::{
+    collate, errors, fdup, fstype, git, glob, hasher, hdd, manifest, normalize, open_files,
+    read_only, retry, signals, sink, skips, sniff, stats, throttle, walk,
+};
+
+use self::fdup::*;
 use clargs::*;
-use fdup::*;
+use errors::{ErrorLog, ErrorPolicy};
+use output::{write_run_summary, write_skips, Format, ResultsWriter, RunSummary, TextWriter};
+use progress::{progress_line, Progress};
 use rayon::prelude::*;
-use std::io::prelude::*;
+use retry::RetryPolicy;
+use sink::OutputSink;
+use stats::{StatsLog, SIZE_BUCKETS};
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io::{self, prelude::*, IsTerminal},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
+use walk::WalkOptions;
 
 fn main() {
+    let mut args = with_default_subcommand(std::env::args_os().collect());
+    if args[1] == DEFAULT_SUBCOMMAND {
+        // Configuration arguments go after the subcommand, to which they belong.
+        match config::with_config_args(args.split_off(1), &long_options()) {
+            Ok(scan_args) => args.extend(scan_args),
+            Err(error) => {
+                eprintln!("fdup: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    let opt = match Command::from_iter(args) {
+        Command::Scan(opt) => opt,
+        Command::Man => {
+            man::write_man_page(&Command::clap(), &mut io::stdout().lock()).unwrap();
+            return;
+        }
+        Command::Manifest(opt) => std::process::exit(remote::run_manifest(opt)),
+        Command::Remote(opt) => std::process::exit(remote::run_remote(opt)),
+        Command::Doctor(opt) => std::process::exit(doctor::run_doctor(opt)),
+    };
     let Opt {
+        acknowledge,
+        across_dirs_only,
+        assert_read_only,
+        background,
+        backend,
+        baseline,
+        by_owner,
+        check_removable,
+        checksum_bits,
+        companions,
+        collation,
+        config: _,
+        empty,
+        error_policy,
+        exclude_fs,
+        explain,
+        explain_skips,
+        fadvise,
+        fields,
+        fingerprints,
+        format,
+        file_timeout,
+        group_must_exclude,
+        group_must_include,
+        hdd_mode,
+        histogram,
+        ignore_newer_than,
+        include_caches,
+        json,
+        keep_in,
+        list_denied,
+        lock,
+        max_bytes,
+        max_files,
+        max_memory,
+        max_open_files,
+        max_read_rate,
+        mmap_threshold,
+        no_config: _,
+        no_pager,
         num_threads,
-        root,
+        only_writable,
+        owner,
+        pager,
+        mut paranoid,
+        precount,
+        profile: _,
+        progress,
+        protect,
+        quiet,
+        readahead,
+        reads_per_device,
+        report_html,
+        report_mount_overlaps,
+        retries,
+        retry_backoff,
+        rollup,
+        roots,
+        safe,
+        same_dir_only,
+        sample,
+        sample_block_size,
+        sample_blocks,
+        sample_threshold,
+        schema_version,
+        scope_per_child,
+        script,
+        script_handlers,
+        mut script_verify,
+        silent,
+        size_census,
+        sizes_only,
+        skip_appledouble,
+        skip_hardlinked,
+        skip_invalid_names,
+        skip_junk,
+        slowest,
         sort,
-    } = Opt::from_args();
+        sparse,
+        split_by,
+        split_output,
+        stats_json,
+        summary,
+        symlinks,
+        types,
+        verify_cmd,
+        walk_order,
+        normalize,
+        git_index,
+        hash_tiers,
+    } = opt;
 
+    signals::install_handlers();
+    // Before any of rayon's threads are started, so that they inherit the priority.
+    if background {
+        if let Err(error) = background::lower_priority() {
+            eprintln!("fdup: couldn't lower the priority: {}", error);
+        }
+    }
+    if assert_read_only {
+        read_only::enforce();
+    }
+    if let Some(max_open_files) = max_open_files {
+        open_files::set_max_open_files(max_open_files);
+    }
+    if let Some(max_read_rate) = max_read_rate {
+        throttle::set_max_read_rate(max_read_rate * 1_000_000.0);
+    }
     if num_threads != 0 {
         rayon::ThreadPoolBuilder::new()
             .num_threads(num_threads)
@@ -21,8 +189,731 @@ fn main() {
             .unwrap();
     }
 
-    group_duplicate_files(sort, &root).for_each(|vec| {
-        let mut stdout = std::io::stdout().lock();
-        writeln!(stdout, "{:?}", vec).unwrap();
+    let sample_options = match sample_threshold {
+        Some(threshold) => vec![SampleOptions {
+            threshold,
+            blocks: sample_blocks,
+            block_size: sample_block_size,
+        }],
+        None => hash_tiers,
+    };
+    let hash_options = HashOptions {
+        new_hasher: hasher::sha512,
+        backend,
+        mmap_threshold,
+        fadvise,
+        sparse,
+        hdd_mode,
+        lock,
+        retry: RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(retry_backoff),
+        },
+        timeout: file_timeout.map(Duration::from_secs),
+        reads_per_device,
+        readahead,
+        checksum_len: checksum_bits.map(|bits| bits / 8),
+    };
+    // Fingerprints and the checksums that the script verifies don't depend on `--checksum-bits`.
+    let full_hash_options = HashOptions {
+        checksum_len: None,
+        ..hash_options
+    };
+
+    let safe = match (safe, &script) {
+        (true, Some(path)) => match script::Safe::next_to(path) {
+            Ok(safe) => Some(safe),
+            Err(error) => {
+                eprintln!(
+                    "fdup: {}: couldn't resolve the script: {}",
+                    path.display(),
+                    error
+                );
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    if safe.is_some() {
+        paranoid = paranoid || verify_cmd.is_none();
+        script_verify = Some(100.0);
+    }
+
+    let verify_description = if paranoid {
+        Some("byte for byte".to_string())
+    } else {
+        verify_cmd.as_ref().map(|cmd| format!("command `{}`", cmd))
+    };
+    let errors = ErrorLog::new(error_policy);
+    let stats = StatsLog::default();
+    let skips = match explain_skips {
+        Some(_) => skips::SkipLog::recording(),
+        None => skips::SkipLog::default(),
+    };
+    let options = ScanOptions {
+        sort,
+        collation,
+        max_memory,
+        max_files,
+        max_bytes,
+        sample: sample_options,
+        sample_sizes: sample.map(|percent| percent / 100.0),
+        ignore_newer_than,
+        skip_appledouble,
+        skip_junk,
+        skip_invalid_names,
+        owner,
+        only_writable,
+        types,
+        keys: if scope_per_child {
+            vec![GroupingKey::child_of_root(&roots)]
+        } else {
+            Vec::new()
+        },
+        empty,
+        skip_hardlinked,
+        spread: match (across_dirs_only, same_dir_only) {
+            (true, _) => Spread::AcrossDirs,
+            (_, true) => Spread::SameDir,
+            _ => Spread::Any,
+        },
+        keep_in,
+        protect,
+        group_must_include,
+        group_must_exclude,
+        verify: if paranoid {
+            Some(Verifier::byte_for_byte())
+        } else {
+            verify_cmd.map(verify::command_verifier)
+        },
+        normalize,
+        git: git_index.then(git::GitBlobs::default),
+        walk: WalkOptions {
+            include_caches,
+            // Symlinks are only listed by the walk when asked for.
+            symlinks: symlinks || explain_skips.is_some(),
+            exclude_fs,
+            order: walk_order,
+        },
+        precount,
+        size_census,
+        sizes_only,
+        slowest_reads: slowest.unwrap_or(0),
+        by_extension: summary,
+        hash: hash_options,
+        errors: errors.clone(),
+        stats: stats.clone(),
+        skips: skips.clone(),
+    };
+
+    let format = if json {
+        Format::Json
+    } else {
+        format.unwrap_or_default()
+    };
+    let format = if silent { Format::Text } else { format };
+    if explain {
+        let context = explain::Context {
+            roots: &roots,
+            threads: rayon::current_num_threads(),
+            keys: if scope_per_child {
+                vec!["child of root"]
+            } else {
+                Vec::new()
+            },
+            verify: verify_description,
+            max_open_files,
+            max_read_rate: max_read_rate.map(|rate| rate * 1_000_000.0),
+            format,
+            fingerprints,
+        };
+        explain::write_pipeline(&mut io::stdout().lock(), &options, &context).unwrap();
+        return;
+    }
+
+    let quiet = quiet || silent;
+    let start = Instant::now();
+    let found = AtomicBool::new(false);
+    let (groups, duplicates) = (AtomicU64::new(0), AtomicU64::new(0));
+    let acknowledged_groups = AtomicU64::new(0);
+    let html_report = report_html.as_ref().map(|_| {
+        let depth = rollup.unwrap_or(usize::MAX);
+        (Mutex::new(Vec::new()), rollup::Rollup::new(depth))
+    });
+    let rollup = rollup.map(rollup::Rollup::new);
+    let owners = by_owner.then(owners::Owners::default);
+    let by_root = (summary && 1 < roots.len()).then(|| by_root::ByRoot::new(&roots));
+    let baseline = baseline.map(|path| match baseline::Baseline::read(&path) {
+        Ok(baseline) => baseline,
+        Err(error) => {
+            eprintln!(
+                "fdup: {}: couldn't read the baseline: {}",
+                path.display(),
+                error
+            );
+            std::process::exit(1);
+        }
     });
+    let acknowledged = acknowledge.map(|path| match acknowledge::Acknowledged::read(&path) {
+        Ok(acknowledged) => acknowledged,
+        Err(error) => {
+            eprintln!(
+                "fdup: {}: couldn't read the acknowledged groups: {}",
+                path.display(),
+                error
+            );
+            std::process::exit(1);
+        }
+    });
+    let script_writer = script.as_ref().map(|path| {
+        let file = read_only::create(path).map(io::BufWriter::new);
+        match file.and_then(|out| {
+            script::ScriptWriter::new(out, &script_handlers, &companions, safe.as_ref())
+        }) {
+            Ok(writer) => writer,
+            Err(error) => {
+                eprintln!(
+                    "fdup: {}: couldn't write the script: {}",
+                    path.display(),
+                    error
+                );
+                std::process::exit(1);
+            }
+        }
+    });
+    let script_writer = Mutex::new(script_writer);
+    // The paths that the script keeps as they are, which groups list first.
+    let kept_by_script = (options.keep_in.clone(), options.protect.clone());
+    // Picks the groups whose kept files the script verifies.
+    let verify_state = RandomState::new();
+    let symlinked_groups = Mutex::new(Vec::new());
+    let split_writer = split_output.as_ref().map(|dir| {
+        let fields = fields.clone();
+        let split = split::SplitOutput::new(
+            dir,
+            split_by,
+            &roots,
+            format,
+            fields,
+            fingerprints,
+            schema_version,
+        );
+        match split {
+            Ok(split) => split,
+            Err(error) => {
+                eprintln!(
+                    "fdup: {}: couldn't create the directory: {}",
+                    dir.display(),
+                    error
+                );
+                std::process::exit(1);
+            }
+        }
+    });
+    let results_writer: Option<Box<dyn OutputSink + Send>> = match format {
+        _ if silent => None,
+        Format::Text => Some(Box::new(TextWriter::new(
+            io::stdout(),
+            fingerprints,
+            sizes_only,
+        ))),
+        format => ResultsWriter::new(format, io::stdout(), fields, fingerprints, schema_version)
+            .unwrap()
+            .map(|writer| Box::new(writer) as _),
+    };
+    let results_writer = Mutex::new(results_writer);
+    // Writes everything besides the groups, once the scan is done or interrupted.
+    let finish = |results_writer: Option<Box<dyn OutputSink + Send>>, interrupted: bool| {
+        let vanished = errors.vanished();
+        let errors = errors.take();
+        let mut stats = stats.take();
+        if let Some(path) = &explain_skips {
+            let written = read_only::create(path).and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                write_skips(&mut out, &skips.take(), &errors)?;
+                out.flush()
+            });
+            if let Err(error) = written {
+                eprintln!(
+                    "fdup: {}: couldn't write the skipped files: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        if let Some(path) = &stats_json {
+            let summary = RunSummary {
+                stats: &stats,
+                groups: groups.load(Ordering::SeqCst),
+                duplicates: duplicates.load(Ordering::SeqCst),
+                acknowledged_groups: acknowledged_groups.load(Ordering::SeqCst),
+                bytes_read: throttle::bytes_read(),
+                vanished,
+                errors: &errors,
+                duration: start.elapsed(),
+                interrupted,
+                schema_version,
+            };
+            let written = read_only::create(path).and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                write_run_summary(&mut out, &summary)?;
+                out.flush()
+            });
+            if let Err(error) = written {
+                eprintln!(
+                    "fdup: {}: couldn't write the summary: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        if let Some(rollup) = rollup.as_ref().filter(|_| !silent) {
+            for (dir, bytes) in rollup.sorted() {
+                println!("{}\t{}", bytes, dir.display());
+            }
+        }
+        if let Some(owners) = owners.as_ref().filter(|_| !silent) {
+            for (owner, bytes) in owners.sorted() {
+                println!("{}\t{}", bytes, owner);
+            }
+        }
+        if let (Some(path), Some((groups, rollup))) = (&report_html, &html_report) {
+            let mut groups = groups.lock().unwrap();
+            groups.sort_by_key(|(size, paths): &(u64, Vec<_>)| {
+                std::cmp::Reverse(size * (paths.len() as u64 - 1))
+            });
+            let report = html::HtmlReport {
+                stats: &stats,
+                groups: &groups,
+                rollup: &rollup.sorted(),
+                errors: errors.len(),
+            };
+            let written = read_only::create(path).and_then(|file| {
+                let mut out = io::BufWriter::new(file);
+                html::write_html_report(&mut out, &report)?;
+                out.flush()
+            });
+            if let Err(error) = written {
+                eprintln!(
+                    "fdup: {}: couldn't write the report: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        if let (Some(path), Some(writer)) = (&script, script_writer.lock().unwrap().take()) {
+            let written = writer
+                .finish()
+                .and_then(|mut out| out.flush())
+                .and_then(|_| script::make_executable(path));
+            if let Err(error) = written {
+                eprintln!(
+                    "fdup: {}: couldn't write the script: {}",
+                    path.display(),
+                    error
+                );
+            }
+        }
+        if let (Some(dir), Some(split)) = (&split_output, &split_writer) {
+            match split.finish() {
+                Ok(paths) if !quiet => {
+                    eprintln!("fdup: wrote the groups to {} files", paths.len());
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    eprintln!(
+                        "fdup: {}: couldn't write the groups: {}",
+                        dir.display(),
+                        error
+                    );
+                }
+            }
+        }
+        let mut empty_files = std::mem::take(&mut stats.empty_files);
+        if sort {
+            collation.sort(&mut empty_files);
+        }
+        let separate_empty_files = match empty {
+            EmptyFiles::ReportSeparately => &empty_files[..],
+            _ => &[],
+        };
+        if let Some(writer) = results_writer {
+            writer.finish(separate_empty_files, &errors).unwrap();
+        }
+        if !quiet && empty == EmptyFiles::Ignore && !empty_files.is_empty() {
+            eprintln!("fdup: ignored {} empty files", empty_files.len());
+        }
+        if !quiet && sizes_only {
+            eprintln!(
+                "fdup: the files were only grouped by size, without being read; the groups are \
+                 candidates for duplicates, not confirmed ones"
+            );
+        }
+        if !quiet && 0 < vanished {
+            eprintln!(
+                "fdup: skipped {} files that were deleted before they could be read",
+                vanished
+            );
+        }
+
+        // Scanning as a user who can't read the whole tree hits lots of these, so they're only
+        // counted unless they're asked for.
+        let (denied, errors): (Vec<_>, Vec<_>) = errors
+            .iter()
+            .partition(|error| error.error.kind() == io::ErrorKind::PermissionDenied);
+        if list_denied {
+            for error in &denied {
+                eprintln!("fdup: {}", error);
+            }
+        }
+        if !quiet && error_policy == ErrorPolicy::Warn {
+            for error in &errors {
+                eprintln!("fdup: {}", error);
+            }
+            if !errors.is_empty() {
+                eprintln!("fdup: skipped {} paths because of errors", errors.len());
+            }
+            if !denied.is_empty() {
+                eprintln!(
+                    "fdup: skipped {} paths because permission was denied",
+                    denied.len()
+                );
+            }
+        }
+
+        if report_mount_overlaps {
+            let mut overlaps = std::mem::take(&mut stats.mount_overlaps);
+            overlaps.sort();
+            for path in overlaps {
+                eprintln!(
+                    "fdup: {}: skipped, since it was already scanned through another path",
+                    path.display()
+                );
+            }
+        }
+        if stats.partition_over_memory != 0 {
+            eprintln!(
+                "fdup: files of the same size took up about {} bytes of memory, more than \
+                 --max-memory; raise it or scan fewer files at a time to keep within it",
+                stats.partition_over_memory
+            );
+        }
+        if stats.truncated {
+            eprintln!(
+                "fdup: stopped finding files at the limit of --max-files or --max-bytes; the \
+                 results are incomplete"
+            );
+        }
+
+        if symlinks {
+            let resolved = symlinks::resolve(&stats.symlinks);
+            let groups = symlinked_groups.lock().unwrap();
+            let to_duplicates = symlinks::links_to_duplicates(&resolved, &groups);
+            if !to_duplicates.is_empty() {
+                eprintln!("fdup: symlinks to duplicates:");
+            }
+            for link in to_duplicates {
+                let dangles = if link.kept {
+                    ""
+                } else {
+                    " (dangles if removed)"
+                };
+                eprintln!(
+                    "fdup:   {} -> {}{}",
+                    link.link.display(),
+                    link.target.display(),
+                    dangles
+                );
+            }
+            let same_target = symlinks::links_with_same_target(&resolved);
+            if !same_target.is_empty() {
+                eprintln!("fdup: symlinks to the same file:");
+            }
+            for (target, links) in same_target {
+                let links: Vec<_> = links
+                    .iter()
+                    .map(|link| link.display().to_string())
+                    .collect();
+                eprintln!("fdup:   {}: {}", target.display(), links.join(", "));
+            }
+        }
+        if histogram {
+            eprintln!("fdup: groups of duplicates by file size:");
+            for ((name, _), bucket) in SIZE_BUCKETS.iter().zip(stats.histogram) {
+                eprintln!(
+                    "fdup:   {:>14}: {} groups, {} bytes reclaimable",
+                    name, bucket.groups, bucket.reclaimable_bytes
+                );
+            }
+        }
+        if slowest.is_some() {
+            eprintln!("fdup: slowest files to hash:");
+            for read in &stats.slowest_reads {
+                eprintln!(
+                    "fdup:   {}: {} bytes in {:.3}s, {:.1} MB/s",
+                    read.path.display(),
+                    read.size,
+                    read.duration.as_secs_f64(),
+                    read.rate() / 1e6
+                );
+            }
+        }
+        if let Some(percent) = sample {
+            let estimate = stats.estimate_reclaimable_bytes(percent / 100.0);
+            let bytes_found = stats.bytes_found.max(1) as f64;
+            eprintln!(
+                "fdup: compared {}% of the sizes shared by more than one file; about {:.0} bytes \
+                 (± {:.0}) would be reclaimable if all were, {:.2}% (± {:.2}%) of the bytes found",
+                percent,
+                estimate.value,
+                estimate.margin,
+                estimate.value * 100.0 / bytes_found,
+                estimate.margin * 100.0 / bytes_found
+            );
+        }
+        if summary {
+            eprintln!(
+                "fdup: {} bytes already saved by hardlinks, {} more bytes reclaimable",
+                stats.linked_bytes, stats.reclaimable_bytes
+            );
+            let bytes = stats.bytes();
+            eprintln!(
+                "fdup: dedupe ratio {:.3}: {} unique bytes of {} found",
+                bytes.ratio(),
+                bytes.unique(),
+                bytes.found
+            );
+            let mut by_extension: Vec<_> = stats
+                .by_extension
+                .iter()
+                .filter(|(_, bytes)| bytes.unique() < bytes.found)
+                .collect();
+            by_extension.sort_by(|(a, a_bytes), (b, b_bytes)| {
+                (b_bytes.reclaimable, a).cmp(&(a_bytes.reclaimable, b))
+            });
+            for (extension, bytes) in by_extension {
+                let extension = match extension.as_str() {
+                    "" => "(none)".to_string(),
+                    extension => format!(".{}", extension),
+                };
+                eprintln!(
+                    "fdup:   {}: dedupe ratio {:.3}, {} unique bytes of {}",
+                    extension,
+                    bytes.ratio(),
+                    bytes.unique(),
+                    bytes.found
+                );
+            }
+            if let Some(by_root) = &by_root {
+                let found_by_root = &stats.found_by_root;
+                for (i, (within, across)) in by_root.duplicates().into_iter().enumerate() {
+                    let (files, bytes) = found_by_root.get(i).copied().unwrap_or_default();
+                    eprintln!(
+                        "fdup: {}: {} files, {} bytes; {} duplicates within it, {} files with \
+                         copies under other roots",
+                        roots[i].display(),
+                        files,
+                        bytes,
+                        within,
+                        across
+                    );
+                }
+            }
+            if acknowledged.is_some() {
+                eprintln!(
+                    "fdup: {} acknowledged groups left out",
+                    acknowledged_groups.load(Ordering::SeqCst)
+                );
+            }
+        }
+    };
+
+    // Only the text results are paged, and only on a terminal.
+    let paging = format == Format::Text
+        && !silent
+        && !no_pager
+        && io::stdout().is_terminal()
+        && pager::start(pager.as_deref());
+    let progress = progress && !paging;
+
+    // On a terminal, the progress is reported on a line of its own that's rewritten in place, and
+    // cleared before anything else is printed.
+    let terminal = progress && io::stderr().is_terminal();
+    let clear_progress = || {
+        if terminal {
+            eprint!("\r\x1b[K");
+        }
+    };
+
+    // Dropped once the scan is done.
+    let (done, scanning) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        // Once interrupted, the groups found so far are closed off with whatever else has been
+        // found, while the scan is still running. Holding both locks keeps any more groups from
+        // being written in the meantime.
+        let (results_writer, finish, stats) = (&results_writer, &finish, &stats);
+        scope.spawn(move || {
+            let interval = Duration::from_secs(if terminal { 1 } else { 30 });
+            let mut reported = Instant::now();
+            while scanning
+                .recv_timeout(Duration::from_millis(100))
+                .is_err_and(|error| error == RecvTimeoutError::Timeout)
+            {
+                if progress && interval <= reported.elapsed() {
+                    reported = Instant::now();
+                    let line = progress_line(&stats.read(|stats| {
+                        // The walk is only timed once it's done.
+                        let walked = !stats.walk_duration.is_zero();
+                        Progress {
+                            files_found: stats.files_found,
+                            files_to_find: stats.precounted_files,
+                            bytes_to_read: walked.then_some(stats.bytes_to_read),
+                            bytes_read: throttle::bytes_read(),
+                            reading_for: start.elapsed().saturating_sub(stats.walk_duration),
+                        }
+                    }));
+                    if terminal {
+                        eprint!("\r\x1b[Kfdup: {}", line);
+                    } else {
+                        eprintln!("fdup: {}", line);
+                    }
+                }
+                if signals::interrupted() {
+                    let mut results_writer = results_writer.lock().unwrap();
+                    let _stdout = io::stdout().lock();
+                    clear_progress();
+                    finish(results_writer.take(), true);
+                    eprintln!("fdup: interrupted; the results are incomplete");
+                    pager::finish();
+                    std::process::exit(130);
+                }
+            }
+            clear_progress();
+        });
+
+        group_duplicate_files_with_checksums(options, &roots).for_each(|(checksum, vec)| {
+            // The checksum computed while grouping, if any, is only a full one without
+            // `--checksum-bits`.
+            let full_checksum = checksum.clone().filter(|_| checksum_bits.is_none());
+            let fingerprint = fingerprints
+                .then(|| {
+                    full_checksum
+                        .clone()
+                        .or_else(|| fdup::get_checksum(&vec[0], full_hash_options, &errors))
+                })
+                .flatten();
+            if baseline
+                .as_ref()
+                .is_some_and(|baseline| baseline.is_known(&vec, fingerprint.as_deref()))
+            {
+                return;
+            }
+            if let Some(acknowledged) = &acknowledged {
+                let checksum = checksum
+                    .or_else(|| fingerprint.clone())
+                    .or_else(|| fdup::get_checksum(&vec[0], hash_options, &errors));
+                if checksum.is_some_and(|checksum| acknowledged.contains(&checksum)) {
+                    acknowledged_groups.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+            found.store(true, Ordering::Relaxed);
+            groups.fetch_add(1, Ordering::Relaxed);
+            duplicates.fetch_add(vec.len() as u64, Ordering::Relaxed);
+            if let Some(by_root) = &by_root {
+                by_root.add_group(&vec);
+            }
+            if check_removable {
+                for path in &vec {
+                    if let Err(reason) = removable::check_removable(path) {
+                        eprintln!(
+                            "fdup: {}: can't be removed or replaced: {}",
+                            path.display(),
+                            reason
+                        );
+                    }
+                }
+                // Hardlinks can't cross filesystems, so these can only be removed or copied over.
+                if let Ok(first) = removable::device(&vec[0]) {
+                    for path in &vec[1..] {
+                        if removable::device(path).is_ok_and(|device| device != first) {
+                            eprintln!(
+                                "fdup: {}: on a different filesystem from {}, so it can't be \
+                                 replaced by a hardlink to it",
+                                path.display(),
+                                vec[0].display()
+                            );
+                        }
+                    }
+                }
+                if let Ok(first) = removable::xattrs(&vec[0]) {
+                    for path in &vec[1..] {
+                        if removable::xattrs(path).is_ok_and(|xattrs| xattrs != first) {
+                            eprintln!(
+                                "fdup: {}: extended attributes differ from those of {}",
+                                path.display(),
+                                vec[0].display()
+                            );
+                        }
+                    }
+                }
+            }
+            if let Some((groups, rollup)) = &html_report {
+                let size = std::fs::symlink_metadata(&vec[0]).map_or(0, |metadata| metadata.len());
+                rollup.add_group(&vec);
+                groups.lock().unwrap().push((size, vec.clone()));
+            }
+            if symlinks {
+                symlinked_groups.lock().unwrap().push(vec.clone());
+            }
+            // Hashed before taking the lock, so that groups are still written in parallel.
+            let verified = script_verify
+                .filter(|&percent| {
+                    let hash = verify_state.hash_one(&vec[0]) as f64;
+                    100.0 <= percent || hash < percent / 100.0 * u64::MAX as f64
+                })
+                .and_then(|_| {
+                    fingerprint
+                        .clone()
+                        .or_else(|| full_checksum.clone())
+                        .or_else(|| fdup::get_checksum(&vec[0], full_hash_options, &errors))
+                });
+            if let Some(writer) = &mut *script_writer.lock().unwrap() {
+                let (keep_in, protect) = &kept_by_script;
+                let kept = fdup::count_kept(&vec, keep_in, protect);
+                writer.write_group(&vec, kept).unwrap();
+                if let Some(checksum) = verified {
+                    writer.verify(&vec[0], checksum);
+                }
+            }
+            if let Some(rollup) = &rollup {
+                rollup.add_group(&vec);
+                return;
+            }
+            if let Some(owners) = &owners {
+                owners.add_group(&vec);
+                return;
+            }
+            if let Some(split) = &split_writer {
+                if let Err(error) = split.write_group(&vec, fingerprint.as_deref()) {
+                    eprintln!("fdup: couldn't write a group: {}", error);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            if let Some(writer) = &mut *results_writer.lock().unwrap() {
+                if format == Format::Text {
+                    clear_progress();
+                }
+                writer.write_group(&vec, fingerprint.as_deref()).unwrap();
+            }
+        });
+        drop(done);
+    });
+    finish(results_writer.into_inner().unwrap(), false);
+    pager::finish();
+
+    if silent && found.into_inner() {
+        std::process::exit(2);
+    }
 }
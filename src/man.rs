@@ -0,0 +1,207 @@
+//! Man page generated from the definitions of the command-line arguments, so that it never falls
+//! out of date with them.
+
+use crate::clargs::DEFAULT_SUBCOMMAND;
+use std::io::{self, Write};
+use structopt::clap::App;
+
+/// Sections written after the options, which aren't described by the arguments themselves.
+const SECTIONS: &[(&str, &str)] = &[
+    (
+        "OUTPUT",
+        "Each group of duplicate files is printed on a line of its own, as a list of paths. With \
+         --json, the results are printed as a single JSON document instead, with the groups, the \
+         empty files reported on their own and the errors because of which paths were left out \
+         of the groups. With --format msgpack, they're printed as a stream of MessagePack \
+         objects: an array of paths for each group, then a map of the empty files and the \
+         errors. Errors and counts of skipped files are reported on stderr.",
+    ),
+    (
+        "SIGNALS",
+        "SIGUSR1 pauses reading file contents, once the reads already in progress are done, and \
+         reports how many bytes were read so far on stderr. SIGUSR2 resumes the scan. SIGINT \
+         interrupts it, once the groups found so far have been written out along with everything \
+         else that's reported at the end of a scan.",
+    ),
+    (
+        "EXIT STATUS",
+        "0 if the scan finished, even if some paths were skipped because of errors. 1 if the \
+         arguments are invalid. 2 if duplicates were found, with --silent. 101 if the scan was \
+         aborted because of an error, with --errors fail. 130 if the scan was interrupted by \
+         SIGINT.",
+    ),
+];
+
+/// Write the man page of `app` to `out`, in roff. The options described are those of its
+/// `DEFAULT_SUBCOMMAND`.
+pub fn write_man_page(app: &App, out: &mut impl Write) -> io::Result<()> {
+    // clap 2 has no API to list the arguments of an app, but the fields of its parser are public,
+    // if left out of its docs.
+    let meta = &app.p.meta;
+    let about = meta.long_about.or(meta.about).unwrap_or_default();
+    let (summary, description) = about.split_once('\n').unwrap_or((about, ""));
+    let name = escape(&meta.name);
+    let scan = app
+        .p
+        .subcommands
+        .iter()
+        .find(|subcommand| subcommand.p.meta.name == DEFAULT_SUBCOMMAND)
+        .unwrap_or(app);
+
+    writeln!(out, ".TH {} 1", meta.name.to_uppercase())?;
+    writeln!(out, ".SH NAME")?;
+    writeln!(out, "{} \\- {}", name, escape(summary.trim()))?;
+    writeln!(out, ".SH SYNOPSIS")?;
+    for (i, subcommand) in app.p.subcommands.iter().enumerate() {
+        if 0 < i {
+            writeln!(out, ".br")?;
+        }
+        let subcommand_name = escape(&subcommand.p.meta.name);
+        if subcommand.p.meta.name == DEFAULT_SUBCOMMAND {
+            write!(out, "\\fB{}\\fR [\\fB{}\\fR]", name, subcommand_name)?;
+        } else {
+            write!(out, "\\fB{} {}\\fR", name, subcommand_name)?;
+        }
+        if !subcommand.p.flags.is_empty() || !subcommand.p.opts.is_empty() {
+            write!(out, " [OPTIONS]")?;
+        }
+        for positional in subcommand.p.positionals.values() {
+            write!(out, " \\fI{}\\fR...", escape(positional.b.name))?;
+        }
+        writeln!(out)?;
+    }
+    if !description.trim().is_empty() {
+        writeln!(out, ".SH DESCRIPTION")?;
+        writeln!(out, "{}", paragraphs(description))?;
+    }
+    writeln!(out, ".SH COMMANDS")?;
+    for subcommand in &app.p.subcommands {
+        let about = subcommand.p.meta.long_about.or(subcommand.p.meta.about);
+        writeln!(out, ".TP")?;
+        writeln!(out, "\\fB{}\\fR", escape(&subcommand.p.meta.name))?;
+        let about = paragraphs(about.unwrap_or_default());
+        // Trailing periods are stripped from single paragraphs of help.
+        let period = if about.ends_with('.') { "" } else { "." };
+        writeln!(out, "{}{}", about, period)?;
+    }
+
+    let mut options: Vec<(&str, Option<char>, Option<&str>, &str)> = Vec::new();
+    for flag in &scan.p.flags {
+        let help = flag.b.long_help.or(flag.b.help).unwrap_or_default();
+        let long = flag.s.long.unwrap_or(flag.b.name);
+        options.push((long, flag.s.short, None, help));
+    }
+    for opt in &scan.p.opts {
+        let help = opt.b.long_help.or(opt.b.help).unwrap_or_default();
+        let long = opt.s.long.unwrap_or(opt.b.name);
+        options.push((long, opt.s.short, Some(opt.b.name), help));
+    }
+    options.sort_by_key(|(long, _, _, _)| *long);
+
+    writeln!(out, ".SH OPTIONS")?;
+    for (long, short, value, help) in options {
+        writeln!(out, ".TP")?;
+        if let Some(short) = short {
+            write!(out, "\\fB\\-{}\\fR, ", short)?;
+        }
+        match value {
+            Some(value) => {
+                let (long, value) = (escape(long), escape(value));
+                writeln!(out, "\\fB\\-\\-{}\\fR \\fI{}\\fR", long, value)?
+            }
+            None => writeln!(out, "\\fB\\-\\-{}\\fR", escape(long))?,
+        }
+        // ...and from the help of arguments, which is always a single paragraph.
+        write!(out, "{}.", escape(help))?;
+        if let Some(opt) = scan.p.opts.iter().find(|opt| opt.s.long == Some(long)) {
+            if let Some(values) = &opt.v.possible_vals {
+                write!(out, " Possible values: {}.", escape(&values.join(", ")))?;
+            }
+            if let Some(default) = opt.v.default_val {
+                write!(out, " Defaults to {}.", escape(&default.to_string_lossy()))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    for positional in scan.p.positionals.values() {
+        let help = positional.b.long_help.or(positional.b.help);
+        writeln!(out, ".TP")?;
+        writeln!(out, "\\fI{}\\fR", escape(positional.b.name))?;
+        writeln!(out, "{}.", escape(help.unwrap_or_default()))?;
+    }
+
+    for (title, text) in SECTIONS {
+        writeln!(out, ".SH {}", title)?;
+        writeln!(out, "{}", escape(text))?;
+    }
+    Ok(())
+}
+
+/// # Returns
+///
+/// `s` escaped, with a paragraph break between each of its paragraphs.
+fn paragraphs(s: &str) -> String {
+    escape(s.trim()).replace("\n\n", "\n.PP\n")
+}
+
+/// # Returns
+///
+/// `s`, with the characters that roff would otherwise interpret escaped.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for line in s.lines() {
+        if !escaped.is_empty() {
+            escaped.push('\n');
+        }
+        if line.starts_with('.') || line.starts_with('\'') {
+            escaped.push_str("\\&");
+        }
+        for c in line.chars() {
+            match c {
+                '\\' => escaped.push_str("\\e"),
+                '-' => escaped.push_str("\\-"),
+                c => escaped.push(c),
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clargs::Command;
+    use structopt::StructOpt;
+
+    // Check that every argument is documented.
+    #[test]
+    fn test_write_man_page() {
+        let app = Command::clap();
+        let mut man_page = Vec::new();
+        write_man_page(&app, &mut man_page).unwrap();
+        let man_page = String::from_utf8(man_page).unwrap();
+
+        assert!(man_page.starts_with(".TH FDUP 1\n"));
+        assert!(man_page.contains("\n\\fBfdup\\fR [\\fBscan\\fR] [OPTIONS] \\fIroots\\fR...\n"));
+        assert!(man_page.contains("\n\\fBfdup man\\fR\n"));
+        let app = &app.p.subcommands[0];
+        for opt in &app.p.opts {
+            let long = escape(opt.s.long.unwrap());
+            assert!(man_page.contains(&format!("\\fB\\-\\-{}\\fR \\fI", long)));
+        }
+        for flag in &app.p.flags {
+            let long = escape(flag.s.long.unwrap());
+            assert!(man_page.contains(&format!("\\fB\\-\\-{}\\fR\n", long)));
+        }
+        assert!(man_page.contains(
+            "nonzero exit status. Possible values: skip, warn, fail. Defaults to warn.\n"
+        ));
+        assert!(man_page.contains(".SH EXIT STATUS\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a\\-b \\ec", escape("a-b \\c"));
+        assert_eq!("\\&.a\n\\&'b", escape(".a\n'b"));
+    }
+}
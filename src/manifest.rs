@@ -0,0 +1,247 @@
+//! Manifests of the files under some roots: their paths, sizes and checksums, written out so that
+//! trees on different machines can be compared by what's listed in them, without copying any of
+//! their contents across.
+
+use crate::{
+    fdup::{get_checksum, is_excluded, Checksum, ScanOptions},
+    walk::walk,
+};
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::ControlFlow,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// What every manifest starts with, followed by its entries.
+const HEADER: &[u8] = b"fdup manifest 1\n";
+
+/// File listed in a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Checksum of its whole contents, as computed with `ScanOptions::hash`.
+    pub checksum: Checksum,
+}
+
+/// # Returns
+///
+/// Entries of the regular files under `roots` whose sizes are `wanted`, in no particular order.
+/// Files are walked and excluded as by `group_duplicate_files`, except that every one of them is
+/// hashed in full, whether or not another has the same size. Files that couldn't be read are left
+/// out, and their errors recorded in `options.errors`.
+pub fn entries<'a>(
+    options: &'a ScanOptions,
+    roots: &[PathBuf],
+    wanted: impl Fn(u64) -> bool + Sync,
+) -> impl ParallelIterator<Item = Entry> + 'a {
+    let found = Mutex::new(Vec::new());
+    walk(roots, options.walk, &options.errors, |files| {
+        let files = files.into_iter().filter(|(path, metadata)| {
+            !metadata.file_type().is_symlink()
+                && !is_excluded(path.file_name(), options)
+                && (!options.skip_invalid_names || path.is_unicode())
+                && wanted(metadata.len())
+        });
+        let files: Vec<_> = files
+            .map(|(path, metadata)| (path.to_path_buf(), metadata.len()))
+            .collect();
+        found.lock().unwrap().extend(files);
+        ControlFlow::Continue(())
+    });
+    found
+        .into_inner()
+        .unwrap()
+        .into_par_iter()
+        .filter_map(move |(path, size)| {
+            let checksum = get_checksum(&path, options.hash, &options.errors)?;
+            Some(Entry {
+                path,
+                size,
+                checksum,
+            })
+        })
+}
+
+/// Write the manifest of `entries` to `out`, as they're found.
+pub fn write_manifest(
+    out: &mut (impl Write + Send),
+    entries: impl ParallelIterator<Item = Entry>,
+) -> io::Result<()> {
+    out.write_all(HEADER)?;
+    let out = Mutex::new(out);
+    entries.try_for_each(|entry| write_entry(&mut *out.lock().unwrap(), &entry))?;
+    out.into_inner().unwrap().flush()
+}
+
+/// Write `entry` as `<size> <checksum in hex> <path>`, terminated by a NUL byte, since that's the
+/// only byte that paths can't contain.
+fn write_entry(out: &mut impl Write, entry: &Entry) -> io::Result<()> {
+    write!(out, "{} ", entry.size)?;
+    for byte in entry.checksum.iter() {
+        write!(out, "{:02x}", byte)?;
+    }
+    out.write_all(b" ")?;
+    out.write_all(&path_to_bytes(&entry.path))?;
+    out.write_all(b"\0")
+}
+
+/// # Returns
+///
+/// Entries of the manifest read from `input`, as written by `write_manifest`.
+pub fn read_manifest(input: &mut impl BufRead) -> io::Result<Vec<Entry>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+
+    let mut header = vec![0; HEADER.len()];
+    input
+        .read_exact(&mut header)
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("not a manifest"),
+            _ => error,
+        })?;
+    if header != HEADER {
+        return Err(invalid("not a manifest"));
+    }
+
+    let mut entries = Vec::new();
+    let mut record = Vec::new();
+    while input.read_until(b'\0', &mut record)? != 0 {
+        if record.pop() != Some(b'\0') {
+            return Err(invalid("truncated manifest"));
+        }
+        let mut fields = record.splitn(3, |&b| b == b' ');
+        let (size, checksum, path) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(size), Some(checksum), Some(path)) => (size, checksum, path),
+            _ => return Err(invalid("malformed entry in manifest")),
+        };
+        let size = std::str::from_utf8(size).ok().and_then(|s| s.parse().ok());
+        match (size, parse_hex(checksum)) {
+            (Some(size), Some(checksum)) if !path.is_empty() => entries.push(Entry {
+                path: path_from_bytes(path),
+                size,
+                checksum,
+            }),
+            _ => return Err(invalid("malformed entry in manifest")),
+        }
+        record.clear();
+    }
+    Ok(entries)
+}
+
+/// # Returns
+///
+/// Bytes spelled by `hex`, two digits per byte, or `None` if it isn't valid hex.
+fn parse_hex(hex: &[u8]) -> Option<Checksum> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    hex.chunks(2)
+        .map(|pair| Some((digit(pair[0])? * 16 + digit(pair[1])?) as u8))
+        .collect()
+}
+
+/// # Returns
+///
+/// Entries of `a` and of `b` with the same size and checksum, grouped into the entries of each,
+/// for each size and checksum that both have entries of, in no particular order.
+pub fn match_entries(
+    a: impl IntoIterator<Item = Entry>,
+    b: impl IntoIterator<Item = Entry>,
+) -> Vec<(Vec<Entry>, Vec<Entry>)> {
+    type Groups = HashMap<(u64, Checksum), (Vec<Entry>, Vec<Entry>)>;
+    let mut groups: Groups = HashMap::new();
+    for entry in a {
+        let key = (entry.size, entry.checksum.clone());
+        groups.entry(key).or_default().0.push(entry);
+    }
+    for entry in b {
+        if let Some((_, b)) = groups.get_mut(&(entry.size, entry.checksum.clone())) {
+            b.push(entry);
+        }
+    }
+    groups
+        .into_values()
+        .filter(|(_, b)| !b.is_empty())
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &std::path::Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    PathBuf::from(OsStr::from_bytes(path))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(path).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+    };
+
+    // Check that manifests are read back as they're written, and grouped by their entries.
+    #[test]
+    fn test_manifest() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(root.join("d")).unwrap();
+        for (path, contents) in [("a", "same"), ("d/b c", "same"), ("c", "diff"), ("e", "")] {
+            write(root.join(path), contents).unwrap();
+        }
+
+        let options = ScanOptions::default();
+        let roots = [root.clone()];
+        let mut manifest = Vec::new();
+        write_manifest(&mut manifest, entries(&options, &roots, |_| true)).unwrap();
+        let mut read = read_manifest(&mut &manifest[..]).unwrap();
+        read.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut written: Vec<Entry> = entries(&options, &roots, |_| true).collect();
+        written.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(4, read.len());
+        assert_eq!(written, read);
+
+        let sized: Vec<Entry> = entries(&options, &roots, |size| size == 4).collect();
+        assert_eq!(3, sized.len());
+        let (a, others): (Vec<_>, Vec<_>) = sized
+            .into_iter()
+            .partition(|entry| entry.path == root.join("a"));
+        let matched = match_entries(a.clone(), others);
+        assert_eq!(1, matched.len());
+        assert_eq!(a, matched[0].0);
+        let paths: Vec<_> = matched[0].1.iter().map(|entry| &entry.path).collect();
+        assert_eq!(vec![&root.join("d/b c")], paths);
+
+        assert!(read_manifest(&mut &b"[\"a\", \"b\"]\n"[..]).is_err());
+        assert!(read_manifest(&mut &manifest[..manifest.len() - 1]).is_err());
+        let mut malformed = HEADER.to_vec();
+        malformed.extend(b"4 abc /a\0");
+        assert!(read_manifest(&mut &malformed[..]).is_err());
+        assert_eq!(Some(vec![0x0a, 0xff].into()), parse_hex(b"0aFF"));
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fs::File, io, ops::Deref};
+
+/// Read-only, private memory mapping of an entire file.
+///
+/// The mapping is released when this is dropped. If the underlying file is truncated by another
+/// process while it's mapped, reading the truncated region raises `SIGBUS`; callers should only
+/// map files they expect to stay put for the duration of the read.
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// The mapping is read-only and never aliased mutably, so it can be shared between threads.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// # Returns
+    ///
+    /// Mapping of the first `len` bytes of `file`, or an error if the platform doesn't support
+    /// memory mapping or the mapping could not be created.
+    #[cfg(unix)]
+    pub fn map(file: &File, len: usize) -> io::Result<Mmap> {
+        use std::{os::unix::io::AsRawFd, ptr};
+
+        // `mmap` rejects zero-length mappings.
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot map an empty file",
+            ));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The mapping is read front to back exactly once; a failed hint is harmless.
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+        unsafe {
+            libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        }
+
+        Ok(Mmap {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn map(_file: &File, _len: usize) -> io::Result<Mmap> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "memory mapping is not supported on this platform",
+        ))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
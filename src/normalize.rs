@@ -0,0 +1,422 @@
+//! Checksums of documents that leave out the parts of them that change every time they're saved,
+//! so that saves of the same document are duplicates even though their bytes differ.
+
+use crate::{
+    fdup::open_regular_file,
+    hasher::{Checksum, ContentHasher, NewHasher},
+    open_files,
+    throttle::throttle,
+};
+use std::{convert::TryInto, io::Read, path::Path, str::FromStr};
+
+/// Kind of document whose volatile parts are left out of its checksum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Normalize {
+    /// PDF documents, named `*.pdf`, whose creation and modification dates and IDs are left out,
+    /// along with the cross-reference offsets that change with their lengths.
+    Pdf,
+
+    /// Zip containers, like Office and OpenDocument files, named as in `ZIP_EXTENSIONS`, whose
+    /// members are compared in the order of their names, leaving out their timestamps.
+    Zip,
+}
+
+/// Extensions of the files that are compared as zip containers.
+const ZIP_EXTENSIONS: [&str; 9] = [
+    "zip", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub", "jar",
+];
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pdf" => Ok(Normalize::Pdf),
+            "zip" => Ok(Normalize::Zip),
+            _ => Err(format!("unknown kind of document: {}", s)),
+        }
+    }
+}
+
+impl Normalize {
+    /// # Returns
+    ///
+    /// `true` if and only if the file at `path` is this kind of document, going by its extension,
+    /// since there's no telling without reading it.
+    pub fn applies_to(self, path: &Path) -> bool {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        match self {
+            Normalize::Pdf => extension.eq_ignore_ascii_case("pdf"),
+            Normalize::Zip => ZIP_EXTENSIONS
+                .iter()
+                .any(|zip| extension.eq_ignore_ascii_case(zip)),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Checksum of the file at `path`, computed with a hasher created by `new_hasher`, leaving out
+    /// its volatile parts. The whole file is read into memory. Zip containers that can't be parsed
+    /// are hashed as they are.
+    pub fn checksum(self, path: &Path, new_hasher: NewHasher) -> std::io::Result<Checksum> {
+        let _permit = open_files::acquire(1);
+        let mut contents = Vec::new();
+        open_regular_file(path)?.read_to_end(&mut contents)?;
+        throttle(contents.len());
+        let mut hasher = new_hasher();
+        match self {
+            Normalize::Pdf => hash_pdf(&contents, &mut *hasher),
+            Normalize::Zip => match zip_members(&contents) {
+                Some(members) => hash_zip_members(members, &mut *hasher),
+                None => hasher.update(&contents),
+            },
+        }
+        Ok(hasher.finalize())
+    }
+}
+
+/// Keys of the document information dictionary and of the trailer whose values change whenever a
+/// document is saved.
+const PDF_KEYS: [&[u8]; 3] = [b"/CreationDate", b"/ModDate", b"/ID"];
+
+/// Properties of XMP metadata whose values change whenever a document is saved, written either as
+/// elements or as attributes.
+const XMP_PROPERTIES: [&[u8]; 5] = [
+    b"xmp:CreateDate",
+    b"xmp:ModifyDate",
+    b"xmp:MetadataDate",
+    b"xmpMM:DocumentID",
+    b"xmpMM:InstanceID",
+];
+
+/// Feed `pdf` to `hasher`, leaving out the values of `PDF_KEYS` and `XMP_PROPERTIES`, the
+/// cross-reference table and the offset of the last one. Compressed streams are hashed as they are,
+/// so metadata in them isn't left out.
+fn hash_pdf(pdf: &[u8], hasher: &mut dyn ContentHasher) {
+    // Start of the bytes that haven't been fed to `hasher` yet.
+    let mut kept = 0;
+    let mut i = 0;
+    while i < pdf.len() {
+        match volatile_value(pdf, i) {
+            Some((start, end)) => {
+                hasher.update(&pdf[kept..start]);
+                kept = end;
+                i = end.max(i + 1);
+            }
+            None => i += 1,
+        }
+    }
+    hasher.update(&pdf[kept.min(pdf.len())..]);
+}
+
+/// # Returns
+///
+/// Range of the volatile value of the key or keyword at `i` in `pdf`, if one starts there.
+fn volatile_value(pdf: &[u8], i: usize) -> Option<(usize, usize)> {
+    let rest = &pdf[i..];
+    match rest[0] {
+        b'/' => {
+            let key = PDF_KEYS.iter().find(|key| {
+                rest.starts_with(key) && pdf.get(i + key.len()).is_none_or(|&b| is_delimiter(b))
+            })?;
+            let start = skip_whitespace(pdf, i + key.len());
+            Some((start, skip_object(pdf, start)))
+        }
+        b'x' if rest.starts_with(b"xmp") => {
+            let property = XMP_PROPERTIES
+                .iter()
+                .find(|property| rest.starts_with(property))?;
+            let start = i + property.len();
+            match (pdf.get(start), pdf.get(start + 1)) {
+                (Some(b'>'), _) => Some((start + 1, find(pdf, start + 1, b'<'))),
+                (Some(b'='), Some(&quote)) if quote == b'"' || quote == b'\'' => {
+                    Some((start + 2, find(pdf, start + 2, quote)))
+                }
+                _ => None,
+            }
+        }
+        // Offsets change along with the lengths of the values before them.
+        b'x' if rest.starts_with(b"xref")
+            && (i == 0 || is_whitespace(pdf[i - 1]))
+            && pdf.get(i + 4).is_some_and(|&b| is_whitespace(b)) =>
+        {
+            let end = pdf[i..]
+                .windows(b"trailer".len())
+                .position(|window| window == b"trailer")
+                .map_or(pdf.len(), |position| i + position);
+            Some((i + 4, end))
+        }
+        b's' if rest.starts_with(b"startxref") => {
+            let start = skip_whitespace(pdf, i + b"startxref".len());
+            let digits = pdf[start..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count();
+            Some((start, start + digits))
+        }
+        _ => None,
+    }
+}
+
+/// # Returns
+///
+/// Index just past the object that starts at `i` in `pdf`: a string, an array, or any other token.
+fn skip_object(pdf: &[u8], i: usize) -> usize {
+    match pdf.get(i) {
+        None => pdf.len(),
+        Some(b'(') => {
+            // Parentheses nest unless they're escaped.
+            let mut depth = 0;
+            let mut j = i;
+            while j < pdf.len() {
+                match pdf[j] {
+                    b'\\' => j += 1,
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return j + 1;
+                        }
+                    }
+                    _ => {}
+                }
+                j += 1;
+            }
+            pdf.len()
+        }
+        Some(b'<') => (find(pdf, i, b'>') + 1).min(pdf.len()),
+        Some(b'[') => {
+            let mut j = skip_whitespace(pdf, i + 1);
+            while j < pdf.len() && pdf[j] != b']' {
+                j = skip_whitespace(pdf, skip_object(pdf, j));
+            }
+            (j + 1).min(pdf.len())
+        }
+        Some(_) => {
+            let token = pdf[i..].iter().take_while(|&&b| !is_delimiter(b)).count();
+            i + token.max(1)
+        }
+    }
+}
+
+/// # Returns
+///
+/// Index of the first `byte` at or after `i` in `pdf`, or its length if there's none.
+fn find(pdf: &[u8], i: usize, byte: u8) -> usize {
+    pdf[i.min(pdf.len())..]
+        .iter()
+        .position(|&b| b == byte)
+        .map_or(pdf.len(), |position| i + position)
+}
+
+fn skip_whitespace(pdf: &[u8], i: usize) -> usize {
+    i + pdf[i.min(pdf.len())..]
+        .iter()
+        .take_while(|&&b| is_whitespace(b))
+        .count()
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b'\0' | b'\t' | b'\n' | b'\x0c' | b'\r' | b' ')
+}
+
+fn is_delimiter(b: u8) -> bool {
+    is_whitespace(b) || b"()<>[]{}/%".contains(&b)
+}
+
+/// Member of a zip container, as listed in its central directory.
+#[derive(Debug, PartialEq, Eq)]
+struct ZipMember<'a> {
+    name: &'a [u8],
+    method: u16,
+    crc32: u32,
+    size: u32,
+    /// Contents of the member, as compressed with `method`.
+    data: &'a [u8],
+}
+
+/// Feed the names, compression methods, CRC-32s, sizes and compressed contents of `members` to
+/// `hasher`, in the order of their names, so that their order and timestamps don't matter.
+/// Members compressed differently are told apart, since their contents can't be decompressed to
+/// compare them.
+fn hash_zip_members(mut members: Vec<ZipMember>, hasher: &mut dyn ContentHasher) {
+    members.sort_by_key(|member| member.name);
+    for member in members {
+        hasher.update(&(member.name.len() as u64).to_le_bytes());
+        hasher.update(member.name);
+        hasher.update(&member.method.to_le_bytes());
+        hasher.update(&member.crc32.to_le_bytes());
+        hasher.update(&member.size.to_le_bytes());
+        hasher.update(&(member.data.len() as u64).to_le_bytes());
+        hasher.update(member.data);
+    }
+}
+
+/// # Returns
+///
+/// Members of the zip container `zip`, as listed by its central directory. `None` if it isn't one,
+/// or if it's a Zip64 one, which isn't supported.
+fn zip_members(zip: &[u8]) -> Option<Vec<ZipMember<'_>>> {
+    let u16_at = |i: usize| Some(u16::from_le_bytes(zip.get(i..i + 2)?.try_into().ok()?));
+    let u32_at = |i: usize| Some(u32::from_le_bytes(zip.get(i..i + 4)?.try_into().ok()?));
+
+    // The end of central directory record is followed by a comment of up to 64 KiB.
+    let search_from = zip.len().saturating_sub(22 + u16::MAX as usize);
+    let end = (search_from..zip.len().saturating_sub(21))
+        .rev()
+        .find(|&i| u32_at(i) == Some(0x0605_4b50))?;
+    let count = u16_at(end + 10)?;
+    let mut entry = u32_at(end + 16)? as usize;
+    if count == u16::MAX || entry == u32::MAX as usize {
+        return None;
+    }
+
+    let mut members = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        if u32_at(entry)? != 0x0201_4b50 {
+            return None;
+        }
+        let compressed_size = u32_at(entry + 20)?;
+        let size = u32_at(entry + 24)?;
+        let name_len = u16_at(entry + 28)? as usize;
+        let local = u32_at(entry + 42)?;
+        if compressed_size == u32::MAX || size == u32::MAX || local == u32::MAX {
+            return None;
+        }
+        let local = local as usize;
+        if u32_at(local)? != 0x0403_4b50 {
+            return None;
+        }
+        let data_start = local + 30 + u16_at(local + 26)? as usize + u16_at(local + 28)? as usize;
+        members.push(ZipMember {
+            name: zip.get(entry + 46..entry + 46 + name_len)?,
+            method: u16_at(entry + 10)?,
+            crc32: u32_at(entry + 16)?,
+            size,
+            data: zip.get(data_start..data_start + compressed_size as usize)?,
+        });
+        let extra_len = u16_at(entry + 30)? as usize;
+        let comment_len = u16_at(entry + 32)? as usize;
+        entry += 46 + name_len + extra_len + comment_len;
+    }
+    Some(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::sha512;
+
+    fn pdf_checksum(pdf: &[u8]) -> Checksum {
+        let mut hasher = sha512();
+        hash_pdf(pdf, &mut *hasher);
+        hasher.finalize()
+    }
+
+    // Check that saves of the same document hash the same, and different documents don't.
+    #[test]
+    fn test_hash_pdf() {
+        let pdf = |date: &str, id: &str, offset: &str, text: &str| {
+            format!(
+                "%PDF-1.4\n1 0 obj\n<< /Title (a) /CreationDate (D:{0}) /ModDate (D:{0}) >>\n\
+                 endobj\n2 0 obj\n<< /Length 9 >>\nstream\n<x:xmpmeta><xmp:CreateDate>{0}\
+                 </xmp:CreateDate><rdf:Description xmpMM:DocumentID=\"{1}\"/></x:xmpmeta>\n\
+                 endstream\nendobj\n3 0 obj\n({3})\nendobj\nxref\n0 4\n{2} 00000 n \n\
+                 trailer\n<< /Info 1 0 R /ID [<{1}> <{1}>] >>\nstartxref\n{2}\n%%EOF\n",
+                date, id, offset, text
+            )
+            .into_bytes()
+        };
+        let original = pdf("20200101", "abc", "0000000009", "text");
+        let resaved = pdf("20211231235959Z", "defghi", "0000001234", "text");
+        let edited = pdf("20200101", "abc", "0000000009", "edit");
+        assert_eq!(pdf_checksum(&original), pdf_checksum(&resaved));
+        assert_ne!(pdf_checksum(&original), pdf_checksum(&edited));
+        // keys that only start like volatile ones aren't left out
+        assert_ne!(
+            pdf_checksum(b"<< /IDTree (a) >>"),
+            pdf_checksum(b"<< /IDTree (b) >>")
+        );
+    }
+
+    #[test]
+    fn test_skip_object() {
+        assert_eq!(9, skip_object(b"(a(b)\\)c) d", 0));
+        assert_eq!(5, skip_object(b"<0a1> d", 0));
+        assert_eq!(12, skip_object(b"[<01> (a]) ] d", 0));
+        assert_eq!(2, skip_object(b"12 0 R", 0));
+        // truncated objects end with the document
+        assert_eq!(3, skip_object(b"(ab", 0));
+        assert_eq!(4, skip_object(b"[(a)", 0));
+    }
+
+    /// # Returns
+    ///
+    /// Zip container of `members`, each a name, its contents, stored uncompressed, and the time
+    /// it was last modified at, in the order given.
+    fn zip(members: &[(&str, &str, u16)]) -> Vec<u8> {
+        let (mut zip, mut central_directory) = (Vec::new(), Vec::new());
+        for (name, contents, time) in members {
+            let header = |signature: u32, version: &[u8]| {
+                let mut header = signature.to_le_bytes().to_vec();
+                header.extend(version);
+                header.extend([0, 0, 0, 0]); // flags, method
+                header.extend(time.to_le_bytes());
+                header.extend([0, 0]); // date
+                header.extend((contents.len() as u32 ^ 0xabcd).to_le_bytes()); // "CRC-32"
+                header.extend((contents.len() as u32).to_le_bytes());
+                header.extend((contents.len() as u32).to_le_bytes());
+                header.extend((name.len() as u16).to_le_bytes());
+                header.extend([0, 0]); // extra field
+                header
+            };
+            let mut entry = header(0x0201_4b50, &[20, 0, 20, 0]);
+            entry.extend([0; 10]); // comment, disk, attributes
+            entry.extend((zip.len() as u32).to_le_bytes());
+            entry.extend(name.as_bytes());
+            central_directory.extend(entry);
+            zip.extend(header(0x0403_4b50, &[20, 0]));
+            zip.extend(name.as_bytes());
+            zip.extend(contents.as_bytes());
+        }
+        let offset = zip.len() as u32;
+        zip.extend(&central_directory);
+        zip.extend(0x0605_4b50u32.to_le_bytes());
+        zip.extend([0; 4]); // disks
+        zip.extend((members.len() as u16).to_le_bytes());
+        zip.extend((members.len() as u16).to_le_bytes());
+        zip.extend((central_directory.len() as u32).to_le_bytes());
+        zip.extend(offset.to_le_bytes());
+        zip.extend([0, 0]); // comment
+        zip
+    }
+
+    fn zip_checksum(zip: &[u8]) -> Option<Checksum> {
+        let mut hasher = sha512();
+        hash_zip_members(zip_members(zip)?, &mut *hasher);
+        Some(hasher.finalize())
+    }
+
+    // Check that containers of the same members hash the same, whatever their order and
+    // timestamps, and different ones don't.
+    #[test]
+    fn test_hash_zip_members() {
+        let original = zip(&[("a.xml", "<a/>", 1), ("b.xml", "<b/>", 2)]);
+        let resaved = zip(&[("b.xml", "<b/>", 3), ("a.xml", "<a/>", 4)]);
+        let edited = zip(&[("a.xml", "<a/>", 1), ("b.xml", "<c/>", 2)]);
+        assert_eq!(2, zip_members(&original).unwrap().len());
+        assert_eq!(b"<b/>", zip_members(&original).unwrap()[1].data);
+        assert_eq!(zip_checksum(&original), zip_checksum(&resaved));
+        assert_ne!(zip_checksum(&original), zip_checksum(&edited));
+        assert_eq!(None, zip_members(b"PK not a zip"));
+        assert_eq!(None, zip_members(&original[..original.len() - 1]));
+    }
+
+    #[test]
+    fn test_applies_to() {
+        assert!(Normalize::Pdf.applies_to(Path::new("a/b.PDF")));
+        assert!(!Normalize::Pdf.applies_to(Path::new("a/pdf")));
+        assert!(Normalize::Zip.applies_to(Path::new("a.docx")));
+        assert!(!Normalize::Zip.applies_to(Path::new("a.pdf")));
+    }
+}
@@ -0,0 +1,130 @@
+//! Limit on how many files fdup keeps open at the same time.
+//!
+//! Every rayon worker opens files on its own, as do the threads that read files with a timeout,
+//! so without a limit shared by all of them a large number of threads can run past the process's
+//! limit on file descriptors, and opens start failing with `EMFILE`.
+
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// File descriptors left for everything that isn't counted, like the standard streams, spill
+/// files and io_uring rings, on top of one per thread.
+const RESERVED: usize = 32;
+
+static LIMIT: OnceLock<OpenFileLimit> = OnceLock::new();
+
+/// Counting semaphore for open files.
+#[derive(Debug)]
+pub struct OpenFileLimit {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+/// Permission to keep some number of files open, which are counted until it's dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limit: &'a OpenFileLimit,
+    files: usize,
+}
+
+impl OpenFileLimit {
+    pub fn new(max: usize) -> OpenFileLimit {
+        OpenFileLimit {
+            max: max.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    /// Wait until `files` more files can be opened without exceeding the limit. Asking for more
+    /// than the limit waits until no other file is open.
+    pub fn acquire(&self, files: usize) -> Permit<'_> {
+        let files = files.min(self.max);
+        let mut open = self.open.lock().unwrap();
+        while self.max < *open + files {
+            open = self.closed.wait(open).unwrap();
+        }
+        *open += files;
+        Permit { limit: self, files }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.limit.open.lock().unwrap() -= self.files;
+        self.limit.closed.notify_all();
+    }
+}
+
+/// Limit the number of files open at the same time to `max`, instead of the default derived from
+/// the limit on file descriptors. Only has an effect before the first file is opened.
+pub fn set_max_open_files(max: usize) {
+    let _ = LIMIT.set(OpenFileLimit::new(max));
+}
+
+/// Wait until `files` more files can be opened without exceeding the limit of this process.
+pub fn acquire(files: usize) -> Permit<'static> {
+    LIMIT
+        .get_or_init(|| OpenFileLimit::new(default_max_open_files()))
+        .acquire(files)
+}
+
+/// # Returns
+///
+/// Number of files that can be open at the same time without running out of file descriptors,
+/// leaving some for those that aren't counted.
+fn default_max_open_files() -> usize {
+    let reserved = RESERVED + rayon::current_num_threads();
+    fd_limit().map_or(usize::MAX, |limit| limit.saturating_sub(reserved))
+}
+
+/// # Returns
+///
+/// Soft limit on the number of file descriptors of this process, if it has one.
+#[cfg(unix)]
+pub fn fd_limit() -> Option<usize> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    Some(limit.rlim_cur as usize)
+}
+
+#[cfg(not(unix))]
+pub fn fd_limit() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::atomic::AtomicUsize, sync::atomic::Ordering, thread, time::Duration};
+
+    // Check that no more files are counted as open than the limit, across threads.
+    #[test]
+    fn test_open_file_limit() {
+        let limit = OpenFileLimit::new(3);
+        let open = AtomicUsize::new(0);
+        let max_open = AtomicUsize::new(0);
+        let (limit, open, max_open) = (&limit, &open, &max_open);
+        thread::scope(|scope| {
+            for files in [1, 2, 1, 3, 5, 1, 2, 1] {
+                scope.spawn(move || {
+                    let _permit = limit.acquire(files);
+                    let files = files.min(3);
+                    let now_open = open.fetch_add(files, Ordering::SeqCst) + files;
+                    max_open.fetch_max(now_open, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    open.fetch_sub(files, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(max_open.load(Ordering::SeqCst) <= 3);
+        assert_eq!(0, *limit.open.lock().unwrap());
+    }
+}
@@ -0,0 +1,1006 @@
+use crate::{
+    errors::{Operation, ScanError},
+    sink::OutputSink,
+    skips::Skip,
+    stats::Stats,
+};
+use std::{
+    convert::TryFrom,
+    fs::Metadata,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Version of the layout of the machine-readable output: the results written by `JsonWriter` and
+/// `MsgpackWriter`, and the summary written by `write_run_summary`, each of which says which
+/// version it follows in a `schema_version` field.
+///
+/// Within a version, fields are only ever added, so consumers should ignore the ones they don't
+/// know. The version is bumped whenever a field is removed or renamed, or its type or meaning
+/// changes, and the layouts of earlier versions can still be asked for, down to version 1, which
+/// is the layout from before it was versioned, without a `schema_version` field. Fields added
+/// since are written in earlier versions too, unless they'd break them.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// How the groups of duplicates found by a scan are printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One line per group.
+    #[default]
+    Text,
+
+    /// As written by `JsonWriter`.
+    Json,
+
+    /// As written by `MsgpackWriter`.
+    Msgpack,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "msgpack" => Ok(Format::Msgpack),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// Fact about a file that can be written along with its path in the machine-readable formats, so
+/// that consumers don't have to look it up again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    /// ID of the device that the file is on.
+    Dev,
+
+    /// Number of the file's inode on its device.
+    Inode,
+
+    /// Number of hardlinks to the file's inode.
+    Nlink,
+
+    /// When the file's contents were last modified, in seconds since the Unix epoch.
+    Mtime,
+
+    /// When the file's inode was last changed, in seconds since the Unix epoch.
+    Ctime,
+
+    /// User ID of the file's owner.
+    Uid,
+
+    /// Group ID of the file's owner.
+    Gid,
+
+    /// Permission bits of the file, including the setuid, setgid and sticky bits, e.g. 420 for
+    /// `0644`.
+    Mode,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" => Ok(Field::Dev),
+            "inode" => Ok(Field::Inode),
+            "nlink" => Ok(Field::Nlink),
+            "mtime" => Ok(Field::Mtime),
+            "ctime" => Ok(Field::Ctime),
+            "uid" => Ok(Field::Uid),
+            "gid" => Ok(Field::Gid),
+            "mode" => Ok(Field::Mode),
+            _ => Err(format!("unknown field: {}", s)),
+        }
+    }
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Dev => "dev",
+            Field::Inode => "inode",
+            Field::Nlink => "nlink",
+            Field::Mtime => "mtime",
+            Field::Ctime => "ctime",
+            Field::Uid => "uid",
+            Field::Gid => "gid",
+            Field::Mode => "mode",
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Value of this field for a file with `metadata`, which is 0 where it isn't known, as with
+    /// everything but `Mtime` on platforms other than Unix.
+    #[cfg(unix)]
+    pub fn value(self, metadata: &Metadata) -> i128 {
+        use std::os::unix::fs::MetadataExt;
+
+        match self {
+            Field::Dev => metadata.dev() as i128,
+            Field::Inode => metadata.ino() as i128,
+            Field::Nlink => metadata.nlink() as i128,
+            Field::Mtime => metadata.mtime() as i128,
+            Field::Ctime => metadata.ctime() as i128,
+            Field::Uid => metadata.uid() as i128,
+            Field::Gid => metadata.gid() as i128,
+            Field::Mode => (metadata.mode() & 0o7777) as i128,
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn value(self, metadata: &Metadata) -> i128 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let since_epoch = |time: SystemTime| match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i128,
+            Err(error) => -(error.duration().as_secs() as i128),
+        };
+        match self {
+            Field::Mtime => metadata.modified().map_or(0, since_epoch),
+            _ => 0,
+        }
+    }
+}
+
+/// # Returns
+///
+/// `bytes` in hex, two lowercase digits per byte, as `sha512sum` prints checksums.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// # Returns
+///
+/// Values of `fields` for the file at `path`, without following symlinks, or `None` if its
+/// metadata couldn't be read.
+fn field_values(fields: &[Field], path: &Path) -> Option<Vec<i128>> {
+    let metadata = std::fs::symlink_metadata(path).ok()?;
+    Some(fields.iter().map(|field| field.value(&metadata)).collect())
+}
+
+/// Write `group` as a line of text, preceded by `fingerprint` and a tab if `fingerprints`, or by
+/// `-` if it's missing.
+pub fn write_text_group(
+    out: &mut impl Write,
+    group: &[PathBuf],
+    fingerprints: bool,
+    fingerprint: Option<&[u8]>,
+) -> io::Result<()> {
+    if fingerprints {
+        write!(out, "{}\t", fingerprint.map_or("-".to_string(), hex))?;
+    }
+    writeln!(out, "{:?}", group)
+}
+
+/// Writer of the results of a scan as text: each group as by `write_text_group`, preceded by
+/// `unconfirmed` and a tab if the groups are only candidates for duplicates, and the empty files
+/// reported separately on a line of their own. Errors aren't written, since they're reported on
+/// stderr.
+pub struct TextWriter<W: Write> {
+    out: W,
+    fingerprints: bool,
+    unconfirmed: bool,
+}
+
+impl<W: Write> TextWriter<W> {
+    pub fn new(out: W, fingerprints: bool, unconfirmed: bool) -> TextWriter<W> {
+        TextWriter {
+            out,
+            fingerprints,
+            unconfirmed,
+        }
+    }
+}
+
+impl<W: Write> OutputSink for TextWriter<W> {
+    fn write_group(&mut self, group: &[PathBuf], fingerprint: Option<&[u8]>) -> io::Result<()> {
+        if self.unconfirmed {
+            write!(self.out, "unconfirmed\t")?;
+        }
+        write_text_group(&mut self.out, group, self.fingerprints, fingerprint)
+    }
+
+    fn finish(mut self: Box<Self>, empty_files: &[PathBuf], _: &[ScanError]) -> io::Result<()> {
+        if !empty_files.is_empty() {
+            writeln!(self.out, "empty files: {:?}", empty_files)?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Writer of the results of a scan in one of the machine-readable formats.
+pub enum ResultsWriter<W: Write> {
+    Json(JsonWriter<W>),
+    Msgpack(MsgpackWriter<W>),
+}
+
+impl<W: Write> ResultsWriter<W> {
+    /// # Returns
+    ///
+    /// Writer of results in `format` to `out`, in the layout of `schema_version`, with `fields`
+    /// written along with each path of the groups, and their fingerprints if `fingerprints`, or
+    /// `None` if it's `Format::Text`.
+    pub fn new(
+        format: Format,
+        out: W,
+        fields: Vec<Field>,
+        fingerprints: bool,
+        schema_version: u32,
+    ) -> io::Result<Option<ResultsWriter<W>>> {
+        Ok(match format {
+            Format::Text => None,
+            Format::Json => Some(ResultsWriter::Json(JsonWriter::new(
+                out,
+                fields,
+                fingerprints,
+                schema_version,
+            )?)),
+            Format::Msgpack => Some(ResultsWriter::Msgpack(MsgpackWriter::new(
+                out,
+                fields,
+                fingerprints,
+                schema_version,
+            ))),
+        })
+    }
+
+    /// Write `group`, along with `fingerprint`, the checksum of its files' contents, if the
+    /// fingerprints are written. It's written as missing if it's `None`, e.g. because none of the
+    /// files could be read again.
+    pub fn write_group(
+        &mut self,
+        group: &[impl AsRef<Path>],
+        fingerprint: Option<&[u8]>,
+    ) -> io::Result<()> {
+        match self {
+            ResultsWriter::Json(writer) => writer.write_group(group, fingerprint),
+            ResultsWriter::Msgpack(writer) => writer.write_group(group, fingerprint),
+        }
+    }
+
+    /// Write `empty_files` and `errors`, and close the results.
+    pub fn finish(self, empty_files: &[PathBuf], errors: &[ScanError]) -> io::Result<W> {
+        match self {
+            ResultsWriter::Json(writer) => writer.finish(empty_files, errors),
+            ResultsWriter::Msgpack(writer) => writer.finish(empty_files, errors),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for ResultsWriter<W> {
+    fn write_group(&mut self, group: &[PathBuf], fingerprint: Option<&[u8]>) -> io::Result<()> {
+        ResultsWriter::write_group(self, group, fingerprint)
+    }
+
+    fn finish(self: Box<Self>, empty_files: &[PathBuf], errors: &[ScanError]) -> io::Result<()> {
+        ResultsWriter::finish(*self, empty_files, errors)?.flush()
+    }
+}
+
+/// Writes the results of a scan as a single JSON document of the form
+///
+/// ```text
+/// {"schema_version":2,"groups":[["path",...],...],"empty_files":["path",...],
+///  "errors":[{"path":...,"operation":...,"kind":...,"message":...}]}
+/// ```
+///
+/// Groups are written as soon as they're found, and empty files reported on their own and errors
+/// once the scan is done, so that consumers can tell which paths the groups don't cover. Paths
+/// that aren't valid unicode are written lossily. If there are `fields`, each path of the groups
+/// is written as an object of the form `{"path":...,"dev":...}` instead, with `null` values for
+/// files whose metadata couldn't be read. With `fingerprints`, each group is written as an object
+/// of the form `{"fingerprint":...,"paths":[...]}`, with a `null` fingerprint if it's missing.
+pub struct JsonWriter<W: Write> {
+    out: W,
+    fields: Vec<Field>,
+    fingerprints: bool,
+    groups: usize,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(
+        mut out: W,
+        fields: Vec<Field>,
+        fingerprints: bool,
+        schema_version: u32,
+    ) -> io::Result<JsonWriter<W>> {
+        out.write_all(b"{")?;
+        if 2 <= schema_version {
+            write!(out, r#""schema_version":{},"#, schema_version)?;
+        }
+        out.write_all(br#""groups":["#)?;
+        Ok(JsonWriter {
+            out,
+            fields,
+            fingerprints,
+            groups: 0,
+        })
+    }
+
+    pub fn write_group(
+        &mut self,
+        group: &[impl AsRef<Path>],
+        fingerprint: Option<&[u8]>,
+    ) -> io::Result<()> {
+        if 0 < self.groups {
+            self.out.write_all(b",")?;
+        }
+        self.groups += 1;
+
+        if self.fingerprints {
+            self.out.write_all(br#"{"fingerprint":"#)?;
+            match fingerprint {
+                Some(fingerprint) => write_string(&mut self.out, &hex(fingerprint))?,
+                None => self.out.write_all(b"null")?,
+            }
+            self.out.write_all(br#","paths":"#)?;
+        }
+        self.out.write_all(b"[")?;
+        for (i, path) in group.iter().enumerate() {
+            if 0 < i {
+                self.out.write_all(b",")?;
+            }
+            if self.fields.is_empty() {
+                write_path(&mut self.out, path.as_ref())?;
+                continue;
+            }
+            self.out.write_all(br#"{"path":"#)?;
+            write_path(&mut self.out, path.as_ref())?;
+            let values = field_values(&self.fields, path.as_ref());
+            for (j, field) in self.fields.iter().enumerate() {
+                write!(self.out, r#","{}":"#, field.name())?;
+                match &values {
+                    Some(values) => write!(self.out, "{}", values[j])?,
+                    None => self.out.write_all(b"null")?,
+                }
+            }
+            self.out.write_all(b"}")?;
+        }
+        self.out.write_all(b"]")?;
+        if self.fingerprints {
+            self.out.write_all(b"}")?;
+        }
+        Ok(())
+    }
+
+    /// Write `empty_files` and `errors`, and close the document.
+    pub fn finish(mut self, empty_files: &[PathBuf], errors: &[ScanError]) -> io::Result<W> {
+        self.out.write_all(br#"],"empty_files":["#)?;
+        for (i, path) in empty_files.iter().enumerate() {
+            if 0 < i {
+                self.out.write_all(b",")?;
+            }
+            write_path(&mut self.out, path)?;
+        }
+        self.out.write_all(br#"],"errors":["#)?;
+        for (i, error) in errors.iter().enumerate() {
+            if 0 < i {
+                self.out.write_all(b",")?;
+            }
+            self.out.write_all(br#"{"path":"#)?;
+            write_path(&mut self.out, &error.path)?;
+            self.out.write_all(br#","operation":"#)?;
+            write_string(&mut self.out, error.operation.name())?;
+            self.out.write_all(br#","kind":"#)?;
+            write_string(&mut self.out, &format!("{:?}", error.error.kind()))?;
+            self.out.write_all(br#","message":"#)?;
+            write_string(&mut self.out, &error.error.to_string())?;
+            self.out.write_all(b"}")?;
+        }
+        self.out.write_all(b"]}\n")?;
+        Ok(self.out)
+    }
+}
+
+/// Figures about a whole run, for dashboards that track them across runs.
+pub struct RunSummary<'a> {
+    pub stats: &'a Stats,
+
+    /// Groups of duplicates reported, and the paths in them.
+    pub groups: u64,
+    pub duplicates: u64,
+
+    /// Groups of duplicates left out because they were acknowledged as intended.
+    pub acknowledged_groups: u64,
+
+    /// Bytes of file contents that had to be read to tell the files apart.
+    pub bytes_read: u64,
+
+    /// Files left out because they were deleted between being found and being read.
+    pub vanished: u64,
+
+    pub errors: &'a [ScanError],
+
+    /// How long the whole run took.
+    pub duration: Duration,
+
+    /// Whether the run was interrupted, so that the figures only cover part of it.
+    pub interrupted: bool,
+
+    /// Version of the layout that the summary is written in, as with `SCHEMA_VERSION`.
+    pub schema_version: u32,
+}
+
+/// Write `summary` as a single JSON document of the form
+///
+/// ```text
+/// {"schema_version":2,"interrupted":false,"truncated":false,
+///  "files":{"found":...,"empty":...,"duplicates":...,"vanished":...},"groups":...,
+///  "acknowledged_groups":...,
+///  "bytes":{"found":...,"read":...,"linked":...,"reclaimable":...},
+///  "errors":{"total":...,"permission_denied":...,"by_operation":{"read_dir":...,...}},
+///  "durations_secs":{"total":...,"walk":...,"group":...}}
+/// ```
+pub fn write_run_summary(out: &mut impl Write, summary: &RunSummary) -> io::Result<()> {
+    let RunSummary { stats, errors, .. } = summary;
+    out.write_all(b"{")?;
+    if 2 <= summary.schema_version {
+        write!(out, r#""schema_version":{},"#, summary.schema_version)?;
+    }
+    write!(
+        out,
+        concat!(
+            r#""interrupted":{},"truncated":{},"#,
+            r#""files":{{"found":{},"empty":{},"duplicates":{},"vanished":{}}},"groups":{},"#
+        ),
+        summary.interrupted,
+        stats.truncated,
+        stats.files_found,
+        stats.empty_files.len(),
+        summary.duplicates,
+        summary.vanished,
+        summary.groups
+    )?;
+    if 2 <= summary.schema_version {
+        write!(
+            out,
+            r#""acknowledged_groups":{},"#,
+            summary.acknowledged_groups
+        )?;
+    }
+    write!(
+        out,
+        r#""bytes":{{"found":{},"read":{},"linked":{},"reclaimable":{}}},"#,
+        stats.bytes_found, summary.bytes_read, stats.linked_bytes, stats.reclaimable_bytes
+    )?;
+    let denied = errors
+        .iter()
+        .filter(|error| error.error.kind() == io::ErrorKind::PermissionDenied)
+        .count();
+    write!(
+        out,
+        r#""errors":{{"total":{},"permission_denied":{},"by_operation":{{"#,
+        errors.len(),
+        denied
+    )?;
+    for (i, &operation) in Operation::ALL.iter().enumerate() {
+        if 0 < i {
+            out.write_all(b",")?;
+        }
+        let count = errors
+            .iter()
+            .filter(|error| error.operation == operation)
+            .count();
+        write_string(out, operation.name())?;
+        write!(out, ":{}", count)?;
+    }
+    let group_duration = summary.duration.saturating_sub(stats.walk_duration);
+    writeln!(
+        out,
+        r#"}}}},"durations_secs":{{"total":{:.3},"walk":{:.3},"group":{:.3}}}}}"#,
+        summary.duration.as_secs_f64(),
+        stats.walk_duration.as_secs_f64(),
+        group_duration.as_secs_f64()
+    )
+}
+
+/// Write a JSON object for each of `skips` and `errors`, one per line, of the form
+///
+/// ```text
+/// {"path":...,"reason":"unique_size"}
+/// {"path":...,"reason":"error","operation":"hash"}
+/// ```
+///
+/// naming why each file was left out of the results.
+pub fn write_skips(out: &mut impl Write, skips: &[Skip], errors: &[ScanError]) -> io::Result<()> {
+    for skip in skips {
+        out.write_all(br#"{"path":"#)?;
+        write_path(out, &skip.path)?;
+        out.write_all(br#","reason":"#)?;
+        write_string(out, skip.reason.name())?;
+        out.write_all(b"}\n")?;
+    }
+    for error in errors {
+        out.write_all(br#"{"path":"#)?;
+        write_path(out, &error.path)?;
+        out.write_all(br#","reason":"error","operation":"#)?;
+        write_string(out, error.operation.name())?;
+        out.write_all(b"}\n")?;
+    }
+    Ok(())
+}
+
+fn write_path(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    write_string(out, &path.to_string_lossy())
+}
+
+/// Writes the results of a scan as a stream of MessagePack objects: an array of paths for each
+/// group, as soon as it's found, and once the scan is done a map of the form
+///
+/// ```text
+/// {"schema_version":2,"empty_files":["path",...],
+///  "errors":[{"path":...,"operation":...,"kind":...,"message":...}]}
+/// ```
+///
+/// so that consumers can read groups until they read a map. Paths that aren't valid unicode are
+/// written lossily, and paths written along with `fields` as maps, as with `JsonWriter`. With
+/// `fingerprints`, the array of each group starts with its fingerprint, or nil if it's missing,
+/// before its paths.
+pub struct MsgpackWriter<W: Write> {
+    out: W,
+    fields: Vec<Field>,
+    fingerprints: bool,
+    schema_version: u32,
+}
+
+impl<W: Write> MsgpackWriter<W> {
+    pub fn new(
+        out: W,
+        fields: Vec<Field>,
+        fingerprints: bool,
+        schema_version: u32,
+    ) -> MsgpackWriter<W> {
+        MsgpackWriter {
+            out,
+            fields,
+            fingerprints,
+            schema_version,
+        }
+    }
+
+    pub fn write_group(
+        &mut self,
+        group: &[impl AsRef<Path>],
+        fingerprint: Option<&[u8]>,
+    ) -> io::Result<()> {
+        let out = &mut self.out;
+        let len = self.fingerprints as usize + group.len();
+        write_msgpack_header(out, MsgpackHeader::Array, len)?;
+        if self.fingerprints {
+            match fingerprint {
+                Some(fingerprint) => write_msgpack_str(out, &hex(fingerprint))?,
+                None => out.write_all(&[0xc0])?,
+            }
+        }
+        for path in group {
+            let path = path.as_ref();
+            if self.fields.is_empty() {
+                write_msgpack_str(out, &path.to_string_lossy())?;
+                continue;
+            }
+            write_msgpack_header(out, MsgpackHeader::Map, 1 + self.fields.len())?;
+            write_msgpack_str(out, "path")?;
+            write_msgpack_str(out, &path.to_string_lossy())?;
+            let values = field_values(&self.fields, path);
+            for (j, field) in self.fields.iter().enumerate() {
+                write_msgpack_str(out, field.name())?;
+                match &values {
+                    Some(values) => write_msgpack_int(out, values[j])?,
+                    None => out.write_all(&[0xc0])?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `empty_files` and `errors`, which end the stream.
+    pub fn finish(mut self, empty_files: &[PathBuf], errors: &[ScanError]) -> io::Result<W> {
+        let out = &mut self.out;
+        let versioned = 2 <= self.schema_version;
+        write_msgpack_header(out, MsgpackHeader::Map, 2 + versioned as usize)?;
+        if versioned {
+            write_msgpack_str(out, "schema_version")?;
+            write_msgpack_int(out, self.schema_version.into())?;
+        }
+        write_msgpack_str(out, "empty_files")?;
+        write_msgpack_header(out, MsgpackHeader::Array, empty_files.len())?;
+        for path in empty_files {
+            write_msgpack_str(out, &path.to_string_lossy())?;
+        }
+        write_msgpack_str(out, "errors")?;
+        write_msgpack_header(out, MsgpackHeader::Array, errors.len())?;
+        for error in errors {
+            write_msgpack_header(out, MsgpackHeader::Map, 4)?;
+            write_msgpack_str(out, "path")?;
+            write_msgpack_str(out, &error.path.to_string_lossy())?;
+            write_msgpack_str(out, "operation")?;
+            write_msgpack_str(out, error.operation.name())?;
+            write_msgpack_str(out, "kind")?;
+            write_msgpack_str(out, &format!("{:?}", error.error.kind()))?;
+            write_msgpack_str(out, "message")?;
+            write_msgpack_str(out, &error.error.to_string())?;
+        }
+        out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Kinds of MessagePack values whose headers hold their lengths.
+#[derive(Clone, Copy)]
+enum MsgpackHeader {
+    Str,
+    Array,
+    Map,
+}
+
+/// Write the header of a MessagePack value of `kind` and `len`, in its most compact form.
+fn write_msgpack_header(out: &mut impl Write, kind: MsgpackHeader, len: usize) -> io::Result<()> {
+    // (largest length with a fixed-size header, fixed-size marker, 8-, 16- and 32-bit markers)
+    let (fix_max, fix, markers) = match kind {
+        MsgpackHeader::Str => (31, 0xa0, [Some(0xd9), Some(0xda), Some(0xdb)]),
+        MsgpackHeader::Array => (15, 0x90, [None, Some(0xdc), Some(0xdd)]),
+        MsgpackHeader::Map => (15, 0x80, [None, Some(0xde), Some(0xdf)]),
+    };
+    match (len, markers) {
+        (len, _) if len <= fix_max => out.write_all(&[fix | len as u8]),
+        (len, [Some(marker), _, _]) if len <= u8::MAX as usize => {
+            out.write_all(&[marker, len as u8])
+        }
+        (len, [_, Some(marker), _]) if len <= u16::MAX as usize => {
+            out.write_all(&[marker])?;
+            out.write_all(&(len as u16).to_be_bytes())
+        }
+        (len, [_, _, Some(marker)]) => {
+            let len = u32::try_from(len).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "too long for MessagePack")
+            })?;
+            out.write_all(&[marker])?;
+            out.write_all(&len.to_be_bytes())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Write `s` as a MessagePack string.
+fn write_msgpack_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    write_msgpack_header(out, MsgpackHeader::Str, s.len())?;
+    out.write_all(s.as_bytes())
+}
+
+/// Write `n` as a MessagePack integer, in its most compact form.
+fn write_msgpack_int(out: &mut impl Write, n: i128) -> io::Result<()> {
+    match n {
+        0..=0x7f => out.write_all(&[n as u8]),
+        -32..=-1 => out.write_all(&[n as i8 as u8]),
+        -0x80..=-33 => out.write_all(&[0xd0, n as i8 as u8]),
+        -0x8000..=-0x81 => {
+            out.write_all(&[0xd1])?;
+            out.write_all(&(n as i16).to_be_bytes())
+        }
+        -0x8000_0000..=-0x8001 => {
+            out.write_all(&[0xd2])?;
+            out.write_all(&(n as i32).to_be_bytes())
+        }
+        0x80..=0xff => out.write_all(&[0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.write_all(&[0xcd])?;
+            out.write_all(&(n as u16).to_be_bytes())
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.write_all(&[0xce])?;
+            out.write_all(&(n as u32).to_be_bytes())
+        }
+        n if 0 < n => {
+            let n = u64::try_from(n).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "too large for MessagePack")
+            })?;
+            out.write_all(&[0xcf])?;
+            out.write_all(&n.to_be_bytes())
+        }
+        n => {
+            let n = i64::try_from(n).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "too small for MessagePack")
+            })?;
+            out.write_all(&[0xd3])?;
+            out.write_all(&n.to_be_bytes())
+        }
+    }
+}
+
+/// Write `s` as a JSON string literal.
+fn write_string(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_all(br#"\""#)?,
+            '\\' => out.write_all(br"\\")?,
+            '\n' => out.write_all(br"\n")?,
+            '\r' => out.write_all(br"\r")?,
+            '\t' => out.write_all(br"\t")?,
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skips::SkipReason;
+
+    #[test]
+    fn test_text_writer() {
+        let mut out = Vec::new();
+        let mut writer = Box::new(TextWriter::new(&mut out, true, false));
+        let group = [PathBuf::from("a"), PathBuf::from("b")];
+        writer.write_group(&group, Some(&[0xbe, 0xef])).unwrap();
+        writer.write_group(&group, None).unwrap();
+        writer.finish(&[PathBuf::from("e")], &[]).unwrap();
+        assert_eq!(
+            "beef\t[\"a\", \"b\"]\n-\t[\"a\", \"b\"]\nempty files: [\"e\"]\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        let mut writer = Box::new(TextWriter::new(&mut out, false, true));
+        writer.write_group(&group, None).unwrap();
+        writer.finish(&[], &[]).unwrap();
+        assert_eq!(
+            "unconfirmed\t[\"a\", \"b\"]\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_json_writer() {
+        let mut writer = JsonWriter::new(Vec::new(), Vec::new(), false, 1).unwrap();
+        let out = writer.finish(&[], &[]).unwrap();
+        assert_eq!(
+            "{\"groups\":[],\"empty_files\":[],\"errors\":[]}\n",
+            String::from_utf8(out).unwrap()
+        );
+        writer = JsonWriter::new(Vec::new(), Vec::new(), false, SCHEMA_VERSION).unwrap();
+        let out = writer.finish(&[], &[]).unwrap();
+        assert_eq!(
+            "{\"schema_version\":2,\"groups\":[],\"empty_files\":[],\"errors\":[]}\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        writer = JsonWriter::new(Vec::new(), Vec::new(), false, 1).unwrap();
+        writer.write_group(&["a", "b\"c"], None).unwrap();
+        writer.write_group(&["d\\e", "f\ng\u{1}"], None).unwrap();
+        let errors = [ScanError {
+            path: PathBuf::from("h"),
+            operation: Operation::Hash,
+            error: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+        }];
+        let out = writer
+            .finish(&[PathBuf::from("i"), PathBuf::from("j")], &errors)
+            .unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"groups":[["a","b\"c"],["d\\e","f\ng\u0001"]],"empty_files":["i","j"],"#,
+                r#""errors":[{"path":"h","operation":"hash","kind":"PermissionDenied","#,
+                r#""message":"denied"}]}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_run_summary() {
+        let stats = Stats {
+            files_found: 5,
+            bytes_found: 100,
+            walk_duration: Duration::from_millis(250),
+            empty_files: vec![PathBuf::from("a")],
+            linked_bytes: 10,
+            reclaimable_bytes: 20,
+            ..Default::default()
+        };
+        let errors = [ScanError {
+            path: PathBuf::from("b"),
+            operation: Operation::ReadDir,
+            error: io::ErrorKind::PermissionDenied.into(),
+        }];
+        let summary = RunSummary {
+            stats: &stats,
+            groups: 1,
+            duplicates: 3,
+            acknowledged_groups: 2,
+            bytes_read: 60,
+            vanished: 4,
+            errors: &errors,
+            duration: Duration::from_secs(1),
+            interrupted: false,
+            schema_version: SCHEMA_VERSION,
+        };
+        let mut out = Vec::new();
+        write_run_summary(&mut out, &summary).unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"schema_version":2,"interrupted":false,"truncated":false,"#,
+                r#""files":{"found":5,"empty":1,"duplicates":3,"vanished":4},"groups":1,"#,
+                r#""acknowledged_groups":2,"#,
+                r#""bytes":{"found":100,"read":60,"linked":10,"reclaimable":20},"#,
+                r#""errors":{"total":1,"permission_denied":1,"by_operation":{"read_dir":1,"#,
+                r#""metadata":0,"sniff":0,"sample":0,"key":0,"hash":0,"verify":0}},"#,
+                r#""durations_secs":{"total":1.000,"walk":0.250,"group":0.750}}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        let summary = RunSummary {
+            schema_version: 1,
+            ..summary
+        };
+        let mut out = Vec::new();
+        write_run_summary(&mut out, &summary).unwrap();
+        assert!(out.starts_with(br#"{"interrupted":false,"#));
+    }
+
+    #[test]
+    fn test_write_skips() {
+        let skips = [Skip {
+            path: PathBuf::from("a\"b"),
+            reason: SkipReason::UniqueSize,
+        }];
+        let errors = [ScanError {
+            path: PathBuf::from("c"),
+            operation: Operation::Sniff,
+            error: io::ErrorKind::NotFound.into(),
+        }];
+        let mut out = Vec::new();
+        write_skips(&mut out, &skips, &errors).unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"path":"a\"b","reason":"unique_size"}"#,
+                "\n",
+                r#"{"path":"c","reason":"error","operation":"sniff"}"#,
+                "\n"
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_msgpack_writer() {
+        let mut writer = MsgpackWriter::new(Vec::new(), Vec::new(), false, 1);
+        writer.write_group(&["a", "bc"], None).unwrap();
+        let errors = [ScanError {
+            path: PathBuf::from("d"),
+            operation: Operation::Hash,
+            error: io::Error::new(io::ErrorKind::NotFound, "gone"),
+        }];
+        let out = writer.finish(&[PathBuf::from("e")], &errors).unwrap();
+
+        let mut expected = vec![0x92, 0xa1, b'a', 0xa2, b'b', b'c', 0x82];
+        expected.extend(b"\xabempty_files\x91\xa1e\xa6errors\x91\x84");
+        expected.extend(b"\xa4path\xa1d\xa9operation\xa4hash");
+        expected.extend(b"\xa4kind\xa8NotFound\xa7message\xa4gone");
+        assert_eq!(expected, out);
+
+        let writer = MsgpackWriter::new(Vec::new(), Vec::new(), false, SCHEMA_VERSION);
+        let out = writer.finish(&[], &[]).unwrap();
+        let mut expected = vec![0x83, 0xae];
+        expected.extend(b"schema_version\x02\xabempty_files\x90\xa6errors\x90");
+        assert_eq!(expected, out);
+    }
+
+    // Check that fields are written along with the paths, and null for missing files.
+    #[test]
+    fn test_fields() {
+        let metadata = std::fs::symlink_metadata(std::env::temp_dir()).unwrap();
+        let modified = metadata.modified().unwrap();
+        let modified = modified.duration_since(std::time::UNIX_EPOCH).unwrap();
+        assert_eq!(modified.as_secs() as i128, Field::Mtime.value(&metadata));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            // without the bits of the file type
+            assert_eq!(
+                metadata.mode() as i128 & 0o7777,
+                Field::Mode.value(&metadata)
+            );
+        }
+
+        let fields = vec![Field::Nlink, Field::Dev];
+        let dir = std::env::temp_dir();
+        let metadata = std::fs::symlink_metadata(&dir).unwrap();
+        let (nlink, dev) = (Field::Nlink.value(&metadata), Field::Dev.value(&metadata));
+        let missing = dir.join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+
+        let mut writer =
+            JsonWriter::new(Vec::new(), fields.clone(), false, SCHEMA_VERSION).unwrap();
+        writer.write_group(&[&dir, &missing], None).unwrap();
+        let out = String::from_utf8(writer.finish(&[], &[]).unwrap()).unwrap();
+        let mut expected = Vec::new();
+        write_path(&mut expected, &dir).unwrap();
+        let expected = format!(
+            r#"{{"path":{},"nlink":{},"dev":{}}}"#,
+            String::from_utf8(expected).unwrap(),
+            nlink,
+            dev
+        );
+        assert!(out.contains(&expected));
+        assert!(out.contains(r#""nlink":null,"dev":null}]]"#));
+
+        let mut writer = MsgpackWriter::new(Vec::new(), fields, false, 1);
+        writer.write_group(&[&missing], None).unwrap();
+        let out = writer.finish(&[], &[]).unwrap();
+        assert_eq!(&[0x91, 0x83, 0xa4], &out[..3]);
+        assert!(out
+            .windows(13)
+            .any(|window| window == b"\xa5nlink\xc0\xa3dev\xc0\x82"));
+    }
+
+    // Check that groups are written along with their fingerprints, and null for missing ones.
+    #[test]
+    fn test_fingerprints() {
+        assert_eq!("00ff1a", hex(&[0x00, 0xff, 0x1a]));
+
+        let mut writer = JsonWriter::new(Vec::new(), Vec::new(), true, SCHEMA_VERSION).unwrap();
+        writer
+            .write_group(&["a", "b"], Some(&[0xbe, 0xef]))
+            .unwrap();
+        writer.write_group(&["c", "d"], None).unwrap();
+        let out = String::from_utf8(writer.finish(&[], &[]).unwrap()).unwrap();
+        assert!(out.contains(concat!(
+            r#""groups":[{"fingerprint":"beef","paths":["a","b"]},"#,
+            r#"{"fingerprint":null,"paths":["c","d"]}],"#
+        )));
+
+        let mut writer = MsgpackWriter::new(Vec::new(), Vec::new(), true, SCHEMA_VERSION);
+        writer.write_group(&["a"], Some(&[0xbe, 0xef])).unwrap();
+        writer.write_group(&["b"], None).unwrap();
+        let out = writer.finish(&[], &[]).unwrap();
+        assert_eq!(b"\x92\xa4beef\xa1a\x92\xc0\xa1b", &out[..12]);
+    }
+
+    #[test]
+    fn test_write_msgpack_int() {
+        let int = |n| {
+            let mut out = Vec::new();
+            write_msgpack_int(&mut out, n).unwrap();
+            out
+        };
+        assert_eq!(vec![0x7f], int(0x7f));
+        assert_eq!(vec![0xe0], int(-32));
+        assert_eq!(vec![0xcc, 0x80], int(0x80));
+        assert_eq!(vec![0xcd, 1, 0], int(0x100));
+        assert_eq!(vec![0xce, 0, 1, 0, 0], int(0x1_0000));
+        assert_eq!(vec![0xcf, 0, 0, 0, 1, 0, 0, 0, 0], int(0x1_0000_0000));
+        assert_eq!(vec![0xd0, 0xdf], int(-33));
+        assert_eq!(vec![0xd1, 0xff, 0x7f], int(-0x81));
+        assert_eq!(vec![0xd2, 0xff, 0xff, 0x7f, 0xff], int(-0x8001));
+        assert_eq!(
+            vec![0xd3, 0xff, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff],
+            int(-0x8000_0001)
+        );
+        assert!(write_msgpack_int(&mut Vec::new(), u64::MAX as i128 + 1).is_err());
+    }
+
+    #[test]
+    fn test_write_msgpack_header() {
+        let header = |kind, len| {
+            let mut out = Vec::new();
+            write_msgpack_header(&mut out, kind, len).unwrap();
+            out
+        };
+        assert_eq!(vec![0xbf], header(MsgpackHeader::Str, 31));
+        assert_eq!(vec![0xd9, 32], header(MsgpackHeader::Str, 32));
+        assert_eq!(vec![0xdc, 0, 16], header(MsgpackHeader::Array, 16));
+        assert_eq!(vec![0xde, 1, 0], header(MsgpackHeader::Map, 256));
+        assert_eq!(vec![0xdd, 0, 1, 0, 0], header(MsgpackHeader::Array, 65536));
+    }
+}
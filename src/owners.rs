@@ -0,0 +1,144 @@
+//! Reclaimable space aggregated per owner, to tell which accounts hold the most duplicates.
+
+use crate::fdup::get_file_id;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, Metadata},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// Bytes that could be reclaimed from the files of each owner, by their user ID.
+#[derive(Debug, Default)]
+pub struct Owners {
+    bytes: Mutex<HashMap<u32, u64>>,
+}
+
+impl Owners {
+    /// Add the space taken up by the duplicates in `group`, all but the first of which could be
+    /// reclaimed. Each inode is counted once, towards its owner, and paths whose metadata can't be
+    /// read anymore aren't counted at all, as with `Rollup::add_group`.
+    pub fn add_group(&self, group: &[PathBuf]) {
+        let mut inodes = HashSet::new();
+        let mut reclaimable = Vec::new();
+        for path in group {
+            let metadata = match fs::symlink_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            // The first inode is the one that would be kept.
+            if inodes.insert(get_file_id(&metadata)) && 1 < inodes.len() {
+                if let Some(uid) = owner_of(&metadata) {
+                    reclaimable.push((uid, metadata.len()));
+                }
+            }
+        }
+
+        let mut bytes = self.bytes.lock().unwrap();
+        for (uid, size) in reclaimable {
+            *bytes.entry(uid).or_default() += size;
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Names of the owners with bytes that could be reclaimed from their files so far, or their
+    /// IDs if they have none, and how many, from the most bytes to the least.
+    pub fn sorted(&self) -> Vec<(String, u64)> {
+        let bytes = self.bytes.lock().unwrap();
+        let mut bytes: Vec<(u32, u64)> = bytes.iter().map(|(&uid, &n)| (uid, n)).collect();
+        bytes.sort_by(|(a_uid, a_bytes), (b_uid, b_bytes)| {
+            b_bytes.cmp(a_bytes).then_with(|| a_uid.cmp(b_uid))
+        });
+        bytes
+            .into_iter()
+            .map(|(uid, n)| (user_name(uid).unwrap_or_else(|| uid.to_string()), n))
+            .collect()
+    }
+}
+
+/// # Returns
+///
+/// User ID of the owner of the file with `metadata`. Only implemented on Unix; elsewhere, files
+/// have none.
+#[cfg(unix)]
+fn owner_of(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.uid())
+}
+
+#[cfg(not(unix))]
+fn owner_of(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+/// # Returns
+///
+/// Name of the user with the ID `uid`, if it has one.
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    use std::{ffi::CStr, mem, ptr};
+
+    let mut passwd = mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    match (status, result.is_null()) {
+        (0, false) => {
+            let name = unsafe { CStr::from_ptr(passwd.assume_init().pw_name) };
+            Some(name.to_string_lossy().into_owned())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+// Owners are only known on Unix.
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, hard_link, remove_dir_all, write},
+    };
+
+    #[test]
+    fn test_owners() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        for path in ["1", "2", "3"] {
+            write(root.join(path), "abc").unwrap();
+        }
+        hard_link(root.join("2"), root.join("4")).unwrap();
+
+        let group: Vec<PathBuf> = ["1", "2", "4", "3", "missing"]
+            .iter()
+            .map(|path| root.join(path))
+            .collect();
+        let owners = Owners::default();
+        owners.add_group(&group);
+        owners.add_group(&group[..2]);
+        let uid = owner_of(&fs::metadata(&root).unwrap()).unwrap();
+        let name = user_name(uid).unwrap_or_else(|| uid.to_string());
+        assert_eq!(vec![(name, 9)], owners.sorted());
+        assert!(Owners::default().sorted().is_empty());
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+//! Pager that the text output is piped through when stdout is a terminal, as git does, so that the
+//! first groups reported aren't lost to the scrollback.
+
+use std::{
+    io::{self, Write},
+    process::Child,
+    sync::Mutex,
+};
+
+/// Pager used when none is given and `PAGER` isn't set. `-F` quits right away if the output fits
+/// on the screen, `-S` keeps each group on a single line, so that moving a line up or down moves
+/// by a group, and `-X` leaves the output on the screen once it quits.
+const DEFAULT_PAGER: &str = "less -FSX";
+
+/// Pager that stdout is piped to, if any.
+static PAGER: Mutex<Option<Child>> = Mutex::new(None);
+
+/// # Returns
+///
+/// Program and arguments of the pager, given as `pager`, or by `env`, the value of `PAGER`, or
+/// `DEFAULT_PAGER`, split on whitespace rather than by a shell. `None` if it's empty or `cat`,
+/// which would page nothing.
+fn pager_command(pager: Option<&str>, env: Option<&str>) -> Option<Vec<String>> {
+    let command = pager
+        .or(env.filter(|env| !env.trim().is_empty()))
+        .unwrap_or(DEFAULT_PAGER);
+    let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+    match command.first().map(String::as_str) {
+        None | Some("cat") => None,
+        Some(_) => Some(command),
+    }
+}
+
+/// Pipe stdout to the pager `pager`, or the one given by `PAGER`, until `finish` is called. Once
+/// the pager quits, the next write to stdout ends the process, as `SIGPIPE` would for any other
+/// program. Nothing is paged if the pager can't be started, or on platforms other than Unix.
+///
+/// # Returns
+///
+/// `true` if and only if stdout is now piped to the pager.
+#[cfg(unix)]
+pub fn start(pager: Option<&str>) -> bool {
+    use std::{
+        env,
+        os::unix::io::AsRawFd,
+        process::{Command, Stdio},
+    };
+
+    let env = env::var("PAGER").ok();
+    let command = match pager_command(pager, env.as_deref()) {
+        Some(command) => command,
+        None => return false,
+    };
+    let mut child = match Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    let stdin = child.stdin.take().unwrap();
+    let _ = io::stdout().flush();
+    unsafe {
+        libc::dup2(stdin.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+    drop(stdin);
+    *PAGER.lock().unwrap() = Some(child);
+    true
+}
+
+#[cfg(not(unix))]
+pub fn start(_pager: Option<&str>) -> bool {
+    false
+}
+
+/// Close stdout, if it's piped to a pager, and wait for the pager to quit, so that fdup doesn't
+/// exit while the output is still being read. Nothing can be written to stdout afterwards.
+pub fn finish() {
+    if let Some(mut child) = PAGER.lock().unwrap().take() {
+        let _ = io::stdout().flush();
+        // The pager reads until every end of the pipe is closed.
+        #[cfg(unix)]
+        unsafe {
+            libc::close(libc::STDOUT_FILENO);
+        }
+        let _ = child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_command() {
+        let command = |words: &[&str]| Some(words.iter().map(|word| word.to_string()).collect());
+        assert_eq!(command(&["less", "-FSX"]), pager_command(None, None));
+        assert_eq!(command(&["less", "-FSX"]), pager_command(None, Some(" ")));
+        assert_eq!(command(&["most"]), pager_command(None, Some("most")));
+        assert_eq!(
+            command(&["less", "-R"]),
+            pager_command(Some(" less  -R "), Some("most"))
+        );
+        assert_eq!(None, pager_command(Some(""), Some("most")));
+        assert_eq!(None, pager_command(None, Some("cat")));
+    }
+}
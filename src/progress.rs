@@ -0,0 +1,129 @@
+//! Progress of a running scan, as reported on stderr with `--progress`.
+
+use std::time::Duration;
+
+/// Figures that the progress of a scan is estimated from.
+pub struct Progress {
+    pub files_found: u64,
+
+    /// Files that will have been found once they all are, if they were counted beforehand.
+    pub files_to_find: Option<u64>,
+
+    /// Bytes of the files that may be read, or `None` while the files are still being found.
+    pub bytes_to_read: Option<u64>,
+
+    pub bytes_read: u64,
+
+    /// How long the files have been read for, since they were all found.
+    pub reading_for: Duration,
+}
+
+/// # Returns
+///
+/// Line describing `progress`: how many files were found while they're being found, then how much
+/// of them has been read, and how long reading the rest should take at the throughput so far.
+pub fn progress_line(progress: &Progress) -> String {
+    let bytes_to_read = match (progress.bytes_to_read, progress.files_to_find) {
+        (Some(bytes_to_read), _) => bytes_to_read,
+        (None, Some(files_to_find)) => {
+            // Files may have been added since they were counted.
+            let percent = (progress.files_found * 100 / files_to_find.max(1)).min(99);
+            return format!(
+                "found {} of {} files ({}%)",
+                progress.files_found, files_to_find, percent
+            );
+        }
+        (None, None) => return format!("found {} files", progress.files_found),
+    };
+    // Sampled blocks are read on top of whole files, so more may be read than expected. The scan
+    // is only done once it's done, whatever the figures say.
+    let percent = match bytes_to_read {
+        0 => 99,
+        _ => (progress.bytes_read * 100 / bytes_to_read).min(99),
+    };
+    let mut line = format!(
+        "read {} of {} ({}%)",
+        format_bytes(progress.bytes_read),
+        format_bytes(bytes_to_read),
+        percent
+    );
+    let seconds = progress.reading_for.as_secs_f64();
+    if 0 < progress.bytes_read && 1.0 <= seconds {
+        let rate = progress.bytes_read as f64 / seconds;
+        let remaining = bytes_to_read.saturating_sub(progress.bytes_read) as f64;
+        let left = Duration::from_secs_f64(remaining / rate);
+        line.push_str(&format!(", about {} left", format_duration(left)));
+    }
+    line
+}
+
+/// # Returns
+///
+/// `bytes` in the largest decimal unit in which it's at least 1, as in `SIZE_BUCKETS`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["KB", "MB", "GB", "TB", "PB", "EB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while 1000.0 <= value && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// # Returns
+///
+/// `duration` in its two largest units, down to seconds, e.g. `1h 05m`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        let mut progress = Progress {
+            files_found: 12,
+            files_to_find: None,
+            bytes_to_read: None,
+            bytes_read: 0,
+            reading_for: Duration::ZERO,
+        };
+        assert_eq!("found 12 files", progress_line(&progress));
+        progress.files_to_find = Some(48);
+        assert_eq!("found 12 of 48 files (25%)", progress_line(&progress));
+        progress.bytes_to_read = Some(4_000_000_000);
+        assert_eq!("read 0 B of 4.0 GB (0%)", progress_line(&progress));
+        progress.bytes_read = 1_000_000_000;
+        progress.reading_for = Duration::from_secs(100);
+        assert_eq!(
+            "read 1.0 GB of 4.0 GB (25%), about 5m 00s left",
+            progress_line(&progress)
+        );
+        progress.bytes_read = 5_000_000_000;
+        assert_eq!(
+            "read 5.0 GB of 4.0 GB (99%), about 0s left",
+            progress_line(&progress)
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!("999 B", format_bytes(999));
+        assert_eq!("1.5 KB", format_bytes(1_500));
+        assert_eq!("18.4 EB", format_bytes(u64::MAX));
+        assert_eq!("59s", format_duration(Duration::from_secs(59)));
+        assert_eq!("1m 01s", format_duration(Duration::from_secs(61)));
+        assert_eq!("10h 05m", format_duration(Duration::from_secs(36_300)));
+    }
+}
@@ -0,0 +1,87 @@
+//! Guard that every write to the filesystem goes through, so that once it's enforced, e.g. when
+//! scanning media under legal hold, fdup can be relied on to leave the filesystem as it found it.
+
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether writes are refused.
+static ENFORCED: AtomicBool = AtomicBool::new(false);
+
+/// Refuse every write to the filesystem made through this module from then on, for the rest of
+/// the process.
+pub fn enforce() {
+    ENFORCED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enforced() -> bool {
+    ENFORCED.load(Ordering::SeqCst)
+}
+
+/// # Returns
+///
+/// Error for `path` if writes are refused, which should then be left as it is.
+pub fn check(path: &Path) -> io::Result<()> {
+    if is_enforced() {
+        Err(refused(path))
+    } else {
+        Ok(())
+    }
+}
+
+/// # Returns
+///
+/// Error for a write to `path` that was refused.
+fn refused(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "refusing to write to {}, since the filesystem is to be left as it is",
+            path.display()
+        ),
+    )
+}
+
+/// # Returns
+///
+/// File at `path`, created or truncated as with `File::create`, unless writes are refused.
+pub fn create(path: &Path) -> io::Result<File> {
+    check(path)?;
+    File::create(path)
+}
+
+/// Create a directory at `path`, as with `fs::create_dir`, unless writes are refused.
+pub fn create_dir(path: &Path) -> io::Result<()> {
+    check(path)?;
+    fs::create_dir(path)
+}
+
+/// Create a directory at `path` and any of its ancestors that are missing, as with
+/// `fs::create_dir_all`, unless writes are refused.
+pub fn create_dir_all(path: &Path) -> io::Result<()> {
+    check(path)?;
+    fs::create_dir_all(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes can't be allowed again once refused, so only the refusal itself is checked, to leave
+    // the other tests able to write.
+    #[test]
+    fn test_refused() {
+        let error = refused(Path::new("a/b"));
+        assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+        assert_eq!(
+            "refusing to write to a/b, since the filesystem is to be left as it is",
+            error.to_string()
+        );
+        if !is_enforced() {
+            assert!(check(Path::new("a/b")).is_ok());
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Directories on other machines, compared with local ones by the manifests that `fdup manifest`
+//! writes when it's run there over ssh.
+
+use crate::{
+    clargs::{ManifestOpt, RemoteOpt},
+    errors::{ErrorLog, ErrorPolicy},
+    fdup::ScanOptions,
+    manifest::{self, Entry},
+    script::write_quoted,
+    walk::WalkOptions,
+};
+use rayon::prelude::*;
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fmt,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Directory on another machine, given as `[user@]host:path`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Remote {
+    pub host: String,
+    pub path: PathBuf,
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.path.display())
+    }
+}
+
+impl Remote {
+    /// # Returns
+    ///
+    /// Entries of the manifest of the files under the directory, written by running
+    /// `remote_fdup manifest` on the host through `ssh`, with the paths that they have there.
+    pub fn fetch_manifest(&self, ssh: &str, remote_fdup: &str) -> io::Result<Vec<Entry>> {
+        // ssh joins the command up for the remote shell to split, so the path has to be quoted.
+        let mut command = format!("{} manifest -- ", remote_fdup).into_bytes();
+        write_quoted(&mut command, &self.path)?;
+        let mut child = Command::new(ssh)
+            .arg("--")
+            .arg(&self.host)
+            .arg(os_string_from_bytes(command))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let entries = manifest::read_manifest(&mut BufReader::new(child.stdout.take().unwrap()));
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{} exited with {}", ssh, status)));
+        }
+        entries
+    }
+
+    /// # Returns
+    ///
+    /// How the file at `path` on the host is written in the results.
+    fn display_path(&self, path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}:{}", self.host, path.display()))
+    }
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Run `fdup manifest`, writing the manifest of the files under the roots to stdout.
+///
+/// # Returns
+///
+/// Exit status of fdup.
+pub fn run_manifest(opt: ManifestOpt) -> i32 {
+    let ManifestOpt {
+        include_caches,
+        roots,
+    } = opt;
+    let options = ScanOptions {
+        walk: WalkOptions {
+            include_caches,
+            ..WalkOptions::default()
+        },
+        errors: ErrorLog::new(ErrorPolicy::Warn),
+        ..ScanOptions::default()
+    };
+    let entries = manifest::entries(&options, &roots, |_| true);
+    let written = manifest::write_manifest(&mut io::BufWriter::new(io::stdout()), entries);
+    for error in options.errors.take() {
+        eprintln!("fdup: {}", error);
+    }
+    match written {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("fdup: couldn't write the manifest: {}", error);
+            1
+        }
+    }
+}
+
+/// Run `fdup remote`, printing the groups of local files that have duplicates on the remote
+/// machine, along with them.
+///
+/// # Returns
+///
+/// Exit status of fdup.
+pub fn run_remote(opt: RemoteOpt) -> i32 {
+    let RemoteOpt {
+        ssh,
+        remote_fdup,
+        sort,
+        remote,
+        roots,
+    } = opt;
+    let remote_entries = match remote.fetch_manifest(&ssh, &remote_fdup) {
+        Ok(entries) => entries,
+        Err(error) => {
+            eprintln!("fdup: {}: couldn't read the manifest: {}", remote, error);
+            return 1;
+        }
+    };
+
+    let options = ScanOptions {
+        errors: ErrorLog::new(ErrorPolicy::Warn),
+        ..ScanOptions::default()
+    };
+    // Only local files that may be duplicates of remote ones are read.
+    let sizes: HashSet<u64> = remote_entries.iter().map(|entry| entry.size).collect();
+    let local: Vec<Entry> =
+        manifest::entries(&options, &roots, |size| sizes.contains(&size)).collect();
+    for (local, remote_copies) in manifest::match_entries(local, remote_entries) {
+        let mut local: Vec<PathBuf> = local.into_iter().map(|entry| entry.path).collect();
+        let mut remote_copies: Vec<PathBuf> = remote_copies
+            .iter()
+            .map(|entry| remote.display_path(&entry.path))
+            .collect();
+        if sort {
+            local.sort();
+            remote_copies.sort();
+        }
+        local.extend(remote_copies);
+        println!("{:?}", local);
+    }
+    for error in options.errors.take() {
+        eprintln!("fdup: {}", error);
+    }
+    0
+}
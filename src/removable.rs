@@ -0,0 +1,343 @@
+//! Checks of whether files could be removed or replaced, e.g. by hardlinks to their duplicates,
+//! so that the reasons they can't be are known before anything is done to any of them.
+
+use std::{ffi::OsString, fmt, io, path::Path};
+
+/// Reason why a file can't be removed or replaced by the current user.
+#[derive(Debug)]
+pub enum NotRemovable {
+    /// The directory that the file is in can't be written to.
+    ParentNotWritable,
+
+    /// The directory that the file is in is sticky, and neither it nor the file are owned by the
+    /// current user.
+    StickyParent,
+
+    /// The file, or the directory that it's in, is flagged as immutable.
+    Immutable,
+
+    /// The file, or the directory that it's in, is flagged as append-only.
+    AppendOnly,
+
+    /// The file or the directory that it's in couldn't be checked.
+    Error(io::Error),
+}
+
+impl fmt::Display for NotRemovable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotRemovable::ParentNotWritable => f.write_str("its directory isn't writable"),
+            NotRemovable::StickyParent => {
+                f.write_str("its directory is sticky, and neither of them are owned by this user")
+            }
+            NotRemovable::Immutable => f.write_str("it or its directory is immutable"),
+            NotRemovable::AppendOnly => f.write_str("it or its directory is append-only"),
+            NotRemovable::Error(error) => write!(f, "it couldn't be checked: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for NotRemovable {
+    fn from(error: io::Error) -> Self {
+        NotRemovable::Error(error)
+    }
+}
+
+/// # Returns
+///
+/// Why the file at `path` can't be removed or replaced by the current user, if it can't.
+pub fn check_removable(path: &Path) -> Result<(), NotRemovable> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    check_parent(path, parent)?;
+    check_flags(path)?;
+    check_flags(parent)
+}
+
+#[cfg(unix)]
+fn check_parent(path: &Path, parent: &Path) -> Result<(), NotRemovable> {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    };
+
+    let parent_path = CString::new(parent.as_os_str().as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    // Removing an entry takes both writing to and searching its directory. As in `is_writable`,
+    // the effective user ID is what's checked.
+    let mode = libc::W_OK | libc::X_OK;
+    let result =
+        unsafe { libc::faccessat(libc::AT_FDCWD, parent_path.as_ptr(), mode, libc::AT_EACCESS) };
+    if result != 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::EACCES) | Some(libc::EPERM) | Some(libc::EROFS) => {
+                Err(NotRemovable::ParentNotWritable)
+            }
+            _ => Err(error.into()),
+        };
+    }
+
+    let parent_metadata = std::fs::metadata(parent)?;
+    if parent_metadata.mode() & libc::S_ISVTX != 0 {
+        let uid = unsafe { libc::geteuid() };
+        let file_uid = std::fs::symlink_metadata(path)?.uid();
+        if uid != 0 && uid != file_uid && uid != parent_metadata.uid() {
+            return Err(NotRemovable::StickyParent);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_parent(_path: &Path, parent: &Path) -> Result<(), NotRemovable> {
+    if std::fs::metadata(parent)?.permissions().readonly() {
+        return Err(NotRemovable::ParentNotWritable);
+    }
+    Ok(())
+}
+
+/// Inode flags, as returned by the `FS_IOC_GETFLAGS` ioctl.
+#[cfg(target_os = "linux")]
+const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+#[cfg(target_os = "linux")]
+const FS_APPEND_FL: libc::c_int = 0x20;
+
+#[cfg(target_os = "linux")]
+fn check_flags(path: &Path) -> Result<(), NotRemovable> {
+    use std::{
+        fs::File,
+        os::unix::{fs::OpenOptionsExt, io::AsRawFd},
+    };
+
+    let _permit = crate::open_files::acquire(1);
+    // Never blocks on special files, like in `open_regular_file`.
+    let file = File::options()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    let mut flags: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        let error = io::Error::last_os_error();
+        // Filesystems without inode flags have nothing to check.
+        return match error.raw_os_error() {
+            Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) | Some(libc::EINVAL) => Ok(()),
+            _ => Err(error.into()),
+        };
+    }
+    if flags & FS_IMMUTABLE_FL != 0 {
+        return Err(NotRemovable::Immutable);
+    }
+    if flags & FS_APPEND_FL != 0 {
+        return Err(NotRemovable::AppendOnly);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_flags(_path: &Path) -> Result<(), NotRemovable> {
+    Ok(())
+}
+
+/// # Returns
+///
+/// ID of the device that the file at `path` is on, without following symlinks. Files on different
+/// devices can't be hardlinked to each other. Always 0 where there are no device IDs.
+#[cfg(unix)]
+pub fn device(path: &Path) -> io::Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(std::fs::symlink_metadata(path)?.dev())
+}
+
+#[cfg(not(unix))]
+pub fn device(path: &Path) -> io::Result<u64> {
+    std::fs::symlink_metadata(path).map(|_| 0)
+}
+
+/// # Returns
+///
+/// Extended attributes of the file at `path`, including security labels like SELinux contexts, as
+/// pairs of names and values sorted by name. Symlinks aren't followed.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn xattrs(path: &Path) -> io::Result<Vec<(OsString, Vec<u8>)>> {
+    use std::{
+        ffi::{CStr, CString},
+        os::unix::ffi::{OsStrExt, OsStringExt},
+    };
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let names = match read_xattr_buffer(|buf, size| unsafe { list_xattrs(&path, buf, size) }) {
+        Ok(names) => names,
+        // Filesystems without extended attributes have none to list.
+        Err(error) if error.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+    let mut xattrs = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let c_name = CStr::from_bytes_with_nul(&[name, &[0]].concat())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+            .to_owned();
+        let value = read_xattr_buffer(|buf, size| unsafe { get_xattr(&path, &c_name, buf, size) })?;
+        xattrs.push((OsString::from_vec(name.to_vec()), value));
+    }
+    xattrs.sort();
+    Ok(xattrs)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn xattrs(_path: &Path) -> io::Result<Vec<(OsString, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+/// # Returns
+///
+/// Bytes written by `read`, which is called with a buffer and its size and returns how many bytes
+/// it wrote, or how many it would write if given no buffer, like `llistxattr`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn read_xattr_buffer(
+    read: impl Fn(*mut libc::c_void, usize) -> libc::ssize_t,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let written = read(buf.as_mut_ptr().cast(), buf.len());
+        if 0 <= written {
+            buf.truncate(written as usize);
+            return Ok(buf);
+        }
+        // The attributes changed in between, so try again.
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ERANGE) {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn list_xattrs(path: &std::ffi::CStr, buf: *mut libc::c_void, size: usize) -> isize {
+    libc::llistxattr(path.as_ptr(), buf.cast(), size)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn get_xattr(
+    path: &std::ffi::CStr,
+    name: &std::ffi::CStr,
+    buf: *mut libc::c_void,
+    size: usize,
+) -> isize {
+    libc::lgetxattr(path.as_ptr(), name.as_ptr(), buf, size)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list_xattrs(path: &std::ffi::CStr, buf: *mut libc::c_void, size: usize) -> isize {
+    libc::listxattr(path.as_ptr(), buf.cast(), size, libc::XATTR_NOFOLLOW)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn get_xattr(
+    path: &std::ffi::CStr,
+    name: &std::ffi::CStr,
+    buf: *mut libc::c_void,
+    size: usize,
+) -> isize {
+    libc::getxattr(
+        path.as_ptr(),
+        name.as_ptr(),
+        buf,
+        size,
+        0,
+        libc::XATTR_NOFOLLOW,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+    };
+
+    #[test]
+    fn test_check_removable() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(root.join("d")).unwrap();
+        write(root.join("d/f"), "").unwrap();
+
+        check_removable(&root.join("d/f")).unwrap();
+        assert!(matches!(
+            check_removable(&root.join("d/missing")),
+            Err(NotRemovable::Error(_))
+        ));
+        assert_eq!(
+            device(&root.join("d")).unwrap(),
+            device(&root.join("d/f")).unwrap()
+        );
+        assert!(device(&root.join("d/missing")).is_err());
+
+        #[cfg(unix)]
+        {
+            use std::{
+                fs::{set_permissions, Permissions},
+                os::unix::fs::PermissionsExt,
+            };
+
+            set_permissions(root.join("d"), Permissions::from_mode(0o555)).unwrap();
+            let result = check_removable(&root.join("d/f"));
+            set_permissions(root.join("d"), Permissions::from_mode(0o755)).unwrap();
+            // root can write to anything
+            if unsafe { libc::geteuid() } != 0 {
+                assert!(matches!(result, Err(NotRemovable::ParentNotWritable)));
+            }
+        }
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_xattrs() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        write(root.join("f"), "").unwrap();
+
+        // Some filesystems and security modules add attributes of their own, so only those of a
+        // file set in the test are checked.
+        #[cfg(target_os = "linux")]
+        {
+            use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+            let path = CString::new(root.join("f").as_os_str().as_bytes()).unwrap();
+            let (name, value) = (b"user.fdup\0", b"a");
+            let result = unsafe {
+                libc::lsetxattr(
+                    path.as_ptr(),
+                    name.as_ptr().cast(),
+                    value.as_ptr().cast(),
+                    value.len(),
+                    0,
+                )
+            };
+            // e.g. tmpfs without user xattrs
+            if result == 0 {
+                let xattrs = xattrs(&root.join("f")).unwrap();
+                assert!(xattrs.contains(&(OsString::from("user.fdup"), b"a".to_vec())));
+            }
+        }
+        assert!(xattrs(&root.join("missing")).is_err());
+        remove_dir_all(&root).unwrap();
+    }
+}
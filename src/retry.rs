@@ -0,0 +1,109 @@
+use std::{io, thread, time::Duration};
+
+/// How many times IO operations that fail with transient errors are retried, and how long to wait
+/// between attempts.
+///
+/// Network filesystems occasionally fail reads that succeed when they're simply tried again. A
+/// long scan shouldn't be thrown away because of one such hiccup, so an error is only surfaced
+/// once every retry has failed as well.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Number of times an operation is retried after its first attempt.
+    pub retries: u32,
+
+    /// How long to wait before the first retry. Each following retry waits twice as long as the
+    /// one before it.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// # Returns
+    ///
+    /// Result of the first attempt at `op` that succeeds or fails with a permanent error, or the
+    /// error of the last attempt if every retry fails with a transient one.
+    pub fn run<T>(self, mut op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut backoff = self.backoff;
+        let mut retries = 0;
+        loop {
+            match op() {
+                Err(err) if retries < self.retries && is_transient(&err) => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    retries += 1;
+                }
+                result => break result,
+            }
+        }
+    }
+}
+
+/// # Returns
+///
+/// `true` if and only if the operation that failed with `err` may succeed if it's tried again.
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        // The file was opened again by the next attempt, which might get a fresh NFS handle.
+        #[cfg(unix)]
+        _ if err.raw_os_error() == Some(libc::ESTALE) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // # Returns
+    //
+    // Result of running `policy` on an operation that fails with each of `errors` in turn before
+    // succeeding, and the number of attempts made.
+    fn run_failing(policy: RetryPolicy, errors: &[io::ErrorKind]) -> (io::Result<()>, usize) {
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            match errors.get(attempts - 1) {
+                Some(&kind) => Err(io::Error::from(kind)),
+                None => Ok(()),
+            }
+        });
+        (result, attempts)
+    }
+
+    #[test]
+    fn test_run() {
+        let policy = RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        use io::ErrorKind::{Interrupted, NotFound, TimedOut};
+
+        let (result, attempts) = run_failing(policy, &[]);
+        assert!(result.is_ok());
+        assert_eq!(1, attempts);
+
+        // transient errors are retried
+        let (result, attempts) = run_failing(policy, &[Interrupted, TimedOut]);
+        assert!(result.is_ok());
+        assert_eq!(3, attempts);
+
+        // until the retries run out
+        let (result, attempts) = run_failing(policy, &[Interrupted, TimedOut, Interrupted]);
+        assert_eq!(Interrupted, result.unwrap_err().kind());
+        assert_eq!(3, attempts);
+
+        // permanent errors aren't retried
+        let (result, attempts) = run_failing(policy, &[Interrupted, NotFound]);
+        assert_eq!(NotFound, result.unwrap_err().kind());
+        assert_eq!(2, attempts);
+    }
+}
@@ -0,0 +1,137 @@
+//! Reclaimable space aggregated per directory, to tell which directories hold the duplicates
+//! rather than listing every group of them.
+
+use crate::fdup::get_file_id;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Bytes that could be reclaimed in each directory, counting subdirectories deeper than `depth`
+/// towards their ancestor at that depth.
+#[derive(Debug)]
+pub struct Rollup {
+    depth: usize,
+    bytes: Mutex<HashMap<PathBuf, u64>>,
+}
+
+impl Rollup {
+    pub fn new(depth: usize) -> Rollup {
+        Rollup {
+            depth,
+            bytes: Default::default(),
+        }
+    }
+
+    /// Add the space taken up by the duplicates in `group`, all but the first of which could be
+    /// reclaimed. Each inode is counted once, in the directory of the first of its paths, and
+    /// paths whose metadata can't be read anymore aren't counted at all.
+    pub fn add_group(&self, group: &[PathBuf]) {
+        let mut inodes = HashSet::new();
+        let mut reclaimable = Vec::new();
+        for path in group {
+            let metadata = match fs::symlink_metadata(path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            // The first inode is the one that would be kept.
+            if inodes.insert(get_file_id(&metadata)) && 1 < inodes.len() {
+                reclaimable.push((self.directory_of(path), metadata.len()));
+            }
+        }
+
+        let mut bytes = self.bytes.lock().unwrap();
+        for (dir, size) in reclaimable {
+            *bytes.entry(dir).or_default() += size;
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Directory of `path`, truncated to `depth` components besides the root.
+    fn directory_of(&self, path: &Path) -> PathBuf {
+        let mut dir = PathBuf::new();
+        let mut depth = 0;
+        for component in path.parent().unwrap_or(Path::new("")).components() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => {}
+                _ if self.depth <= depth => break,
+                _ => depth += 1,
+            }
+            dir.push(component);
+        }
+        if dir.as_os_str().is_empty() {
+            dir.push(".");
+        }
+        dir
+    }
+
+    /// # Returns
+    ///
+    /// Directories with bytes that could be reclaimed in them so far, and how many, from the most
+    /// bytes to the least.
+    pub fn sorted(&self) -> Vec<(PathBuf, u64)> {
+        let bytes = self.bytes.lock().unwrap();
+        let mut bytes: Vec<(PathBuf, u64)> =
+            bytes.iter().map(|(dir, &n)| (dir.clone(), n)).collect();
+        bytes.sort_by(|(a_dir, a_bytes), (b_dir, b_bytes)| {
+            b_bytes.cmp(a_bytes).then_with(|| a_dir.cmp(b_dir))
+        });
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, hard_link, remove_dir_all, write},
+    };
+
+    #[test]
+    fn test_rollup() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        for dir in ["a", "b/c", "b/d"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        for path in ["a/1", "b/c/1", "b/d/1"] {
+            write(root.join(path), "abc").unwrap();
+        }
+        hard_link(root.join("b/c/1"), root.join("b/c/2")).unwrap();
+
+        let depth = root.components().count() - 1;
+        let group: Vec<PathBuf> = ["a/1", "b/c/1", "b/c/2", "b/d/1", "missing"]
+            .iter()
+            .map(|path| root.join(path))
+            .collect();
+
+        let rollup = Rollup::new(depth + 2);
+        rollup.add_group(&group);
+        assert_eq!(
+            vec![(root.join("b/c"), 3), (root.join("b/d"), 3)],
+            rollup.sorted()
+        );
+
+        let rollup = Rollup::new(depth + 1);
+        rollup.add_group(&group);
+        rollup.add_group(&group[..2]);
+        assert_eq!(vec![(root.join("b"), 9)], rollup.sorted());
+
+        assert_eq!(
+            PathBuf::from("."),
+            Rollup::new(0).directory_of(Path::new("a"))
+        );
+        assert_eq!(
+            PathBuf::from("/"),
+            Rollup::new(0).directory_of(Path::new("/a/b"))
+        );
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,898 @@
+//! Shell script that deals with the duplicates found by a scan, modeled on the ones written by
+//! rmlint, so that they can be reviewed before anything is done to them.
+
+use crate::{glob::PathPattern, hasher::Checksum};
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Way of dealing with a duplicate in the script, given the copy of it to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handler {
+    /// Replace it by a reflink to the copy to keep, sharing its blocks, where the filesystem
+    /// supports that.
+    Clone,
+
+    /// Replace it by a hardlink to the copy to keep.
+    Hardlink,
+
+    /// Remove it.
+    Remove,
+
+    /// Leave it as it is.
+    Keep,
+}
+
+impl FromStr for Handler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clone" => Ok(Handler::Clone),
+            "hardlink" => Ok(Handler::Hardlink),
+            "remove" => Ok(Handler::Remove),
+            "keep" => Ok(Handler::Keep),
+            _ => Err(format!("unknown handler: {}", s)),
+        }
+    }
+}
+
+impl Handler {
+    fn name(self) -> &'static str {
+        match self {
+            Handler::Clone => "clone",
+            Handler::Hardlink => "hardlink",
+            Handler::Remove => "remove",
+            Handler::Keep => "keep",
+        }
+    }
+}
+
+/// Where a script written in safe mode, as with `ScriptWriter::new`, moves the files that it gets
+/// rid of, and records how to undo what it did.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Safe {
+    /// Directory that duplicates are moved to, each in a numbered directory of its own, instead of
+    /// being removed, or before being replaced.
+    pub trash: PathBuf,
+
+    /// Shell script that each move the script makes is appended to as a command that undoes it,
+    /// created the first time that the script is run for real, and kept across runs.
+    pub journal: PathBuf,
+
+    /// Paths that the script never touches, matched against those of the duplicates once they're
+    /// resolved to absolute paths without symlinks, however the roots were spelled. Duplicates
+    /// that can't be resolved aren't touched either.
+    pub protected: Vec<PathPattern>,
+}
+
+impl Safe {
+    /// # Returns
+    ///
+    /// Safe mode for the script at `script`, with the trash and the journal next to it, named after
+    /// it with `.trash` and `.undo` appended, protecting `SYSTEM_PATHS`.
+    pub fn next_to(script: &Path) -> io::Result<Safe> {
+        let script = std::path::absolute(script)?;
+        let with_suffix = |suffix: &str| {
+            let mut path = script.clone().into_os_string();
+            path.push(suffix);
+            PathBuf::from(path)
+        };
+        Ok(Safe {
+            trash: with_suffix(".trash"),
+            journal: with_suffix(".undo"),
+            protected: SYSTEM_PATHS
+                .iter()
+                .map(|path| path.parse().unwrap())
+                .collect(),
+        })
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if `path` can't be resolved, or matches one of `protected` once it is.
+    fn protects(&self, path: &Path) -> bool {
+        match resolve(path) {
+            Ok(path) => self.protected.iter().any(|pattern| pattern.matches(&path)),
+            Err(_) => true,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `true` if and only if the directory that `path` is in is protected, as with `protects`, so
+    /// that nothing may be moved to `path`.
+    fn protects_dir_of(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => self.protects(dir),
+            _ => self.protects(Path::new(".")),
+        }
+    }
+}
+
+/// # Returns
+///
+/// `path` resolved to an absolute path without symlinks, spelled as `SYSTEM_PATHS` are.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let resolved = std::fs::canonicalize(path)?;
+    // Windows resolves to verbatim paths, e.g. `\\?\C:\Windows`.
+    #[cfg(windows)]
+    if let Some(rest) = resolved.to_str().and_then(|s| s.strip_prefix(r"\\?\")) {
+        if !rest.starts_with("UNC") {
+            return Ok(PathBuf::from(rest));
+        }
+    }
+    Ok(resolved)
+}
+
+/// Paths that scripts written in safe mode never touch by default, those of the operating system.
+#[cfg(not(windows))]
+pub const SYSTEM_PATHS: &[&str] = &[
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/proc",
+    "/sbin",
+    "/sys",
+    "/usr",
+    "/var/lib",
+    "/System",
+    "/Library",
+    "/private/etc",
+    "/private/var/db",
+];
+
+#[cfg(windows)]
+pub const SYSTEM_PATHS: &[&str] = &[
+    r"C:\Windows",
+    r"C:\Program Files",
+    r"C:\Program Files (x86)",
+    r"C:\ProgramData",
+];
+
+/// Functions that the script is made up of, besides the calls to `dedupe` for each duplicate.
+/// Replacements are made through a temporary file next to the duplicate, with a name that nothing
+/// else has, so that it's never missing if they fail, and no other file is ever overwritten.
+const FUNCTIONS: &str = r#"DRY_RUN=$SAFE
+if [ "$1" = -n ]; then
+    DRY_RUN=1
+elif [ "$1" = --force ]; then
+    DRY_RUN=
+fi
+
+run() {
+    if [ -n "$DRY_RUN" ]; then
+        echo "$@"
+    else
+        "$@"
+    fi
+}
+
+# $1 as a single-quoted shell word, whatever characters it has.
+quote() {
+    QUOTED=$(printf '%sx' "$1" | sed "s/'/'\\\\''/g")
+    printf "'%s'" "${QUOTED%x}"
+}
+
+# Append the command $1, with the arguments after it quoted, to the journal, which undoes what the
+# script did when run.
+journal() {
+    if [ -n "$DRY_RUN" ]; then
+        return
+    fi
+    {
+        printf '%s' "$1"
+        shift
+        for arg in "$@"; do
+            printf ' '
+            quote "$arg"
+        done
+        echo
+    } >> "$JOURNAL"
+}
+
+# Move $1 to a directory of its own in the trash, recording how to move it back in the journal.
+trash() {
+    TRASHED=$((TRASHED + 1))
+    while [ -e "$TRASH/$TRASHED" ]; do
+        TRASHED=$((TRASHED + 1))
+    done
+    run mkdir -p -- "$TRASH/$TRASHED" && run mv -- "$1" "$TRASH/$TRASHED/" || return 1
+    journal restore "$TRASH/$TRASHED/${1##*/}" "$1"
+}
+
+# Get rid of $1: move it to the trash if there's one, and remove it otherwise.
+discard() {
+    if [ -n "$TRASH" ]; then
+        trash "$1"
+    else
+        run rm -f -- "$1"
+    fi
+}
+
+# Make an empty temporary file next to $1, named in $TMP, so that the file that replaces $1 is
+# made under a name that nothing else has.
+make_tmp() {
+    if [ -n "$DRY_RUN" ]; then
+        TMP="$1.fdup-XXXXXX"
+    else
+        TMP=$(mktemp -- "$1.fdup-XXXXXX")
+    fi
+}
+
+# Replace $1 by the temporary file $2, moving it to the trash first if there's one.
+replace() {
+    if [ -n "$TRASH" ]; then
+        trash "$1" || { rm -f -- "$2"; return 1; }
+    fi
+    run mv -f -- "$2" "$1"
+}
+
+handle_clone() {
+    make_tmp "$1" || return 1
+    run cp --reflink=always -p -- "$2" "$TMP" || { rm -f -- "$TMP"; return 1; }
+    replace "$1" "$TMP"
+}
+
+handle_hardlink() {
+    make_tmp "$1" || return 1
+    run ln -f -- "$2" "$TMP" || { rm -f -- "$TMP"; return 1; }
+    replace "$1" "$TMP"
+}
+
+handle_remove() {
+    discard "$1" && REMOVED=1
+}
+
+handle_keep() {
+    echo "keeping $1"
+}
+
+# SHA512 checksum of the contents of $1, in hex, read through stdin so that it's printed on its own
+# whatever the name of the file.
+checksum() {
+    if command -v sha512sum >/dev/null 2>&1; then
+        sha512sum < "$1"
+    else
+        shasum -a 512 < "$1"
+    fi | cut -d ' ' -f 1
+}
+
+# Check that the kept file $1 still has the checksum $2 that it had during the scan, now that its
+# duplicates have been dealt with.
+verify() {
+    if [ -n "$DRY_RUN" ]; then
+        echo verify "$1"
+        return
+    fi
+    VERIFIED=$((VERIFIED + 1))
+    if [ "$(checksum "$1")" != "$2" ]; then
+        echo "fdup: $1: no longer has the checksum that it had during the scan" >&2
+        MISMATCHED=$((MISMATCHED + 1))
+        STATUS=1
+    fi
+}
+
+# Deal with the duplicate $1 of $2 with the first of $HANDLERS that succeeds, unless it's changed
+# since the scan or is already a hardlink to $2.
+dedupe() {
+    REMOVED=
+    if [ "$1" -ef "$2" ]; then
+        return
+    fi
+    if ! cmp -s -- "$1" "$2"; then
+        echo "fdup: $1: no longer identical to $2; skipped" >&2
+        return
+    fi
+    for handler in $HANDLERS; do
+        if "handle_$handler" "$1" "$2"; then
+            return
+        fi
+    done
+    echo "fdup: $1: couldn't be dealt with" >&2
+    STATUS=1
+}
+
+# Deal with the companion $1 of the duplicate just deduped, e.g. a sidecar file, if the duplicate
+# was removed: move it to $2, where the kept file's companion would be, unless there's one
+# already, in which case it's removed if it's identical to that one, and left as it is otherwise.
+companion() {
+    if [ -z "$REMOVED" ] || [ ! -e "$1" ]; then
+        return
+    fi
+    if [ ! -e "$2" ]; then
+        run mv -- "$1" "$2" && journal restore "$2" "$1"
+    elif cmp -s -- "$1" "$2"; then
+        discard "$1"
+    else
+        echo "fdup: $1: companion of a removed duplicate, but differs from $2; left as it is" >&2
+        STATUS=1
+    fi
+}
+
+STATUS=0
+VERIFIED=0
+MISMATCHED=0
+TRASHED=0
+if [ -n "$JOURNAL" ] && [ -z "$DRY_RUN" ] && [ ! -e "$JOURNAL" ]; then
+    cat > "$JOURNAL" << 'EOF'
+#!/bin/sh
+# Written by a script of fdup as it ran. Moves each file that it moved, to the trash or elsewhere,
+# back to where it was.
+
+restore() {
+    mv -f -- "$1" "$2" || echo "fdup: $2: couldn't be restored" >&2
+}
+
+EOF
+    chmod +x "$JOURNAL"
+fi
+"#;
+
+/// Writes a POSIX shell script that, when run, deals with the duplicates in each group with the
+/// first of its handlers that succeeds, keeping the first path of the group. Duplicates that
+/// changed since the scan are skipped. Run with `-n`, it only prints what it would do.
+///
+/// Companions of the duplicates, files next to them with the same name but for one of
+/// `companions` as their extensions, such as the sidecars of photos, are moved next to the kept
+/// file if their duplicate is removed, as by `companions`, unless it has its own.
+///
+/// Once every group has been dealt with, the script re-hashes the kept files given to `verify`,
+/// and reports those whose checksums changed, e.g. because the storage corrupted them while their
+/// duplicates were being replaced.
+///
+/// In `safe` mode, the script only prints what it would do unless it's run with `--force`, and
+/// moves what it gets rid of to the trash, recording how to move it back in the journal.
+pub struct ScriptWriter<W: Write> {
+    out: W,
+    safe: Option<Safe>,
+    companions: Vec<String>,
+    groups: usize,
+    duplicates: usize,
+    /// Kept files to verify, and their checksums during the scan.
+    verified: Vec<(PathBuf, Checksum)>,
+}
+
+impl<W: Write> ScriptWriter<W> {
+    pub fn new(
+        mut out: W,
+        handlers: &[Handler],
+        companions: &[String],
+        safe: Option<&Safe>,
+    ) -> io::Result<ScriptWriter<W>> {
+        let names: Vec<&str> = handlers.iter().map(|handler| handler.name()).collect();
+        writeln!(out, "#!/bin/sh")?;
+        writeln!(
+            out,
+            "# Written by fdup. Keeps the first path of each group of duplicates, and deals with \
+             the others,"
+        )?;
+        writeln!(
+            out,
+            "# trying these handlers in order: {}",
+            names.join(", ")
+        )?;
+        match safe {
+            Some(_) => {
+                writeln!(
+                    out,
+                    "# Only prints what would be done unless run with --force, and moves the \
+                     duplicates to the trash"
+                )?;
+                writeln!(out, "# instead of getting rid of them for good.")?;
+            }
+            None => writeln!(out, "# Run with -n to print what would be done instead.")?,
+        }
+        writeln!(out)?;
+        writeln!(out, "HANDLERS='{}'", names.join(" "))?;
+        match safe {
+            Some(safe) => {
+                writeln!(out, "SAFE=1")?;
+                write!(out, "TRASH=")?;
+                write_quoted(&mut out, &safe.trash)?;
+                write!(out, "\nJOURNAL=")?;
+                write_quoted(&mut out, &safe.journal)?;
+                writeln!(out)?;
+            }
+            None => {
+                writeln!(out, "SAFE=")?;
+                writeln!(out, "TRASH=")?;
+                writeln!(out, "JOURNAL=")?;
+            }
+        }
+        out.write_all(FUNCTIONS.as_bytes())?;
+        Ok(ScriptWriter {
+            out,
+            safe: safe.cloned(),
+            companions: companions.to_vec(),
+            groups: 0,
+            duplicates: 0,
+            verified: Vec::new(),
+        })
+    }
+
+    /// Have the script deal with the paths of `group` after the first `kept`, keeping the first of
+    /// them, and the rest of the first `kept` as they are, e.g. because they're protected. At least
+    /// the first path is always kept, and so are those protected in safe mode.
+    pub fn write_group(&mut self, group: &[impl AsRef<Path>], kept: usize) -> io::Result<()> {
+        let kept = kept.clamp(1, group.len());
+        let (protected, duplicates): (Vec<&Path>, Vec<&Path>) = group[kept..]
+            .iter()
+            .map(AsRef::as_ref)
+            .partition(|path| self.safe.as_ref().is_some_and(|safe| safe.protects(path)));
+        self.groups += 1;
+        self.duplicates += duplicates.len();
+        writeln!(self.out)?;
+        for path in group[..kept].iter().map(AsRef::as_ref).chain(protected) {
+            write!(self.out, "# ")?;
+            write_quoted(&mut self.out, path)?;
+            writeln!(self.out)?;
+        }
+        for path in duplicates {
+            write!(self.out, "dedupe ")?;
+            write_quoted(&mut self.out, path)?;
+            write!(self.out, " ")?;
+            write_quoted(&mut self.out, group[0].as_ref())?;
+            writeln!(self.out)?;
+            // Companions are moved next to the kept file, which may be in a protected directory.
+            let safe = self.safe.as_ref();
+            let companions = companions(path, group[0].as_ref(), &self.companions)
+                .into_iter()
+                .filter(|(_, kept)| !safe.is_some_and(|safe| safe.protects_dir_of(kept)));
+            for (companion, kept) in companions {
+                write!(self.out, "companion ")?;
+                write_quoted(&mut self.out, &companion)?;
+                write!(self.out, " ")?;
+                write_quoted(&mut self.out, &kept)?;
+                writeln!(self.out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Have the script check that the kept file at `path` still has `checksum`, its full SHA512
+    /// checksum, once it's done.
+    pub fn verify(&mut self, path: &Path, checksum: Checksum) {
+        self.verified.push((path.to_path_buf(), checksum));
+    }
+
+    /// Write the end of the script, with the kept files that it verifies, and how many groups and
+    /// duplicates it deals with.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.verified.is_empty() {
+            writeln!(self.out)?;
+            writeln!(self.out, "# Kept files to verify, and their checksums")?;
+            for (path, checksum) in &self.verified {
+                write!(self.out, "verify ")?;
+                write_quoted(&mut self.out, path)?;
+                write!(self.out, " ")?;
+                for byte in checksum.iter() {
+                    write!(self.out, "{:02x}", byte)?;
+                }
+                writeln!(self.out)?;
+            }
+            writeln!(self.out, "if [ -z \"$DRY_RUN\" ]; then")?;
+            writeln!(
+                self.out,
+                "    echo \"fdup: verified $VERIFIED kept files, $MISMATCHED of which changed\" >&2"
+            )?;
+            writeln!(self.out, "fi")?;
+        }
+        if self.safe.is_some() {
+            writeln!(self.out)?;
+            writeln!(self.out, "if [ -n \"$DRY_RUN\" ]; then")?;
+            writeln!(
+                self.out,
+                "    echo \"fdup: nothing was done; run with --force to do it\" >&2"
+            )?;
+            writeln!(self.out, "else")?;
+            writeln!(
+                self.out,
+                "    echo \"fdup: run $JOURNAL to undo what was done\" >&2"
+            )?;
+            writeln!(self.out, "fi")?;
+        }
+        writeln!(self.out)?;
+        writeln!(
+            self.out,
+            "# {} groups, {} duplicates",
+            self.groups, self.duplicates
+        )?;
+        writeln!(self.out, "exit $STATUS")?;
+        Ok(self.out)
+    }
+}
+
+/// # Returns
+///
+/// Companions of the file at `path` that exist, with one of `extensions` instead of its own, as in
+/// `IMG_1.xmp` for `IMG_1.CR2`, or after it, as in `IMG_1.CR2.xmp`, each along with the path that
+/// the companion of the same form of the file at `kept` would have.
+pub fn companions(path: &Path, kept: &Path, extensions: &[String]) -> Vec<(PathBuf, PathBuf)> {
+    let mut companions = Vec::new();
+    for extension in extensions {
+        let renamed = |path: &Path| path.with_extension(extension);
+        let appended = |path: &Path| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(".");
+            name.push(extension);
+            PathBuf::from(name)
+        };
+        for pair in [
+            (renamed(path), renamed(kept)),
+            (appended(path), appended(kept)),
+        ] {
+            // Files without extensions have the same companions either way.
+            if pair.0 != path && pair.0.exists() && !companions.contains(&pair) {
+                companions.push(pair);
+            }
+        }
+    }
+    companions
+}
+
+/// Let the file at `path` be executed by anyone who can read it, as a script.
+#[cfg(unix)]
+pub fn make_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | (permissions.mode() & 0o444) >> 2);
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(not(unix))]
+pub fn make_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Write `path` as a single-quoted shell word. Paths that aren't valid unicode are written as they
+/// are, where they're bytes.
+pub fn write_quoted(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+
+        path.as_os_str().as_bytes()
+    };
+    #[cfg(not(unix))]
+    let lossy = path.to_string_lossy();
+    #[cfg(not(unix))]
+    let bytes = lossy.as_bytes();
+
+    out.write_all(b"'")?;
+    for (i, part) in bytes.split(|&b| b == b'\'').enumerate() {
+        if 0 < i {
+            out.write_all(br"'\''")?;
+        }
+        out.write_all(part)?;
+    }
+    out.write_all(b"'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{self, create_dir_all, remove_dir_all, write},
+        process::Command,
+    };
+
+    #[test]
+    fn test_script_writer() {
+        let mut writer =
+            ScriptWriter::new(Vec::new(), &[Handler::Clone, Handler::Remove], &[], None).unwrap();
+        writer.write_group(&["a", "b", "it's"], 1).unwrap();
+        let script = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("\nHANDLERS='clone remove'\n"));
+        assert!(script.contains("\n# 'a'\ndedupe 'b' 'a'\ndedupe 'it'\\''s' 'a'\n"));
+        assert!(script.ends_with("\n# 1 groups, 2 duplicates\nexit $STATUS\n"));
+        assert!(!script.contains("\nverify "));
+        assert!(script.contains("\nSAFE=\nTRASH=\nJOURNAL=\n"));
+
+        let mut writer = ScriptWriter::new(Vec::new(), &[Handler::Remove], &[], None).unwrap();
+        writer.write_group(&["a", "b"], 0).unwrap();
+        writer.verify(Path::new("a"), Box::new([0x0a, 0xff]));
+        let script = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(script.contains("\ndedupe 'b' 'a'\n"));
+        assert!(script.contains("\nverify 'a' 0aff\n"));
+    }
+
+    // Check that paths that aren't valid unicode are written byte for byte.
+    #[cfg(unix)]
+    #[test]
+    fn test_write_quoted_invalid_names() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let mut out = Vec::new();
+        write_quoted(&mut out, Path::new(OsStr::from_bytes(b"a\xff'\xfe"))).unwrap();
+        assert_eq!(b"'a\xff'\\''\xfe'".to_vec(), out);
+    }
+
+    #[test]
+    fn test_companions() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        for path in ["a.raw", "b.raw", "b.xmp", "b.raw.srt", "b.txt"] {
+            write(root.join(path), path).unwrap();
+        }
+
+        let extensions = ["xmp".to_string(), "srt".to_string()];
+        let (duplicate, kept) = (root.join("b.raw"), root.join("a.raw"));
+        assert_eq!(
+            vec![
+                (root.join("b.xmp"), root.join("a.xmp")),
+                (root.join("b.raw.srt"), root.join("a.raw.srt"))
+            ],
+            companions(&duplicate, &kept, &extensions)
+        );
+        assert!(companions(&kept, &duplicate, &extensions).is_empty());
+        assert!(companions(&duplicate, &kept, &["raw".to_string()]).is_empty());
+        remove_dir_all(&root).unwrap();
+    }
+
+    // Check that the script deals with duplicates, but not with files that changed since.
+    #[cfg(unix)]
+    #[test]
+    fn test_run_script() {
+        use std::os::unix::fs::MetadataExt;
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        for (path, contents) in [("a", "same"), ("b", "same"), ("c", "same"), ("d", "diff")] {
+            write(root.join(path), contents).unwrap();
+        }
+
+        let script = |handlers: &[Handler], group: &[&str]| {
+            let mut writer = ScriptWriter::new(Vec::new(), handlers, &[], None).unwrap();
+            let group: Vec<_> = group.iter().map(|path| root.join(path)).collect();
+            writer.write_group(&group, 1).unwrap();
+            let script = root.join("script.sh");
+            write(&script, writer.finish().unwrap()).unwrap();
+            Command::new("sh").arg(&script).output().unwrap()
+        };
+        // A file named as a temporary file of the script would be is left as it is.
+        write(root.join("b.fdup-tmp"), "mine").unwrap();
+        let output = script(&[Handler::Clone, Handler::Hardlink], &["a", "b", "d"]);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("no longer identical"));
+        let (a, b) = (fs::metadata(root.join("a")), fs::metadata(root.join("b")));
+        assert_eq!(a.unwrap().ino(), b.unwrap().ino());
+        assert!(root.join("d").exists());
+        assert_eq!("mine", fs::read_to_string(root.join("b.fdup-tmp")).unwrap());
+        let leftovers = fs::read_dir(&root)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("b.fdup-") && name != "b.fdup-tmp"
+            })
+            .count();
+        assert_eq!(0, leftovers);
+
+        // The kept file is verified once its duplicates are dealt with.
+        let mut hasher = crate::hasher::sha512();
+        hasher.update(b"same");
+        let checksum = hasher.finalize();
+        let verify = |checksum: Checksum| {
+            let mut writer =
+                ScriptWriter::new(Vec::new(), &[Handler::Hardlink], &[], None).unwrap();
+            writer
+                .write_group(&[root.join("a"), root.join("b")], 1)
+                .unwrap();
+            writer.verify(&root.join("a"), checksum);
+            let script = root.join("script.sh");
+            write(&script, writer.finish().unwrap()).unwrap();
+            Command::new("sh").arg(&script).output().unwrap()
+        };
+        let output = verify(checksum.clone());
+        assert!(output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(stderr.contains("verified 1 kept files, 0 of which changed"));
+        let mut changed = checksum.into_vec();
+        changed[0] ^= 1;
+        let output = verify(changed.into_boxed_slice());
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(stderr.contains("no longer has the checksum"));
+        assert!(stderr.contains("verified 1 kept files, 1 of which changed"));
+
+        script(&[Handler::Keep], &["a", "c"]);
+        assert!(root.join("c").exists());
+        script(&[Handler::Remove], &["a", "c"]);
+        assert!(!root.join("c").exists());
+        assert!(root.join("a").exists());
+
+        // Kept paths after the first, e.g. protected ones, are left as they are.
+        for path in ["k1", "k2", "k3"] {
+            write(root.join(path), "same").unwrap();
+        }
+        let mut writer = ScriptWriter::new(Vec::new(), &[Handler::Remove], &[], None).unwrap();
+        let group = ["k1", "k2", "k3"].map(|path| root.join(path));
+        writer.write_group(&group, 2).unwrap();
+        let written = writer.finish().unwrap();
+        assert!(String::from_utf8_lossy(&written).contains("# 1 groups, 1 duplicates"));
+        write(root.join("script.sh"), written).unwrap();
+        let output = Command::new("sh")
+            .arg(root.join("script.sh"))
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(root.join("k1").exists() && root.join("k2").exists());
+        assert!(!root.join("k3").exists());
+
+        // Companions of removed duplicates are moved next to the kept file, unless it has its own.
+        let files = [
+            ("p.raw", "same"),
+            ("q.raw", "same"),
+            ("q.xmp", "meta"),
+            ("r.raw", "same"),
+            ("r.xmp", "other"),
+        ];
+        for (path, contents) in files {
+            write(root.join(path), contents).unwrap();
+        }
+        let mut writer =
+            ScriptWriter::new(Vec::new(), &[Handler::Remove], &["xmp".to_string()], None).unwrap();
+        let group = ["p.raw", "q.raw", "r.raw"].map(|path| root.join(path));
+        writer.write_group(&group, 1).unwrap();
+        write(root.join("script.sh"), writer.finish().unwrap()).unwrap();
+        let output = Command::new("sh")
+            .arg(root.join("script.sh"))
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("r.xmp: companion"));
+        assert_eq!("meta", fs::read_to_string(root.join("p.xmp")).unwrap());
+        assert!(!root.join("q.xmp").exists());
+        assert!(root.join("r.xmp").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    // Check that in safe mode, the script does nothing unless forced to, and that what it then does
+    // can be undone by running the journal.
+    #[cfg(unix)]
+    #[test]
+    fn test_run_safe_script() {
+        use std::os::unix::fs::MetadataExt;
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        for path in ["a", "it's", "c"] {
+            write(root.join(path), "same").unwrap();
+        }
+
+        let script = root.join("script.sh");
+        let safe = Safe::next_to(&script).unwrap();
+        assert_eq!(root.join("script.sh.trash"), safe.trash);
+        let handlers = [Handler::Hardlink, Handler::Remove];
+        let mut writer = ScriptWriter::new(Vec::new(), &handlers, &[], Some(&safe)).unwrap();
+        writer
+            .write_group(&["a", "it's", "c"].map(|path| root.join(path)), 1)
+            .unwrap();
+        write(&script, writer.finish().unwrap()).unwrap();
+        let ino = |path| fs::metadata(root.join(path)).unwrap().ino();
+        let inodes = [ino("a"), ino("it's"), ino("c")];
+
+        let output = Command::new("sh").arg(&script).output().unwrap();
+        assert!(String::from_utf8_lossy(&output.stderr).contains("run with --force"));
+        assert_eq!(inodes, [ino("a"), ino("it's"), ino("c")]);
+        assert!(!safe.journal.exists());
+
+        let output = Command::new("sh")
+            .arg(&script)
+            .arg("--force")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(ino("a"), ino("it's"));
+        assert_eq!(ino("a"), ino("c"));
+        assert!(safe.trash.join("1").join("it's").exists());
+
+        let output = Command::new("sh").arg(&safe.journal).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(inodes, [ino("a"), ino("it's"), ino("c")]);
+        assert_eq!("same", fs::read_to_string(root.join("it's")).unwrap());
+        remove_dir_all(&root).unwrap();
+    }
+
+    // Check that in safe mode, the script never touches protected paths, even when they're found
+    // under a relative root, and so spelled differently from how they're protected.
+    #[cfg(unix)]
+    #[test]
+    fn test_run_safe_script_protected() {
+        use crate::fdup::{group_duplicate_files, ScanOptions};
+        use rayon::prelude::*;
+        use std::os::unix::fs::MetadataExt;
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        for dir in ["p", "q"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        for path in ["p/z", "q/x", "q/y"] {
+            write(root.join(path), "same").unwrap();
+        }
+
+        // The same directory, relative to the working directory that the script is run from.
+        let cwd = env::current_dir().unwrap();
+        let relative: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        let roots = [relative.join(root.strip_prefix("/").unwrap())];
+        let relative = &roots[0];
+        let options = ScanOptions {
+            sort: true,
+            ..ScanOptions::default()
+        };
+        let groups: Vec<_> = group_duplicate_files(options, &roots).collect();
+        assert_eq!(1, groups.len());
+        assert_eq!(relative.join("q/y"), groups[0][2]);
+
+        let script = root.join("script.sh");
+        let mut safe = Safe::next_to(&script).unwrap();
+        let protected = fs::canonicalize(root.join("q")).unwrap();
+        safe.protected = vec![protected.to_str().unwrap().parse().unwrap()];
+        let mut writer =
+            ScriptWriter::new(Vec::new(), &[Handler::Remove], &[], Some(&safe)).unwrap();
+        writer.write_group(&groups[0], 0).unwrap();
+        let written = writer.finish().unwrap();
+        assert!(String::from_utf8_lossy(&written).contains("# 1 groups, 0 duplicates"));
+        write(&script, written).unwrap();
+        let ino = |path| fs::metadata(root.join(path)).unwrap().ino();
+        let inodes = [ino("p/z"), ino("q/x"), ino("q/y")];
+        let output = Command::new("sh")
+            .arg(&script)
+            .arg("--force")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(inodes, [ino("p/z"), ino("q/x"), ino("q/y")]);
+        assert!(!safe.trash.exists());
+
+        // Nor are companions of duplicates moved next to a kept file that's protected.
+        write(root.join("p/z.xmp"), "meta").unwrap();
+        let mut writer = ScriptWriter::new(
+            Vec::new(),
+            &[Handler::Remove],
+            &["xmp".to_string()],
+            Some(&safe),
+        )
+        .unwrap();
+        writer
+            .write_group(&[&groups[0][1], &groups[0][0]], 1)
+            .unwrap();
+        let written = writer.finish().unwrap();
+        assert!(!String::from_utf8_lossy(&written).contains("\ncompanion "));
+        write(&script, written).unwrap();
+        let output = Command::new("sh")
+            .arg(&script)
+            .arg("--force")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(!root.join("p/z").exists());
+        assert!(root.join("p/z.xmp").exists());
+        assert!(!root.join("q/x.xmp").exists());
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! Signals that control a running scan: `SIGUSR1` pauses reading file contents and `SIGUSR2`
+//! resumes it, so that a long scan can yield to other work without being started over, and
+//! `SIGINT` interrupts it, once the results found so far are written out.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// Set by the signal handlers, which can't do anything that isn't async-signal-safe, like waking
+/// up a condition variable.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the current pause was reported already, so that it's only reported once.
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// How often paused threads check whether they were resumed.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Pause on `SIGUSR1`, resume on `SIGUSR2` and record `SIGINT` rather than dying of it from now
+/// on. Only implemented on Unix.
+pub fn install_handlers() {
+    #[cfg(unix)]
+    unsafe {
+        extern "C" fn pause(_: libc::c_int) {
+            PAUSED.store(true, Ordering::SeqCst);
+        }
+        extern "C" fn resume(_: libc::c_int) {
+            PAUSED.store(false, Ordering::SeqCst);
+        }
+        extern "C" fn interrupt(_: libc::c_int) {
+            INTERRUPTED.store(true, Ordering::SeqCst);
+            // Threads waiting for the scan to be resumed would never notice.
+            PAUSED.store(false, Ordering::SeqCst);
+        }
+
+        for (signal, handler) in [
+            (libc::SIGUSR1, pause as extern "C" fn(libc::c_int)),
+            (libc::SIGUSR2, resume),
+            (libc::SIGINT, interrupt),
+        ] {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as libc::sighandler_t;
+            // Reads interrupted by the signals are restarted rather than failing.
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut());
+        }
+    }
+}
+
+/// # Returns
+///
+/// `true` if and only if `SIGINT` was received.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Block the calling thread for as long as the scan is paused. The first thread to notice a pause
+/// reports it on stderr, along with the progress given by `progress`.
+pub fn wait_while_paused(progress: impl FnOnce() -> String) {
+    if !PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    if !REPORTED.swap(true, Ordering::SeqCst) {
+        eprintln!("fdup: paused after {}; send SIGUSR2 to resume", progress());
+    }
+    while PAUSED.load(Ordering::SeqCst) {
+        thread::sleep(POLL_INTERVAL);
+    }
+    if REPORTED.swap(false, Ordering::SeqCst) && !interrupted() {
+        eprintln!("fdup: resumed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_pause() {
+        install_handlers();
+        wait_while_paused(|| unreachable!());
+
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(PAUSED.load(Ordering::SeqCst));
+        let resume = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            unsafe { libc::raise(libc::SIGUSR2) };
+        });
+        wait_while_paused(|| "testing".to_string());
+        assert!(!PAUSED.load(Ordering::SeqCst));
+        resume.join().unwrap();
+    }
+}
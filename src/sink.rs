@@ -0,0 +1,106 @@
+//! Destinations of the results of a scan, which every format of them is written through, whether
+//! it's one of those of the binary or one of an embedder's own.
+
+use crate::{
+    errors::ScanError,
+    fdup::{group_duplicate_files, EmptyFiles, ScanOptions},
+};
+use rayon::prelude::*;
+use std::{io, path::PathBuf, sync::Mutex};
+
+/// Destination of the results of a scan: the groups of duplicates as they're found, then the rest
+/// of the results once the scan is done.
+pub trait OutputSink {
+    /// Write `group`, along with `fingerprint`, the checksum of its files' contents, if it was
+    /// computed for it.
+    fn write_group(&mut self, group: &[PathBuf], fingerprint: Option<&[u8]>) -> io::Result<()>;
+
+    /// Write `empty_files`, those reported separately from the groups as with
+    /// `EmptyFiles::ReportSeparately`, and `errors`, those because of which paths were left out of
+    /// the results, and close off the results, once the scan is done or interrupted.
+    fn finish(self: Box<Self>, empty_files: &[PathBuf], errors: &[ScanError]) -> io::Result<()>;
+}
+
+/// Scan `roots` as `group_duplicate_files` does, writing each group to `sink` as it's found, and
+/// then the empty files reported separately, if `options.empty` says so, and the errors recorded
+/// in `options.errors`, which are then removed from it. Stops at the first error that `sink`
+/// returns, without finishing it.
+pub fn scan_into(
+    options: ScanOptions,
+    roots: &[PathBuf],
+    sink: Box<dyn OutputSink + Send>,
+) -> io::Result<()> {
+    let (errors, stats, empty) = (options.errors.clone(), options.stats.clone(), options.empty);
+    let sink = Mutex::new(sink);
+    group_duplicate_files(options, roots)
+        .try_for_each(|group| sink.lock().unwrap().write_group(&group, None))?;
+    let empty_files = match empty {
+        EmptyFiles::ReportSeparately => stats.read(|stats| stats.empty_files.clone()),
+        _ => Vec::new(),
+    };
+    sink.into_inner()
+        .unwrap()
+        .finish(&empty_files, &errors.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+        sync::Arc,
+    };
+
+    /// Results written to a sink, shared with the test.
+    #[derive(Default)]
+    struct Written {
+        groups: Vec<Vec<PathBuf>>,
+        empty_files: Vec<PathBuf>,
+        finished: bool,
+    }
+
+    struct TestSink(Arc<Mutex<Written>>);
+
+    impl OutputSink for TestSink {
+        fn write_group(&mut self, group: &[PathBuf], _: Option<&[u8]>) -> io::Result<()> {
+            self.0.lock().unwrap().groups.push(group.to_vec());
+            Ok(())
+        }
+
+        fn finish(self: Box<Self>, empty_files: &[PathBuf], _: &[ScanError]) -> io::Result<()> {
+            let mut written = self.0.lock().unwrap();
+            written.empty_files = empty_files.to_vec();
+            written.finished = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_scan_into() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let roots = [env::temp_dir().join(prefix)];
+        let dir = &roots[0];
+        if dir.exists() {
+            remove_dir_all(dir).unwrap();
+        }
+        create_dir_all(dir).unwrap();
+        for (path, contents) in [("a", "same"), ("b", "same"), ("c", "diff"), ("e", "")] {
+            write(dir.join(path), contents).unwrap();
+        }
+
+        let written = Arc::new(Mutex::new(Written::default()));
+        let options = ScanOptions {
+            sort: true,
+            empty: EmptyFiles::ReportSeparately,
+            ..ScanOptions::default()
+        };
+        let sink = Box::new(TestSink(written.clone()));
+        scan_into(options, &roots, sink).unwrap();
+        let written = written.lock().unwrap();
+        assert_eq!(vec![vec![dir.join("a"), dir.join("b")]], written.groups);
+        assert_eq!(vec![dir.join("e")], written.empty_files);
+        assert!(written.finished);
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Why files found under the roots were left out of the results, recorded file by file on demand,
+//! to tell why two files that are known to be duplicates weren't reported as such.
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Why a file was left out of the results, other than an IO error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// It's a symlink, which is never followed to a file.
+    Symlink,
+
+    /// Its name is left out, as with `ScanOptions::skip_appledouble` or `skip_junk`.
+    ExcludedName,
+
+    /// Its path isn't valid unicode, as with `ScanOptions::skip_invalid_names`.
+    InvalidName,
+
+    /// It was modified too recently, as with `ScanOptions::ignore_newer_than`.
+    TooNew,
+
+    /// It isn't owned by `ScanOptions::owner`.
+    NotOwned,
+
+    /// It can't be written to, as with `ScanOptions::only_writable`.
+    NotWritable,
+
+    /// It was found past `ScanOptions::max_files` or `max_bytes`.
+    Limit,
+
+    /// No other file has its size.
+    UniqueSize,
+
+    /// It's empty, and empty files aren't grouped, as with `ScanOptions::empty`.
+    Empty,
+
+    /// Its size wasn't among those sampled, as with `ScanOptions::sample_sizes`.
+    UnsampledSize,
+
+    /// Its content type isn't one of `ScanOptions::types`.
+    ContentType,
+
+    /// No other file of its size has the same key, for one of `ScanOptions::keys`.
+    UniqueKey,
+
+    /// No other file of its size has the same checksum of sampled blocks.
+    UniqueSample,
+
+    /// No other file of its size has the same checksum.
+    UniqueChecksum,
+
+    /// Its group was left out for being made up of hardlinks to a single inode.
+    Hardlinked,
+
+    /// Its group was left out for the directories that its files are in, as with
+    /// `ScanOptions::spread`.
+    Spread,
+
+    /// Its group was left out by `ScanOptions::keep_in` or `protect`.
+    Kept,
+
+    /// Its group was left out by `ScanOptions::group_must_include` or `group_must_exclude`.
+    GroupFiltered,
+}
+
+impl SkipReason {
+    /// # Returns
+    ///
+    /// Name of this reason in machine-readable output.
+    pub fn name(self) -> &'static str {
+        match self {
+            SkipReason::Symlink => "symlink",
+            SkipReason::ExcludedName => "excluded_name",
+            SkipReason::InvalidName => "invalid_name",
+            SkipReason::TooNew => "too_new",
+            SkipReason::NotOwned => "not_owned",
+            SkipReason::NotWritable => "not_writable",
+            SkipReason::Limit => "limit",
+            SkipReason::UniqueSize => "unique_size",
+            SkipReason::Empty => "empty",
+            SkipReason::UnsampledSize => "unsampled_size",
+            SkipReason::ContentType => "content_type",
+            SkipReason::UniqueKey => "unique_key",
+            SkipReason::UniqueSample => "unique_sample",
+            SkipReason::UniqueChecksum => "unique_checksum",
+            SkipReason::Hardlinked => "hardlinked",
+            SkipReason::Spread => "spread",
+            SkipReason::Kept => "kept",
+            SkipReason::GroupFiltered => "group_filtered",
+        }
+    }
+}
+
+/// File left out of the results, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Skip {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// Files left out of the results, and why, if they're being recorded at all, which they aren't by
+/// default, since every file with a unique size would be. Clones share the same record, as with
+/// `ErrorLog`.
+#[derive(Clone, Debug, Default)]
+pub struct SkipLog {
+    skips: Option<Arc<Mutex<Vec<Skip>>>>,
+}
+
+impl SkipLog {
+    /// # Returns
+    ///
+    /// Log that records the files that are left out.
+    pub fn recording() -> SkipLog {
+        SkipLog {
+            skips: Some(Default::default()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.skips.is_some()
+    }
+
+    /// Record that the files at `paths` were left out for `reason`, if recording. `paths` is only
+    /// iterated over if so.
+    pub fn record<I: IntoIterator<Item = PathBuf>>(
+        &self,
+        reason: SkipReason,
+        paths: impl FnOnce() -> I,
+    ) {
+        if let Some(skips) = &self.skips {
+            let skipped = paths().into_iter().map(|path| Skip { path, reason });
+            skips.lock().unwrap().extend(skipped);
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Files recorded as left out so far, which are then removed from the log.
+    pub fn take(&self) -> Vec<Skip> {
+        match &self.skips {
+            Some(skips) => std::mem::take(&mut *skips.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Check that skips are only recorded when asked for, and shared between clones.
+    #[test]
+    fn test_skip_log() {
+        let skips = SkipLog::default();
+        skips.record(SkipReason::Symlink, || -> Vec<PathBuf> {
+            panic!("iterated over without recording")
+        });
+        assert!(!skips.is_recording());
+        assert!(skips.take().is_empty());
+
+        let skips = SkipLog::recording();
+        skips
+            .clone()
+            .record(SkipReason::Symlink, || [PathBuf::from("a")]);
+        skips.record(SkipReason::UniqueSize, || [PathBuf::from("b")]);
+        let expected = vec![
+            Skip {
+                path: PathBuf::from("a"),
+                reason: SkipReason::Symlink,
+            },
+            Skip {
+                path: PathBuf::from("b"),
+                reason: SkipReason::UniqueSize,
+            },
+        ];
+        assert_eq!(expected, skips.take());
+        assert!(skips.take().is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+//! Content types told apart by the magic bytes that files start with, regardless of their names.
+
+use crate::{open_files, throttle::throttle};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+};
+
+/// Broad kind of content that a file holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentType {
+    Image,
+    Video,
+    Audio,
+    Archive,
+    Document,
+}
+
+impl FromStr for ContentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "image" => Ok(ContentType::Image),
+            "video" => Ok(ContentType::Video),
+            "audio" => Ok(ContentType::Audio),
+            "archive" => Ok(ContentType::Archive),
+            "document" => Ok(ContentType::Document),
+            _ => Err(format!("unknown content type: {}", s)),
+        }
+    }
+}
+
+/// Number of bytes at the start of a file that are enough to sniff its content type.
+const HEADER_LEN: usize = 512;
+
+/// Magic bytes found at a given offset into files of each content type.
+const SIGNATURES: &[(usize, &[u8], ContentType)] = &[
+    (0, b"\xff\xd8\xff", ContentType::Image),
+    (0, b"\x89PNG\r\n\x1a\n", ContentType::Image),
+    (0, b"GIF87a", ContentType::Image),
+    (0, b"GIF89a", ContentType::Image),
+    (0, b"BM", ContentType::Image),
+    (0, b"II*\0", ContentType::Image),
+    (0, b"MM\0*", ContentType::Image),
+    (0, b"\0\0\x01\0", ContentType::Image),
+    (8, b"WEBP", ContentType::Image),
+    (8, b"AVI ", ContentType::Video),
+    (8, b"WAVE", ContentType::Audio),
+    (0, b"\x1a\x45\xdf\xa3", ContentType::Video),
+    (0, b"FLV", ContentType::Video),
+    (0, b"\0\0\x01\xba", ContentType::Video),
+    (0, b"ID3", ContentType::Audio),
+    (0, b"\xff\xfb", ContentType::Audio),
+    (0, b"\xff\xf3", ContentType::Audio),
+    (0, b"\xff\xf2", ContentType::Audio),
+    (0, b"fLaC", ContentType::Audio),
+    (0, b"OggS", ContentType::Audio),
+    (0, b"PK\x03\x04", ContentType::Archive),
+    (0, b"\x1f\x8b", ContentType::Archive),
+    (0, b"BZh", ContentType::Archive),
+    (0, b"\xfd7zXZ\0", ContentType::Archive),
+    (0, b"7z\xbc\xaf\x27\x1c", ContentType::Archive),
+    (0, b"Rar!\x1a\x07", ContentType::Archive),
+    (0, b"\x28\xb5\x2f\xfd", ContentType::Archive),
+    (257, b"ustar", ContentType::Archive),
+    (0, b"%PDF-", ContentType::Document),
+    (0, b"%!PS", ContentType::Document),
+    (0, b"{\\rtf", ContentType::Document),
+    (
+        0,
+        b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+        ContentType::Document,
+    ),
+];
+
+/// ISO base media file brands, found after `ftyp` at offset 4, of images and audio. Every other
+/// brand is taken to be a video.
+const FTYP_BRANDS: &[(&[u8], ContentType)] = &[
+    (b"heic", ContentType::Image),
+    (b"heix", ContentType::Image),
+    (b"mif1", ContentType::Image),
+    (b"avif", ContentType::Image),
+    (b"M4A ", ContentType::Audio),
+    (b"M4B ", ContentType::Audio),
+];
+
+/// # Returns
+///
+/// Content type of a file that starts with `header`, if it's recognized.
+pub fn sniff(header: &[u8]) -> Option<ContentType> {
+    if header.get(4..8) == Some(b"ftyp") {
+        let brand = header.get(8..12)?;
+        let image_or_audio = FTYP_BRANDS.iter().find(|(other, _)| *other == brand);
+        return Some(image_or_audio.map_or(ContentType::Video, |&(_, content_type)| content_type));
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| header.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|&(_, _, content_type)| content_type)
+}
+
+/// # Returns
+///
+/// Content type of the file whose filepath is `path`, if it's recognized.
+pub fn sniff_file(path: &Path) -> io::Result<Option<ContentType>> {
+    let _permit = open_files::acquire(1);
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    File::open(path)?
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+    throttle(header.len());
+    Ok(sniff(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        let mut tar = vec![0u8; 300];
+        tar[257..262].copy_from_slice(b"ustar");
+        let cases: &[(&[u8], Option<ContentType>)] = &[
+            (b"\x89PNG\r\n\x1a\n....", Some(ContentType::Image)),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some(ContentType::Image)),
+            (b"\0\0\0\x18ftypheic", Some(ContentType::Image)),
+            (b"\0\0\0\x18ftypisom", Some(ContentType::Video)),
+            (b"RIFF\0\0\0\0AVI LIST", Some(ContentType::Video)),
+            (b"\0\0\0\x18ftypM4A ", Some(ContentType::Audio)),
+            (b"RIFF\0\0\0\0WAVEfmt ", Some(ContentType::Audio)),
+            (b"ID3\x03", Some(ContentType::Audio)),
+            (b"PK\x03\x04", Some(ContentType::Archive)),
+            (&tar, Some(ContentType::Archive)),
+            (b"%PDF-1.7", Some(ContentType::Document)),
+            (b"hello", None),
+            (b"", None),
+            (b"\0\0\0\x18ftyp", None),
+        ];
+        for &(header, expected) in cases {
+            assert_eq!(expected, sniff(header), "{:?}", header);
+        }
+    }
+}
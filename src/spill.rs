@@ -0,0 +1,329 @@
+use crate::{compact_path::CompactPath, fdup::FileEntry, read_only};
+use std::{
+    convert::TryInto,
+    env,
+    fs::{read, remove_dir_all, remove_file, File},
+    io::{self, BufWriter, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Number of files that spilled entries are spread across. Each of them is later read back into
+/// memory on its own, so this is roughly the factor by which spilling reduces memory use.
+const PARTITIONS: usize = 64;
+
+/// Number of times that a partition that doesn't fit in memory once it's read back may be spilled
+/// again, into partitions of its own.
+const MAX_LEVEL: u32 = 4;
+
+/// Files found under the root, either held in memory or spilled to disk.
+pub enum Collected {
+    InMemory(Vec<FileEntry>),
+    Spilled(Spill),
+}
+
+/// Collects the files found under the root, holding them in memory for as long as they're
+/// estimated to take up no more than a given number of bytes, and spilling them to disk once they
+/// exceed it.
+pub struct Collector {
+    max_memory: usize,
+    collected: Mutex<(Collected, usize)>,
+}
+
+impl Collector {
+    pub fn new(max_memory: usize) -> Collector {
+        Collector {
+            max_memory,
+            collected: Mutex::new((Collected::InMemory(Vec::new()), 0)),
+        }
+    }
+
+    pub fn add(&self, files: Vec<FileEntry>) {
+        let mut guard = self.collected.lock().unwrap();
+        let (collected, used) = &mut *guard;
+        match collected {
+            Collected::InMemory(entries) => {
+                *used += files.iter().map(estimate_memory).sum::<usize>();
+                entries.extend(files);
+                if self.max_memory < *used {
+                    let mut spill = Spill::new(0, self.max_memory).unwrap();
+                    for entry in entries.drain(..) {
+                        spill.write(&entry).unwrap();
+                    }
+                    *collected = Collected::Spilled(spill);
+                }
+            }
+            Collected::Spilled(spill) => {
+                for entry in files {
+                    spill.write(&entry).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Reserve memory for `files` more, as long as they'd fit in `max_memory`.
+    pub fn reserve(&self, files: usize) {
+        if let Collected::InMemory(entries) = &mut self.collected.lock().unwrap().0 {
+            entries.reserve(files.min(self.max_memory / mem::size_of::<FileEntry>()));
+        }
+    }
+
+    pub fn finish(self) -> Collected {
+        self.collected.into_inner().unwrap().0
+    }
+}
+
+/// # Returns
+///
+/// Approximate number of bytes of memory taken up by `entry`.
+fn estimate_memory(entry: &FileEntry) -> usize {
+    mem::size_of::<FileEntry>() + entry.path.name_len()
+}
+
+/// # Returns
+///
+/// Approximate number of bytes of memory taken up by `entries`.
+pub fn estimate_total_memory(entries: &[FileEntry]) -> usize {
+    entries.iter().map(estimate_memory).sum()
+}
+
+/// Temporary files holding spilled entries, deleted when this is dropped.
+///
+/// Entries are spread across the files by size, so that each file holds every entry of the sizes
+/// that were spilled to it. Files can therefore be grouped by size one partition at a time.
+pub struct Spill {
+    dir: PathBuf,
+    writers: Vec<BufWriter<File>>,
+    /// How many times the entries have been spilled before, each time to a partition of a spill
+    /// of the level before.
+    level: u32,
+    /// Bytes of memory that a partition may take up once it's read back before it's spilled again.
+    max_memory: usize,
+}
+
+impl Spill {
+    fn new(level: u32, max_memory: usize) -> io::Result<Spill> {
+        // Distinguishes spills made by the same process.
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!(
+            "fdup-spill-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let dir = env::temp_dir().join(name);
+        read_only::create_dir(&dir)?;
+
+        let mut spill = Spill {
+            dir,
+            writers: Vec::with_capacity(PARTITIONS),
+            level,
+            max_memory,
+        };
+        for partition in 0..PARTITIONS {
+            let file = read_only::create(&spill.partition_path(partition))?;
+            spill.writers.push(BufWriter::new(file));
+        }
+        Ok(spill)
+    }
+
+    fn partition_path(&self, partition: usize) -> PathBuf {
+        self.dir.join(partition.to_string())
+    }
+
+    /// # Returns
+    ///
+    /// Index of the partition that entries of size `size` are spilled to by a spill of `level`.
+    fn partition_of(size: usize, level: u32) -> usize {
+        // Sizes are often multiples of a power of two, so mix them up before picking a partition.
+        // Each level picks by other bits of the mix, so that the sizes of a partition that's
+        // spilled again are spread out.
+        let mixed = (size as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (mixed >> (32 + 6 * level)) as usize % PARTITIONS
+    }
+
+    fn write(&mut self, entry: &FileEntry) -> io::Result<()> {
+        let path = encode_path(&entry.path.to_path_buf());
+        let writer = &mut self.writers[Spill::partition_of(entry.size, self.level)];
+        writer.write_all(&(entry.size as u64).to_le_bytes())?;
+        writer.write_all(&entry.id.0.to_le_bytes())?;
+        writer.write_all(&entry.id.1.to_le_bytes())?;
+        writer.write_all(&(path.len() as u64).to_le_bytes())?;
+        writer.write_all(&path)
+    }
+
+    /// # Returns
+    ///
+    /// Entries in each partition, read back into memory one partition at a time. Each partition's
+    /// file is deleted once it has been read. Partitions that take up more than `max_memory` once
+    /// they're read back are spilled again, up to `MAX_LEVEL` times, unless all of their entries
+    /// have the same size, in which case they can't be split up.
+    pub fn into_partitions(mut self) -> Box<dyn Iterator<Item = Vec<FileEntry>>> {
+        for writer in &mut self.writers {
+            writer.flush().unwrap();
+        }
+        self.writers.clear();
+
+        Box::new((0..PARTITIONS).flat_map(move |partition| {
+            let path = self.partition_path(partition);
+            let entries = decode_entries(&read(&path).unwrap());
+            remove_file(path).unwrap();
+            let splittable =
+                self.level < MAX_LEVEL && entries.iter().any(|entry| entry.size != entries[0].size);
+            if splittable && self.max_memory < estimate_total_memory(&entries) {
+                let mut spill = Spill::new(self.level + 1, self.max_memory).unwrap();
+                for entry in &entries {
+                    spill.write(entry).unwrap();
+                }
+                drop(entries);
+                spill.into_partitions()
+            } else {
+                Box::new(std::iter::once(entries))
+            }
+        }))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        self.writers.clear();
+        let _ = remove_dir_all(&self.dir);
+    }
+}
+
+fn decode_entries(bytes: &[u8]) -> Vec<FileEntry> {
+    let mut entries = Vec::new();
+    let mut rest = bytes;
+    let mut take = |len: usize| {
+        let (taken, remainder) = rest.split_at(len);
+        rest = remainder;
+        taken
+    };
+
+    // Entries from the same directory tend to be spilled one after another, so they can mostly
+    // share their parent again.
+    let mut last_parent: Option<(PathBuf, Arc<CompactPath>)> = None;
+
+    let mut remaining = bytes.len();
+    while 0 < remaining {
+        let mut take_u64 = || u64::from_le_bytes(take(8).try_into().unwrap());
+        let size = take_u64() as usize;
+        let id = (take_u64(), take_u64());
+        let path_len = take_u64() as usize;
+        let path = decode_path(take(path_len));
+        remaining -= 4 * 8 + path_len;
+
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+                if last_parent.as_ref().map(|(path, _)| path.as_path()) != Some(parent) {
+                    let compact_parent = Arc::new(CompactPath::new(None, parent.as_os_str()));
+                    last_parent = Some((parent.to_path_buf(), compact_parent));
+                }
+                let parent = last_parent.as_ref().map(|(_, parent)| parent.clone());
+                CompactPath::new(parent, name)
+            }
+            _ => CompactPath::new(None, path.as_os_str()),
+        };
+        entries.push(FileEntry { path, size, id });
+    }
+    entries
+}
+
+#[cfg(unix)]
+fn encode_path(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn decode_path(bytes: &[u8]) -> PathBuf {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+// Paths that aren't valid unicode can't be spilled on these platforms.
+#[cfg(not(unix))]
+fn encode_path(path: &Path) -> Vec<u8> {
+    path.to_str().unwrap().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn decode_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(std::str::from_utf8(bytes).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn test_entries() -> Vec<FileEntry> {
+        (0..1000)
+            .map(|i| FileEntry {
+                path: CompactPath::new(None, format!("/some/dir/{}", i).as_ref()),
+                size: (i % 37) * 4096,
+                id: (1, i as u64),
+            })
+            .collect()
+    }
+
+    fn collect(max_memory: usize) -> Collected {
+        let collector = Collector::new(max_memory);
+        for chunk in test_entries().chunks(10) {
+            collector.add(chunk.to_vec());
+        }
+        collector.finish()
+    }
+
+    // Check that entries stay in memory while they fit, and are spilled otherwise.
+    #[test]
+    fn test_collector() {
+        match collect(usize::MAX) {
+            Collected::InMemory(entries) => assert_eq!(test_entries().len(), entries.len()),
+            Collected::Spilled(_) => panic!("entries were spilled"),
+        }
+
+        let spill = match collect(1024) {
+            Collected::InMemory(_) => panic!("entries were not spilled"),
+            Collected::Spilled(spill) => spill,
+        };
+        let dir = spill.dir.clone();
+        let partitions: Vec<Vec<FileEntry>> = spill.into_partitions().collect();
+        assert!(!dir.exists());
+
+        // every entry is read back exactly once
+        let read_back: HashSet<(PathBuf, usize, (u64, u64))> = partitions
+            .iter()
+            .flatten()
+            .map(|entry| (entry.path.to_path_buf(), entry.size, entry.id))
+            .collect();
+        let expected: HashSet<(PathBuf, usize, (u64, u64))> = test_entries()
+            .into_iter()
+            .map(|entry| (entry.path.to_path_buf(), entry.size, entry.id))
+            .collect();
+        assert_eq!(test_entries().len(), partitions.iter().flatten().count());
+        assert_eq!(expected, read_back);
+
+        // all entries of the same size end up in the same partition, and sizes are spread out
+        let sizes: Vec<HashSet<usize>> = partitions
+            .iter()
+            .map(|partition| partition.iter().map(|entry| entry.size).collect())
+            .collect();
+        let total_sizes: usize = sizes.iter().map(HashSet::len).sum();
+        assert_eq!(37, total_sizes);
+        assert!(sizes.iter().all(|sizes| sizes.len() < 37));
+
+        // partitions that don't fit are spilled again, until they're down to one size
+        assert!(PARTITIONS < partitions.len());
+        for (partition, sizes) in partitions.iter().zip(&sizes) {
+            assert!(estimate_total_memory(partition) <= 1024 || sizes.len() <= 1);
+        }
+    }
+}
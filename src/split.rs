@@ -0,0 +1,242 @@
+//! Results split across several files, one per size class of the groups or per top-level
+//! directory that they're in, so that a huge report can be reviewed, or handed to different
+//! owners, a piece at a time.
+
+use crate::{
+    fdup::root_of,
+    output::{Field, Format, ResultsWriter, TextWriter},
+    read_only,
+    sink::OutputSink,
+    stats::{bucket_index, SIZE_BUCKETS},
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufWriter},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+/// Names of the files of the groups in each bucket of `SIZE_BUCKETS`, without their extension.
+const SIZE_CLASS_NAMES: [&str; SIZE_BUCKETS.len()] =
+    ["under-1KB", "1KB-100KB", "100KB-10MB", "10MB-and-over"];
+
+/// Name of the file of the groups whose first files are right under a root, rather than in one of
+/// its directories.
+const ROOT_FILES_NAME: &str = "root-files";
+
+/// What groups are split by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SplitBy {
+    /// The size class of their files, as in `SIZE_BUCKETS`.
+    #[default]
+    Size,
+
+    /// The directory right under the root that their first file is in.
+    TopDir,
+}
+
+impl FromStr for SplitBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(SplitBy::Size),
+            "top-dir" => Ok(SplitBy::TopDir),
+            _ => Err(format!("unknown way to split: {}", s)),
+        }
+    }
+}
+
+/// Writer of the groups of a scan to a file each for the part of the results that they're in, all
+/// in the same directory, each created once it has a group. Every file is in `format`, and in
+/// the machine-readable ones they're whole results with neither empty files nor errors, which are
+/// reported with the rest of the results as usual.
+pub struct SplitOutput {
+    dir: PathBuf,
+    by: SplitBy,
+    roots: Vec<PathBuf>,
+    format: Format,
+    fields: Vec<Field>,
+    fingerprints: bool,
+    schema_version: u32,
+    parts: Mutex<HashMap<String, Box<dyn OutputSink + Send>>>,
+}
+
+impl SplitOutput {
+    /// # Returns
+    ///
+    /// Writer of the groups found under `roots` to files in `dir`, which is created if it doesn't
+    /// exist, split `by` either way, in `format` and with `fields`, `fingerprints` and
+    /// `schema_version` as `ResultsWriter` takes them.
+    pub fn new(
+        dir: &Path,
+        by: SplitBy,
+        roots: &[PathBuf],
+        format: Format,
+        fields: Vec<Field>,
+        fingerprints: bool,
+        schema_version: u32,
+    ) -> io::Result<SplitOutput> {
+        read_only::create_dir_all(dir)?;
+        Ok(SplitOutput {
+            dir: dir.to_path_buf(),
+            by,
+            roots: roots.to_vec(),
+            format,
+            fields,
+            fingerprints,
+            schema_version,
+            parts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// # Returns
+    ///
+    /// Name of the file that `group` goes in, without its extension.
+    fn part_name(&self, group: &[PathBuf]) -> String {
+        match self.by {
+            SplitBy::Size => {
+                let size =
+                    std::fs::symlink_metadata(&group[0]).map_or(0, |metadata| metadata.len());
+                SIZE_CLASS_NAMES[bucket_index(size)].to_string()
+            }
+            SplitBy::TopDir => {
+                let root = match root_of(&group[0], &self.roots) {
+                    Some(root) => root,
+                    None => return ROOT_FILES_NAME.to_string(),
+                };
+                let relative = group[0].strip_prefix(&self.roots[root]).unwrap();
+                let mut components = relative.components();
+                let name = match (components.next(), components.next()) {
+                    (Some(Component::Normal(name)), Some(_)) => name.to_string_lossy(),
+                    _ => ROOT_FILES_NAME.into(),
+                };
+                // Directories of different roots may have the same name.
+                if 1 < self.roots.len() {
+                    format!("{}-{}", root + 1, name)
+                } else {
+                    name.into_owned()
+                }
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Path of the file of the part named `name`.
+    fn part_path(&self, name: &str) -> PathBuf {
+        let extension = match self.format {
+            Format::Text => "txt",
+            Format::Json => "json",
+            Format::Msgpack => "msgpack",
+        };
+        self.dir.join(format!("{}.{}", name, extension))
+    }
+
+    /// Write `group`, with its `fingerprint` if they're written, to the file of its part.
+    pub fn write_group(&self, group: &[PathBuf], fingerprint: Option<&[u8]>) -> io::Result<()> {
+        let name = self.part_name(group);
+        let mut parts = self.parts.lock().unwrap();
+        let part = match parts.get_mut(&name) {
+            Some(part) => part,
+            None => {
+                let out = BufWriter::new(read_only::create(&self.part_path(&name))?);
+                let part: Box<dyn OutputSink + Send> = match self.format {
+                    Format::Text => Box::new(TextWriter::new(out, self.fingerprints, false)),
+                    format => {
+                        let fields = self.fields.clone();
+                        let (fingerprints, schema_version) =
+                            (self.fingerprints, self.schema_version);
+                        let writer =
+                            ResultsWriter::new(format, out, fields, fingerprints, schema_version)?;
+                        Box::new(writer.unwrap())
+                    }
+                };
+                parts.entry(name).or_insert(part)
+            }
+        };
+        part.write_group(group, fingerprint)
+    }
+
+    /// Close off every file.
+    ///
+    /// # Returns
+    ///
+    /// Paths of the files written, sorted.
+    pub fn finish(&self) -> io::Result<Vec<PathBuf>> {
+        let mut names = Vec::new();
+        let parts = std::mem::take(&mut *self.parts.lock().unwrap());
+        for (name, part) in parts {
+            part.finish(&[], &[])?;
+            names.push(name);
+        }
+        names.sort();
+        Ok(names.iter().map(|name| self.part_path(name)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{read_to_string, remove_dir_all, write},
+    };
+
+    #[test]
+    fn test_part_name_top_dir() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let dir = env::temp_dir().join(prefix);
+        let roots = [PathBuf::from("/a"), PathBuf::from("/b")];
+        let split = |roots: &[PathBuf]| {
+            SplitOutput::new(&dir, SplitBy::TopDir, roots, Format::Text, vec![], false, 2).unwrap()
+        };
+
+        let group = |path: &str| vec![PathBuf::from(path)];
+        assert_eq!("src", split(&roots[..1]).part_name(&group("/a/src/x/y")));
+        assert_eq!(
+            ROOT_FILES_NAME,
+            split(&roots[..1]).part_name(&group("/a/y"))
+        );
+        assert_eq!("2-src", split(&roots).part_name(&group("/b/src/y")));
+        assert_eq!(ROOT_FILES_NAME, split(&roots).part_name(&group("/c/src/y")));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_group() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let dir = env::temp_dir().join(prefix);
+        if dir.exists() {
+            remove_dir_all(&dir).unwrap();
+        }
+        let out = dir.join("out");
+        let split =
+            SplitOutput::new(&out, SplitBy::Size, &[], Format::Text, vec![], false, 2).unwrap();
+        let (small, large) = (vec![dir.join("small")], vec![dir.join("large")]);
+        write(&small[0], "a").unwrap();
+        write(&large[0], vec![0; 2000]).unwrap();
+
+        split.write_group(&small, None).unwrap();
+        split.write_group(&large, None).unwrap();
+        split.write_group(&small, None).unwrap();
+        let paths = split.finish().unwrap();
+        assert_eq!(
+            vec![out.join("1KB-100KB.txt"), out.join("under-1KB.txt")],
+            paths
+        );
+        assert_eq!(
+            format!("{:?}\n{:?}\n", small, small),
+            read_to_string(&paths[1]).unwrap()
+        );
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_by_from_str() {
+        assert_eq!(Ok(SplitBy::Size), "size".parse());
+        assert_eq!(Ok(SplitBy::TopDir), "top-dir".parse());
+        assert!("owner".parse::<SplitBy>().is_err());
+    }
+}
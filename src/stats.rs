@@ -0,0 +1,314 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Figures about a scan, besides the groups of duplicates that it finds.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Files found under the roots that weren't excluded, and the bytes that they take up.
+    pub files_found: u64,
+    pub bytes_found: u64,
+
+    /// Files and bytes counted by a first, metadata-only walk before the files were found, if one
+    /// was asked for.
+    pub precounted_files: Option<u64>,
+    pub precounted_bytes: Option<u64>,
+
+    /// Files found under each of the roots, by index, and the bytes that they take up, if there's
+    /// more than one root. Files under more than one count towards the innermost, as with
+    /// `fdup::root_of`. Empty until any file is found.
+    pub found_by_root: Vec<(u64, u64)>,
+
+    /// Whether files were left out because `ScanOptions::max_files` or `max_bytes` was reached.
+    pub truncated: bool,
+
+    /// How long it took to find the files, before any of them were grouped.
+    pub walk_duration: Duration,
+
+    /// Bytes taken up by the files with the same size as another, which are the ones that may be
+    /// read. Known once the files have been grouped by size, if they weren't spilled to disk;
+    /// otherwise, it grows as each partition is.
+    pub bytes_to_read: u64,
+
+    /// Approximate bytes of memory taken up by the largest partition of spilled files that didn't
+    /// fit in `ScanOptions::max_memory` once it was read back, because its files all have the same
+    /// size or it couldn't be spilled again, or 0 if they all did.
+    pub partition_over_memory: u64,
+
+    /// Empty files that were left out of the groups, as chosen by `EmptyFiles`.
+    pub empty_files: Vec<PathBuf>,
+
+    /// Symlinks found under the roots, which are never grouped, if they're walked as with
+    /// `WalkOptions::symlinks`.
+    pub symlinks: Vec<PathBuf>,
+
+    /// Directories that weren't walked because the same directory had already been walked through
+    /// another path, e.g. because it's a bind mount, as returned by `walk`.
+    pub mount_overlaps: Vec<PathBuf>,
+
+    /// Bytes that the groups of duplicates would take up if hardlinks were copies, but are
+    /// already saved by them.
+    pub linked_bytes: u64,
+
+    /// Bytes that could be saved by replacing all but one of the copies in each group of
+    /// duplicates by hardlinks to it.
+    pub reclaimable_bytes: u64,
+
+    /// Groups of duplicates and their reclaimable bytes, by the size of their files, bucketed as in
+    /// `SIZE_BUCKETS`.
+    pub histogram: [Bucket; SIZE_BUCKETS.len()],
+
+    /// Reclaimable bytes by the size of the files they're in, only recorded when sizes are
+    /// sampled, as with `ScanOptions::sample_sizes`, to estimate those of the whole scan from.
+    pub reclaimable_by_size: HashMap<u64, u64>,
+
+    /// Files that took the longest to hash, from the slowest, as many as
+    /// `ScanOptions::slowest_reads`.
+    pub slowest_reads: Vec<SlowRead>,
+
+    /// Bytes found, and those of duplicates, by the extension of the files' names, lowercased, or
+    /// "" for those without one, only recorded when `ScanOptions::by_extension` is set.
+    pub by_extension: HashMap<String, ByteCounts>,
+}
+
+/// Bytes taken up by some of the files found, and by the duplicates among them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ByteCounts {
+    /// As with `Stats::bytes_found`.
+    pub found: u64,
+    /// As with `Stats::linked_bytes`. In `Stats::by_extension`, each extra link of a file counts
+    /// towards the extension of its own name.
+    pub linked: u64,
+    /// As with `Stats::reclaimable_bytes`. In `Stats::by_extension`, each extra copy in a group
+    /// counts towards the extension of its own name.
+    pub reclaimable: u64,
+}
+
+impl ByteCounts {
+    /// # Returns
+    ///
+    /// Bytes that would be left if every duplicate were hardlinked to one copy of it.
+    pub fn unique(&self) -> u64 {
+        self.found.saturating_sub(self.linked + self.reclaimable)
+    }
+
+    /// # Returns
+    ///
+    /// `unique` as a fraction of the bytes found, or 1 if none were: the dedupe ratio.
+    pub fn ratio(&self) -> f64 {
+        match self.found {
+            0 => 1.0,
+            found => self.unique() as f64 / found as f64,
+        }
+    }
+}
+
+/// File that was hashed, and how long it took.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowRead {
+    pub path: PathBuf,
+    pub size: u64,
+    pub duration: Duration,
+}
+
+impl SlowRead {
+    /// # Returns
+    ///
+    /// Bytes per second at which the file was hashed.
+    pub fn rate(&self) -> f64 {
+        self.size as f64 / self.duration.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Names of the buckets of `Stats::histogram`, and the sizes that they end at, exclusively.
+pub const SIZE_BUCKETS: [(&str, u64); 4] = [
+    ("< 1 KB", 1_000),
+    ("1 KB - 100 KB", 100_000),
+    ("100 KB - 10 MB", 10_000_000),
+    (">= 10 MB", u64::MAX),
+];
+
+/// Groups of duplicates of sizes in some range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Bucket {
+    pub groups: u64,
+    pub reclaimable_bytes: u64,
+}
+
+impl Stats {
+    /// # Returns
+    ///
+    /// Bytes found, and those of the duplicates recorded so far, as `ByteCounts`.
+    /// Files under hardlinks that aren't duplicates of any other file count once per link.
+    pub fn bytes(&self) -> ByteCounts {
+        ByteCounts {
+            found: self.bytes_found,
+            linked: self.linked_bytes,
+            reclaimable: self.reclaimable_bytes,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Bucket of `histogram` for groups of files of `size` bytes.
+    pub fn bucket_mut(&mut self, size: u64) -> &mut Bucket {
+        &mut self.histogram[bucket_index(size)]
+    }
+
+    /// Record that the file at `path`, of `size` bytes, took `duration` to hash, if it's one of the
+    /// `keep` slowest so far. `path` is only called if so.
+    pub fn record_read(
+        &mut self,
+        path: impl FnOnce() -> PathBuf,
+        size: u64,
+        duration: Duration,
+        keep: usize,
+    ) {
+        let i = self
+            .slowest_reads
+            .partition_point(|read| duration <= read.duration);
+        if keep <= i {
+            return;
+        }
+        let path = path();
+        self.slowest_reads.insert(
+            i,
+            SlowRead {
+                path,
+                size,
+                duration,
+            },
+        );
+        self.slowest_reads.truncate(keep);
+    }
+}
+
+/// # Returns
+///
+/// Index of the bucket of `SIZE_BUCKETS` that `size` falls in.
+pub fn bucket_index(size: u64) -> usize {
+    SIZE_BUCKETS
+        .iter()
+        .position(|&(_, end)| size < end)
+        .unwrap_or(SIZE_BUCKETS.len() - 1)
+}
+
+/// Figure of a whole scan estimated from a sample of it, with the margin of error of its 95%
+/// confidence interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub margin: f64,
+}
+
+impl Stats {
+    /// # Returns
+    ///
+    /// Estimate of the bytes that would be reclaimable if every size had been compared, given
+    /// that each was sampled with probability `fraction`. Each size is one unit of the sample,
+    /// since duplicates always have the same size, so the estimate is their total scaled up by
+    /// `1 / fraction`, with the variance of a Poisson sample.
+    pub fn estimate_reclaimable_bytes(&self, fraction: f64) -> Estimate {
+        let total: f64 = self.reclaimable_by_size.values().map(|&y| y as f64).sum();
+        let squares: f64 = self
+            .reclaimable_by_size
+            .values()
+            .map(|&y| (y as f64).powi(2))
+            .sum();
+        let variance = (1.0 - fraction) / fraction.powi(2) * squares;
+        Estimate {
+            value: total / fraction,
+            margin: 1.96 * variance.sqrt(),
+        }
+    }
+}
+
+/// `Stats` recorded during a scan. Clones share the same record, so that one can be kept to read
+/// them back once the scan is done.
+#[derive(Clone, Debug, Default)]
+pub struct StatsLog {
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl StatsLog {
+    /// Record something about the scan, by updating the stats recorded so far with `update`.
+    pub fn record(&self, update: impl FnOnce(&mut Stats)) {
+        update(&mut self.stats.lock().unwrap());
+    }
+
+    /// # Returns
+    ///
+    /// Result of `read` on the stats recorded so far, e.g. to report progress while the scan is
+    /// still running.
+    pub fn read<T>(&self, read: impl FnOnce(&Stats) -> T) -> T {
+        read(&self.stats.lock().unwrap())
+    }
+
+    /// # Returns
+    ///
+    /// Stats recorded so far, which are then reset.
+    pub fn take(&self) -> Stats {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_record_read() {
+        let mut stats = Stats::default();
+        let read = |path: &str, secs| (PathBuf::from(path), Duration::from_secs(secs));
+        for (path, duration) in [read("a", 2), read("b", 1), read("c", 3), read("d", 1)] {
+            stats.record_read(|| path, 10, duration, 2);
+        }
+        let paths: Vec<_> = stats.slowest_reads.iter().map(|read| &read.path).collect();
+        assert_eq!(vec![Path::new("c"), Path::new("a")], paths);
+        assert_eq!(5.0, stats.slowest_reads[1].rate());
+
+        stats.record_read(
+            || panic!("recorded past the slowest"),
+            10,
+            Duration::ZERO,
+            0,
+        );
+        assert_eq!(2, stats.slowest_reads.len());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let stats = Stats {
+            bytes_found: 100,
+            linked_bytes: 10,
+            reclaimable_bytes: 15,
+            ..Default::default()
+        };
+        assert_eq!(75, stats.bytes().unique());
+        assert_eq!(0.75, stats.bytes().ratio());
+        assert_eq!(1.0, ByteCounts::default().ratio());
+    }
+
+    #[test]
+    fn test_estimate_reclaimable_bytes() {
+        let stats = Stats {
+            reclaimable_by_size: HashMap::from([(1, 10), (2, 20)]),
+            ..Default::default()
+        };
+        let estimate = stats.estimate_reclaimable_bytes(0.5);
+        assert_eq!(60.0, estimate.value);
+        // 1.96 * sqrt(0.5 / 0.25 * (10^2 + 20^2))
+        assert!((estimate.margin - 61.98).abs() < 0.01);
+        assert_eq!(
+            Estimate {
+                value: 30.0,
+                margin: 0.0
+            },
+            stats.estimate_reclaimable_bytes(1.0)
+        );
+    }
+}
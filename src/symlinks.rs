@@ -0,0 +1,148 @@
+//! Symlinks that resolve to duplicates, so that it's known which of them would dangle if the
+//! duplicates were removed, and which of the copies of a file are only links to it.
+
+use rayon::prelude::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Symlink that resolves to a file in a group of duplicates.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LinkToDuplicate {
+    pub link: PathBuf,
+
+    /// Path of the file, as it is in its group.
+    pub target: PathBuf,
+
+    /// Whether the file is the first of its group, i.e. the copy that's kept, so that the link
+    /// wouldn't dangle once the rest are removed.
+    pub kept: bool,
+}
+
+/// # Returns
+///
+/// Each of `symlinks` along with the canonical path of what it resolves to, directly or through
+/// other symlinks, leaving out those that dangle or loop.
+pub fn resolve(symlinks: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    symlinks
+        .par_iter()
+        .filter_map(|link| Some((link.clone(), link.canonicalize().ok()?)))
+        .collect()
+}
+
+/// # Returns
+///
+/// Links among `resolved`, as returned by `resolve`, to files in `groups`, sorted by link.
+pub fn links_to_duplicates(
+    resolved: &[(PathBuf, PathBuf)],
+    groups: &[Vec<PathBuf>],
+) -> Vec<LinkToDuplicate> {
+    let duplicates: HashMap<PathBuf, (&Path, bool)> = groups
+        .par_iter()
+        .flat_map_iter(|group| {
+            group.iter().enumerate().filter_map(|(i, path)| {
+                let canonical = path.canonicalize().ok()?;
+                Some((canonical, (path.as_path(), i == 0)))
+            })
+        })
+        .collect();
+    let mut links: Vec<LinkToDuplicate> = resolved
+        .iter()
+        .filter_map(|(link, target)| {
+            let &(path, kept) = duplicates.get(target)?;
+            Some(LinkToDuplicate {
+                link: link.clone(),
+                target: path.to_path_buf(),
+                kept,
+            })
+        })
+        .collect();
+    links.sort_by(|a, b| a.link.cmp(&b.link));
+    links
+}
+
+/// # Returns
+///
+/// Groups of the links among `resolved`, as returned by `resolve`, that resolve to the same
+/// canonical path, along with it, leaving out those with less than 2 links. Sorted by path, as
+/// are the links in each group.
+pub fn links_with_same_target(resolved: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, Vec<PathBuf>)> {
+    let mut by_target: HashMap<&Path, Vec<PathBuf>> = HashMap::new();
+    for (link, target) in resolved {
+        by_target.entry(target).or_default().push(link.clone());
+    }
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = by_target
+        .into_iter()
+        .filter(|(_, links)| 1 < links.len())
+        .map(|(target, mut links)| {
+            links.sort();
+            (target.to_path_buf(), links)
+        })
+        .collect();
+    groups.sort();
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        for path in ["a", "b", "c"] {
+            write(root.join(path), "").unwrap();
+        }
+        // "to_b2" resolves to "b" through "to_b".
+        for (link, target) in [
+            ("to_a", "a"),
+            ("to_b", "b"),
+            ("to_b2", "to_b"),
+            ("bad", "x"),
+        ] {
+            symlink(root.join(target), root.join(link)).unwrap();
+        }
+
+        let links: Vec<PathBuf> = ["to_a", "to_b", "to_b2", "bad"]
+            .iter()
+            .map(|link| root.join(link))
+            .collect();
+        let resolved = resolve(&links);
+        let to_duplicates = links_to_duplicates(&resolved, &[vec![root.join("a"), root.join("b")]]);
+        let same_target = links_with_same_target(&resolved);
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(3, resolved.len());
+        let link_to = |link: &str, target: &str, kept| LinkToDuplicate {
+            link: root.join(link),
+            target: root.join(target),
+            kept,
+        };
+        assert_eq!(
+            vec![
+                link_to("to_a", "a", true),
+                link_to("to_b", "b", false),
+                link_to("to_b2", "b", false),
+            ],
+            to_duplicates
+        );
+        assert_eq!(
+            vec![(root.join("b"), vec![root.join("to_b"), root.join("to_b2")])],
+            same_target
+        );
+    }
+}
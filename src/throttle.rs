@@ -0,0 +1,95 @@
+//! Limit on the rate at which fdup reads file contents, shared by every thread, so that a scan
+//! can run on a busy file server without starving everything else of IO bandwidth. Reads are also
+//! where a paused scan stops, and where how far it got is counted.
+
+use crate::signals;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+static LIMIT: OnceLock<TokenBucket> = OnceLock::new();
+
+/// Bytes of file contents read so far.
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+/// Token bucket holding up to one second's worth of bytes, so that reads can burst that far ahead
+/// of the rate before they're slowed down.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    /// Bytes that can be read without waiting, which goes negative while readers are waiting for
+    /// it to refill, and when it was last refilled.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: f64) -> TokenBucket {
+        TokenBucket {
+            bytes_per_sec,
+            state: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    /// Take `bytes` out of the bucket, sleeping until they've been earned if it doesn't hold that
+    /// many.
+    pub fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, refilled) = &mut *state;
+            let now = Instant::now();
+            let earned = now.duration_since(*refilled).as_secs_f64() * self.bytes_per_sec;
+            *tokens = (*tokens + earned).min(self.bytes_per_sec) - bytes as f64;
+            *refilled = now;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        thread::sleep(wait);
+    }
+}
+
+/// Limit the rate at which contents are read to `bytes_per_sec`, across every thread. Only has an
+/// effect the first time it's called.
+pub fn set_max_read_rate(bytes_per_sec: f64) {
+    let _ = LIMIT.set(TokenBucket::new(bytes_per_sec));
+}
+
+/// # Returns
+///
+/// Bytes of file contents read so far.
+pub fn bytes_read() -> u64 {
+    BYTES_READ.load(Ordering::Relaxed)
+}
+
+/// Account for `bytes` that were just read, sleeping as long as needed to keep to the limit, if
+/// one was set, or for as long as the scan is paused.
+pub fn throttle(bytes: usize) {
+    let bytes_read = BYTES_READ.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+    signals::wait_while_paused(|| format!("reading {} bytes of files", bytes_read));
+    if let Some(bucket) = LIMIT.get() {
+        bucket.consume(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(10_000.0);
+        let start = Instant::now();
+        bucket.consume(10_000);
+        assert!(start.elapsed() < Duration::from_millis(50));
+        bucket.consume(1_000);
+        bucket.consume(1_000);
+        assert!(Duration::from_millis(190) <= start.elapsed());
+    }
+}
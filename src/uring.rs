@@ -0,0 +1,495 @@
+//! Minimal io_uring driver behind the experimental `io-uring` hashing backend.
+//!
+//! Each rayon worker owns one ring. A batch of files is hashed by keeping several of them in
+//! flight at once: every file is opened, read chunk by chunk and closed through the ring, and each
+//! completed read is fed to that file's hasher before its next read is queued. This replaces the
+//! open/read/.../close syscalls issued per file by the default backend with a handful of
+//! `io_uring_enter` calls per batch.
+
+use crate::hasher::{Checksum, NewHasher};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// # Returns
+///
+/// Checksums of the files at `paths`, or the errors that prevented them from being read, in the
+/// same order, computed with hashers created by `new_hasher` through this thread's ring. If
+/// io_uring is unavailable, each file is hashed with `fallback` instead.
+pub fn hash_files(
+    paths: &[PathBuf],
+    new_hasher: NewHasher,
+    fallback: impl Fn(&Path) -> io::Result<Checksum>,
+) -> Vec<io::Result<Checksum>> {
+    #[cfg(target_os = "linux")]
+    if let Some(checksums) = imp::hash_files(paths, new_hasher) {
+        return checksums;
+    }
+
+    paths.iter().map(|path| fallback(path)).collect()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use crate::hasher::{Checksum, ContentHasher, NewHasher};
+    use std::{
+        cell::RefCell,
+        ffi::CString,
+        io, mem,
+        os::unix::ffi::OsStrExt,
+        path::PathBuf,
+        ptr,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    /// Number of submission queue entries in each ring.
+    const RING_ENTRIES: u32 = 64;
+    /// Number of files each ring hashes at the same time.
+    const FILES_IN_FLIGHT: usize = 32;
+    /// Number of bytes requested by each read.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    const IORING_OFF_SQ_RING: libc::off_t = 0;
+    const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+    const IORING_OFF_SQES: libc::off_t = 0x10000000;
+    const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+    const IORING_ENTER_GETEVENTS: u32 = 1;
+    const IORING_OP_OPENAT: u8 = 18;
+    const IORING_OP_CLOSE: u8 = 19;
+    const IORING_OP_READ: u8 = 22;
+
+    /// `user_data` of close requests, whose completions are ignored.
+    const CLOSE_USER_DATA: u64 = u64::MAX;
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct SqringOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        flags: u32,
+        dropped: u32,
+        array: u32,
+        resv1: u32,
+        resv2: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct CqringOffsets {
+        head: u32,
+        tail: u32,
+        ring_mask: u32,
+        ring_entries: u32,
+        overflow: u32,
+        cqes: u32,
+        flags: u32,
+        resv1: u32,
+        resv2: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Params {
+        sq_entries: u32,
+        cq_entries: u32,
+        flags: u32,
+        sq_thread_cpu: u32,
+        sq_thread_idle: u32,
+        features: u32,
+        wq_fd: u32,
+        resv: [u32; 3],
+        sq_off: SqringOffsets,
+        cq_off: CqringOffsets,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Sqe {
+        opcode: u8,
+        flags: u8,
+        ioprio: u16,
+        fd: i32,
+        off: u64,
+        addr: u64,
+        len: u32,
+        op_flags: u32,
+        user_data: u64,
+        buf_index: u16,
+        personality: u16,
+        file_index: u32,
+        addr3: u64,
+        pad: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Cqe {
+        user_data: u64,
+        res: i32,
+        flags: u32,
+    }
+
+    /// Shared memory region mapped from a ring's file descriptor.
+    struct Region {
+        ptr: *mut u8,
+        len: usize,
+    }
+
+    impl Region {
+        fn map(fd: libc::c_int, len: usize, offset: libc::off_t) -> io::Result<Region> {
+            let ptr = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED | libc::MAP_POPULATE,
+                    fd,
+                    offset,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Region {
+                ptr: ptr as *mut u8,
+                len,
+            })
+        }
+
+        fn at<T>(&self, offset: u32) -> *mut T {
+            unsafe { self.ptr.add(offset as usize) as *mut T }
+        }
+    }
+
+    impl Drop for Region {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr as *mut libc::c_void, self.len);
+            }
+        }
+    }
+
+    struct Ring {
+        fd: libc::c_int,
+        sq_entries: u32,
+        sq_head: *const AtomicU32,
+        sq_tail: *const AtomicU32,
+        sq_mask: u32,
+        sq_array: *mut u32,
+        sqes: *mut Sqe,
+        cq_head: *const AtomicU32,
+        cq_tail: *const AtomicU32,
+        cq_mask: u32,
+        cqes: *const Cqe,
+        /// Entries queued since the last call to `io_uring_enter`.
+        to_submit: u32,
+        // Kept alive for as long as the pointers above are in use.
+        _regions: Vec<Region>,
+    }
+
+    impl Ring {
+        fn new(entries: u32) -> io::Result<Ring> {
+            let mut params = Params::default();
+            let fd = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_setup,
+                    entries,
+                    &mut params as *mut Params,
+                )
+            } as libc::c_int;
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let close_on_error = |error| {
+                unsafe { libc::close(fd) };
+                error
+            };
+
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len =
+                params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+            let single_mmap = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+
+            let mut regions = Vec::with_capacity(3);
+            let sq_ring_len = if single_mmap {
+                sq_len.max(cq_len)
+            } else {
+                sq_len
+            };
+            regions.push(Region::map(fd, sq_ring_len, IORING_OFF_SQ_RING).map_err(close_on_error)?);
+            if !single_mmap {
+                regions.push(Region::map(fd, cq_len, IORING_OFF_CQ_RING).map_err(close_on_error)?);
+            }
+            let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+            regions.push(Region::map(fd, sqes_len, IORING_OFF_SQES).map_err(close_on_error)?);
+
+            let sq_ring = &regions[0];
+            let cq_ring = &regions[if single_mmap { 0 } else { 1 }];
+            let sqes = &regions[regions.len() - 1];
+
+            unsafe {
+                Ok(Ring {
+                    fd,
+                    sq_entries: params.sq_entries,
+                    sq_head: sq_ring.at(params.sq_off.head),
+                    sq_tail: sq_ring.at(params.sq_off.tail),
+                    sq_mask: *sq_ring.at::<u32>(params.sq_off.ring_mask),
+                    sq_array: sq_ring.at(params.sq_off.array),
+                    sqes: sqes.at(0),
+                    cq_head: cq_ring.at(params.cq_off.head),
+                    cq_tail: cq_ring.at(params.cq_off.tail),
+                    cq_mask: *cq_ring.at::<u32>(params.cq_off.ring_mask),
+                    cqes: cq_ring.at(params.cq_off.cqes),
+                    to_submit: 0,
+                    _regions: regions,
+                })
+            }
+        }
+
+        /// Queue `sqe` for submission. The caller must ensure that no more than `sq_entries`
+        /// requests are outstanding at any time.
+        fn push(&mut self, sqe: Sqe) {
+            unsafe {
+                let tail = (*self.sq_tail).load(Ordering::Relaxed);
+                let head = (*self.sq_head).load(Ordering::Acquire);
+                debug_assert!(tail.wrapping_sub(head) < self.sq_entries);
+
+                let index = tail & self.sq_mask;
+                ptr::write(self.sqes.add(index as usize), sqe);
+                *self.sq_array.add(index as usize) = index;
+                (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+            }
+            self.to_submit += 1;
+        }
+
+        /// Submit every queued request and block until at least one completion is available.
+        fn submit_and_wait(&mut self) -> io::Result<()> {
+            loop {
+                let submitted = unsafe {
+                    libc::syscall(
+                        libc::SYS_io_uring_enter,
+                        self.fd,
+                        self.to_submit,
+                        1u32,
+                        IORING_ENTER_GETEVENTS,
+                        ptr::null::<libc::sigset_t>(),
+                        0usize,
+                    )
+                };
+                if submitted < 0 {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(error);
+                }
+                self.to_submit -= submitted as u32;
+                return Ok(());
+            }
+        }
+
+        fn pop(&mut self) -> Option<Cqe> {
+            unsafe {
+                let head = (*self.cq_head).load(Ordering::Relaxed);
+                let tail = (*self.cq_tail).load(Ordering::Acquire);
+                if head == tail {
+                    return None;
+                }
+                let cqe = *self.cqes.add((head & self.cq_mask) as usize);
+                (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+                Some(cqe)
+            }
+        }
+    }
+
+    impl Drop for Ring {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    /// A file being hashed through the ring.
+    struct Job {
+        index: usize,
+        path: CString,
+        /// Negative until the file has been opened.
+        fd: libc::c_int,
+        offset: u64,
+        buffer: Box<[u8]>,
+        hasher: Box<dyn ContentHasher>,
+    }
+
+    impl Job {
+        fn openat(&self, slot: usize) -> Sqe {
+            Sqe {
+                opcode: IORING_OP_OPENAT,
+                fd: libc::AT_FDCWD,
+                addr: self.path.as_ptr() as u64,
+                // Opening a FIFO mustn't block; see `open_regular_file`.
+                op_flags: (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NONBLOCK) as u32,
+                user_data: slot as u64,
+                ..Sqe::default()
+            }
+        }
+
+        fn read(&mut self, slot: usize) -> Sqe {
+            Sqe {
+                opcode: IORING_OP_READ,
+                fd: self.fd,
+                off: self.offset,
+                addr: self.buffer.as_mut_ptr() as u64,
+                len: self.buffer.len() as u32,
+                user_data: slot as u64,
+                ..Sqe::default()
+            }
+        }
+
+        fn close(&self) -> Sqe {
+            Sqe {
+                opcode: IORING_OP_CLOSE,
+                fd: self.fd,
+                user_data: CLOSE_USER_DATA,
+                ..Sqe::default()
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// An error unless `fd` refers to a regular file.
+    fn check_regular_file(fd: i32) -> io::Result<()> {
+        let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
+        if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match unsafe { stat.assume_init() }.st_mode & libc::S_IFMT {
+            libc::S_IFREG => Ok(()),
+            _ => Err(crate::fdup::not_a_regular_file()),
+        }
+    }
+
+    thread_local! {
+        // `None` once setting up a ring on this thread has failed.
+        static RING: RefCell<Option<Option<Ring>>> = const { RefCell::new(None) };
+    }
+
+    /// # Returns
+    ///
+    /// Checksums of the files at `paths`, or the errors that prevented them from being read, in
+    /// the same order, or `None` if io_uring is unavailable on this thread.
+    pub fn hash_files(
+        paths: &[PathBuf],
+        new_hasher: NewHasher,
+    ) -> Option<Vec<io::Result<Checksum>>> {
+        RING.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let ring = cell
+                .get_or_insert_with(|| Ring::new(RING_ENTRIES).ok())
+                .as_mut()?;
+            Some(hash_files_with(ring, paths, new_hasher))
+        })
+    }
+
+    fn hash_files_with(
+        ring: &mut Ring,
+        paths: &[PathBuf],
+        new_hasher: NewHasher,
+    ) -> Vec<io::Result<Checksum>> {
+        let mut checksums: Vec<Option<io::Result<Checksum>>> =
+            (0..paths.len()).map(|_| None).collect();
+        let mut slots: Vec<Option<Job>> = (0..FILES_IN_FLIGHT).map(|_| None).collect();
+        let _permit = crate::open_files::acquire(FILES_IN_FLIGHT.min(paths.len()));
+        let mut next_path = 0;
+        // Requests, including unacknowledged closes, that haven't been reaped yet.
+        let mut in_flight = 0;
+
+        loop {
+            // Start on as many new files as there are free slots.
+            for (slot, job) in slots.iter_mut().enumerate() {
+                if job.is_some() || next_path == paths.len() || ring.sq_entries <= in_flight {
+                    continue;
+                }
+                let path = CString::new(paths[next_path].as_os_str().as_bytes()).unwrap();
+                let new_job = Job {
+                    index: next_path,
+                    path,
+                    fd: -1,
+                    offset: 0,
+                    buffer: vec![0u8; CHUNK_SIZE].into_boxed_slice(),
+                    hasher: new_hasher(),
+                };
+                ring.push(new_job.openat(slot));
+                *job = Some(new_job);
+                in_flight += 1;
+                next_path += 1;
+            }
+
+            if in_flight == 0 {
+                break;
+            }
+            ring.submit_and_wait().unwrap();
+
+            // Every completion frees up the request it answers, so each one may queue at most one
+            // follow-up request without exceeding the ring's capacity.
+            while let Some(cqe) = ring.pop() {
+                in_flight -= 1;
+                if cqe.user_data == CLOSE_USER_DATA {
+                    continue;
+                }
+
+                let slot = cqe.user_data as usize;
+                let job = slots[slot].as_mut().unwrap();
+                if cqe.res < 0 {
+                    let error = io::Error::from_raw_os_error(-cqe.res);
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        // Retry the same request.
+                        ring.push(if job.fd < 0 {
+                            job.openat(slot)
+                        } else {
+                            job.read(slot)
+                        });
+                        in_flight += 1;
+                        continue;
+                    }
+                    let job = slots[slot].take().unwrap();
+                    checksums[job.index] = Some(Err(error));
+                    if 0 <= job.fd {
+                        ring.push(job.close());
+                        in_flight += 1;
+                    }
+                    continue;
+                }
+
+                if job.fd < 0 {
+                    job.fd = cqe.res;
+                    if let Err(error) = check_regular_file(job.fd) {
+                        ring.push(job.close());
+                        let job = slots[slot].take().unwrap();
+                        checksums[job.index] = Some(Err(error));
+                    } else {
+                        ring.push(job.read(slot));
+                    }
+                } else if cqe.res == 0 {
+                    ring.push(job.close());
+                    let job = slots[slot].take().unwrap();
+                    checksums[job.index] = Some(Ok(job.hasher.finalize()));
+                } else {
+                    let size = cqe.res as usize;
+                    crate::throttle::throttle(size);
+                    job.hasher.update(&job.buffer[..size]);
+                    job.offset += size as u64;
+                    ring.push(job.read(slot));
+                }
+                in_flight += 1;
+            }
+        }
+
+        checksums.into_iter().map(Option::unwrap).collect()
+    }
+}
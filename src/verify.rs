@@ -0,0 +1,82 @@
+//! Confirming duplicates with an external command, like `cmp`.
+
+use crate::fdup::Verifier;
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+/// # Returns
+///
+/// Verifier that runs `command` through the shell, with the paths of the two files it's given
+/// appended to it as arguments. Exiting with status 0 means that they're duplicates and 1 that
+/// they aren't, as with `cmp`; anything else is an error.
+pub fn command_verifier(command: String) -> Verifier {
+    Verifier::new(move |a, b| {
+        let status = shell(&command)
+            .arg(a)
+            .arg(b)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(io::Error::other(format!(
+                "`{}` failed: {}",
+                command, status
+            ))),
+        }
+    })
+}
+
+/// # Returns
+///
+/// Command that runs `command` through the shell, with the arguments given to it appended.
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    // The arguments after the script are its positional parameters, after its own name.
+    shell.arg("-c").arg(format!("{} \"$@\"", command)).arg("sh");
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{remove_dir_all, write},
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_verifier() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        std::fs::create_dir_all(&root).unwrap();
+        let (a, b, c) = (root.join("a b"), root.join("b"), root.join("c"));
+        write(&a, "same").unwrap();
+        write(&b, "same").unwrap();
+        write(&c, "diff").unwrap();
+
+        let cmp = command_verifier("cmp -s".to_string());
+        assert!(cmp.verify(&a, &b).unwrap());
+        assert!(!cmp.verify(&a, &c).unwrap());
+        assert!(cmp.verify(&a, &root.join("missing")).is_err());
+        assert!(command_verifier("exit 3;".to_string())
+            .verify(&a, &b)
+            .is_err());
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,705 @@
+use crate::{
+    compact_path::CompactPath,
+    errors::{ErrorLog, Operation, ScanError},
+    fdup::get_file_id,
+    fstype::FsTypes,
+    glob::CASE_INSENSITIVE,
+    open_files,
+};
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Options controlling which parts of the trees under the roots are walked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WalkOptions {
+    /// Walk cache directories too. They're tagged with a `CACHEDIR.TAG` file, as specified at
+    /// https://bford.info/cachedir/, and otherwise skipped, since they hold nothing that can't be
+    /// regenerated.
+    pub include_caches: bool,
+
+    /// Pass the symlinks in each directory to `on_files` too, with their own metadata rather than
+    /// that of their targets.
+    pub symlinks: bool,
+
+    /// Types of filesystems that aren't walked, e.g. pseudo filesystems such as procfs, whose
+    /// files are generated on the fly and may never end. Checked with `statfs` for every
+    /// directory that's on another device than its parent, i.e. every mount point below the
+    /// roots, but never for the roots themselves.
+    pub exclude_fs: FsTypes,
+
+    /// Order in which directories are walked, and so in which their files are found.
+    pub order: WalkOrder,
+}
+
+/// Order in which the directories under a root are walked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// Whichever order the directories are read in, in parallel, which is the fastest.
+    #[default]
+    Unordered,
+
+    /// Level by level, the directories of each level read in parallel but their files passed on
+    /// in order, and the entries of each directory sorted by name.
+    BreadthFirst,
+
+    /// Each directory before its subdirectories, which are walked one at a time, in order of
+    /// name, as `find -s` does. Directories are read one after the other.
+    DepthFirstSorted,
+}
+
+impl FromStr for WalkOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unordered" => Ok(WalkOrder::Unordered),
+            "breadth-first" => Ok(WalkOrder::BreadthFirst),
+            "depth-first-sorted" => Ok(WalkOrder::DepthFirstSorted),
+            _ => Err(format!("unknown walk order: {}", s)),
+        }
+    }
+}
+
+/// Name of the file that tags the directory it's in as a cache directory.
+const CACHEDIR_TAG: &str = "CACHEDIR.TAG";
+
+/// What every `CACHEDIR_TAG` file starts with.
+const CACHEDIR_TAG_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// Walk the trees under `roots` in parallel, calling `on_files` with the paths and metadata of
+/// the regular files in each directory, one batch per directory, and of its symlinks if
+/// `options.symlinks`. Symlinks are not followed, unless they're one of `roots`. Once `on_files`
+/// returns `ControlFlow::Break`, no more directories are read, though batches of those already
+/// being read may still be passed to it.
+///
+/// Unless `options.order` says otherwise, every directory is read by its own rayon task, so
+/// enumeration scales across however many threads the pool has, and batches are passed to
+/// `on_files` in no particular order. Otherwise, they're passed in the same order on every walk
+/// of the same trees. Files and directories that can't be read are left out, and their errors
+/// recorded in `errors`.
+///
+/// Roots are walked one after the other, and each directory is only walked once, identified by its
+/// `(device, inode)` pair, no matter how many of `roots` it can be reached through: directories
+/// that overlap with an earlier root, or are aliases of one, are skipped. So are files among
+/// `roots` that are in a directory that has already been walked, or will be.
+///
+/// Directories below the roots are also skipped according to `options`.
+///
+/// # Returns
+///
+/// Paths of the directories below the roots that were skipped because they had already been
+/// walked through another path, and aren't roots themselves. Directories can't be hardlinked, so
+/// those are the bind mounts and repeated mounts of the same filesystem, which make the same files
+/// visible at several paths.
+pub fn walk(
+    roots: &[PathBuf],
+    options: WalkOptions,
+    errors: &ErrorLog,
+    on_files: impl Fn(Vec<(CompactPath, Metadata)>) -> ControlFlow<()> + Sync,
+) -> Vec<PathBuf> {
+    let visited_dirs = Mutex::new(HashSet::new());
+    let overlaps = Mutex::new(Vec::new());
+    // Directories among `roots`, which are skipped without being reported as overlaps.
+    let mut root_dirs = HashSet::new();
+    let stopped = AtomicBool::new(false);
+    // Files among `roots` that were reported on their own, identified by their parent directory
+    // and their name, as returned by `root_file_name`.
+    let mut root_files = HashSet::new();
+
+    for root in roots {
+        if stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        let metadata = match fs::metadata(root) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                record(errors, root.clone(), Operation::Metadata, error);
+                continue;
+            }
+        };
+        let root_path = CompactPath::new(None, root.as_os_str());
+
+        if metadata.is_dir() {
+            root_dirs.insert(get_file_id(&metadata));
+            if visited_dirs.lock().unwrap().insert(get_file_id(&metadata)) {
+                let walker = Walker {
+                    on_files: &on_files,
+                    visited_dirs: &visited_dirs,
+                    root_dirs: &root_dirs,
+                    overlaps: &overlaps,
+                    root_files: &root_files,
+                    stopped: &stopped,
+                    options,
+                    errors,
+                };
+                let root_dir = Dir {
+                    path: Arc::new(root_path),
+                    full_path: root.clone(),
+                    dev: get_file_id(&metadata).0,
+                    is_root: true,
+                };
+                match options.order {
+                    WalkOrder::Unordered => rayon::scope(|scope| walker.walk_dir(scope, root_dir)),
+                    WalkOrder::BreadthFirst => walker.walk_breadth_first(root_dir),
+                    WalkOrder::DepthFirstSorted => walker.walk_depth_first(root_dir),
+                }
+            }
+        } else if metadata.is_file() {
+            let parent = match root.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let parent_id = match fs::metadata(parent) {
+                Ok(metadata) => get_file_id(&metadata),
+                Err(error) => {
+                    record(errors, parent.to_path_buf(), Operation::Metadata, error);
+                    continue;
+                }
+            };
+            let name = root_file_name(root, &metadata);
+            if !visited_dirs.lock().unwrap().contains(&parent_id)
+                && root_files.insert((parent_id, name))
+                && on_files(vec![(root_path, metadata)]).is_break()
+            {
+                stopped.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    overlaps.into_inner().unwrap()
+}
+
+/// # Returns
+///
+/// Name of the file at `path` among the roots, as it's told apart from the others in its
+/// directory: in lower case if that directory is case-insensitive, so that `File.TXT` and
+/// `file.txt` are recognized as the same file rather than as duplicates of each other. Only
+/// folded where `CASE_INSENSITIVE`: always on Windows, and on macOS if the same file can be
+/// opened by the name in another case, since APFS volumes can be case-sensitive too.
+fn root_file_name(path: &Path, metadata: &Metadata) -> OsString {
+    let name = path.file_name().unwrap();
+    let folded = OsString::from(name.to_string_lossy().to_lowercase());
+    if !CASE_INSENSITIVE || folded == name {
+        // Nothing to fold, unless the name is only in lower case already.
+        return if CASE_INSENSITIVE {
+            folded
+        } else {
+            name.to_os_string()
+        };
+    }
+    if cfg!(windows) {
+        return folded;
+    }
+    match fs::metadata(path.with_file_name(&folded)) {
+        Ok(other) if get_file_id(&other) == get_file_id(metadata) => folded,
+        _ => name.to_os_string(),
+    }
+}
+
+/// # Returns
+///
+/// Whether the file named `name` in the directory identified by `dir_id` was reported as one of
+/// the roots, whether or not its name was folded by `root_file_name`.
+fn is_root_file(root_files: &RootFiles, dir_id: (u64, u64), name: &OsStr) -> bool {
+    root_files.contains(&(dir_id, name.to_os_string()))
+        || (CASE_INSENSITIVE
+            && root_files.contains(&(
+                dir_id,
+                OsString::from(name.to_string_lossy().to_lowercase()),
+            )))
+}
+
+fn record(errors: &ErrorLog, path: PathBuf, operation: Operation, error: io::Error) {
+    errors.record(ScanError {
+        path,
+        operation,
+        error,
+    });
+}
+
+/// Files among the roots, identified by their parent directory and `root_file_name`.
+type RootFiles = HashSet<((u64, u64), OsString)>;
+
+struct Walker<'a, F> {
+    on_files: &'a F,
+    visited_dirs: &'a Mutex<HashSet<(u64, u64)>>,
+    root_dirs: &'a HashSet<(u64, u64)>,
+    overlaps: &'a Mutex<Vec<PathBuf>>,
+    root_files: &'a RootFiles,
+    stopped: &'a AtomicBool,
+    options: WalkOptions,
+    errors: &'a ErrorLog,
+}
+
+/// # Returns
+///
+/// `true` if and only if the file at `path` is a valid `CACHEDIR_TAG`.
+fn is_cachedir_tag(path: &Path) -> bool {
+    let mut signature = [0u8; CACHEDIR_TAG_SIGNATURE.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok_and(|_| signature == CACHEDIR_TAG_SIGNATURE)
+}
+
+/// Directory to be walked.
+struct Dir {
+    path: Arc<CompactPath>,
+    /// Same as `path`, built once to read the directory with.
+    full_path: PathBuf,
+    /// Device that it's on.
+    dev: u64,
+    is_root: bool,
+}
+
+/// Files and subdirectories of a directory, as listed by `Walker::list_dir`.
+struct Listing {
+    files: Vec<(CompactPath, Metadata)>,
+    /// Along with the `(device, inode)` pair that identifies each.
+    subdirs: Vec<(Dir, (u64, u64))>,
+}
+
+impl<'a, F> Walker<'a, F>
+where
+    F: Fn(Vec<(CompactPath, Metadata)>) -> ControlFlow<()> + Sync,
+{
+    /// Walk `dir`, which has already been marked as visited, and its subdirectories, each in a
+    /// task of its own.
+    fn walk_dir<'scope>(&'scope self, scope: &rayon::Scope<'scope>, dir: Dir) {
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let listing = match self.list_dir(&dir) {
+            Some(listing) => listing,
+            None => return,
+        };
+        for (subdir, id) in listing.subdirs {
+            if self.visit(&subdir, id) {
+                scope.spawn(move |scope| self.walk_dir(scope, subdir));
+            }
+        }
+        self.pass_on(listing.files);
+    }
+
+    /// Walk `dir`, which has already been marked as visited, and its subdirectories, a level at
+    /// a time.
+    fn walk_breadth_first(&self, dir: Dir) {
+        use rayon::prelude::*;
+
+        let mut level = vec![dir];
+        while !level.is_empty() && !self.stopped.load(Ordering::Relaxed) {
+            let listings: Vec<Option<Listing>> =
+                level.par_iter().map(|dir| self.list_dir(dir)).collect();
+            level = Vec::new();
+            for listing in listings.into_iter().flatten() {
+                for (subdir, id) in listing.subdirs {
+                    if self.visit(&subdir, id) {
+                        level.push(subdir);
+                    }
+                }
+                self.pass_on(listing.files);
+            }
+        }
+    }
+
+    /// Walk `dir`, which has already been marked as visited, and its subdirectories, depth first.
+    fn walk_depth_first(&self, dir: Dir) {
+        // Kept on the heap rather than recursed into, however deep the trees are.
+        let mut stack = vec![dir];
+        while let Some(dir) = stack.pop() {
+            if self.stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            let listing = match self.list_dir(&dir) {
+                Some(listing) => listing,
+                None => continue,
+            };
+            let first = stack.len();
+            for (subdir, id) in listing.subdirs {
+                if self.visit(&subdir, id) {
+                    stack.push(subdir);
+                }
+            }
+            // Popped in order of name.
+            stack[first..].reverse();
+            self.pass_on(listing.files);
+        }
+    }
+
+    /// Mark the directory identified by `id` as visited, or record `dir` as an overlap if it
+    /// already was, through another path that isn't a root.
+    ///
+    /// # Returns
+    ///
+    /// `true` if and only if it wasn't visited before, and so should be walked.
+    fn visit(&self, dir: &Dir, id: (u64, u64)) -> bool {
+        let first_visit = self.visited_dirs.lock().unwrap().insert(id);
+        if !first_visit && !self.root_dirs.contains(&id) {
+            self.overlaps.lock().unwrap().push(dir.full_path.clone());
+        }
+        first_visit
+    }
+
+    fn pass_on(&self, files: Vec<(CompactPath, Metadata)>) {
+        if !files.is_empty() && (self.on_files)(files).is_break() {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Files and subdirectories of `dir`, sorted by name unless the walk is unordered, or `None`
+    /// if it's a cache directory or can't be read. Roots are listed even if they're cache
+    /// directories.
+    fn list_dir(&self, dir: &Dir) -> Option<Listing> {
+        let record = |path, operation, error| record(self.errors, path, operation, error);
+        let dir_path = &dir.full_path;
+
+        // Only needed to rule out files that were already reported as roots.
+        let dir_id = if self.root_files.is_empty() {
+            None
+        } else {
+            match fs::metadata(dir_path) {
+                Ok(metadata) => Some(get_file_id(&metadata)),
+                Err(error) => {
+                    record(dir_path.clone(), Operation::Metadata, error);
+                    return None;
+                }
+            }
+        };
+
+        // The directory stays open until its entries are dropped, and its `CACHEDIR.TAG` may be
+        // opened in the meantime.
+        let check_cache = !dir.is_root && !self.options.include_caches;
+        let _permit = open_files::acquire(1 + check_cache as usize);
+        let entries = match fs::read_dir(dir_path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                record(dir_path.clone(), Operation::ReadDir, error);
+                return None;
+            }
+        };
+        let mut entries: Vec<fs::DirEntry> = entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(error) => {
+                    record(dir_path.clone(), Operation::ReadDir, error);
+                    None
+                }
+            })
+            .collect();
+        let is_cache = || {
+            entries
+                .iter()
+                .any(|entry| entry.file_name() == CACHEDIR_TAG && is_cachedir_tag(&entry.path()))
+        };
+        if check_cache && is_cache() {
+            return None;
+        }
+        if self.options.order != WalkOrder::Unordered {
+            entries.sort_by_cached_key(fs::DirEntry::file_name);
+        }
+
+        let mut listing = Listing {
+            files: Vec::new(),
+            subdirs: Vec::new(),
+        };
+        for entry in entries {
+            // Usually known without a syscall of its own, so check it before anything else.
+            let file_type = match entry.file_type() {
+                Ok(file_type)
+                    if file_type.is_dir()
+                        || file_type.is_file()
+                        || (self.options.symlinks && file_type.is_symlink()) =>
+                {
+                    file_type
+                }
+                Ok(_) => continue,
+                Err(error) => {
+                    record(entry.path(), Operation::Metadata, error);
+                    continue;
+                }
+            };
+            // Doesn't follow symlinks, so those that are passed on have their own metadata.
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(error) => {
+                    record(entry.path(), Operation::Metadata, error);
+                    continue;
+                }
+            };
+            let name = entry.file_name();
+
+            if file_type.is_dir() {
+                let id = get_file_id(&metadata);
+                let subdir_path = entry.path();
+                if id.0 != dir.dev && self.options.exclude_fs.contains_fs_of(&subdir_path) {
+                    continue;
+                }
+                let subdir = Dir {
+                    path: Arc::new(CompactPath::new(Some(dir.path.clone()), &name)),
+                    full_path: subdir_path,
+                    dev: id.0,
+                    is_root: false,
+                };
+                listing.subdirs.push((subdir, id));
+            } else {
+                if let Some(dir_id) = dir_id {
+                    if is_root_file(self.root_files, dir_id, &name) {
+                        continue;
+                    }
+                }
+                let path = CompactPath::new(Some(dir.path.clone()), &name);
+                listing.files.push((path, metadata));
+            }
+        }
+        Some(listing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        env,
+        fs::{create_dir_all, remove_dir_all, write},
+    };
+    use walkdir::WalkDir;
+
+    // # Returns
+    //
+    // Paths and sizes of the files found under `roots`.
+    fn find(roots: &[PathBuf], options: WalkOptions) -> Vec<(PathBuf, u64)> {
+        let found = Mutex::new(Vec::new());
+        walk(roots, options, &ErrorLog::default(), |files| {
+            let mut found = found.lock().unwrap();
+            for (path, metadata) in files {
+                found.push((path.to_path_buf(), metadata.len()));
+            }
+            ControlFlow::Continue(())
+        });
+        found.into_inner().unwrap()
+    }
+
+    // Check that the files found match those found by `walkdir`, and that each file's metadata is
+    // its own.
+    #[test]
+    fn test_walk() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        for dir in ["a/b/c", "a/d", "e"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        for (file, content) in [("f", "1"), ("a/f", "22"), ("a/b/c/f", "333"), ("a/d/g", "")] {
+            write(root.join(file), content).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("a"), root.join("e/link")).unwrap();
+
+        let walk_roots = |roots: &[PathBuf]| find(roots, WalkOptions::default());
+        let found = walk_roots(std::slice::from_ref(&root));
+        // Roots that overlap with earlier ones add nothing, nor does an alias of the root.
+        let overlapping = walk_roots(&[
+            root.clone(),
+            root.join("a"),
+            root.join("f"),
+            root.join("a/b/../b/c/f"),
+            root.join("e/../."),
+        ]);
+        // Nor are they reported as overlaps, which only mounts below the roots can be.
+        let overlaps = walk(
+            &[root.join("a"), root.clone(), root.join("a/b")],
+            WalkOptions::default(),
+            &ErrorLog::default(),
+            |_| ControlFlow::Continue(()),
+        );
+        assert!(overlaps.is_empty());
+        // Roots that can't be read are recorded and left out.
+        let errors = ErrorLog::default();
+        let missing = [root.join("missing")];
+        walk(&missing, WalkOptions::default(), &errors, |_| {
+            panic!("found files")
+        });
+        let errors = errors.take();
+        assert_eq!(1, errors.len());
+        assert_eq!(root.join("missing"), errors[0].path);
+        assert_eq!(Operation::Metadata, errors[0].operation);
+        // Files and directories that are roots are only found through themselves.
+        let nested = walk_roots(&[root.join("a/b"), root.join("f"), root.clone()]);
+        // Symlinks are only found when asked for, and never followed.
+        let with_symlinks = WalkOptions {
+            symlinks: true,
+            ..WalkOptions::default()
+        };
+        let with_symlinks = find(std::slice::from_ref(&root), with_symlinks);
+        // Roots are walked whatever filesystem they're on.
+        let excluding_fs = WalkOptions {
+            exclude_fs: "devtmpfs,tmpfs,overlay".parse().unwrap(),
+            ..WalkOptions::default()
+        };
+        let excluding_fs = find(std::slice::from_ref(&root), excluding_fs);
+        // Nothing more is walked once it's been stopped.
+        let batches = Mutex::new(0);
+        walk(
+            &[root.join("f"), root.join("a/f"), root.clone()],
+            WalkOptions::default(),
+            &ErrorLog::default(),
+            |_| {
+                *batches.lock().unwrap() += 1;
+                ControlFlow::Break(())
+            },
+        );
+
+        let expected: HashSet<(PathBuf, u64)> = WalkDir::new(&root)
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| (entry.path().to_path_buf(), entry.metadata().unwrap().len()))
+            .collect();
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(4, expected.len());
+        assert_eq!(4, found.len());
+        assert_eq!(expected, found.into_iter().collect());
+        assert_eq!(expected, excluding_fs.into_iter().collect());
+        assert_eq!(4, overlapping.len());
+        assert_eq!(expected, overlapping.into_iter().collect());
+        assert_eq!(4, nested.len());
+        assert!(nested.contains(&(root.join("a/b/c/f"), 3)));
+        assert!(nested.contains(&(root.join("f"), 1)));
+        #[cfg(unix)]
+        {
+            assert_eq!(5, with_symlinks.len());
+            assert!(with_symlinks
+                .iter()
+                .any(|(path, _)| path == &root.join("e/link")));
+        }
+        assert_eq!(1, batches.into_inner().unwrap());
+    }
+
+    // Check that the ordered walks find the files in the same order every time.
+    #[test]
+    fn test_walk_order() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        for dir in ["b", "a/c"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["g", "f", "b/y", "a/x", "a/c/z"] {
+            write(root.join(file), "").unwrap();
+        }
+        let in_order = |order| {
+            let options = WalkOptions {
+                order,
+                ..WalkOptions::default()
+            };
+            let found = find(std::slice::from_ref(&root), options);
+            let paths: Vec<PathBuf> = found.into_iter().map(|(path, _)| path).collect();
+            paths
+        };
+        let paths =
+            |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(|path| root.join(path)).collect() };
+
+        let breadth_first = in_order(WalkOrder::BreadthFirst);
+        let depth_first = in_order(WalkOrder::DepthFirstSorted);
+        let unordered = in_order(WalkOrder::Unordered);
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(paths(&["f", "g", "a/x", "b/y", "a/c/z"]), breadth_first);
+        assert_eq!(paths(&["f", "g", "a/x", "a/c/z", "b/y"]), depth_first);
+        assert_eq!(
+            depth_first.into_iter().collect::<HashSet<_>>(),
+            unordered.into_iter().collect()
+        );
+        assert!("depth-first".parse::<WalkOrder>().is_err());
+    }
+
+    // Check that a file among the roots is found once, whatever the case that it's named in, on
+    // case-insensitive filesystems, and that names are only folded on those.
+    #[test]
+    fn test_walk_root_case() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        create_dir_all(&root).unwrap();
+        write(root.join("File.TXT"), "1").unwrap();
+        let case_insensitive = root.join("file.txt").exists();
+
+        let roots = [root.join("File.TXT"), root.join("file.txt"), root.clone()];
+        let found = find(&roots, WalkOptions::default());
+        let metadata = fs::metadata(root.join("File.TXT")).unwrap();
+        let name = root_file_name(&root.join("File.TXT"), &metadata);
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec![(root.join("File.TXT"), 1)], found);
+        let folded = CASE_INSENSITIVE && (case_insensitive || cfg!(windows));
+        assert_eq!(folded, name == "file.txt");
+    }
+
+    // Check that cache directories are only walked when they're included, or roots.
+    #[test]
+    fn test_walk_caches() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let root = env::temp_dir().join(prefix);
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        for dir in ["cache/a", "not_cache"] {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        let signature = std::str::from_utf8(CACHEDIR_TAG_SIGNATURE).unwrap();
+        for (file, content) in [
+            ("f", ""),
+            ("cache/CACHEDIR.TAG", &format!("{}\n# comment", signature)),
+            ("cache/a/f", ""),
+            ("not_cache/CACHEDIR.TAG", "Signature: something else"),
+        ] {
+            write(root.join(file), content).unwrap();
+        }
+
+        let find_paths = |root: &Path, include_caches| -> HashSet<PathBuf> {
+            let options = WalkOptions {
+                include_caches,
+                ..WalkOptions::default()
+            };
+            let found = find(&[root.to_path_buf()], options);
+            found.into_iter().map(|(path, _)| path).collect()
+        };
+        let skipped = find_paths(&root, false);
+        let included = find_paths(&root, true);
+        let cache_as_root = find_paths(&root.join("cache"), false);
+        remove_dir_all(&root).unwrap();
+
+        let paths = |paths: &[&str]| -> HashSet<PathBuf> {
+            paths.iter().map(|path| root.join(path)).collect()
+        };
+        assert_eq!(paths(&["f", "not_cache/CACHEDIR.TAG"]), skipped);
+        assert_eq!(
+            paths(&[
+                "f",
+                "cache/CACHEDIR.TAG",
+                "cache/a/f",
+                "not_cache/CACHEDIR.TAG"
+            ]),
+            included
+        );
+        assert_eq!(paths(&["cache/CACHEDIR.TAG", "cache/a/f"]), cache_as_root);
+    }
+}